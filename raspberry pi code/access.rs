@@ -0,0 +1,107 @@
+use serde::Deserialize;
+
+use crate::commands::ServerCommand;
+
+// Viewers can watch and look things up; admins can also change how the camera behaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Admin,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    // Role of server commands without a "role" next to "issued_by", as from servers that
+    // don't track who is allowed what. Admin keeps those working as before.
+    pub server_default_role: Role,
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        Self { server_default_role: Role::Admin }
+    }
+}
+
+impl AccessConfig {
+    // The role the server says the command's issuer has. One that can't be read gets the
+    // least it could have meant.
+    pub fn server_role(&self, json: &serde_json::Value) -> Role {
+        match json.get("role") {
+            None => self.server_default_role,
+            Some(role) => serde_json::from_value(role.clone()).unwrap_or_else(|_| {
+                eprintln!("Unknown role {} on a server command, treating it as a viewer", role);
+                Role::Viewer
+            }),
+        }
+    }
+}
+
+pub fn required_for_command(command: &ServerCommand) -> Role {
+    match command {
+        ServerCommand::Ptz(_)
+        | ServerCommand::DumpCongestionHistory
+        | ServerCommand::EchoTest(_)
+        | ServerCommand::Snapshot(_)
+        | ServerCommand::StatsQuery(_)
+        | ServerCommand::TimelineQuery(_)
+        | ServerCommand::ViewerActive(_) => Role::Viewer,
+        // Changes what the camera sees, records, sends or alerts on, reads back who did what,
+        // or uploads decrypted recordings to wherever the command says
+        ServerCommand::Alarm(_)
+        | ServerCommand::PauseStream
+        | ServerCommand::ResumeStream
+        | ServerCommand::BurstCapture(_)
+        | ServerCommand::CancelBurst(_)
+        | ServerCommand::Illuminator(_)
+        | ServerCommand::CameraControls(_)
+        | ServerCommand::ExportClip(_)
+        | ServerCommand::CustodyExport(_)
+        | ServerCommand::Overlay(_)
+        | ServerCommand::ClearOverlay(_)
+        | ServerCommand::AuditQuery(_)
+        | ServerCommand::LogStream(_)
+        | ServerCommand::StreamPriorities(_)
+        | ServerCommand::Maintenance(_)
+        | ServerCommand::EndMaintenance => Role::Admin,
+    }
+}
+
+// For the control socket's methods as the REST API offers them; viewers get snapshots
+// from GET /api/snapshot
+pub fn required_for_method(method: &str) -> Role {
+    match method {
+        "status" => Role::Viewer,
+        _ => Role::Admin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use serde_json::json;
+
+    // Whether the camera acts on an export_clip the server says `role` sent
+    fn export_allowed(role: &str) -> bool {
+        let message = json!({ "command": "export_clip", "from": 0, "to": 60_000, "upload_url": "https://elsewhere.example/clip", "role": role });
+        let command = commands::parse_command(&message).unwrap().unwrap();
+        AccessConfig::default().server_role(&message) >= required_for_command(&command)
+    }
+
+    #[test]
+    fn viewers_cannot_export_clips() {
+        assert!(!export_allowed("viewer"));
+        assert!(export_allowed("admin"));
+    }
+}
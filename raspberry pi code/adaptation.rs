@@ -0,0 +1,343 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+use crate::encoder::Codec;
+use crate::resolution::{Resolution, ResolutionConfig};
+use crate::scene_complexity::{codec_factor, quality_scale, SceneComplexityConfig};
+
+// How the stream adapts to the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdaptationStrategy {
+    // Steps down only after sustained congestion and back up slowly; suits LAN NVRs
+    Conservative,
+    // Follows the congestion indicators closely in both directions; suits LTE uplinks
+    Aggressive,
+    // Keeps the estimated bitrate under a budget that backs off under congestion
+    BitrateTarget,
+    // Does what the server's network feedback says and nothing else
+    ServerDriven,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AdaptationConfig {
+    pub strategy: AdaptationStrategy,
+    // bitrate_target: budget on a healthy network, and the floor it backs off to
+    pub target_kbps: u32,
+    pub min_kbps: u32,
+    // bitrate_target: frame rate the bitrate estimate assumes
+    pub frame_rate: u32,
+}
+
+impl Default for AdaptationConfig {
+    fn default() -> Self {
+        Self {
+            strategy: AdaptationStrategy::Conservative,
+            target_kbps: 2000,
+            min_kbps: 300,
+            frame_rate: 15,
+        }
+    }
+}
+
+// What the controller gets to look at on every check
+pub struct Signals {
+    pub queue_size: u64,
+    pub consecutive_failures: u32,
+    pub server_congested: bool,
+    // The server's latest suggestion, or the current settings if it made none
+    pub suggested_quality: u32,
+    pub suggested_resolution: Resolution,
+    // Measured encoded detail at quality 100 with MJPEG, when frame sizes are tracked
+    pub bits_per_pixel: Option<f64>,
+    pub codec: Codec,
+}
+
+pub trait AdaptationPolicy: Send {
+    fn name(&self) -> &'static str;
+    // Whether the stream counts as congested, and the resolution and quality to use
+    fn update(&mut self, now: Instant, signals: &Signals) -> (bool, Resolution, u32);
+    // 0-10, higher means more congested
+    fn level(&self) -> u8;
+    fn check_interval(&self) -> Duration;
+    // Relative detail of the scene, for policies that let it move their thresholds
+    fn set_scene_complexity(&mut self, _relative: f64, _config: &SceneComplexityConfig) {}
+}
+
+// `now` is passed in so the simulation can run the controller on a virtual clock
+pub fn policy(config: &AdaptationConfig, resolutions: ResolutionConfig, now: Instant) -> Box<dyn AdaptationPolicy> {
+    match config.strategy {
+        AdaptationStrategy::Conservative => Box::new(NetworkState::new(CONSERVATIVE, resolutions, now)),
+        AdaptationStrategy::Aggressive => Box::new(NetworkState::new(AGGRESSIVE, resolutions, now)),
+        AdaptationStrategy::BitrateTarget => Box::new(BitrateTarget::new(config.clone(), resolutions, now)),
+        AdaptationStrategy::ServerDriven => Box::new(ServerDriven { congested: false }),
+    }
+}
+
+// Knobs of the hysteresis controller
+struct Tuning {
+    name: &'static str,
+    // How far the level moves towards the indicators per check
+    level_step: u8,
+    // Stable checks before the level may fall
+    stable_before_decay: u32,
+    // Minimum time at one resolution before stepping down or up
+    hold_before_reduce: Duration,
+    hold_before_increase: Duration,
+    // Stable checks before stepping back up
+    stable_before_increase: u32,
+    // Level above which an ordinary scene steps down
+    reduce_above_level: u8,
+    check_interval: Duration,
+    stable_check_interval: Duration,
+}
+
+const CONSERVATIVE: Tuning = Tuning {
+    name: "conservative",
+    level_step: 1,
+    stable_before_decay: 6,
+    hold_before_reduce: Duration::from_secs(2),
+    hold_before_increase: Duration::from_secs(15),
+    stable_before_increase: 20,
+    reduce_above_level: 6,
+    check_interval: Duration::from_secs(2),
+    stable_check_interval: Duration::from_secs(5),
+};
+
+const AGGRESSIVE: Tuning = Tuning {
+    name: "aggressive",
+    level_step: 10,
+    stable_before_decay: 1,
+    hold_before_reduce: Duration::from_secs(1),
+    hold_before_increase: Duration::from_secs(5),
+    stable_before_increase: 5,
+    reduce_above_level: 4,
+    check_interval: Duration::from_secs(1),
+    stable_check_interval: Duration::from_secs(2),
+};
+
+// Congestion level with inertia, switching between the high and low rung
+struct NetworkState {
+    tuning: Tuning,
+    is_congested: bool,
+    congestion_level: u8,       // 0-10 scale, higher means more congested
+    stability_counter: u32,     // counts stable measurements before allowing changes
+    last_resolution_change: Instant, // prevent rapid resolution changes
+    resolutions: ResolutionConfig, // ladder the high/low resolutions come from
+    reduce_above_level: u8,     // congestion level that steps down; follows scene complexity
+}
+
+impl NetworkState {
+    fn new(tuning: Tuning, resolutions: ResolutionConfig, now: Instant) -> Self {
+        Self {
+            reduce_above_level: tuning.reduce_above_level,
+            tuning,
+            is_congested: false,
+            congestion_level: 0,
+            stability_counter: 0,
+            last_resolution_change: now,
+            resolutions,
+        }
+    }
+}
+
+impl AdaptationPolicy for NetworkState {
+    fn name(&self) -> &'static str {
+        self.tuning.name
+    }
+
+    // Update congestion state with hysteresis
+    fn update(&mut self, now: Instant, signals: &Signals) -> (bool, Resolution, u32) {
+        // Combine multiple congestion indicators
+        let new_congestion_indicators: u32 =
+            (if signals.queue_size > 20 { 2 } else if signals.queue_size > 10 { 1 } else { 0 }) +
+            (if signals.consecutive_failures > 3 { 3 } else if signals.consecutive_failures > 0 { 1 } else { 0 }) +
+            (if signals.server_congested { 3 } else { 0 });
+
+        // Gradually adjust congestion level (with inertia)
+        let indicated = new_congestion_indicators.min(10) as u8;
+        if indicated > self.congestion_level {
+            self.congestion_level = (self.congestion_level + self.tuning.level_step).min(indicated);
+        } else if indicated < self.congestion_level && self.stability_counter >= self.tuning.stable_before_decay {
+            self.congestion_level = self.congestion_level.saturating_sub(self.tuning.level_step).max(indicated);
+        }
+
+        // Reset stability counter if indicators changed significantly
+        if (new_congestion_indicators as i32 - self.congestion_level as i32).abs() > 2 {
+            self.stability_counter = 0;
+        } else {
+            self.stability_counter += 1;
+        }
+
+        // Determine if we should change resolution and quality based on congestion level
+        // and how long since the last change
+        let time_since_last_change = now.duration_since(self.last_resolution_change);
+
+        let should_reduce = self.congestion_level > self.reduce_above_level &&
+                           time_since_last_change > self.tuning.hold_before_reduce &&
+                           !self.is_congested;
+
+        let should_increase = self.congestion_level < 3 &&
+                              time_since_last_change > self.tuning.hold_before_increase &&
+                              self.is_congested &&
+                              self.stability_counter > self.tuning.stable_before_increase;
+
+        // Calculate target quality and resolution
+        let (resolution, quality) = if should_reduce || self.is_congested {
+            self.is_congested = true;
+            self.last_resolution_change = now;
+            (self.resolutions.low(), 50 - self.congestion_level as u32 * 2)
+        } else if should_increase {
+            self.is_congested = false;
+            self.last_resolution_change = now;
+            (self.resolutions.high(), 70)
+        } else {
+            // Maintain higher resolution but adjust quality based on current congestion
+            (self.resolutions.high(), 70 - self.congestion_level as u32 * 3)
+        };
+
+        // Log meaningful state changes
+        if should_reduce {
+            println!("Network congestion detected (level {}). Reducing resolution to {}, quality to {}",
+                    self.congestion_level, resolution, quality);
+        } else if should_increase {
+            println!("Network stable (level {}) for {} frames. Increasing resolution to {}, quality to {}",
+                    self.congestion_level, self.stability_counter, resolution, quality);
+        }
+
+        (self.is_congested, resolution, quality.max(20))
+    }
+
+    fn level(&self) -> u8 {
+        self.congestion_level
+    }
+
+    // Check less frequently when stable
+    fn check_interval(&self) -> Duration {
+        if self.stability_counter > 15 {
+            self.tuning.stable_check_interval
+        } else {
+            self.tuning.check_interval
+        }
+    }
+
+    // Simple scenes keep the high resolution through more congestion, busy ones step down sooner
+    fn set_scene_complexity(&mut self, relative: f64, config: &SceneComplexityConfig) {
+        let base = self.tuning.reduce_above_level;
+        self.reduce_above_level = if relative < config.simple_below {
+            base + 2
+        } else if relative > config.complex_above {
+            base.saturating_sub(2)
+        } else {
+            base
+        };
+    }
+}
+
+// Additive increase, multiplicative decrease on a bitrate budget; the settings are the
+// best rung and quality whose estimated bitrate fits in it
+struct BitrateTarget {
+    config: AdaptationConfig,
+    resolutions: ResolutionConfig,
+    budget_kbps: f64,
+    last_decrease: Instant,
+    congested: bool,
+}
+
+// Bits per pixel of an ordinary scene until frame sizes have been measured
+const ASSUMED_BITS_PER_PIXEL: f64 = 2.0;
+
+impl BitrateTarget {
+    fn new(config: AdaptationConfig, resolutions: ResolutionConfig, now: Instant) -> Self {
+        Self {
+            budget_kbps: config.target_kbps as f64,
+            config,
+            resolutions,
+            last_decrease: now,
+            congested: false,
+        }
+    }
+
+    fn estimated_kbps(&self, resolution: Resolution, quality: u32, bits_per_pixel: f64, codec: Codec) -> f64 {
+        let pixels = (resolution.width * resolution.height) as f64;
+        pixels * bits_per_pixel * quality_scale(quality) * codec_factor(codec) * self.config.frame_rate as f64 / 1000.0
+    }
+
+    // The highest quality on each rung that fits; a rung is only worth it at quality 50 or
+    // better, otherwise the next one down gets a go
+    fn settings_for(&self, bits_per_pixel: f64, codec: Codec) -> (Resolution, u32) {
+        let rungs = self.resolutions.rungs();
+        for (index, &rung) in rungs.iter().enumerate() {
+            let fitting = (20..=90).rev().step_by(5)
+                .find(|&quality| self.estimated_kbps(rung, quality, bits_per_pixel, codec) <= self.budget_kbps);
+            match fitting {
+                Some(quality) if quality >= 50 || index + 1 == rungs.len() => return (rung, quality),
+                _ => {}
+            }
+        }
+        (self.resolutions.low(), 20)
+    }
+}
+
+impl AdaptationPolicy for BitrateTarget {
+    fn name(&self) -> &'static str {
+        "bitrate_target"
+    }
+
+    fn update(&mut self, now: Instant, signals: &Signals) -> (bool, Resolution, u32) {
+        let target = self.config.target_kbps as f64;
+        let floor = (self.config.min_kbps as f64).min(target);
+        self.congested = signals.server_congested || signals.consecutive_failures > 0 || signals.queue_size > 10;
+
+        if self.congested {
+            // Give the last decrease time to show before backing off again
+            if now.duration_since(self.last_decrease) >= Duration::from_secs(2) {
+                self.budget_kbps = (self.budget_kbps * 0.7).max(floor);
+                self.last_decrease = now;
+                println!("Network congested, bitrate budget down to {:.0} kbps", self.budget_kbps);
+            }
+        } else {
+            self.budget_kbps = (self.budget_kbps + target * 0.05).min(target);
+        }
+
+        let bits_per_pixel = signals.bits_per_pixel.unwrap_or(ASSUMED_BITS_PER_PIXEL);
+        let (resolution, quality) = self.settings_for(bits_per_pixel, signals.codec);
+        (self.congested, resolution, quality)
+    }
+
+    // How far the budget is below target
+    fn level(&self) -> u8 {
+        let target = self.config.target_kbps.max(1) as f64;
+        ((1.0 - self.budget_kbps / target) * 10.0).round().clamp(0.0, 10.0) as u8
+    }
+
+    fn check_interval(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+}
+
+// Leaves every decision to the server's network feedback, for servers that see all
+// cameras on a link and share it out themselves
+struct ServerDriven {
+    congested: bool,
+}
+
+impl AdaptationPolicy for ServerDriven {
+    fn name(&self) -> &'static str {
+        "server_driven"
+    }
+
+    fn update(&mut self, _now: Instant, signals: &Signals) -> (bool, Resolution, u32) {
+        self.congested = signals.server_congested;
+        (self.congested, signals.suggested_resolution, signals.suggested_quality.clamp(20, 90))
+    }
+
+    fn level(&self) -> u8 {
+        if self.congested { 10 } else { 0 }
+    }
+
+    fn check_interval(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+}
@@ -0,0 +1,333 @@
+use serde::Deserialize;
+use std::{future::pending, process::Stdio, time::Duration};
+use tokio::{
+    process::Command,
+    sync::{broadcast, broadcast::error::RecvError, mpsc, oneshot, watch},
+    time::{sleep, sleep_until, Instant},
+};
+
+use crate::events::{CameraEvent, CameraEvents};
+use crate::mqtt::{self, MqttConfig};
+use crate::presence::{self, PresenceConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmMode {
+    Disarmed,
+    // Someone is home: usually only tamper alerts
+    ArmedHome,
+    ArmedAway,
+}
+
+impl AlarmMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            AlarmMode::Disarmed => "disarmed",
+            AlarmMode::ArmedHome => "armed_home",
+            AlarmMode::ArmedAway => "armed_away",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModeBehavior {
+    // Which detectors raise an alarm: "motion", "tamper"
+    pub alert_on: Vec<String>,
+    // Whether the siren command runs when the alarm goes off
+    pub siren: bool,
+}
+
+impl ModeBehavior {
+    fn alerts_for(&self, event: &CameraEvent) -> bool {
+        self.alert_on.iter().any(|d| d == event.kind())
+    }
+}
+
+impl Default for ModeBehavior {
+    fn default() -> Self {
+        Self {
+            alert_on: vec!["motion".to_string(), "tamper".to_string()],
+            siren: false,
+        }
+    }
+}
+
+// A physical keyswitch read with libgpiod's gpioget
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyswitchConfig {
+    pub chip: String,
+    pub line: u32,
+    // Mode while the switch is closed; open means disarmed
+    pub armed_mode: AlarmMode,
+}
+
+impl Default for KeyswitchConfig {
+    fn default() -> Self {
+        Self {
+            chip: "gpiochip0".to_string(),
+            line: 17,
+            armed_mode: AlarmMode::ArmedAway,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlarmConfig {
+    pub initial_mode: AlarmMode,
+    // Time to leave after arming before detectors count
+    pub exit_delay_seconds: u64,
+    // Time to disarm after an alerting event before the alarm goes off
+    pub entry_delay_seconds: u64,
+    pub armed_home: ModeBehavior,
+    pub armed_away: ModeBehavior,
+    // Program and arguments that sound the siren; killed after siren_seconds
+    pub siren_command: Option<Vec<String>>,
+    pub siren_seconds: u64,
+    pub keyswitch: Option<KeyswitchConfig>,
+    // Arm when everyone's phone has left and disarm when someone gets home
+    pub presence: Option<PresenceConfig>,
+    // Publish the state to an MQTT broker and take arm/disarm commands from it
+    pub mqtt: Option<MqttConfig>,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self {
+            initial_mode: AlarmMode::Disarmed,
+            exit_delay_seconds: 30,
+            entry_delay_seconds: 30,
+            armed_home: ModeBehavior { alert_on: vec!["tamper".to_string()], siren: false },
+            armed_away: ModeBehavior { alert_on: vec!["motion".to_string(), "tamper".to_string()], siren: true },
+            siren_command: None,
+            siren_seconds: 120,
+            keyswitch: None,
+            presence: None,
+            mqtt: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlarmState {
+    Disarmed,
+    // Exit delay running
+    Arming(AlarmMode),
+    Armed(AlarmMode),
+    // Entry delay running
+    Pending(AlarmMode),
+    Triggered(AlarmMode),
+}
+
+impl AlarmState {
+    pub fn name(self) -> String {
+        match self {
+            AlarmState::Disarmed => "disarmed".to_string(),
+            AlarmState::Arming(mode) => format!("arming ({})", mode.name()),
+            AlarmState::Armed(mode) => mode.name().to_string(),
+            AlarmState::Pending(mode) => format!("entry delay ({})", mode.name()),
+            AlarmState::Triggered(mode) => format!("triggered ({})", mode.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlarmCommand {
+    pub mode: AlarmMode,
+}
+
+// Controls the alarm from the protocol, chat bots, the keyswitch or MQTT
+#[derive(Clone)]
+pub struct AlarmHandle {
+    tx: mpsc::Sender<AlarmMode>,
+    state: watch::Receiver<AlarmState>,
+}
+
+impl AlarmHandle {
+    pub async fn set_mode(&self, mode: AlarmMode) {
+        let _ = self.tx.send(mode).await;
+    }
+
+    pub fn state(&self) -> AlarmState {
+        *self.state.borrow()
+    }
+
+    // Sees every change of state from now on
+    pub fn changes(&self) -> watch::Receiver<AlarmState> {
+        self.state.clone()
+    }
+}
+
+// Runs the arm/disarm state machine. Detector events go in; the events that should
+// alert someone come out, for the notifiers to subscribe to.
+pub fn spawn_alarm(config: AlarmConfig, events: &CameraEvents) -> (AlarmHandle, CameraEvents) {
+    let (tx, mut rx) = mpsc::channel::<AlarmMode>(8);
+    let (alerts_tx, _) = broadcast::channel(16);
+    let alerts = CameraEvents::from_sender(alerts_tx.clone());
+    let initial = match config.initial_mode {
+        AlarmMode::Disarmed => AlarmState::Disarmed,
+        mode => AlarmState::Armed(mode),
+    };
+    let (shared_state, state_rx) = watch::channel(initial);
+    let handle = AlarmHandle { tx, state: state_rx };
+    let mut event_rx = events.subscribe();
+
+    if let Some(keyswitch) = config.keyswitch.clone() {
+        tokio::spawn(watch_keyswitch(keyswitch, handle.clone()));
+    }
+    if let Some(presence) = config.presence.clone() {
+        tokio::spawn(presence::run_presence_listener(presence, handle.clone()));
+    }
+    if let Some(mqtt) = config.mqtt.clone() {
+        tokio::spawn(mqtt::run_alarm_mqtt(mqtt, handle.clone()));
+    }
+
+    tokio::spawn(async move {
+        let mut state = initial;
+        let mut deadline: Option<Instant> = None;
+        // The event that started the entry delay, reported if nobody disarms in time
+        let mut pending_event: Option<CameraEvent> = None;
+        let mut siren: Option<oneshot::Sender<()>> = None;
+
+        loop {
+            let timer = async move {
+                match deadline {
+                    Some(at) => sleep_until(at).await,
+                    None => pending::<()>().await,
+                }
+            };
+
+            let previous = state;
+            tokio::select! {
+                Some(mode) = rx.recv() => {
+                    deadline = None;
+                    pending_event = None;
+                    if let Some(stop) = siren.take() {
+                        let _ = stop.send(());
+                    }
+                    state = match mode {
+                        AlarmMode::Disarmed => AlarmState::Disarmed,
+                        mode if config.exit_delay_seconds > 0 => {
+                            deadline = Some(Instant::now() + Duration::from_secs(config.exit_delay_seconds));
+                            AlarmState::Arming(mode)
+                        }
+                        mode => AlarmState::Armed(mode),
+                    };
+                }
+                event = event_rx.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    };
+                    match state {
+                        AlarmState::Armed(mode) if behavior(&config, mode).alerts_for(&event) => {
+                            if config.entry_delay_seconds > 0 {
+                                deadline = Some(Instant::now() + Duration::from_secs(config.entry_delay_seconds));
+                                pending_event = Some(event);
+                                state = AlarmState::Pending(mode);
+                            } else {
+                                let _ = alerts_tx.send(event);
+                                state = AlarmState::Triggered(mode);
+                                siren = sound_siren(&config, mode);
+                            }
+                        }
+                        // Already going off: keep reporting what the detectors see
+                        AlarmState::Triggered(mode) if behavior(&config, mode).alerts_for(&event) => {
+                            let _ = alerts_tx.send(event);
+                        }
+                        _ => {}
+                    }
+                }
+                _ = timer => {
+                    deadline = None;
+                    state = match state {
+                        AlarmState::Arming(mode) => AlarmState::Armed(mode),
+                        AlarmState::Pending(mode) => {
+                            if let Some(event) = pending_event.take() {
+                                let _ = alerts_tx.send(event);
+                            }
+                            siren = sound_siren(&config, mode);
+                            AlarmState::Triggered(mode)
+                        }
+                        other => other,
+                    };
+                }
+            }
+
+            if state != previous {
+                println!("Alarm: {} -> {}", previous.name(), state.name());
+                shared_state.send_replace(state);
+            }
+        }
+    });
+
+    (handle, alerts)
+}
+
+fn behavior(config: &AlarmConfig, mode: AlarmMode) -> &ModeBehavior {
+    static DISARMED: ModeBehavior = ModeBehavior { alert_on: Vec::new(), siren: false };
+    match mode {
+        AlarmMode::Disarmed => &DISARMED,
+        AlarmMode::ArmedHome => &config.armed_home,
+        AlarmMode::ArmedAway => &config.armed_away,
+    }
+}
+
+// Dropping the returned sender silences the siren early
+fn sound_siren(config: &AlarmConfig, mode: AlarmMode) -> Option<oneshot::Sender<()>> {
+    if !behavior(config, mode).siren {
+        return None;
+    }
+    let (program, args) = config.siren_command.as_ref()?.split_first()?;
+    println!("Alarm triggered, sounding siren");
+    let mut child = match Command::new(program).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to run siren command: {}", e);
+            return None;
+        }
+    };
+
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let duration = Duration::from_secs(config.siren_seconds);
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sleep(duration) => {}
+            _ = stop_rx => {}
+        }
+        let _ = child.kill().await;
+    });
+    Some(stop_tx)
+}
+
+async fn watch_keyswitch(config: KeyswitchConfig, alarm: AlarmHandle) {
+    let mut last: Option<bool> = None;
+    loop {
+        let output = Command::new("gpioget")
+            .args([config.chip.as_str(), &config.line.to_string()])
+            .stderr(Stdio::null())
+            .output()
+            .await;
+        match output {
+            Ok(output) if output.status.success() => {
+                let closed = String::from_utf8_lossy(&output.stdout).trim() == "1";
+                // Only act on changes, so other controls aren't overridden every poll
+                if last.is_some_and(|was| was != closed) {
+                    let mode = if closed { config.armed_mode } else { AlarmMode::Disarmed };
+                    println!("Keyswitch set alarm to {}", mode.name());
+                    alarm.set_mode(mode).await;
+                }
+                last = Some(closed);
+            }
+            Ok(output) => eprintln!("gpioget exited with {}", output.status),
+            Err(e) => {
+                eprintln!("Keyswitch disabled, failed to run gpioget: {}", e);
+                return;
+            }
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
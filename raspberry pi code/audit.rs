@@ -0,0 +1,151 @@
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    // Append-only log of server-issued commands, one JSON object per line
+    pub path: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: "audit.log".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditQueryCommand {
+    // Time range in UNIX millis
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    // Most recent entries first, at most this many
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+// Every entry carries the hash of the previous one, so editing or removing a line
+// breaks the chain from that point on.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: String,
+    last_hash: Arc<Mutex<String>>,
+}
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn entry_hash(prev_hash: &str, body: &str) -> String {
+    let digest = Sha256::digest(format!("{}{}", prev_hash, body).as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Self {
+        let log = Self {
+            path: config.path.clone(),
+            last_hash: Arc::new(Mutex::new(GENESIS_HASH.to_string())),
+        };
+        match log.verify() {
+            Ok(last_hash) => *log.last_hash.lock().unwrap() = last_hash,
+            Err(e) => eprintln!("Audit log {} failed verification: {}", config.path, e),
+        }
+        log
+    }
+
+    // who: the operator/user the server says issued the command; what: command name
+    pub fn record(&self, who: &str, what: &str, parameters: &serde_json::Value, outcome: &str) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let mut last_hash = self.last_hash.lock().unwrap();
+        let body = json!({
+            "timestamp": timestamp,
+            "who": who,
+            "what": what,
+            "parameters": parameters,
+            "outcome": outcome,
+        })
+        .to_string();
+        let hash = entry_hash(&last_hash, &body);
+        let line = json!({ "entry": body, "prev_hash": *last_hash, "hash": hash }).to_string();
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        match written {
+            Ok(()) => *last_hash = hash,
+            Err(e) => eprintln!("Failed to write audit log {}: {}", self.path, e),
+        }
+    }
+
+    // Walk the whole chain; returns the hash of the last entry
+    pub fn verify(&self) -> Result<String, String> {
+        let Ok(file) = std::fs::File::open(&self.path) else {
+            return Ok(GENESIS_HASH.to_string());
+        };
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let record: serde_json::Value = serde_json::from_str(&line)
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+            let body = record["entry"].as_str().unwrap_or_default();
+            if record["prev_hash"].as_str() != Some(prev_hash.as_str())
+                || record["hash"].as_str() != Some(entry_hash(&prev_hash, body).as_str())
+            {
+                return Err(format!("chain broken at line {}", number + 1));
+            }
+            prev_hash = entry_hash(&prev_hash, body);
+        }
+        Ok(prev_hash)
+    }
+
+    // Raw lines for [from, to), hashes included, so the excerpt can be checked against
+    // the entries around it
+    pub fn excerpt(&self, from: u64, to: u64) -> Vec<String> {
+        std::fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| {
+                let timestamp = serde_json::from_str::<serde_json::Value>(line)
+                    .ok()
+                    .and_then(|record| serde_json::from_str::<serde_json::Value>(record["entry"].as_str()?).ok())
+                    .and_then(|entry| entry["timestamp"].as_u64());
+                timestamp.is_some_and(|timestamp| timestamp >= from && timestamp < to)
+            })
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn query(&self, query: &AuditQueryCommand) -> serde_json::Value {
+        let entries: Vec<serde_json::Value> = std::fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter_map(|record| serde_json::from_str::<serde_json::Value>(record["entry"].as_str()?).ok())
+            .filter(|entry| {
+                let timestamp = entry["timestamp"].as_u64().unwrap_or(0);
+                query.from.is_none_or(|from| timestamp >= from) && query.to.is_none_or(|to| timestamp < to)
+            })
+            .collect();
+        let newest: Vec<_> = entries.into_iter().rev().take(query.limit).collect();
+
+        let verification = match self.verify() {
+            Ok(_) => json!({ "intact": true }),
+            Err(e) => json!({ "intact": false, "error": e }),
+        };
+        json!({ "audit_log": { "entries": newest, "verification": verification } })
+    }
+}
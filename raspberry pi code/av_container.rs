@@ -0,0 +1,200 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::capture_clock::{CaptureClock, FrameTimestamp};
+use crate::frame::Frame;
+use crate::frame_pool::FramePool;
+use crate::sound_events::SAMPLE_RATE;
+
+// Packets in the container once the server answers the join with {"container": "av"}.
+// Every binary message is one packet, all integers big-endian:
+//
+//   "SCAV"      magic
+//   u8          version, 1
+//   u8          kind: 1 video, 2 audio
+//   u8          flags: bit 0 motion (video), bit 1 concealed (video), bit 2 more of
+//               this payload follows in the next packet
+//   u8          stream id length, then the stream id
+//   u64         pts: millis on the camera's capture clock, shared by audio and video
+//   u64         the capture clock's UNIX millis at pts
+//   u32         header length, then a JSON header: frame stats for video, the sample
+//               format for audio
+//   ...         payload: one encoded video frame, or interleaved PCM samples
+//
+// Both kinds are stamped from the same clock when captured, so the server can lay
+// them out in an MP4 without going by when they happened to arrive.
+//
+// A payload that doesn't fit the server's max_message_bytes is split over packets with
+// the same pts; only the first carries the header, the rest have a zero-length one.
+const MAGIC: &[u8; 4] = b"SCAV";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    Video = 1,
+    Audio = 2,
+}
+
+pub const FLAG_MOTION: u8 = 1;
+pub const FLAG_CONCEALED: u8 = 2;
+pub const FLAG_MORE: u8 = 4;
+
+// Audio shares the frame queue with the video streams under this stream id, so it is
+// counted against the uplink like they are
+pub const AUDIO_STREAM: &str = "audio";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AudioUplinkConfig {
+    // Audio per packet; shorter is closer to the video, longer is less overhead
+    pub packet_ms: u32,
+}
+
+impl Default for AudioUplinkConfig {
+    fn default() -> Self {
+        Self { packet_ms: 100 }
+    }
+}
+
+pub fn packet(kind: PacketKind, flags: u8, stream_id: &str, timestamp: FrameTimestamp, header: &serde_json::Value, payload: &[u8]) -> Message {
+    packet_with_header(kind, flags, stream_id, timestamp, &header.to_string(), payload)
+}
+
+fn packet_with_header(kind: PacketKind, flags: u8, stream_id: &str, timestamp: FrameTimestamp, header: &str, payload: &[u8]) -> Message {
+    let stream_id = &stream_id.as_bytes()[..stream_id.len().min(255)];
+    let mut bytes = Vec::with_capacity(28 + stream_id.len() + header.len() + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[VERSION, kind as u8, flags, stream_id.len() as u8]);
+    bytes.extend_from_slice(stream_id);
+    bytes.extend_from_slice(&timestamp.monotonic_ms.to_be_bytes());
+    bytes.extend_from_slice(&timestamp.wall_ms.to_be_bytes());
+    bytes.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(payload);
+    Message::Binary(bytes)
+}
+
+// The payload over as many packets as it takes to keep each within max_bytes (0 for no
+// limit); always at least one
+pub fn packets(kind: PacketKind, flags: u8, stream_id: &str, timestamp: FrameTimestamp, header: &serde_json::Value, payload: &[u8], max_bytes: usize) -> Vec<Message> {
+    let overhead = 28 + stream_id.len().min(255);
+    let first_overhead = overhead + header.to_string().len();
+    if max_bytes == 0 || first_overhead + payload.len() <= max_bytes {
+        return vec![packet(kind, flags, stream_id, timestamp, header, payload)];
+    }
+    // Even a tiny limit moves the payload forward
+    let first = max_bytes.saturating_sub(first_overhead).clamp(1, payload.len());
+    let rest = max_bytes.saturating_sub(overhead).max(1);
+    let mut messages = vec![packet(kind, flags | FLAG_MORE, stream_id, timestamp, header, &payload[..first])];
+    let mut chunks = payload[first..].chunks(rest).peekable();
+    while let Some(chunk) = chunks.next() {
+        let more = if chunks.peek().is_some() { FLAG_MORE } else { 0 };
+        messages.push(packet_with_header(kind, flags | more, stream_id, timestamp, "", chunk));
+    }
+    messages
+}
+
+pub fn audio_header(samples: usize) -> serde_json::Value {
+    json!({
+        "codec": "pcm_s16le",
+        "sample_rate": SAMPLE_RATE,
+        "channels": 1,
+        "samples": samples,
+    })
+}
+
+// A run of microphone samples and the capture time of the first one
+#[derive(Debug, Clone)]
+pub struct AudioPacket {
+    pub timestamp: FrameTimestamp,
+    pub samples: Arc<Vec<i16>>,
+}
+
+impl AudioPacket {
+    // Queued like a video frame: it can be dropped on the way, and the uplink sends it
+    // as an audio packet
+    pub fn frame(&self, pool: &FramePool) -> Frame {
+        let pcm: Vec<u8> = self.samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        Frame {
+            data: pool.acquire(&pcm),
+            stream_id: Arc::from(AUDIO_STREAM),
+            motion: false,
+            timestamp: self.timestamp,
+            base64: None,
+            concealment: None,
+            standalone: true,
+        }
+    }
+}
+
+// Microphone audio for the uplink, fed by the sound detector's audio pipeline since
+// the microphone can only be opened once
+#[derive(Clone)]
+pub struct AudioTap {
+    tx: broadcast::Sender<AudioPacket>,
+    clock: CaptureClock,
+    packet_samples: usize,
+}
+
+impl AudioTap {
+    pub fn new(config: &AudioUplinkConfig, clock: CaptureClock) -> Self {
+        let (tx, _) = broadcast::channel(16);
+        let packet_samples = (SAMPLE_RATE as usize * config.packet_ms.max(10) as usize / 1000).max(1);
+        Self { tx, clock, packet_samples }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AudioPacket> {
+        self.tx.subscribe()
+    }
+
+    // One per run of the audio pipeline
+    pub fn packetizer(&self) -> Packetizer {
+        Packetizer { tap: self.clone(), base: None, sent_samples: 0, pending: Vec::with_capacity(self.packet_samples) }
+    }
+}
+
+// Drift between counted samples and the capture clock that is put down to a gap in
+// the audio (an overrun, or the pipeline stalling) rather than clock rate
+const RESYNC_MS: u64 = 200;
+
+// Cuts the PCM into packets and timestamps them by counting samples from the first
+// one, so the audio clock doesn't jitter with when reads happen to return
+pub struct Packetizer {
+    tap: AudioTap,
+    // Capture time of the first sample counted
+    base: Option<FrameTimestamp>,
+    sent_samples: u64,
+    pending: Vec<i16>,
+}
+
+impl Packetizer {
+    pub fn push(&mut self, samples: &[i16]) {
+        // The samples just read finished arriving now
+        let now = self.tap.clock.now();
+        let read_ms = samples.len() as u64 * 1000 / SAMPLE_RATE as u64;
+        let counted_ms = (self.sent_samples + self.pending.len() as u64) * 1000 / SAMPLE_RATE as u64;
+        let expected = self.base.map(|base| base.monotonic_ms + counted_ms);
+        if expected.is_none_or(|expected| expected.abs_diff(now.monotonic_ms.saturating_sub(read_ms)) > RESYNC_MS) {
+            self.pending.clear();
+            self.sent_samples = 0;
+            self.base = Some(FrameTimestamp {
+                wall_ms: now.wall_ms.saturating_sub(read_ms),
+                monotonic_ms: now.monotonic_ms.saturating_sub(read_ms),
+            });
+        }
+
+        self.pending.extend_from_slice(samples);
+        while self.pending.len() >= self.tap.packet_samples {
+            let samples: Vec<i16> = self.pending.drain(..self.tap.packet_samples).collect();
+            let base = self.base.expect("set above");
+            let offset_ms = self.sent_samples * 1000 / SAMPLE_RATE as u64;
+            let timestamp = FrameTimestamp { wall_ms: base.wall_ms + offset_ms, monotonic_ms: base.monotonic_ms + offset_ms };
+            self.sent_samples += samples.len() as u64;
+            // Nobody listening just means no connection uses the container
+            let _ = self.tap.tx.send(AudioPacket { timestamp, samples: Arc::new(samples) });
+        }
+    }
+}
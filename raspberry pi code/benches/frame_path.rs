@@ -0,0 +1,116 @@
+// Benchmarks for the per-frame hot path: finding frame boundaries in the encoder output
+// and wrapping frames for the uplink. Run with `cargo bench --bench frame_path`.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::json;
+
+// The binary has no library target, so the modules under test are compiled in directly
+#[allow(dead_code)]
+#[path = "../framing.rs"]
+mod framing;
+#[allow(dead_code)]
+#[path = "../envelope.rs"]
+mod envelope;
+
+use envelope::{Envelope, FieldNaming, FrameFields};
+
+// Typical encoded MJPEG frame sizes at quality 70
+const FRAME_SIZES: [(&str, usize); 3] = [
+    ("640x360", 40 * 1024),
+    ("1280x720", 150 * 1024),
+    ("1920x1080", 350 * 1024),
+];
+
+// A JPEG-shaped frame: SOI, a JFIF header, entropy-coded-looking data with 0xFF bytes
+// stuffed the way encoders do, and EOI
+fn synthetic_jpeg(size: usize) -> Vec<u8> {
+    let mut frame = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0];
+    frame.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+    let mut state: u32 = 0x9E37_79B9;
+    while frame.len() < size - 2 {
+        // xorshift; deterministic so runs are comparable
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let byte = (state >> 24) as u8;
+        frame.push(byte);
+        if byte == 0xFF {
+            frame.push(0x00);
+        }
+    }
+    frame.extend_from_slice(&[0xFF, 0xD9]);
+    frame
+}
+
+fn fields<'a>(data: &'a [u8], base64: Option<&'a str>, stats: &'a serde_json::Value) -> FrameFields<'a> {
+    FrameFields { camera_id: "camera", stream_id: "main", data, base64, timestamp: 1_700_000_000_000, stats }
+}
+
+fn frame_stats() -> serde_json::Value {
+    json!({
+        "resolution": "1280x720",
+        "quality": 70,
+        "codec": "mjpeg",
+        "motion": false,
+        "monotonic_ms": 123_456_789u64,
+        "queue": { "strategy": "drop_new", "capacity": 60, "high_watermark": 50, "low_watermark": 15, "depth": 3 }
+    })
+}
+
+fn jpeg_scanning(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpeg_scan");
+    for (name, size) in FRAME_SIZES {
+        // A few frames back to back plus the start of the next, as a pipe read returns them
+        let frame = synthetic_jpeg(size);
+        let mut stream = frame.repeat(4);
+        stream.extend_from_slice(&frame[..frame.len() / 2]);
+        group.throughput(Throughput::Bytes(stream.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &stream, |b, stream| {
+            b.iter(|| {
+                let mut frames = 0;
+                let consumed = framing::extract_jpeg_frames(black_box(stream), |_| frames += 1);
+                (consumed, frames)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn envelopes(c: &mut Criterion) {
+    let stats = frame_stats();
+    let mut group = c.benchmark_group("envelope");
+    for (name, size) in FRAME_SIZES {
+        let frame = synthetic_jpeg(size);
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        for envelope in Envelope::SUPPORTED {
+            group.bench_with_input(BenchmarkId::new(envelope.name(), name), &frame, |b, frame| {
+                b.iter(|| envelope::encode_frame(envelope, FieldNaming::Standard, &fields(black_box(frame), None, &stats)))
+            });
+        }
+        // The JSON envelope with the base64 done ahead of time by the frame pipeline
+        let encoded = {
+            use base64::prelude::*;
+            BASE64_STANDARD.encode(&frame)
+        };
+        group.bench_with_input(BenchmarkId::new("json_pre_encoded", name), &frame, |b, frame| {
+            b.iter(|| envelope::encode_frame(Envelope::Json, FieldNaming::Standard, &fields(black_box(frame), Some(&encoded), &stats)))
+        });
+    }
+    group.finish();
+}
+
+fn json_serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json");
+    group.bench_function("frame_stats", |b| b.iter(|| black_box(frame_stats()).to_string()));
+    for (name, size) in FRAME_SIZES {
+        let frame = synthetic_jpeg(size);
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(BenchmarkId::new("base64", name), &frame, |b, frame| {
+            use base64::prelude::*;
+            b.iter(|| BASE64_STANDARD.encode(black_box(frame)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(frame_path, jpeg_scanning, envelopes, json_serialization);
+criterion_main!(frame_path);
@@ -0,0 +1,99 @@
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BoostConfig {
+    // Added to the controller's JPEG quality while an operator is watching
+    pub quality_bonus: u32,
+    pub max_quality: u32,
+    // While warm, the thermal frame rate cap is raised to this for the boost (0 leaves
+    // it); never while hot
+    pub warm_framerate: u32,
+    // How long a viewer_active command lasts without its own "seconds"
+    pub default_seconds: u64,
+    pub max_seconds: u64,
+    // Afterwards the bonus fades out over this long instead of dropping at once
+    pub decay_seconds: u64,
+}
+
+impl Default for BoostConfig {
+    fn default() -> Self {
+        Self {
+            quality_bonus: 15,
+            max_quality: 90,
+            warm_framerate: 25,
+            default_seconds: 60,
+            max_seconds: 600,
+            decay_seconds: 30,
+        }
+    }
+}
+
+// {"command": "viewer_active", "seconds": 120}; repeat it to keep the boost going
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewerActiveCommand {
+    pub seconds: Option<u64>,
+}
+
+// Time-boxed quality/frame rate boost while the server says an operator is watching
+#[derive(Clone)]
+pub struct ViewerBoost {
+    config: BoostConfig,
+    until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ViewerBoost {
+    pub fn new(config: BoostConfig) -> Self {
+        Self { config, until: Arc::new(Mutex::new(None)) }
+    }
+
+    // Start or extend the boost; returns how long it now lasts
+    pub fn boost(&self, command: &ViewerActiveCommand) -> Duration {
+        let seconds = command.seconds.unwrap_or(self.config.default_seconds).min(self.config.max_seconds);
+        let until = Instant::now() + Duration::from_secs(seconds);
+        let mut current = self.until.lock().unwrap();
+        // A shorter request doesn't cut an earlier, longer one short
+        if !current.is_some_and(|current| current >= until) {
+            *current = Some(until);
+        }
+        current.map(|at| at.saturating_duration_since(Instant::now())).unwrap_or_default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.until.lock().unwrap().is_some_and(|until| Instant::now() < until)
+    }
+
+    pub fn warm_framerate(&self) -> Option<u32> {
+        (self.config.warm_framerate > 0 && self.is_active()).then_some(self.config.warm_framerate)
+    }
+
+    // Full bonus while active, then a linear fade to nothing
+    pub fn quality_bonus(&self, now: Instant) -> u32 {
+        let Some(until) = *self.until.lock().unwrap() else {
+            return 0;
+        };
+        if now < until {
+            return self.config.quality_bonus;
+        }
+        let decay = Duration::from_secs(self.config.decay_seconds);
+        let faded = now.duration_since(until);
+        if faded >= decay {
+            return 0;
+        }
+        let remaining = 1.0 - faded.as_secs_f64() / decay.as_secs_f64();
+        (self.config.quality_bonus as f64 * remaining).round() as u32
+    }
+
+    // Boosted quality on top of the controller's choice, capped
+    pub fn apply(&self, quality: u32, now: Instant) -> u32 {
+        let bonus = self.quality_bonus(now);
+        if bonus == 0 {
+            return quality;
+        }
+        (quality + bonus).min(self.config.max_quality.max(quality))
+    }
+}
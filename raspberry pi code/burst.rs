@@ -0,0 +1,340 @@
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    io::Cursor,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::AbortHandle,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::capture_clock::CaptureClock;
+use crate::encoder::Codec;
+use crate::export::ExportProgress;
+use crate::frame_api::{FrameEvent, FrameHub};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BurstConfig {
+    // JPEG quality for the burst, at the top resolution rung
+    pub quality: u32,
+    // The camera switches to the burst settings this long before the start, so the
+    // pipeline restart is over by then. Shorter notice captures at whatever is running.
+    pub prepare_seconds: u64,
+    pub max_seconds: f64,
+    // Starts further ahead than this are rejected, as a burst holds the slot until it is
+    // over or cancelled
+    pub max_lead_seconds: u64,
+    // Frames past this much are left out, and the manifest says so
+    pub max_megabytes: usize,
+}
+
+impl Default for BurstConfig {
+    fn default() -> Self {
+        Self { quality: 95, prepare_seconds: 8, max_seconds: 10.0, max_lead_seconds: 600, max_megabytes: 128 }
+    }
+}
+
+// {"command": "burst_capture", "id": "...", "start_ms": ..., "seconds": 3, "upload_url": "..."}
+#[derive(Debug, Clone, Deserialize)]
+pub struct BurstCaptureCommand {
+    // Echoed back in progress messages so the server can match them up
+    pub id: Option<String>,
+    // UNIX millis on the capture clock. With time sync that is the server's clock, so
+    // every camera given the same start captures the same moment.
+    pub start_ms: u64,
+    pub seconds: f64,
+    // Where the frames and a manifest of their capture times are POSTed, as
+    // multipart/form-data
+    pub upload_url: String,
+}
+
+// {"command": "cancel_burst", "id": "..."}: drops the scheduled burst, or stops one being
+// captured; with an id, only if it is that burst
+#[derive(Debug, Clone, Deserialize)]
+pub struct CancelBurstCommand {
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start_ms: u64,
+    end_ms: u64,
+}
+
+struct Scheduled {
+    id: Option<String>,
+    window: Window,
+    task: AbortHandle,
+}
+
+// Bursts of every frame the camera captures in a time window, kept apart from the live
+// stream: frames are taken before the uplink drops, decimates or pauses anything, and
+// go to their own upload once the window has passed
+#[derive(Clone)]
+pub struct Bursts {
+    config: BurstConfig,
+    clock: CaptureClock,
+    hub: FrameHub,
+    // The burst scheduled or being captured; there is one at a time
+    scheduled: Arc<Mutex<Option<Scheduled>>>,
+}
+
+impl Bursts {
+    pub fn new(config: BurstConfig, clock: CaptureClock, hub: FrameHub) -> Self {
+        Self { config, clock, hub, scheduled: Arc::new(Mutex::new(None)) }
+    }
+
+    // The command's outcome for the audit log; the burst itself runs in the background
+    pub fn schedule(&self, command: BurstCaptureCommand, codec: Codec, camera_id: String, outgoing: mpsc::Sender<Message>) -> String {
+        if codec != Codec::Mjpeg {
+            return "rejected: bursts need MJPEG".to_string();
+        }
+        if !(command.seconds > 0.0 && command.seconds <= self.config.max_seconds) {
+            return format!("rejected: seconds must be more than 0 and at most {}", self.config.max_seconds);
+        }
+        let now = self.clock.now().wall_ms;
+        if command.start_ms < now {
+            return format!("rejected: start was {}ms ago", now - command.start_ms);
+        }
+        if command.start_ms - now > self.config.max_lead_seconds * 1000 {
+            return format!("rejected: start is more than {}s ahead", self.config.max_lead_seconds);
+        }
+        let window = Window { start_ms: command.start_ms, end_ms: command.start_ms + (command.seconds * 1000.0) as u64 };
+        // Held while the task is spawned, so it can't clear the slot before it is filled
+        let mut scheduled = self.scheduled.lock().unwrap();
+        if scheduled.is_some() {
+            return "rejected: another burst is scheduled".to_string();
+        }
+        println!("Burst of {}s scheduled in {}ms", command.seconds, window.start_ms - now);
+
+        let bursts = self.clone();
+        let id = command.id.clone();
+        let task = tokio::spawn(async move {
+            let progress = ExportProgress::new(command.id.clone(), camera_id.clone(), outgoing);
+            progress.report("scheduled", json!({ "start_ms": window.start_ms, "end_ms": window.end_ms })).await;
+            let (frames, truncated) = bursts.capture(window).await;
+            *bursts.scheduled.lock().unwrap() = None;
+
+            let bytes: usize = frames.iter().map(|frame| frame.data.len()).sum();
+            let count = frames.len();
+            println!("Burst captured {} frames ({} bytes), uploading to {}", count, bytes, command.upload_url);
+            progress.report("uploading", json!({ "frames": count, "bytes": bytes, "truncated": truncated })).await;
+            let uploaded = tokio::task::spawn_blocking(move || upload(&command, &camera_id, window, frames, truncated))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match uploaded {
+                Ok(()) => progress.report("done", json!({ "frames": count, "bytes": bytes })).await,
+                Err(e) => {
+                    eprintln!("Burst upload failed: {}", e);
+                    progress.report("failed", json!({ "error": e })).await;
+                }
+            }
+        });
+        *scheduled = Some(Scheduled { id, window, task: task.abort_handle() });
+        "scheduled".to_string()
+    }
+
+    // The command's outcome for the audit log. A burst already uploading is left to finish.
+    pub fn cancel(&self, command: CancelBurstCommand, camera_id: String, outgoing: mpsc::Sender<Message>) -> String {
+        let cancelled = {
+            let mut scheduled = self.scheduled.lock().unwrap();
+            if scheduled.as_ref().is_some_and(|current| command.id.is_some() && command.id != current.id) {
+                return "rejected: a different burst is scheduled".to_string();
+            }
+            scheduled.take()
+        };
+        let Some(cancelled) = cancelled else {
+            return "rejected: no burst is scheduled".to_string();
+        };
+        cancelled.task.abort();
+        println!("Burst cancelled");
+        tokio::spawn(async move {
+            let progress = ExportProgress::new(cancelled.id, camera_id, outgoing);
+            progress.report("cancelled", json!({ "start_ms": cancelled.window.start_ms, "end_ms": cancelled.window.end_ms })).await;
+        });
+        "cancelled".to_string()
+    }
+
+    fn in_window(&self, lead_ms: u64) -> bool {
+        let now = self.clock.now().wall_ms;
+        self.scheduled.lock().unwrap().as_ref().is_some_and(|scheduled| now + lead_ms >= scheduled.window.start_ms && now < scheduled.window.end_ms)
+    }
+
+    // The camera should run at the burst settings: from prepare_seconds ahead of a
+    // burst until it is over
+    pub fn due(&self) -> bool {
+        self.in_window(self.config.prepare_seconds * 1000)
+    }
+
+    // A burst is being captured, and a pipeline restart would leave a gap in it
+    pub fn capturing(&self) -> bool {
+        self.in_window(0)
+    }
+
+    pub fn quality(&self) -> u32 {
+        self.config.quality
+    }
+
+    // Every frame of the main stream stamped inside the window, and whether the size
+    // limit cut it short
+    async fn capture(&self, window: Window) -> (Vec<FrameEvent>, bool) {
+        // Subscribed a moment early, as the hub only copies frames for subscribers
+        let until_start = window.start_ms.saturating_sub(self.clock.now().wall_ms + 500);
+        sleep(Duration::from_millis(until_start)).await;
+
+        let max_bytes = self.config.max_megabytes * 1024 * 1024;
+        let mut events = pin!(self.hub.frames());
+        let mut frames = Vec::new();
+        let mut bytes = 0;
+        loop {
+            // A little past the end, for a frame stamped inside it that is still on its way
+            let remaining = window.end_ms.saturating_sub(self.clock.now().wall_ms) + 500;
+            let Ok(Some(frame)) = timeout(Duration::from_millis(remaining), events.next()).await else {
+                return (frames, false);
+            };
+            if &*frame.stream_id != "main" || frame.timestamp.wall_ms < window.start_ms {
+                continue;
+            }
+            if frame.timestamp.wall_ms >= window.end_ms {
+                return (frames, false);
+            }
+            if bytes + frame.data.len() > max_bytes {
+                eprintln!("Burst reached {}MB, leaving out the rest", self.config.max_megabytes);
+                return (frames, true);
+            }
+            bytes += frame.data.len();
+            frames.push(frame);
+        }
+    }
+}
+
+// Blocks. Each frame part reads from the captured frame itself rather than a copy, so
+// the upload doesn't need the burst's memory twice.
+fn upload(command: &BurstCaptureCommand, camera_id: &str, window: Window, frames: Vec<FrameEvent>, truncated: bool) -> Result<(), String> {
+    // Frame parts are in capture order, the same order as the manifest lists them
+    let manifest = json!({
+        "id": command.id,
+        "camera_id": camera_id,
+        "start_ms": window.start_ms,
+        "end_ms": window.end_ms,
+        "truncated": truncated,
+        "frames": frames.iter().map(|frame| json!({
+            "wall_ms": frame.timestamp.wall_ms,
+            "monotonic_ms": frame.timestamp.monotonic_ms,
+            "motion": frame.motion,
+            "bytes": frame.data.len(),
+        })).collect::<Vec<_>>(),
+    });
+    let mut form = reqwest::blocking::multipart::Form::new().text("manifest", manifest.to_string());
+    for frame in frames {
+        let length = frame.data.len() as u64;
+        let part = reqwest::blocking::multipart::Part::reader_with_length(Cursor::new(frame.data), length)
+            .file_name(format!("{}.jpg", frame.timestamp.wall_ms))
+            .mime_str("image/jpeg")
+            .map_err(|e| e.to_string())?;
+        form = form.part("frame", part);
+    }
+    // No overall timeout; a large burst over a slow link takes a while
+    reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .map_err(|e| e.to_string())?
+        .post(&command.upload_url)
+        .header("X-Camera-Id", camera_id)
+        .multipart(form)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture_clock::FrameTimestamp;
+
+    fn bursts(max_megabytes: usize) -> Bursts {
+        let clock = CaptureClock::new(None);
+        Bursts::new(BurstConfig { max_megabytes, ..BurstConfig::default() }, clock, FrameHub::new())
+    }
+
+    // Captures a window starting now while `frames` are published, stamped `offset_ms`
+    // from its start
+    async fn capture(bursts: &Bursts, seconds: u64, frames: &[(&str, i64, usize)]) -> (Vec<FrameEvent>, bool) {
+        let start_ms = bursts.clock.now().wall_ms;
+        let window = Window { start_ms, end_ms: start_ms + seconds * 1000 };
+        let capturing = tokio::spawn({
+            let bursts = bursts.clone();
+            async move { bursts.capture(window).await }
+        });
+        // Frames published before it subscribes would be lost
+        sleep(Duration::from_millis(50)).await;
+        for &(stream_id, offset_ms, bytes) in frames {
+            let timestamp = FrameTimestamp { wall_ms: start_ms.saturating_add_signed(offset_ms), monotonic_ms: 0 };
+            bursts.hub.publish(&Arc::from(stream_id), &vec![0; bytes], timestamp, false);
+            tokio::task::yield_now().await;
+        }
+        capturing.await.unwrap()
+    }
+
+    fn stamps(frames: &[FrameEvent], start_ms: u64) -> Vec<u64> {
+        frames.iter().map(|frame| frame.timestamp.wall_ms - start_ms).collect()
+    }
+
+    #[tokio::test]
+    async fn keeps_main_stream_frames_inside_the_window() {
+        let bursts = bursts(128);
+        let frames = [("main", -100, 10), ("main", 0, 10), ("door", 100, 10), ("main", 200, 10), ("main", 1000, 10)];
+        let (captured, truncated) = capture(&bursts, 1, &frames).await;
+        assert!(!truncated);
+        let start_ms = captured[0].timestamp.wall_ms;
+        assert_eq!(stamps(&captured, start_ms), vec![0, 200]);
+        assert!(captured.iter().all(|frame| &*frame.stream_id == "main"));
+    }
+
+    #[tokio::test]
+    async fn ends_when_the_window_has_passed_without_a_later_frame() {
+        let bursts = bursts(128);
+        let (captured, truncated) = capture(&bursts, 0, &[]).await;
+        assert!(captured.is_empty());
+        assert!(!truncated);
+    }
+
+    #[tokio::test]
+    async fn leaves_out_frames_past_the_size_limit() {
+        let bursts = bursts(1);
+        let frames = [("main", 0, 400 * 1024), ("main", 100, 400 * 1024), ("main", 200, 400 * 1024), ("main", 300, 10)];
+        let (captured, truncated) = capture(&bursts, 1, &frames).await;
+        assert!(truncated);
+        assert_eq!(captured.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_starts_too_far_ahead_and_cancels_the_scheduled_one() {
+        let bursts = bursts(128);
+        let (outgoing, _incoming) = mpsc::channel(8);
+        let now = bursts.clock.now().wall_ms;
+        let command = |id: &str, lead_ms: u64| BurstCaptureCommand {
+            id: Some(id.to_string()),
+            start_ms: now + lead_ms,
+            seconds: 1.0,
+            upload_url: "http://127.0.0.1:9/".to_string(),
+        };
+        let max_lead_ms = BurstConfig::default().max_lead_seconds * 1000;
+        assert!(bursts.schedule(command("far", max_lead_ms + 60_000), Codec::Mjpeg, "camera".into(), outgoing.clone()).starts_with("rejected"));
+        assert_eq!(bursts.schedule(command("first", 60_000), Codec::Mjpeg, "camera".into(), outgoing.clone()), "scheduled");
+        assert!(bursts.schedule(command("second", 60_000), Codec::Mjpeg, "camera".into(), outgoing.clone()).starts_with("rejected"));
+
+        let cancel = |id: &str| CancelBurstCommand { id: Some(id.to_string()) };
+        assert!(bursts.cancel(cancel("second"), "camera".into(), outgoing.clone()).starts_with("rejected"));
+        assert_eq!(bursts.cancel(cancel("first"), "camera".into(), outgoing.clone()), "cancelled");
+        assert_eq!(bursts.schedule(command("second", 60_000), Codec::Mjpeg, "camera".into(), outgoing.clone()), "scheduled");
+    }
+}
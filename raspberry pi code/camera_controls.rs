@@ -0,0 +1,137 @@
+use serde::Deserialize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AfMode {
+    // Focus stays at lens_position
+    Manual,
+    // Focus once when triggered
+    Auto,
+    Continuous,
+}
+
+impl AfMode {
+    fn property(self) -> &'static str {
+        match self {
+            AfMode::Manual => "manual",
+            AfMode::Auto => "auto",
+            AfMode::Continuous => "continuous",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HdrMode {
+    Off,
+    // Merged on the ISP from several exposures
+    MultiExposure,
+    // Tone-mapped from a single exposure
+    SingleExposure,
+    Night,
+}
+
+impl HdrMode {
+    fn property(self) -> &'static str {
+        match self {
+            HdrMode::Off => "off",
+            HdrMode::MultiExposure => "multi-exposure",
+            HdrMode::SingleExposure => "single-exposure",
+            HdrMode::Night => "night",
+        }
+    }
+}
+
+// libcamera controls for sensors that support them (Camera Module 3). Anything left
+// unset is not passed to libcamerasrc, so other sensors keep working.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CameraControls {
+    pub af_mode: Option<AfMode>,
+    // Focus distance in dioptres (1 / metres); 0.0 is infinity. Used with af_mode "manual"
+    pub lens_position: Option<f32>,
+    pub hdr_mode: Option<HdrMode>,
+}
+
+impl CameraControls {
+    // libcamerasrc properties for these controls
+    pub fn source_args(&self, trigger_focus: bool) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(mode) = self.af_mode {
+            args.push(format!("af-mode={}", mode.property()));
+            if mode == AfMode::Auto && trigger_focus {
+                args.push("af-trigger=start".to_string());
+            }
+        }
+        if let Some(position) = self.lens_position {
+            args.push(format!("lens-position={}", position));
+        }
+        if let Some(mode) = self.hdr_mode {
+            args.push(format!("hdr-mode={}", mode.property()));
+        }
+        args
+    }
+}
+
+// Sent by the server as {"command": "camera_controls", ...}; fields that are absent stay as they are
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraControlsCommand {
+    pub af_mode: Option<AfMode>,
+    pub lens_position: Option<f32>,
+    pub hdr_mode: Option<HdrMode>,
+    // Run an autofocus scan now (af_mode "auto")
+    #[serde(default)]
+    pub af_trigger: bool,
+}
+
+// Controls shared between the protocol handler and the capture manager. libcamerasrc
+// only takes controls at startup, so a change means restarting the pipeline.
+#[derive(Clone)]
+pub struct SharedCameraControls {
+    controls: Arc<Mutex<CameraControls>>,
+    changed: Arc<AtomicBool>,
+    trigger_focus: Arc<AtomicBool>,
+}
+
+impl SharedCameraControls {
+    pub fn new(controls: CameraControls) -> Self {
+        Self {
+            controls: Arc::new(Mutex::new(controls)),
+            changed: Arc::new(AtomicBool::new(false)),
+            // Focus once at startup
+            trigger_focus: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    pub fn apply(&self, command: CameraControlsCommand) {
+        let mut controls = self.controls.lock().unwrap();
+        if command.af_mode.is_some() {
+            controls.af_mode = command.af_mode;
+        }
+        if command.lens_position.is_some() {
+            controls.lens_position = command.lens_position;
+        }
+        if command.hdr_mode.is_some() {
+            controls.hdr_mode = command.hdr_mode;
+        }
+        if command.af_trigger {
+            self.trigger_focus.store(true, Ordering::Relaxed);
+        }
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    // True once after every change
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+
+    // Arguments for the next pipeline start; a pending autofocus trigger is consumed
+    pub fn source_args(&self) -> Vec<String> {
+        let trigger_focus = self.trigger_focus.swap(false, Ordering::Relaxed);
+        self.controls.lock().unwrap().source_args(trigger_focus)
+    }
+}
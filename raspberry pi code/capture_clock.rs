@@ -0,0 +1,110 @@
+use serde_json::json;
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// How often the wall clock is consulted
+const ANCHOR_INTERVAL: Duration = Duration::from_secs(10);
+// Forward corrections larger than this are stepped (first NTP sync on a Pi without an RTC)
+const STEP_THRESHOLD_MS: f64 = 5000.0;
+// Otherwise the clock runs at most 5% fast or slow until it has caught up
+const MAX_SLEW: f64 = 0.05;
+
+// When a frame was captured, by both clocks
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimestamp {
+    // UNIX millis, never going backwards
+    pub wall_ms: u64,
+    // Millis since the capture clock started
+    pub monotonic_ms: u64,
+}
+
+struct ClockState {
+    started: Instant,
+    anchor: Instant,
+    // Timestamp given out at `anchor`
+    anchor_ms: f64,
+    // Timestamp millis per monotonic milli
+    rate: f64,
+    // Added to the system clock to get the time being steered to, e.g. the server's
+    reference_offset_ms: f64,
+}
+
+// Capture timestamps from the monotonic clock, steered towards the wall clock. When
+// NTP corrects the system time the stamps speed up or slow down to meet it instead of
+// jumping, so they never run backwards in the middle of a recording.
+#[derive(Clone)]
+pub struct CaptureClock {
+    state: Arc<Mutex<ClockState>>,
+    // Every anchor is appended here so recordings can be mapped between the clocks
+    anchor_log: Option<String>,
+}
+
+fn system_ms() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as f64
+}
+
+impl CaptureClock {
+    pub fn new(anchor_log: Option<String>) -> Self {
+        let now = Instant::now();
+        let clock = Self {
+            state: Arc::new(Mutex::new(ClockState { started: now, anchor: now, anchor_ms: system_ms(), rate: 1.0, reference_offset_ms: 0.0 })),
+            anchor_log,
+        };
+        clock.log_anchor(0, system_ms(), system_ms(), 1.0);
+        clock
+    }
+
+    pub fn now(&self) -> FrameTimestamp {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut wall_ms = state.anchor_ms + now.duration_since(state.anchor).as_secs_f64() * 1000.0 * state.rate;
+
+        if now.duration_since(state.anchor) >= ANCHOR_INTERVAL {
+            let system = system_ms() + state.reference_offset_ms;
+            let offset = system - wall_ms;
+            if offset > STEP_THRESHOLD_MS {
+                println!("System clock moved forward by {:.0}ms, stepping capture clock", offset);
+                wall_ms = system;
+                state.rate = 1.0;
+            } else {
+                // Close the gap over the next interval
+                state.rate = 1.0 + (offset / ANCHOR_INTERVAL.as_millis() as f64).clamp(-MAX_SLEW, MAX_SLEW);
+            }
+            state.anchor = now;
+            state.anchor_ms = wall_ms;
+            let monotonic_ms = now.duration_since(state.started).as_millis() as u64;
+            self.log_anchor(monotonic_ms, wall_ms, system, state.rate);
+        }
+
+        FrameTimestamp {
+            wall_ms: wall_ms as u64,
+            monotonic_ms: now.duration_since(state.started).as_millis() as u64,
+        }
+    }
+
+    // Steer towards the system clock plus this offset from the next anchor on
+    pub fn set_reference_offset(&self, offset_ms: f64) {
+        self.state.lock().unwrap().reference_offset_ms = offset_ms;
+    }
+
+    fn log_anchor(&self, monotonic_ms: u64, wall_ms: f64, system_ms: f64, rate: f64) {
+        let Some(path) = &self.anchor_log else { return };
+        let line = json!({
+            "monotonic_ms": monotonic_ms,
+            "wall_ms": wall_ms as u64,
+            "system_ms": system_ms as u64,
+            "rate": rate
+        });
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = result {
+            eprintln!("Failed to write clock anchor to {}: {}", path, e);
+        }
+    }
+}
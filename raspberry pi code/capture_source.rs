@@ -0,0 +1,258 @@
+use serde::Deserialize;
+use tokio::{
+    io::AsyncRead,
+    process::{Child, ChildStdout},
+};
+
+use crate::camera_controls::SharedCameraControls;
+use crate::direct_capture;
+use crate::encoder::Codec;
+use crate::framing;
+use crate::synthetic_capture::SyntheticCaptureConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureSource {
+    // Raspberry Pi camera modules
+    Libcamera,
+    // USB webcams and capture cards on Linux
+    V4l2,
+    // Webcams on Windows
+    MediaFoundation,
+    // Webcams on macOS
+    Avfoundation,
+    // Whatever GStreamer finds on this machine
+    Auto,
+    // V4L2 read and JPEG-encoded in Rust, for systems without GStreamer
+    Direct,
+    // Generated JPEGs with chosen read boundaries and damage, for exercising frame
+    // extraction without a camera or GStreamer
+    Synthetic,
+}
+
+impl CaptureSource {
+    fn element(self) -> &'static str {
+        match self {
+            CaptureSource::Libcamera => "libcamerasrc",
+            CaptureSource::V4l2 => "v4l2src",
+            CaptureSource::MediaFoundation => "mfvideosrc",
+            CaptureSource::Avfoundation => "avfvideosrc",
+            CaptureSource::Auto => "autovideosrc",
+            CaptureSource::Direct | CaptureSource::Synthetic => unreachable!("{:?} capture doesn't use GStreamer", self),
+        }
+    }
+
+    // Frames come from a child process of this binary rather than a GStreamer pipeline
+    pub fn without_gstreamer(self) -> bool {
+        matches!(self, CaptureSource::Direct | CaptureSource::Synthetic)
+    }
+}
+
+// Where the encoded stream that process_frames splits into frames comes from: the
+// capture process's stdout on a camera, generated bytes in tests
+pub trait CaptureBackend {
+    type Output: AsyncRead + Unpin + Send + 'static;
+
+    // The stream, once; None after it has been taken
+    fn take_output(&mut self) -> Option<Self::Output>;
+}
+
+impl CaptureBackend for Child {
+    type Output = ChildStdout;
+
+    fn take_output(&mut self) -> Option<ChildStdout> {
+        self.stdout.take()
+    }
+}
+
+// Beyond this without a complete frame, the start of what's buffered is given up on
+const MAX_BUFFERED: usize = 10 * 1024 * 1024;
+// How much of it is kept then, as it might hold the start of a frame
+const KEEP_ON_OVERFLOW: usize = 1024 * 1024;
+
+// Carries partial frames from one read of the encoder's output to the next
+pub struct FrameExtractor {
+    codec: Codec,
+    accumulated: Vec<u8>,
+    ivf_header_seen: bool,
+}
+
+impl FrameExtractor {
+    pub fn new(codec: Codec) -> Self {
+        Self { codec, accumulated: Vec::new(), ivf_header_seen: false }
+    }
+
+    // Add what was just read, calling `on_frame` for every frame it completes
+    pub fn push(&mut self, data: &[u8], mut on_frame: impl FnMut(&[u8])) {
+        self.accumulated.extend_from_slice(data);
+        let position = match self.codec {
+            Codec::Mjpeg => framing::extract_jpeg_frames(&self.accumulated, &mut on_frame),
+            Codec::Vp9 | Codec::Av1 => framing::extract_ivf_frames(&self.accumulated, &mut self.ivf_header_seen, &mut on_frame),
+        };
+        // Keep only the unprocessed data
+        if position > 0 {
+            self.accumulated.drain(..position);
+        }
+        if self.accumulated.len() > MAX_BUFFERED {
+            println!("Buffer too large, discarding old data");
+            self.accumulated.drain(..self.accumulated.len() - KEEP_ON_OVERFLOW);
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    // Defaults to libcamera on Linux (direct when GStreamer isn't installed) and the
+    // platform's camera API elsewhere
+    pub backend: Option<CaptureSource>,
+    // Which camera: a libcamera camera name, a V4L2 device such as /dev/video1, or a
+    // device index on Windows and macOS. Unset picks the first one.
+    pub device: Option<String>,
+    // What the synthetic backend generates
+    pub synthetic: SyntheticCaptureConfig,
+    // Where the capture pipeline hands frames to the uplink's, when recording or a
+    // restream keeps it running apart
+    pub socket_path: String,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self { backend: None, device: None, synthetic: SyntheticCaptureConfig::default(), socket_path: "/tmp/camera-capture".to_string() }
+    }
+}
+
+impl CaptureConfig {
+    pub fn backend(&self) -> CaptureSource {
+        self.backend.unwrap_or(if cfg!(target_os = "windows") {
+            CaptureSource::MediaFoundation
+        } else if cfg!(target_os = "macos") {
+            CaptureSource::Avfoundation
+        } else if direct_capture::gstreamer_available() {
+            CaptureSource::Libcamera
+        } else {
+            CaptureSource::Direct
+        })
+    }
+
+    // Source elements, ending where the caps for the capture resolution go; one-shot
+    // pipelines stop after `num_buffers`. Camera controls only exist for libcamera and
+    // are left out elsewhere.
+    pub fn source_args(&self, num_buffers: Option<u32>, controls: Option<&SharedCameraControls>) -> Vec<String> {
+        let backend = self.backend();
+        let mut args = vec![backend.element().to_string()];
+        if let Some(num_buffers) = num_buffers.filter(|_| backend != CaptureSource::Auto) {
+            args.push(format!("num-buffers={}", num_buffers));
+        }
+        match (&self.device, backend) {
+            (Some(device), CaptureSource::Libcamera) => args.push(format!("camera-name={}", device)),
+            (Some(device), CaptureSource::V4l2) => args.push(format!("device={}", device)),
+            (Some(device), CaptureSource::MediaFoundation | CaptureSource::Avfoundation) => {
+                args.push(format!("device-index={}", device))
+            }
+            // autovideosrc has no way to pick one
+            (Some(_), CaptureSource::Auto | CaptureSource::Direct | CaptureSource::Synthetic) | (None, _) => {}
+        }
+        if backend == CaptureSource::Libcamera {
+            args.extend(controls.map(|controls| controls.source_args()).unwrap_or_default());
+        } else {
+            // libcamera scales in the ISP; webcams only offer a few fixed modes and formats
+            args.extend(["!", "videoconvert", "!", "videoscale"].map(String::from));
+        }
+        // autovideosrc is a bin without num-buffers, so end the stream after it instead
+        if let (Some(num_buffers), CaptureSource::Auto) = (num_buffers, backend) {
+            args.extend(["!".to_string(), "identity".to_string(), format!("eos-after={}", num_buffers)]);
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic_capture::{SyntheticBackend, SyntheticCaptureConfig};
+    use tokio::io::{AsyncRead, AsyncReadExt};
+
+    // Read and extract the way process_frames does
+    async fn extract(mut output: impl AsyncRead + Unpin, codec: Codec) -> Vec<Vec<u8>> {
+        let mut extractor = FrameExtractor::new(codec);
+        let mut buffer = vec![0; 512 * 1024];
+        let mut frames = Vec::new();
+        loop {
+            let read = output.read(&mut buffer).await.unwrap();
+            if read == 0 {
+                return frames;
+            }
+            extractor.push(&buffer[..read], |frame| frames.push(frame.to_vec()));
+        }
+    }
+
+    async fn synthetic_frames(config: SyntheticCaptureConfig) -> Vec<Vec<u8>> {
+        let output = SyntheticBackend::new(config, 64, 48, 70).take_output().unwrap();
+        extract(output, Codec::Mjpeg).await
+    }
+
+    fn is_whole_jpeg(frame: &[u8]) -> bool {
+        frame.starts_with(&[0xFF, 0xD8]) && frame.ends_with(&[0xFF, 0xD9])
+    }
+
+    fn starts_of_image(frame: &[u8]) -> usize {
+        frame.windows(2).filter(|pair| pair == &[0xFF, 0xD8]).count()
+    }
+
+    #[tokio::test]
+    async fn every_frame_comes_out_whole() {
+        let frames = synthetic_frames(SyntheticCaptureConfig { frames: 5, ..Default::default() }).await;
+        assert_eq!(frames.len(), 5);
+        assert!(frames.iter().all(|frame| is_whole_jpeg(frame)));
+    }
+
+    #[tokio::test]
+    async fn frames_cut_across_reads_come_out_the_same() {
+        let whole = synthetic_frames(SyntheticCaptureConfig { frames: 5, ..Default::default() }).await;
+        let cut = synthetic_frames(SyntheticCaptureConfig { frames: 5, write_sizes: vec![1, 7, 333, 2], ..Default::default() }).await;
+        assert_eq!(whole, cut);
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_runs_into_the_next_one() {
+        // Frames 2 and 4 are cut short; 2 ends at 3's end marker and 4 never ends
+        let config = SyntheticCaptureConfig { frames: 4, truncate_every: 2, write_sizes: vec![100], ..Default::default() };
+        let frames = synthetic_frames(config).await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(starts_of_image(&frames[0]), 1);
+        assert_eq!(starts_of_image(&frames[1]), 2);
+        assert!(frames.iter().all(|frame| is_whole_jpeg(frame)));
+    }
+
+    #[tokio::test]
+    async fn stray_bytes_between_frames_are_skipped() {
+        let clean = synthetic_frames(SyntheticCaptureConfig { frames: 6, ..Default::default() }).await;
+        let config = SyntheticCaptureConfig { frames: 6, garbage_every: 1, write_sizes: vec![13, 4096], ..Default::default() };
+        let frames = synthetic_frames(config).await;
+        assert_eq!(frames.len(), 6);
+        assert!(frames.iter().all(|frame| is_whole_jpeg(frame)));
+        // The garbage draws from the same generator, so only the first frame is unchanged
+        assert_eq!(frames[0], clean[0]);
+    }
+
+    #[tokio::test]
+    async fn ivf_header_and_frames_split_across_reads() {
+        let mut stream = b"DKIF".to_vec();
+        stream.extend([0, 0, 32, 0]);
+        stream.resize(32, 0);
+        let payloads: [&[u8]; 3] = [b"first", b"second frame", b"3"];
+        for payload in payloads {
+            stream.extend((payload.len() as u32).to_le_bytes());
+            stream.extend([0u8; 8]);
+            stream.extend_from_slice(payload);
+        }
+        let mut extractor = FrameExtractor::new(Codec::Vp9);
+        let mut frames = Vec::new();
+        for piece in stream.chunks(5) {
+            extractor.push(piece, |frame| frames.push(frame.to_vec()));
+        }
+        assert_eq!(frames, payloads.map(|payload| payload.to_vec()));
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeConfig {
+    // IANA timezone name, e.g. "Africa/Johannesburg" or "Europe/Amsterdam"
+    pub timezone: Tz,
+    // Burn the local time into the live image
+    pub overlay: bool,
+    // strftime format for the overlay
+    pub overlay_format: String,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            overlay: false,
+            overlay_format: "%Y-%m-%d %H:%M:%S %Z".to_string(),
+        }
+    }
+}
+
+impl TimeConfig {
+    // Frames and messages keep UNIX millis; local time is only for what people read
+    pub fn now(&self) -> DateTime<Tz> {
+        Utc::now().with_timezone(&self.timezone)
+    }
+
+    // Sortable local time for file names. The UTC offset keeps the repeated hour
+    // at the end of DST from producing the same name twice.
+    pub fn file_stamp(&self) -> String {
+        self.now().format("%Y%m%d-%H%M%S%z").to_string()
+    }
+
+    // Hour of the day (0-23) in local time, for schedules
+    pub fn local_hour(&self) -> u32 {
+        self.now().hour()
+    }
+
+    // Elements that draw the local time onto the image. clockoverlay formats in the
+    // process timezone, so pipelines using this must be started with `TZ` set.
+    pub fn overlay_args(&self) -> Vec<String> {
+        vec![
+            "clockoverlay".into(),
+            format!("time-format={}", self.overlay_format),
+            "halignment=left".into(),
+            "valignment=top".into(),
+            "shaded-background=true".into(),
+            "!".into(),
+        ]
+    }
+
+    // Value for the `TZ` environment variable of child pipelines
+    pub fn tz_env(&self) -> &'static str {
+        self.timezone.name()
+    }
+}
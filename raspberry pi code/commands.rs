@@ -0,0 +1,89 @@
+use serde::Deserialize;
+
+use crate::alarm::AlarmCommand;
+use crate::audit::AuditQueryCommand;
+use crate::boost::ViewerActiveCommand;
+use crate::burst::{BurstCaptureCommand, CancelBurstCommand};
+use crate::camera_controls::CameraControlsCommand;
+use crate::custody::CustodyExportCommand;
+use crate::echo_test::EchoTestCommand;
+use crate::export::ExportClipCommand;
+use crate::illuminator::IlluminatorCommand;
+use crate::log_stream::LogStreamCommand;
+use crate::maintenance::MaintenanceCommand;
+use crate::overlay::{ClearOverlayCommand, OverlayCommand};
+use crate::snapshot::SnapshotCommand;
+use crate::stats_db::StatsQueryCommand;
+use crate::stream_priority::StreamPrioritiesCommand;
+use crate::timeline::TimelineQueryCommand;
+
+// Commands the server can send, as {"command": "<name>", ...parameters}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ServerCommand {
+    // Move a virtual view of the fisheye image
+    Ptz(PtzCommand),
+    // Arm or disarm the alarm
+    Alarm(AlarmCommand),
+    // Force the IR illuminator on or off, or hand it back to the low-light detector
+    Illuminator(IlluminatorCommand),
+    // Change libcamera controls (autofocus, lens position, HDR)
+    CameraControls(CameraControlsCommand),
+    // Cut a time range out of the local recordings and upload it
+    ExportClip(ExportClipCommand),
+    // Every frame of a time window at full quality, uploaded on its own; cameras given the
+    // same start capture one moment together
+    BurstCapture(BurstCaptureCommand),
+    // Drop the scheduled burst, or stop the one being captured
+    CancelBurst(CancelBurstCommand),
+    // The same footage with the timeline, audit log and device key in a signed archive,
+    // for handing over as evidence
+    CustodyExport(CustodyExportCommand),
+    // Draw a banner, box or arrow on outgoing frames until cleared
+    Overlay(OverlayCommand),
+    ClearOverlay(ClearOverlayCommand),
+    // Read back the audit trail of earlier commands
+    AuditQuery(AuditQueryCommand),
+    // Read back the recent decisions of the congestion controller
+    DumpCongestionHistory,
+    // Round trips and loss at several probe sizes, to tell a slow uplink from a busy camera
+    EchoTest(EchoTestCommand),
+    // Stream the camera's log output as {"log": ...} messages, rate limited
+    LogStream(LogStreamCommand),
+    // Reply with the latest still
+    Snapshot(SnapshotCommand),
+    // Read back per-minute health aggregates
+    StatsQuery(StatsQueryCommand),
+    // Recorded segments, motion intervals and events in a time range, for scrub bars
+    TimelineQuery(TimelineQueryCommand),
+    // Rank the streams for when the uplink can't carry all of them
+    StreamPriorities(StreamPrioritiesCommand),
+    // Stop sending frames while nobody is watching; capture and recording carry on
+    PauseStream,
+    ResumeStream,
+    // An operator is watching; boost quality and frame rate for a while
+    ViewerActive(ViewerActiveCommand),
+    // Technicians on site: keep streaming but don't alert on what they set off, until
+    // the window runs out or it is ended early
+    Maintenance(MaintenanceCommand),
+    EndMaintenance,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PtzCommand {
+    pub view: String,
+    pub pan: Option<f64>,
+    pub tilt: Option<f64>,
+    pub zoom: Option<f64>,
+}
+
+// Who the server says issued a command, for the audit trail
+pub fn issued_by(json: &serde_json::Value) -> &str {
+    json.get("issued_by").and_then(|who| who.as_str()).unwrap_or("server")
+}
+
+// Returns None when the message isn't a command at all
+pub fn parse_command(json: &serde_json::Value) -> Option<Result<ServerCommand, serde_json::Error>> {
+    json.get("command")?;
+    Some(serde_json::from_value(json.clone()))
+}
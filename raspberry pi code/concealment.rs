@@ -0,0 +1,302 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConcealmentConfig {
+    // Close off a frame that only lost its tail instead of replacing it
+    pub repair_truncated: bool,
+    // How much of the frame has to be there to count as only losing its tail: measured
+    // with restart markers when the frame has them, otherwise against the size of the
+    // last good frame
+    pub repair_min_percent: u32,
+    // After this many corrupted frames in a row the previous frame stops standing in for
+    // them and they are dropped, so a broken camera doesn't look like a still scene
+    pub max_substituted: u32,
+}
+
+impl Default for ConcealmentConfig {
+    fn default() -> Self {
+        Self {
+            repair_truncated: true,
+            repair_min_percent: 90,
+            max_substituted: 30,
+        }
+    }
+}
+
+// What was done to a frame that arrived damaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Concealment {
+    // An end-of-image marker was added to a truncated frame
+    Repaired,
+    // The last good frame was sent in its place
+    PreviousFrame,
+}
+
+impl Concealment {
+    pub fn name(self) -> &'static str {
+        match self {
+            Concealment::Repaired => "repaired",
+            Concealment::PreviousFrame => "previous_frame",
+        }
+    }
+}
+
+enum Damage {
+    // The entropy-coded data stops at `at` without an end-of-image marker; the fraction
+    // of restart intervals that arrived, when the frame has them
+    Truncated { at: usize, progress: Option<f64> },
+    Corrupt(String),
+}
+
+// Walk the markers of a JPEG without decoding it: headers complete, a frame header
+// with a sane size, a scan, restart markers in order and as many as the image's MCU
+// count calls for, and an end-of-image marker
+fn inspect(jpeg: &[u8]) -> Result<(), Damage> {
+    let corrupt = |reason: &str| Err(Damage::Corrupt(reason.to_string()));
+    if jpeg.len() < 4 || jpeg[..2] != [0xFF, 0xD8] {
+        return corrupt("no start-of-image marker");
+    }
+
+    let mut pos = 2;
+    let mut size = None;
+    let mut baseline = false;
+    let mut restart_interval = 0u32;
+    let scan_start = loop {
+        while pos < jpeg.len() && jpeg[pos] == 0xFF && jpeg.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        if pos + 4 > jpeg.len() {
+            return corrupt("headers cut off");
+        }
+        if jpeg[pos] != 0xFF {
+            return corrupt("garbage between headers");
+        }
+        let marker = jpeg[pos + 1];
+        let length = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        let segment = pos + 4..pos + 2 + length;
+        if length < 2 || segment.end > jpeg.len() {
+            return corrupt("headers cut off");
+        }
+        let segment = &jpeg[segment];
+        match marker {
+            0xD9 => return corrupt("end of image before any scan"),
+            // Start of frame, any kind but the arithmetic-coding conditioning tables
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                if segment.len() < 6 {
+                    return corrupt("short frame header");
+                }
+                let height = u16::from_be_bytes([segment[1], segment[2]]) as u32;
+                let width = u16::from_be_bytes([segment[3], segment[4]]) as u32;
+                let components = segment[5] as usize;
+                if width == 0 || height == 0 || components == 0 || segment.len() < 6 + components * 3 {
+                    return corrupt("bad frame header");
+                }
+                // Interleaved MCUs cover 8 pixels per unit of the largest sampling factor;
+                // a single-component scan is plain 8x8 blocks
+                let sampling = |shift: u32| match components {
+                    1 => 1,
+                    _ => (0..components).map(|c| ((segment[7 + c * 3] >> shift) & 0x0F).max(1) as u32).max().unwrap_or(1),
+                };
+                let (h_max, v_max) = (sampling(4), sampling(0));
+                let mcus = width.div_ceil(8 * h_max) * height.div_ceil(8 * v_max);
+                size = Some(mcus);
+                baseline = matches!(marker, 0xC0 | 0xC1);
+            }
+            0xDD if segment.len() >= 2 => restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as u32,
+            0xDA => break pos + 2 + length,
+            _ => {}
+        }
+        pos += 2 + length;
+    };
+    let Some(mcus) = size else {
+        return corrupt("scan without a frame header");
+    };
+
+    // Entropy-coded data: 0xFF is always followed by a stuffed 0x00, a restart marker
+    // or, in progressive frames, the headers of the next scan
+    let mut restarts = 0u32;
+    let mut i = scan_start;
+    let mut ended = false;
+    let mut cut = jpeg.len();
+    while i + 1 < jpeg.len() {
+        if jpeg[i] != 0xFF {
+            i += 1;
+            continue;
+        }
+        match jpeg[i + 1] {
+            0x00 | 0xFF => i += 1,
+            0xD0..=0xD7 => {
+                if baseline && (jpeg[i + 1] - 0xD0) as u32 != restarts % 8 {
+                    return corrupt("restart markers out of order");
+                }
+                restarts += 1;
+                i += 2;
+            }
+            0xD9 => {
+                ended = true;
+                break;
+            }
+            // The extractor splits on end-of-image markers, so a frame that lost its
+            // end arrives glued to the start of the next one
+            0xD8 => {
+                cut = i;
+                break;
+            }
+            _ => i += 2,
+        }
+    }
+
+    // Only a single baseline scan has a known number of restart markers
+    let expected = (baseline && restart_interval > 0).then(|| mcus.div_ceil(restart_interval) - 1);
+    if !ended {
+        let progress = expected.map(|expected| restarts as f64 / expected.max(1) as f64);
+        return Err(Damage::Truncated { at: cut, progress });
+    }
+    match expected {
+        Some(expected) if restarts != expected => corrupt(&format!("{} restart markers where the MCU count needs {}", restarts, expected)),
+        _ => Ok(()),
+    }
+}
+
+pub enum Checked {
+    Intact,
+    // Send Concealer::concealed in its place
+    Concealed(Concealment),
+    // Nothing worth sending
+    Dropped,
+}
+
+// Checks every extracted JPEG and keeps the last good one to stand in for a damaged one
+pub struct Concealer {
+    config: ConcealmentConfig,
+    last_good: Vec<u8>,
+    repaired: Vec<u8>,
+    substituted_in_a_row: u32,
+}
+
+impl Concealer {
+    pub fn new(config: ConcealmentConfig) -> Self {
+        Self { config, last_good: Vec::new(), repaired: Vec::new(), substituted_in_a_row: 0 }
+    }
+
+    pub fn check(&mut self, jpeg: &[u8]) -> Checked {
+        let reason = match inspect(jpeg) {
+            Ok(()) => {
+                self.last_good.clear();
+                self.last_good.extend_from_slice(jpeg);
+                self.substituted_in_a_row = 0;
+                return Checked::Intact;
+            }
+            Err(Damage::Truncated { at, progress }) => {
+                let jpeg = &jpeg[..at];
+                let progress = progress.unwrap_or(jpeg.len() as f64 / self.last_good.len().max(1) as f64);
+                if self.config.repair_truncated && progress * 100.0 >= self.config.repair_min_percent as f64 {
+                    // A dangling 0xFF would swallow the marker
+                    let end = if jpeg.last() == Some(&0xFF) { jpeg.len() - 1 } else { jpeg.len() };
+                    self.repaired.clear();
+                    self.repaired.extend_from_slice(&jpeg[..end]);
+                    self.repaired.extend_from_slice(&[0xFF, 0xD9]);
+                    println!("Repaired a truncated frame ({} bytes, {:.0}% there)", jpeg.len(), progress.min(1.0) * 100.0);
+                    return Checked::Concealed(Concealment::Repaired);
+                }
+                format!("truncated at {} bytes", jpeg.len())
+            }
+            Err(Damage::Corrupt(reason)) => reason,
+        };
+
+        if self.last_good.is_empty() || self.substituted_in_a_row >= self.config.max_substituted {
+            eprintln!("Dropping corrupted frame: {}", reason);
+            return Checked::Dropped;
+        }
+        self.substituted_in_a_row += 1;
+        eprintln!("Corrupted frame ({}), sending the previous one instead", reason);
+        Checked::Concealed(Concealment::PreviousFrame)
+    }
+
+    // The bytes to send for a concealed frame
+    pub fn concealed(&self, concealment: Concealment) -> &[u8] {
+        match concealment {
+            Concealment::Repaired => &self.repaired,
+            Concealment::PreviousFrame => &self.last_good,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jpeg_encoder::{ColorType, Encoder};
+
+    // 64x64 grey baseline JPEG, one restart marker every 4 of its 64 MCUs
+    fn jpeg() -> Vec<u8> {
+        let pixels: Vec<u8> = (0..64 * 64).map(|i| (i * 7 % 251) as u8).collect();
+        let mut jpeg = Vec::new();
+        let mut encoder = Encoder::new(&mut jpeg, 80);
+        encoder.set_restart_interval(4);
+        encoder.encode(&pixels, 64, 64, ColorType::Luma).unwrap();
+        jpeg
+    }
+
+    fn restart_markers(jpeg: &[u8]) -> Vec<usize> {
+        (0..jpeg.len() - 1).filter(|&i| jpeg[i] == 0xFF && (0xD0..=0xD7).contains(&jpeg[i + 1])).collect()
+    }
+
+    #[test]
+    fn intact_frame_passes() {
+        let jpeg = jpeg();
+        assert_eq!(restart_markers(&jpeg).len(), 15);
+        assert!(inspect(&jpeg).is_ok());
+    }
+
+    #[test]
+    fn cut_off_frame_is_truncated_with_its_progress() {
+        let jpeg = jpeg();
+        let cut = restart_markers(&jpeg)[7] + 3;
+        match inspect(&jpeg[..cut]) {
+            Err(Damage::Truncated { at, progress: Some(progress) }) => {
+                assert_eq!(at, cut);
+                assert!((progress - 8.0 / 15.0).abs() < 1e-9);
+            }
+            _ => panic!("expected a truncated frame"),
+        }
+    }
+
+    #[test]
+    fn frame_glued_to_the_next_one_is_cut_at_its_start() {
+        let jpeg = jpeg();
+        let end = jpeg.len() - 2;
+        let mut glued = jpeg[..end].to_vec();
+        glued.extend_from_slice(&jpeg);
+        assert!(matches!(inspect(&glued), Err(Damage::Truncated { at, .. }) if at == end));
+    }
+
+    #[test]
+    fn restart_markers_out_of_order_are_corrupt() {
+        let mut jpeg = jpeg();
+        let markers = restart_markers(&jpeg);
+        jpeg.swap(markers[2] + 1, markers[3] + 1);
+        assert!(matches!(inspect(&jpeg), Err(Damage::Corrupt(_))));
+    }
+
+    #[test]
+    fn missing_restart_marker_is_corrupt() {
+        let mut jpeg = jpeg();
+        let last = *restart_markers(&jpeg).last().unwrap();
+        jpeg.drain(last..last + 2);
+        assert!(matches!(inspect(&jpeg), Err(Damage::Corrupt(reason)) if reason.contains("restart markers where")));
+    }
+
+    #[test]
+    fn headers_cut_off_are_corrupt() {
+        let jpeg = jpeg();
+        assert!(matches!(inspect(&jpeg[..20]), Err(Damage::Corrupt(reason)) if reason == "headers cut off"));
+    }
+
+    #[test]
+    fn no_markers_at_all_are_corrupt() {
+        assert!(matches!(inspect(&[0u8; 64]), Err(Damage::Corrupt(reason)) if reason == "no start-of-image marker"));
+        assert!(matches!(inspect(&[]), Err(Damage::Corrupt(_))));
+        assert!(matches!(inspect(&[0xFF, 0xD8, 0xFF, 0xD9, 0x00, 0x02]), Err(Damage::Corrupt(reason)) if reason == "end of image before any scan"));
+    }
+}
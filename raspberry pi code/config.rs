@@ -0,0 +1,345 @@
+use serde::Deserialize;
+
+use crate::access::AccessConfig;
+use crate::adaptation::AdaptationConfig;
+use crate::alarm::AlarmConfig;
+use crate::audit::AuditConfig;
+use crate::av_container::AudioUplinkConfig;
+use crate::boost::BoostConfig;
+use crate::burst::BurstConfig;
+use crate::camera_controls::CameraControls;
+use crate::clock::TimeConfig;
+use crate::concealment::ConcealmentConfig;
+use crate::cpu_budget::CpuBudgetConfig;
+use crate::crash::CrashConfig;
+use crate::decimation::DecimationConfig;
+use crate::digest::DigestConfig;
+use crate::downscale::DownscaleConfig;
+use crate::email::EmailConfig;
+use crate::encode_profiles::EncodeProfilesConfig;
+use crate::encoder::Codec;
+use crate::encoder_experiment::EncoderExperimentConfig;
+use crate::envelope::FieldNaming;
+use crate::event_queue::EventQueueConfig;
+use crate::fisheye::FisheyeConfig;
+use crate::flow_control::FlowControlConfig;
+use crate::go2rtc::Go2RtcConfig;
+use crate::hls::HlsConfig;
+use crate::hotplug::HotplugConfig;
+use crate::http_fallback::HttpFallbackConfig;
+use crate::identity::IdentityConfig;
+use crate::illuminator::IlluminatorConfig;
+use crate::image_quality::ImageQualityConfig;
+use crate::jpeg::JpegConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::motion::MotionConfig;
+use crate::lens::{CalibrationConfig, LensConfig};
+use crate::log_stream::LogStreamConfig;
+use crate::protocol_errors::ProtocolErrorConfig;
+use crate::pipeline::PipelineConfig;
+use crate::privacy::PrivacyConfig;
+use crate::queue::QueuePolicy;
+use crate::recording::RecordingConfig;
+use crate::raw::{AnalyticsConfig, PixelFormat, RawConfig};
+use crate::raw_archive::RawArchiveConfig;
+use crate::resolution::ResolutionConfig;
+use crate::rest_api::RestApiConfig;
+use crate::sandbox::SandboxConfig;
+use crate::scene_complexity::SceneComplexityConfig;
+use crate::server_address::ProxyConfig;
+use crate::sound_events::SoundEventConfig;
+use crate::stabilize::StabilizationConfig;
+use crate::stream_state::ReplacedAction;
+use crate::capture_source::CaptureConfig;
+use crate::rtsp_server::RtspConfig;
+use crate::telegram::TelegramConfig;
+use crate::tenancy::TenancyConfig;
+use crate::test_pattern::TestPatternConfig;
+use crate::thermal::ThermalConfig;
+use crate::time_sync::TimeSyncConfig;
+use crate::uplink_allocator::UplinkAllocatorConfig;
+use crate::viewers::ViewerConfig;
+use crate::virtual_input::VirtualInputConfig;
+use crate::watchdog::WatchdogConfig;
+use crate::stats_db::StatsDbConfig;
+use crate::stills::StillsConfig;
+
+// Runtime configuration, loaded from a JSON file. Every field has a default so a
+// config file only needs to list what it changes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Relay server; a hostname is resolved again on every reconnect, IPv6 and IPv4 are raced
+    pub server_url: String,
+    // HTTP CONNECT or SOCKS5 proxy for the connection to the server
+    pub proxy: Option<ProxyConfig>,
+    // POST frames to an HTTP endpoint at a reduced rate while the WebSocket can't connect
+    pub http_fallback: Option<HttpFallbackConfig>,
+    // When the server reports that another instance joined with our camera id
+    pub on_session_replaced: ReplacedAction,
+    // Site, group and labels sent when joining; commands can be addressed to them
+    pub tenancy: TenancyConfig,
+    // Which server commands need an admin; the REST API's tokens are in rest_api
+    pub access: AccessConfig,
+    // Codecs this device may offer the server, in order of preference.
+    // VP9/AV1 are only worth enabling on hardware that can encode them in real time.
+    pub codecs: Vec<Codec>,
+    // Write an HLS playlist and segments to disk when present
+    pub hls: Option<HlsConfig>,
+    // Periodic still upload, used when running with --stills
+    pub stills: Option<StillsConfig>,
+    // DNG stills from the sensor's raw stream every so often, alongside the live stream
+    pub raw_archive: Option<RawArchiveConfig>,
+    // Quality and limits for burst_capture commands
+    pub burst: BurstConfig,
+    // Lens distortion correction applied before encoding
+    pub lens: Option<LensConfig>,
+    // Digital stabilization for cameras on poles or fences that shake in the wind; costs a
+    // fair amount of CPU and crops the edges off, so it is off unless configured
+    pub stabilization: Option<StabilizationConfig>,
+    // Checkerboard capture settings for --calibrate
+    pub calibration: CalibrationConfig,
+    // Fisheye lens with virtual PTZ views
+    pub fisheye: Option<FisheyeConfig>,
+    // Publish an H.264 stream to go2rtc for Frigate and other NVRs
+    pub go2rtc: Option<Go2RtcConfig>,
+    // Embedded RTSP server for NVRs and VLC, alongside the uplink (built with --features rtsp)
+    pub rtsp: Option<RtspConfig>,
+    // Address for the local HTTP server (health checks), e.g. "0.0.0.0:8080"
+    pub http_listen: Option<String>,
+    // Path of a Unix socket for local JSON-RPC control (status, snapshot, settings, shutdown)
+    pub control_socket: Option<String>,
+    // Token-protected HTTP API for managing the camera over the local network: config,
+    // recordings, snapshots, events and the control socket's methods, and a browser UI on it
+    pub rest_api: Option<RestApiConfig>,
+    // Which camera API to capture from: libcamera on the Pi, V4L2 webcams, or the
+    // native one on Windows and macOS
+    pub capture: CaptureConfig,
+    // Autofocus, lens position and HDR for sensors that support them
+    pub camera_controls: CameraControls,
+    // MJPEG chroma subsampling, progressive scans and restart markers
+    pub jpeg: JpegConfig,
+    // Check each MJPEG frame and repair or replace damaged ones rather than send them
+    pub concealment: Option<ConcealmentConfig>,
+    // Alternate between two JPEG settings and compare frame sizes and PSNR; replaces the
+    // jpeg settings while it is set
+    pub encoder_experiment: Option<EncoderExperimentConfig>,
+    // Encode the bottom rung of the ladder alongside the main stream all the time, so
+    // congestion switches between the two at once instead of restarting the pipeline.
+    // Costs a second encoder's CPU; not with fisheye views.
+    pub encode_profiles: Option<EncodeProfilesConfig>,
+    // Pixel format requested from the camera (NV12, YUY2, ...); the source picks when unset
+    pub pixel_format: Option<PixelFormat>,
+    // Publish uncompressed frames to local consumers
+    pub raw: Option<RawConfig>,
+    // Reduced-rate, reduced-size raw frames for the detectors
+    pub analytics: Option<AnalyticsConfig>,
+    // Periodic blur/exposure/noise analysis (needs `analytics` or `raw`)
+    pub image_quality: Option<ImageQualityConfig>,
+    // Motion detection used to prioritize frames under congestion (needs `analytics` or `raw`)
+    pub motion: Option<MotionConfig>,
+    // Shed detector frames, overlays and re-encoding when the CPU is over this budget
+    pub cpu_budget: Option<CpuBudgetConfig>,
+    // Drop to the low resolution and frame rate, then shed everything optional, as the
+    // SoC heats up
+    pub thermal: Option<ThermalConfig>,
+    // Loud noise and breaking glass heard on the microphone, raised as events with a clip
+    pub sound_events: Option<SoundEventConfig>,
+    // Microphone audio on the uplink, in the A/V container when the server takes it. Uses
+    // the microphone set in `sound_events`, or the default one.
+    pub audio_uplink: Option<AudioUplinkConfig>,
+    // Arm/disarm state with entry/exit delays; without it every event is alerted
+    pub alarm: Option<AlarmConfig>,
+    // Send every event to the server too, from a queue on disk, until it acknowledges them
+    pub event_queue: Option<EventQueueConfig>,
+    // Limits and suppressed event kinds for the server's maintenance command
+    pub maintenance: MaintenanceConfig,
+    // IR LED board switched on at night by the low-light detector (needs `analytics` or `raw`)
+    pub illuminator: Option<IlluminatorConfig>,
+    // Emailed snapshots on motion/tamper events, for setups without the relay server
+    pub email: Option<EmailConfig>,
+    // Telegram bot for alerts and remote snapshots
+    pub telegram: Option<TelegramConfig>,
+    // One report a day through email and Telegram: event counts, thumbnails of the most
+    // notable ones and how well the uplink held up
+    pub digest: Option<DigestConfig>,
+    // Bound unacknowledged frames when the server supports acks
+    pub flow_control: Option<FlowControlConfig>,
+    // Quality and frame rate boost while the server reports an operator watching
+    pub viewer_boost: BoostConfig,
+    // Motion-only uplink while the server reports nobody subscribed
+    pub viewers: ViewerConfig,
+    // When undecodable server messages raise an alert
+    pub protocol_errors: ProtocolErrorConfig,
+    // Largest WebSocket message we send; bigger frames are chunked if the server supports it
+    pub max_message_bytes: usize,
+    // Field names in frame messages, for third-party ingestion endpoints that expect e.g.
+    // image/ts rather than data/timestamp
+    pub field_naming: FieldNaming,
+    // Generated video instead of the camera; also enabled by --test-pattern
+    pub test_pattern: Option<TestPatternConfig>,
+    // MJPEG or raw video from another process instead of the camera; also enabled by --virtual-input
+    pub virtual_input: Option<VirtualInputConfig>,
+    // Let encoded frame sizes shift when the controller steps the resolution down
+    pub scene_complexity: Option<SceneComplexityConfig>,
+    // Native aspect ratio and resolution ladder
+    pub resolution: ResolutionConfig,
+    // Which congestion controller strategy picks the resolution and quality
+    pub adaptation: AdaptationConfig,
+    // Spread frame extraction, processing and serialization over threads for high frame rates
+    pub pipeline: Option<PipelineConfig>,
+    // Panic reports with backtraces and recent output, uploaded on the next start. Keep the
+    // directory under the working directory or it is not writable inside the sandbox.
+    pub crash_reports: CrashConfig,
+    // Log output the server can ask to have streamed to it while troubleshooting
+    pub log_stream: LogStreamConfig,
+    // Metadata kept out of what is sent to the server, e.g. when it is run by a third party
+    pub privacy: Option<PrivacyConfig>,
+    // Device key used for --provision
+    pub identity: IdentityConfig,
+    // Frame rate reduction on the uplink while congested
+    pub decimation: DecimationConfig,
+    // Send MJPEG frames scaled down in software while congested, ahead of the pipeline
+    // changing resolution
+    pub downscale: Option<DownscaleConfig>,
+    // Capacity and overflow behaviour of the frame queue in front of the uplink
+    pub queue: QueuePolicy,
+    // Split the measured uplink between the camera and its virtual views by the priorities
+    // the server assigns and what each produces, instead of first come first served
+    pub uplink_allocator: Option<UplinkAllocatorConfig>,
+    // Full-quality local recording, independent of the uplink quality
+    pub recording: Option<RecordingConfig>,
+    // Tamper-evident log of server-issued commands
+    pub audit: AuditConfig,
+    // Privilege drop and filesystem restrictions applied at startup
+    pub sandbox: SandboxConfig,
+    // Per-minute FPS/bytes/drops/events in a local SQLite file
+    pub stats_db: Option<StatsDbConfig>,
+    // How many minutes of congestion controller decisions to keep for dump_congestion_history
+    pub congestion_history_minutes: u64,
+    // Local timezone for overlays, file names and schedules
+    pub time: TimeConfig,
+    // Align capture timestamps with the server's clock, for multi-camera rigs
+    pub time_sync: Option<TimeSyncConfig>,
+    // Recovery from a capture pipeline that stops producing frames
+    pub watchdog: WatchdogConfig,
+    // Pause capture while a USB camera is unplugged and resume when it's back
+    pub hotplug: HotplugConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server_url: "ws://100.78.140.50:3001".to_string(),
+            proxy: None,
+            http_fallback: None,
+            on_session_replaced: ReplacedAction::default(),
+            tenancy: TenancyConfig::default(),
+            access: AccessConfig::default(),
+            codecs: vec![Codec::Mjpeg],
+            hls: None,
+            stills: None,
+            raw_archive: None,
+            burst: BurstConfig::default(),
+            lens: None,
+            stabilization: None,
+            calibration: CalibrationConfig::default(),
+            fisheye: None,
+            go2rtc: None,
+            rtsp: None,
+            http_listen: None,
+            control_socket: None,
+            rest_api: None,
+            capture: CaptureConfig::default(),
+            camera_controls: CameraControls::default(),
+            jpeg: JpegConfig::default(),
+            concealment: None,
+            encoder_experiment: None,
+            encode_profiles: None,
+            pixel_format: None,
+            raw: None,
+            analytics: None,
+            image_quality: None,
+            motion: None,
+            cpu_budget: None,
+            thermal: None,
+            sound_events: None,
+            audio_uplink: None,
+            alarm: None,
+            event_queue: None,
+            maintenance: MaintenanceConfig::default(),
+            illuminator: None,
+            email: None,
+            telegram: None,
+            digest: None,
+            flow_control: None,
+            viewer_boost: BoostConfig::default(),
+            viewers: ViewerConfig::default(),
+            protocol_errors: ProtocolErrorConfig::default(),
+            max_message_bytes: 1024 * 1024,
+            field_naming: FieldNaming::default(),
+            test_pattern: None,
+            virtual_input: None,
+            scene_complexity: None,
+            resolution: ResolutionConfig::default(),
+            adaptation: AdaptationConfig::default(),
+            pipeline: None,
+            crash_reports: CrashConfig::default(),
+            log_stream: LogStreamConfig::default(),
+            privacy: None,
+            identity: IdentityConfig::default(),
+            decimation: DecimationConfig::default(),
+            downscale: None,
+            queue: QueuePolicy::default(),
+            uplink_allocator: None,
+            recording: None,
+            audit: AuditConfig::default(),
+            sandbox: SandboxConfig::default(),
+            stats_db: None,
+            congestion_history_minutes: 30,
+            time: TimeConfig::default(),
+            time_sync: None,
+            watchdog: WatchdogConfig::default(),
+            hotplug: HotplugConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // Load the config named by `--config <path>` or CAMERA_CONFIG, or use defaults
+    pub fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+        println!("Loading config from {}", path);
+        let contents = std::fs::read_to_string(&path).expect("Failed to read config file");
+        let mut config: Config = serde_json::from_str(&contents).expect("Failed to parse config file");
+
+        // MJPEG is always available as a fallback
+        if !config.codecs.contains(&Codec::Mjpeg) {
+            config.codecs.push(Codec::Mjpeg);
+        }
+        config
+    }
+}
+
+// Whether a bare command line flag such as `--stills` was passed
+pub fn has_flag(flag: &str) -> bool {
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
+
+// The value following a command line flag, e.g. `--simulate script.json`
+pub fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    None
+}
+
+// The config file in use, if any; also rewritten by the REST API
+pub fn config_path() -> Option<String> {
+    flag_value("--config").or_else(|| std::env::var("CAMERA_CONFIG").ok())
+}
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// One decision of the congestion controller, with what it was based on
+#[derive(Debug, Clone, Serialize)]
+pub struct CongestionSample {
+    pub timestamp: u64,
+    // Inputs
+    pub queue_depth: u64,
+    pub consecutive_failures: u32,
+    pub server_congested: bool,
+    // Controller state and what it chose
+    pub level: u8,
+    pub congested: bool,
+    pub resolution: String,
+    pub quality: u32,
+    pub codec: &'static str,
+    pub pipeline_restarted: bool,
+    // Encoded detail relative to an ordinary scene, when measured
+    pub scene_complexity: Option<f64>,
+}
+
+// The last few minutes of controller decisions, for tuning in the field
+#[derive(Clone)]
+pub struct CongestionHistory {
+    samples: Arc<Mutex<VecDeque<CongestionSample>>>,
+    retention_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+impl CongestionHistory {
+    pub fn new(retention_minutes: u64) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::new())),
+            retention_ms: retention_minutes * 60 * 1000,
+        }
+    }
+
+    pub fn timestamp() -> u64 {
+        now_ms()
+    }
+
+    pub fn record(&self, sample: CongestionSample) {
+        let mut samples = self.samples.lock().unwrap();
+        let cutoff = sample.timestamp.saturating_sub(self.retention_ms);
+        while samples.front().is_some_and(|oldest| oldest.timestamp < cutoff) {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    #[cfg_attr(not(all(feature = "dashboard", unix)), allow(dead_code))]
+    pub fn latest(&self) -> Option<CongestionSample> {
+        self.samples.lock().unwrap().back().cloned()
+    }
+
+    pub fn dump(&self) -> serde_json::Value {
+        let samples = self.samples.lock().unwrap();
+        json!({
+            "congestion_history": {
+                "retention_minutes": self.retention_ms / 60_000,
+                "samples": *samples
+            }
+        })
+    }
+}
@@ -0,0 +1,265 @@
+use base64::prelude::*;
+#[cfg(unix)]
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+    Arc,
+};
+#[cfg(unix)]
+use std::{
+    fs::{self, DirBuilder},
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+};
+use tokio::sync::Notify;
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::alarm::{AlarmCommand, AlarmHandle};
+use crate::audit::AuditLog;
+use crate::camera_controls::{CameraControlsCommand, SharedCameraControls};
+use crate::encoder::Codec;
+use crate::illuminator::{IlluminatorCommand, IlluminatorHandle};
+use crate::overlay::{ClearOverlayCommand, OverlayCommand, SharedOverlays};
+use crate::pause::UplinkPause;
+use crate::snapshot::LatestFrame;
+use crate::stream_state::StreamStatus;
+use crate::viewers::ViewerCount;
+
+// JSON-RPC 2.0 error codes
+#[cfg(unix)]
+const PARSE_ERROR: i64 = -32700;
+#[cfg(unix)]
+const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const UNAVAILABLE: i64 = -32000;
+
+#[cfg(unix)]
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    // Absent for notifications, which get no reply
+    id: Option<serde_json::Value>,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+// Everything the control socket can look at or change
+#[derive(Clone)]
+pub struct ControlContext {
+    pub camera_id: String,
+    pub status: StreamStatus,
+    pub quality: Arc<AtomicU32>,
+    pub width: Arc<AtomicU32>,
+    pub height: Arc<AtomicU32>,
+    pub codec: Arc<AtomicU8>,
+    pub queue_size: Arc<AtomicU64>,
+    pub congested: Arc<AtomicBool>,
+    pub paused: UplinkPause,
+    pub viewers: ViewerCount,
+    pub latest_frame: LatestFrame,
+    pub camera_controls: SharedCameraControls,
+    pub overlays: SharedOverlays,
+    pub alarm: Option<AlarmHandle>,
+    pub illuminator: Option<IlluminatorHandle>,
+    pub audit_log: AuditLog,
+    // Notified when a local client asks the camera to stop
+    pub shutdown: Arc<Notify>,
+}
+
+// Local control over a Unix socket, one JSON-RPC request per line, for scripts on the
+// device that shouldn't have to go through the server. Access is limited to the owner
+// and group of the socket file.
+#[cfg(unix)]
+pub async fn run_control_socket(path: String, context: ControlContext) {
+    let listener = match bind_private(&path, 0o660) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind control socket {}: {}", path, e);
+            return;
+        }
+    };
+    println!("Control socket listening on {}", path);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let context = context.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, context).await {
+                        eprintln!("Control connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept control connection: {}", e),
+        }
+    }
+}
+
+// A Unix socket at `path` that is never reachable with looser permissions than `mode`:
+// it is bound inside a new 0700 directory, restricted there, then renamed into place
+#[cfg(unix)]
+pub fn bind_private(path: &str, mode: u32) -> std::io::Result<UnixListener> {
+    let staging = format!("{}.{}.new", path, std::process::id());
+    let staged = format!("{}/socket", staging);
+    // Left behind by a process with the same pid that didn't get to clean up
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+        let _ = fs::remove_file(path);
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    bound
+}
+
+// No Unix sockets to listen on. Never returns, so the supervisor doesn't restart it.
+#[cfg(not(unix))]
+pub async fn run_control_socket(path: String, _context: ControlContext) {
+    eprintln!("Control socket {} not started, it needs Unix sockets", path);
+    std::future::pending::<()>().await;
+}
+
+#[cfg(unix)]
+async fn handle_connection(stream: UnixStream, context: ControlContext) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = handle_line(&line, &context).await {
+            writer.write_all(format!("{}\n", reply).as_bytes()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn error(id: serde_json::Value, code: i64, message: &str) -> serde_json::Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(unix)]
+async fn handle_line(line: &str, context: &ControlContext) -> Option<serde_json::Value> {
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Some(error(serde_json::Value::Null, PARSE_ERROR, &e.to_string())),
+    };
+    let request: Request = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => return Some(error(serde_json::Value::Null, INVALID_REQUEST, &e.to_string())),
+    };
+    let id = request.id.clone().unwrap_or(serde_json::Value::Null);
+    if request.jsonrpc.as_deref() != Some("2.0") {
+        return Some(error(id, INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+    }
+
+    let outcome = invoke(context, &request.method, &request.params, "local").await;
+    request.id.as_ref()?;
+    Some(match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: &serde_json::Value) -> Result<T, (i64, String)> {
+    let params = if params.is_null() { json!({}) } else { params.clone() };
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+// Runs a method for a local client, `issued_by` naming which kind for the audit trail
+pub async fn invoke(context: &ControlContext, method: &str, params: &serde_json::Value, issued_by: &str) -> Result<serde_json::Value, (i64, String)> {
+    let outcome = call(method, params, context).await;
+    // Anything that changes the camera goes into the audit trail like server commands do
+    if method != "status" && method != "snapshot" {
+        let recorded = match &outcome {
+            Ok(_) => "applied".to_string(),
+            Err((_, message)) => format!("rejected: {}", message),
+        };
+        context.audit_log.record(issued_by, method, params, &recorded);
+    }
+    outcome
+}
+
+pub fn status(context: &ControlContext) -> serde_json::Value {
+    let (state, duration) = context.status.snapshot();
+    json!({
+        "camera_id": context.camera_id,
+        "state": state.name(),
+        "state_seconds": duration.as_secs(),
+        "quality": context.quality.load(Ordering::Relaxed),
+        "resolution": format!("{}x{}", context.width.load(Ordering::Relaxed), context.height.load(Ordering::Relaxed)),
+        "codec": Codec::from_u8(context.codec.load(Ordering::Relaxed)).name(),
+        "queue_size": context.queue_size.load(Ordering::Relaxed),
+        "congested": context.congested.load(Ordering::Relaxed),
+        "paused": context.paused.is_paused(),
+        "viewers": context.viewers.count(),
+        "alarm": context.alarm.as_ref().map(|alarm| alarm.state().name()),
+        "illuminator": context.illuminator.as_ref().map(|illuminator| illuminator.state()),
+    })
+}
+
+async fn call(method: &str, request_params: &serde_json::Value, context: &ControlContext) -> Result<serde_json::Value, (i64, String)> {
+    match method {
+        "status" => Ok(status(context)),
+        // Returned rather than written anywhere, so no client picks a file the camera writes
+        "snapshot" => {
+            let Some((jpeg, timestamp)) = context.latest_frame.get_with_timestamp() else {
+                return Err((UNAVAILABLE, "no frame available".to_string()));
+            };
+            Ok(json!({ "timestamp": timestamp, "data": BASE64_STANDARD.encode(jpeg) }))
+        }
+        "camera_controls" => {
+            let command: CameraControlsCommand = params(request_params)?;
+            println!("Applying camera controls from a local client: {:?}", command);
+            context.camera_controls.apply(command);
+            Ok(json!("applied"))
+        }
+        "overlay" => {
+            context.overlays.set(params::<OverlayCommand>(request_params)?);
+            Ok(json!("applied"))
+        }
+        "clear_overlay" => {
+            let command: ClearOverlayCommand = params(request_params)?;
+            context.overlays.clear(command.id.as_deref());
+            Ok(json!("applied"))
+        }
+        "alarm" => {
+            let command: AlarmCommand = params(request_params)?;
+            let Some(alarm) = &context.alarm else {
+                return Err((UNAVAILABLE, "no alarm configured".to_string()));
+            };
+            alarm.set_mode(command.mode).await;
+            Ok(json!("applied"))
+        }
+        "illuminator" => {
+            let command: IlluminatorCommand = params(request_params)?;
+            let Some(illuminator) = &context.illuminator else {
+                return Err((UNAVAILABLE, "no illuminator configured".to_string()));
+            };
+            illuminator.set(command).await;
+            Ok(json!("applied"))
+        }
+        "shutdown" => {
+            println!("Shutdown requested by a local client");
+            // Give the reply a moment to go out first
+            let shutdown = context.shutdown.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                shutdown.notify_one();
+            });
+            Ok(json!("shutting down"))
+        }
+        method => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
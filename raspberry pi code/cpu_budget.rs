@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+
+use crate::overlay::SharedOverlays;
+
+// Optional work that can be given up when the CPU runs short; the capture, encode and
+// uplink of the stream itself are never on this list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shed {
+    // The motion detector looks at fewer frames
+    DetectorRate,
+    // Server overlays are left off the picture from the next pipeline restart; shedding
+    // doesn't restart it, which would be a stutter of its own
+    Overlays,
+    // Frames go out as the camera encoded them, without the JPEG tuner's second encode
+    Reencode,
+}
+
+impl Shed {
+    fn name(self) -> &'static str {
+        match self {
+            Shed::DetectorRate => "detector frame rate",
+            Shed::Overlays => "overlays",
+            Shed::Reencode => "JPEG re-encoding",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Encode,
+    Detect,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CpuBudgetConfig {
+    // Share of the whole CPU, all cores together, the device may use
+    pub budget_percent: f64,
+    // What goes first when usage is over budget; restored in reverse
+    pub shed_order: Vec<Shed>,
+    // While the detector rate is shed it looks at one frame in this many
+    pub detector_keep_every: u32,
+    pub check_interval_seconds: u64,
+    // A shed stage comes back once usage has stayed this far under the budget for
+    // restore_after_seconds
+    pub restore_margin_percent: f64,
+    pub restore_after_seconds: u64,
+}
+
+impl Default for CpuBudgetConfig {
+    fn default() -> Self {
+        Self {
+            budget_percent: 80.0,
+            shed_order: vec![Shed::DetectorRate, Shed::Overlays, Shed::Reencode],
+            detector_keep_every: 4,
+            check_interval_seconds: 5,
+            restore_margin_percent: 15.0,
+            restore_after_seconds: 60,
+        }
+    }
+}
+
+// (busy, total) jiffies over all cores since boot
+fn cpu_times() -> Option<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let fields: Vec<u64> = stat.lines().next()?.split_whitespace().skip(1).filter_map(|f| f.parse().ok()).collect();
+    // user nice system idle iowait irq softirq steal
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields.get(3)? + fields.get(4).unwrap_or(&0);
+    Some((total - idle, total))
+}
+
+// Milliseconds of CPU a process has used, user and system together
+#[cfg(unix)]
+fn process_cpu_ms(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in parentheses may hold spaces; the fields after it don't
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    Some((utime + stime) * 1000 / ticks_per_second)
+}
+
+#[cfg(not(unix))]
+fn process_cpu_ms(_pid: u32) -> Option<u64> {
+    None
+}
+
+// Keeps the device's CPU usage under a budget by shedding optional stages one at a
+// time, so heavy analytics slow themselves down instead of making the stream stutter
+#[derive(Clone)]
+pub struct CpuGovernor {
+    config: CpuBudgetConfig,
+    // How many entries of shed_order are currently shed
+    shed: Arc<AtomicUsize>,
+    // Everything on the list is off regardless of usage, while the SoC is hot
+    forced: Arc<AtomicBool>,
+    overlays: SharedOverlays,
+    encode_us: Arc<AtomicU64>,
+    detect_us: Arc<AtomicU64>,
+    // The GStreamer pipeline, where capture, overlays and the encoder run
+    pipeline_pid: Arc<AtomicU32>,
+}
+
+impl CpuGovernor {
+    // The pipeline to measure from now on, after every restart
+    pub fn watch_pipeline(&self, pid: Option<u32>) {
+        self.pipeline_pid.store(pid.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    // Time a stage spent working, for the per-stage figures in the log
+    pub fn record(&self, stage: Stage, busy: Duration) {
+        let counter = match stage {
+            Stage::Encode => &self.encode_us,
+            Stage::Detect => &self.detect_us,
+        };
+        counter.fetch_add(busy.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_shed(&self, stage: Shed) -> bool {
+        if self.forced.load(Ordering::Relaxed) {
+            return true;
+        }
+        let shed = self.shed.load(Ordering::Relaxed);
+        self.config.shed_order.iter().take(shed).any(|s| *s == stage)
+    }
+
+    // 1 while the detector runs at its normal rate
+    pub fn detector_keep_every(&self) -> u32 {
+        if self.is_shed(Shed::DetectorRate) {
+            self.config.detector_keep_every.max(1)
+        } else {
+            1
+        }
+    }
+
+    // Shed every optional stage at once, or give them back to the budget
+    pub fn force_all(&self, forced: bool) {
+        if self.forced.swap(forced, Ordering::Relaxed) != forced {
+            self.apply_overlays();
+        }
+    }
+
+    // Overlays are the one stage that needs telling, since they live in the pipeline; it
+    // picks the change up when it next restarts
+    fn apply_overlays(&self) {
+        self.overlays.suspend(self.is_shed(Shed::Overlays));
+    }
+}
+
+// Without a budget the governor only sheds when forced to
+pub fn spawn_cpu_governor(config: Option<CpuBudgetConfig>, overlays: SharedOverlays) -> CpuGovernor {
+    let governor = CpuGovernor {
+        config: config.clone().unwrap_or_default(),
+        shed: Arc::new(AtomicUsize::new(0)),
+        forced: Arc::new(AtomicBool::new(false)),
+        overlays,
+        encode_us: Arc::new(AtomicU64::new(0)),
+        detect_us: Arc::new(AtomicU64::new(0)),
+        pipeline_pid: Arc::new(AtomicU32::new(0)),
+    };
+    let state = governor.clone();
+    let Some(config) = config else {
+        return governor;
+    };
+
+    tokio::spawn(async move {
+        let seconds = config.check_interval_seconds.max(1);
+        let mut check = interval(Duration::from_secs(seconds));
+        let mut previous = None;
+        let mut previous_pipeline: Option<(u32, u64)> = None;
+        let mut calm_since: Option<Instant> = None;
+        loop {
+            check.tick().await;
+            let Some((busy, total)) = cpu_times() else {
+                eprintln!("CPU budget disabled, /proc/stat is not readable");
+                return;
+            };
+            let Some((last_busy, last_total)) = previous.replace((busy, total)) else {
+                continue;
+            };
+            let usage = (busy - last_busy) as f64 * 100.0 / (total - last_total).max(1) as f64;
+            // Milliseconds of work per second in each measured stage
+            let per_second = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed) / 1000 / seconds;
+            let (encode, detect) = (per_second(&state.encode_us), per_second(&state.detect_us));
+            // Only over an interval the same pipeline ran for the whole of
+            let pid = state.pipeline_pid.load(Ordering::Relaxed);
+            let pipeline_ms = (pid != 0).then(|| process_cpu_ms(pid)).flatten();
+            let pipeline = match (previous_pipeline, pipeline_ms) {
+                (Some((last_pid, last_ms)), Some(ms)) if last_pid == pid => format!("{}ms/s", ms.saturating_sub(last_ms) / seconds),
+                _ => "unknown".to_string(),
+            };
+            previous_pipeline = pipeline_ms.map(|ms| (pid, ms));
+            let overlays = if state.overlays.drawn() { "with" } else { "without" };
+
+            let shed = state.shed.load(Ordering::Relaxed);
+            if usage > config.budget_percent {
+                calm_since = None;
+                let Some(&stage) = config.shed_order.get(shed) else { continue };
+                eprintln!(
+                    "CPU at {:.0}% against a budget of {:.0}% (pipeline {} {} overlays, re-encode {}ms/s, detection {}ms/s), shedding {}",
+                    usage, config.budget_percent, pipeline, overlays, encode, detect, stage.name()
+                );
+                state.shed.store(shed + 1, Ordering::Relaxed);
+                state.apply_overlays();
+            } else if shed > 0 && usage < config.budget_percent - config.restore_margin_percent {
+                let since = *calm_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_secs(config.restore_after_seconds) {
+                    let stage = config.shed_order[shed - 1];
+                    println!("CPU at {:.0}%, restoring {}", usage, stage.name());
+                    state.shed.store(shed - 1, Ordering::Relaxed);
+                    state.apply_overlays();
+                    // The next stage waits its own turn
+                    calm_since = Some(Instant::now());
+                }
+            } else {
+                calm_since = None;
+            }
+        }
+    });
+
+    governor
+}
@@ -0,0 +1,232 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+#[cfg(unix)]
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use crate::log_stream::{self, LogLevel};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CrashConfig {
+    // Where crash reports are kept until they have been uploaded
+    pub directory: String,
+    // Reports are POSTed here as JSON on the next start; without it they stay on disk
+    pub upload_url: Option<String>,
+    // Lines of recent output included with each report; 0 leaves stdout and stderr alone
+    pub log_lines: usize,
+    // Oldest reports are deleted beyond this many
+    pub keep: usize,
+}
+
+impl Default for CrashConfig {
+    fn default() -> Self {
+        Self {
+            directory: "crashes".to_string(),
+            upload_url: None,
+            log_lines: 200,
+            keep: 20,
+        }
+    }
+}
+
+// The last lines written to stdout and stderr
+static LOG_TAIL: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+// Read ends of the pipes standard output goes through
+#[cfg(unix)]
+static TEE_PIPES: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// Route a standard stream through a pipe; a thread copies everything on to where it went
+// before, remembers the last lines and hands each one to log streaming
+#[cfg(unix)]
+fn tee_stream(fd: i32, lines: usize) -> Result<(), String> {
+    use std::os::fd::FromRawFd;
+    let mut pipe = [0i32; 2];
+    // Safe: plain fd juggling; the new descriptors are owned by the files below
+    let original = unsafe {
+        if libc::pipe(pipe.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        let original = libc::dup(fd);
+        if original < 0 || libc::dup2(pipe[1], fd) < 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        libc::close(pipe[1]);
+        original
+    };
+    TEE_PIPES.lock().unwrap().push(pipe[0]);
+    let reader = unsafe { File::from_raw_fd(pipe[0]) };
+    let mut output = unsafe { File::from_raw_fd(original) };
+
+    std::thread::Builder::new()
+        .name(format!("log-tee-{}", fd))
+        .spawn(move || {
+            let tail = LOG_TAIL.get_or_init(|| Mutex::new(VecDeque::new()));
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            let level = if fd == libc::STDERR_FILENO { LogLevel::Warn } else { LogLevel::Info };
+            while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+                let _ = output.write_all(&line);
+                let text = String::from_utf8_lossy(&line).trim_end().to_string();
+                log_stream::publish(level, &text);
+                if lines > 0 {
+                    let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+                    if tail.len() >= lines {
+                        tail.pop_front();
+                    }
+                    tail.push_back(text);
+                }
+                line.clear();
+            }
+        })
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(unix))]
+fn tee_stream(_fd: i32, _lines: usize) -> Result<(), String> {
+    Err("standard output can only be piped on Unix".to_string())
+}
+
+// Give the tee threads a moment to pass on what is still in the pipes; call before exiting
+#[cfg(unix)]
+pub fn drain_output() {
+    let _ = std::io::stdout().flush();
+    for &fd in TEE_PIPES.lock().unwrap().iter() {
+        for _ in 0..20 {
+            let mut pending: libc::c_int = 0;
+            // Safe: FIONREAD only writes the byte count
+            if unsafe { libc::ioctl(fd, libc::FIONREAD, &mut pending) } != 0 || pending == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+    std::thread::sleep(Duration::from_millis(10));
+}
+
+#[cfg(not(unix))]
+pub fn drain_output() {
+    let _ = std::io::stdout().flush();
+}
+
+fn reports(directory: &str) -> Vec<PathBuf> {
+    let mut reports: Vec<PathBuf> = std::fs::read_dir(directory)
+        .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect())
+        .unwrap_or_default();
+    reports.retain(|path| path.extension().and_then(|e| e.to_str()) == Some("json"));
+    // Named by time, so this is oldest first
+    reports.sort();
+    reports
+}
+
+fn write_report(config: &CrashConfig, report: &serde_json::Value) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(&config.directory).map_err(|e| e.to_string())?;
+    let existing = reports(&config.directory);
+    for old in existing.iter().take((existing.len() + 1).saturating_sub(config.keep.max(1))) {
+        let _ = std::fs::remove_file(old);
+    }
+    let path = Path::new(&config.directory).join(format!("crash-{}.json", now_ms()));
+    std::fs::write(&path, report.to_string()).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// Capture recent output and persist a report with a backtrace for every panic, on top of
+// the panic hook that logs it. A panicking task is restarted or takes the process down,
+// so the report is written before anything else happens. Output is also piped when only
+// `stream_logs` needs it.
+pub fn install(config: &CrashConfig, stream_logs: bool) {
+    if config.log_lines > 0 || stream_logs {
+        for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            if let Err(e) = tee_stream(fd, config.log_lines) {
+                eprintln!("Crash reports and log streaming won't see recent output: {}", e);
+                break;
+            }
+        }
+    }
+
+    let config = config.clone();
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let log: Vec<String> = LOG_TAIL
+            .get()
+            .map(|tail| tail.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect())
+            .unwrap_or_default();
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let report = json!({
+            "timestamp": now_ms(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "thread": std::thread::current().name().unwrap_or("unnamed"),
+            "message": message,
+            "location": info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+            "backtrace": Backtrace::force_capture().to_string(),
+            "log": log,
+        });
+        match write_report(&config, &report) {
+            Ok(path) => eprintln!("Crash report saved to {}", path.display()),
+            Err(e) => eprintln!("Failed to save crash report: {}", e),
+        }
+        previous_hook(info);
+    }));
+}
+
+// Send the reports left by earlier runs and delete the ones the server took
+pub async fn upload_reports(config: CrashConfig, camera_id: String) {
+    let pending = reports(&config.directory);
+    if pending.is_empty() {
+        return;
+    }
+    let Some(url) = &config.upload_url else {
+        println!("{} crash reports in {} (no upload_url configured)", pending.len(), config.directory);
+        return;
+    };
+
+    let client = reqwest::Client::new();
+    let mut uploaded = 0;
+    for path in &pending {
+        let Ok(body) = tokio::fs::read(path).await else { continue };
+        let response = client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Camera-Id", &camera_id)
+            .body(body)
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => {
+                let _ = tokio::fs::remove_file(path).await;
+                uploaded += 1;
+            }
+            Ok(response) => {
+                eprintln!("Crash report upload of {} failed: server returned {}", path.display(), response.status());
+                break;
+            }
+            Err(e) => {
+                eprintln!("Crash report upload of {} failed: {}", path.display(), e);
+                break;
+            }
+        }
+    }
+    println!("Uploaded {} of {} crash reports", uploaded, pending.len());
+}
@@ -0,0 +1,288 @@
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::audit::AuditLog;
+use crate::encryption;
+use crate::export::{self, ExportProgress, Segment};
+use crate::identity::{DeviceIdentity, IdentityConfig};
+use crate::recording::RecordingConfig;
+use crate::timeline::{Timeline, TimelineQueryCommand};
+
+// {"command": "custody_export", "from": ..., "to": ..., "upload_url": ..., "case": "2024-0117"}
+#[derive(Debug, Clone, Deserialize)]
+pub struct CustodyExportCommand {
+    // Echoed back in export_progress messages
+    pub id: Option<String>,
+    // Time range in UNIX millis
+    pub from: u64,
+    pub to: u64,
+    // Where the finished archive is PUT
+    pub upload_url: String,
+    // Case or incident reference, written into the manifest as given
+    pub case: Option<String>,
+}
+
+const FORMAT: &str = "securitycamera-custody/1";
+
+const README: &str = "\
+Chain-of-custody archive (securitycamera-custody/1)
+
+Files, in the order they appear:
+  segments/*.ts   Recorded MPEG-TS segments as written by the camera, decrypted,
+                  never re-encoded. Oldest first.
+  timeline.json   Recordings, motion intervals and detector events in the range,
+                  if the camera keeps a timeline.
+  audit.jsonl     Lines of the camera's command audit log in the range. Each line
+                  carries prev_hash and hash = SHA-256(prev_hash + entry), so the
+                  excerpt can be checked against the full log on the device.
+  device.json     The device's ed25519 public key (base64), its SHA-256
+                  fingerprint and the camera id derived from it.
+  manifest.json   Every file above in order with its size, its SHA-256 and a
+                  running chain hash. It comes last because the archive is
+                  written as it is uploaded and the hashes are only known then.
+  manifest.sig    ed25519 signature (base64) over the exact bytes of
+                  manifest.json, made with the device key.
+
+Verifying:
+  1. Check manifest.sig against manifest.json with the public key in device.json,
+     and that the key's fingerprint is the one enrolled for this camera.
+  2. For each file in manifest order, SHA-256 its contents and compare with
+     \"sha256\". Starting from 64 zeros, chain = SHA-256(previous chain + sha256),
+     all as lowercase hex strings; it has to match \"chain\", and the last one
+     \"chain_head\".
+  A file that was changed, removed, added or reordered breaks the chain from
+  that point on.
+";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Plain ustar, readable by tar on any system the archive is handed to
+fn tar_header(name: &str, size: u64, mtime: u64) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    let octal = |field: &mut [u8], value: u64| {
+        let text = format!("{:0width$o}\0", value, width = field.len() - 1);
+        field.copy_from_slice(text.as_bytes());
+    };
+    header[..name.len().min(100)].copy_from_slice(&name.as_bytes()[..name.len().min(100)]);
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is taken with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+// Writes the tar as it goes and keeps what the manifest needs about each file
+struct ArchiveWriter<'a> {
+    out: &'a mut dyn Write,
+    mtime: u64,
+    written: u64,
+    chain: String,
+    entries: Vec<serde_json::Value>,
+}
+
+impl<'a> ArchiveWriter<'a> {
+    fn new(out: &'a mut dyn Write, mtime: u64) -> Self {
+        Self { out, mtime, written: 0, chain: "0".repeat(64), entries: Vec::new() }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.out.write_all(data).map_err(|e| e.to_string())?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    // A file whose size has to be known up front, as tar puts it in the header. It is
+    // hashed while it is copied; a source that turns out shorter or longer fails the
+    // archive rather than leaving a file in it that doesn't match its header.
+    fn append_from(&mut self, name: &str, mut source: impl Read, size: u64) -> Result<String, String> {
+        self.write(&tar_header(name, size, self.mtime))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut remaining = size;
+        while remaining > 0 {
+            let want = buf.len().min(remaining as usize);
+            let read = source.read(&mut buf[..want]).map_err(|e| format!("{}: {}", name, e))?;
+            if read == 0 {
+                return Err(format!("{}: ended {} bytes short", name, remaining));
+            }
+            hasher.update(&buf[..read]);
+            self.write(&buf[..read])?;
+            remaining -= read as u64;
+        }
+        if source.read(&mut buf[..1]).map_err(|e| format!("{}: {}", name, e))? != 0 {
+            return Err(format!("{}: longer than the {} bytes it was listed with", name, size));
+        }
+        let padding = (512 - size % 512) % 512;
+        self.write(&vec![0u8; padding as usize])?;
+        Ok(hex(&hasher.finalize()))
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.append_from(name, data, data.len() as u64).map(|_| ())
+    }
+
+    // A file listed in the manifest
+    fn append_listed(&mut self, name: &str, source: impl Read, size: u64) -> Result<(), String> {
+        let sha256 = self.append_from(name, source, size)?;
+        self.chain = hex(&Sha256::digest(format!("{}{}", self.chain, sha256).as_bytes()));
+        self.entries.push(json!({ "name": name, "bytes": size, "sha256": sha256, "chain": self.chain }));
+        Ok(())
+    }
+
+    // End of archive: two empty blocks
+    fn finish(mut self) -> Result<u64, String> {
+        self.write(&[0u8; 1024])?;
+        Ok(self.written)
+    }
+}
+
+// Where an archive's contents come from; only available with local recording
+#[derive(Clone)]
+pub struct CustodySources {
+    pub recording: RecordingConfig,
+    pub identity: IdentityConfig,
+    pub timeline: Option<Timeline>,
+    pub audit_log: AuditLog,
+}
+
+// Everything for an incident in one archive that can be checked without the camera:
+// the footage, what happened around it and who touched the camera, hash chained and
+// signed with the device key
+pub async fn export_custody(
+    sources: CustodySources,
+    command: CustodyExportCommand,
+    camera_id: String,
+    issued_by: String,
+    outgoing: mpsc::Sender<Message>
+) {
+    let progress = ExportProgress::new(command.id.clone(), camera_id.clone(), outgoing);
+    match run_custody_export(sources, &command, &camera_id, issued_by, &progress).await {
+        Ok(size) => {
+            println!("Exported custody archive {}-{} ({} bytes) to {}", command.from, command.to, size, command.upload_url);
+            progress.report("done", json!({ "bytes": size })).await;
+        }
+        Err(e) => {
+            eprintln!("Custody export failed: {}", e);
+            progress.report("failed", json!({ "error": e })).await;
+        }
+    }
+}
+
+// Builds the archive on one blocking thread while it is uploaded from another, so
+// neither the footage nor the archive is ever held in memory; the bytes uploaded
+async fn run_custody_export(
+    sources: CustodySources,
+    command: &CustodyExportCommand,
+    camera_id: &str,
+    issued_by: String,
+    progress: &ExportProgress
+) -> Result<u64, String> {
+    // Checked first, so nothing is collected for an archive that can't be signed
+    let key_path = &sources.identity.key_path;
+    let identity = DeviceIdentity::load(key_path).map_err(|e| format!("device key {}: {} (run --provision first)", key_path, e))?;
+    let segments = export::find_segments(&sources.recording, command.from, command.to, progress).await?;
+
+    // Uploaded as it is written; the size is only known at the end
+    progress.report("uploading", json!({})).await;
+    let (writer, reader) = export::pipe();
+    let written = Arc::new(AtomicU64::new(0));
+    let total = written.clone();
+    let (archive_command, archive_progress) = (command.clone(), progress.clone());
+    let archive = tokio::task::spawn_blocking(move || {
+        writer.run(|out| {
+            write_archive(&sources, &archive_command, &identity, &issued_by, &segments, out, &archive_progress)
+                .map(|bytes| total.store(bytes, Ordering::Relaxed))
+        })
+    });
+    let (url, uploader) = (command.upload_url.clone(), camera_id.to_string());
+    let uploaded = tokio::task::spawn_blocking(move || {
+        export::upload(&url, "application/x-tar", &uploader, reqwest::blocking::Body::new(reader))
+    });
+    let uploaded = uploaded.await.map_err(|e| e.to_string())?;
+    let _ = archive.await;
+    uploaded?;
+    Ok(written.load(Ordering::Relaxed))
+}
+
+// The whole archive into `out`; the number of bytes written. Blocks.
+fn write_archive(
+    sources: &CustodySources,
+    command: &CustodyExportCommand,
+    identity: &DeviceIdentity,
+    issued_by: &str,
+    segments: &[Segment],
+    out: &mut dyn Write,
+    progress: &ExportProgress
+) -> Result<u64, String> {
+    // The id the device key vouches for, which is also the one the server enrolled
+    let camera_id = identity.camera_id();
+    let created_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    let mut archive = ArchiveWriter::new(out, created_ms / 1000);
+    archive.append("README.txt", README.as_bytes())?;
+
+    let mut reader = export::SegmentReader::new(&sources.recording)?;
+    for (index, segment) in segments.iter().enumerate() {
+        let size = encryption::plain_len(&segment.name, segment.bytes).ok_or_else(|| format!("{}: not a whole encrypted segment", segment.name))?;
+        let path = Path::new(&segment.name);
+        let plain = encryption::plain_name(path).unwrap_or_else(|| path.to_path_buf());
+        let name = plain.file_name().map_or(segment.name.clone(), |name| name.to_string_lossy().to_string());
+        archive.append_listed(&format!("segments/{}", name), reader.open(segment)?, size)?;
+        progress.report_blocking("collecting", json!({ "percent": (index + 1) * 100 / segments.len() }));
+    }
+    progress.report_blocking("signing", json!({}));
+
+    if let Some(timeline) = &sources.timeline {
+        let query = TimelineQueryCommand { id: command.id.clone(), from: command.from, to: command.to };
+        let timeline = timeline.query(&camera_id, &query).to_string();
+        archive.append_listed("timeline.json", timeline.as_bytes(), timeline.len() as u64)?;
+    }
+    let audit: String = sources.audit_log.excerpt(command.from, command.to).iter().map(|line| format!("{}\n", line)).collect();
+    archive.append_listed("audit.jsonl", audit.as_bytes(), audit.len() as u64)?;
+    let device = json!({
+        "camera_id": camera_id,
+        "algorithm": "ed25519",
+        "public_key": identity.public_key(),
+        "fingerprint_sha256": hex(&identity.fingerprint()),
+    });
+    let device = serde_json::to_vec_pretty(&device).map_err(|e| e.to_string())?;
+    archive.append_listed("device.json", device.as_slice(), device.len() as u64)?;
+
+    let manifest = json!({
+        "format": FORMAT,
+        "camera_id": camera_id,
+        "case": command.case,
+        "requested_by": issued_by,
+        "from": command.from,
+        "to": command.to,
+        "created": created_ms,
+        "files": archive.entries,
+        "chain_head": archive.chain,
+    });
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    let signature = identity.sign(&manifest);
+    archive.append("manifest.json", &manifest)?;
+    archive.append("manifest.sig", signature.as_bytes())?;
+    archive.finish()
+}
@@ -0,0 +1,317 @@
+use std::sync::{atomic::AtomicU64, Arc};
+
+use crate::congestion_history::CongestionHistory;
+use crate::stats_db::StatsCounters;
+use crate::stream_state::StreamStatus;
+
+#[cfg(all(feature = "dashboard", unix))]
+pub use live::{restore_terminal, Dashboard};
+#[cfg(not(all(feature = "dashboard", unix)))]
+pub use unavailable::{restore_terminal, Dashboard};
+
+// What the dashboard shows, sampled once a second
+#[cfg_attr(not(all(feature = "dashboard", unix)), allow(dead_code))]
+pub struct DashboardSources {
+    pub camera_id: String,
+    pub status: StreamStatus,
+    pub stats: StatsCounters,
+    pub queue_size: Arc<AtomicU64>,
+    pub history: CongestionHistory,
+}
+
+// Live status for commissioning on site, in place of the scrolling log (built with
+// --features dashboard, shown with --dashboard). Output capture is Unix only.
+#[cfg(all(feature = "dashboard", unix))]
+mod live {
+    use ratatui::{
+        backend::CrosstermBackend,
+        crossterm::{
+            cursor::{Hide, Show},
+            execute,
+            terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+        },
+        layout::{Constraint, Layout},
+        style::{Color, Style},
+        text::{Line, Span},
+        widgets::{Block, Paragraph, Sparkline},
+        Frame, Terminal,
+    };
+    use std::{
+        collections::VecDeque,
+        fs::File,
+        io::{BufRead, BufReader, IsTerminal, Write},
+        mem::ManuallyDrop,
+        os::fd::FromRawFd,
+        sync::{atomic::Ordering, Arc, Mutex, OnceLock},
+        time::{Duration, Instant},
+    };
+    use tokio::sync::Notify;
+
+    use super::DashboardSources;
+
+    const REFRESH: Duration = Duration::from_secs(1);
+    // Output lines kept for the log pane and printed again on exit
+    const OUTPUT_LINES: usize = 200;
+    // Seconds of bandwidth in the graph
+    const GRAPH_SECONDS: usize = 120;
+
+    #[derive(Default)]
+    struct Output {
+        // Line and whether it went to stderr
+        lines: VecDeque<(String, bool)>,
+        last_error: Option<(String, Instant)>,
+        // The dashboard is gone; output goes straight to the terminal again
+        restored: bool,
+    }
+
+    // The real terminal, which isn't owned by whoever writes to it here
+    fn terminal_file(fd: i32) -> ManuallyDrop<File> {
+        ManuallyDrop::new(unsafe { File::from_raw_fd(fd) })
+    }
+
+    // The real terminal and what would have been printed on it, for restore_terminal
+    static TERMINAL: OnceLock<(i32, Arc<Mutex<Output>>)> = OnceLock::new();
+
+    pub struct Dashboard {
+        terminal: i32,
+        output: Arc<Mutex<Output>>,
+    }
+
+    // Send a standard stream into the log pane instead of the terminal
+    fn capture(fd: i32, output: Arc<Mutex<Output>>) -> Result<(), String> {
+        let mut pipe = [0i32; 2];
+        // Safe: the read end is owned by the file below and the write end replaces fd
+        unsafe {
+            if libc::pipe(pipe.as_mut_ptr()) != 0 || libc::dup2(pipe[1], fd) < 0 {
+                return Err(std::io::Error::last_os_error().to_string());
+            }
+            libc::close(pipe[1]);
+        }
+        let mut reader = BufReader::new(unsafe { File::from_raw_fd(pipe[0]) });
+        let is_error = fd == libc::STDERR_FILENO;
+
+        std::thread::Builder::new()
+            .name(format!("dashboard-output-{}", fd))
+            .spawn(move || {
+                let mut line = Vec::new();
+                while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+                    let text = String::from_utf8_lossy(&line).trim_end().to_string();
+                    line.clear();
+                    let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+                    if output.restored {
+                        if let Some((fd, _)) = TERMINAL.get() {
+                            let _ = writeln!(terminal_file(*fd), "{}", text);
+                        }
+                    }
+                    if is_error {
+                        output.last_error = Some((text.clone(), Instant::now()));
+                    }
+                    if output.lines.len() >= OUTPUT_LINES {
+                        output.lines.pop_front();
+                    }
+                    output.lines.push_back((text, is_error));
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    impl Dashboard {
+        // Take over stdout and stderr; anything printed from here on shows up in the log
+        // pane. None when there is no terminal to draw on.
+        pub fn start() -> Option<Self> {
+            if !std::io::stdout().is_terminal() {
+                eprintln!("The dashboard needs a terminal, carrying on with plain output");
+                return None;
+            }
+            // Safe: dup only hands out a new descriptor
+            let terminal = unsafe { libc::dup(libc::STDOUT_FILENO) };
+            if terminal < 0 {
+                eprintln!("Failed to start the dashboard: {}", std::io::Error::last_os_error());
+                return None;
+            }
+            let output = Arc::new(Mutex::new(Output::default()));
+            for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+                if let Err(e) = capture(fd, output.clone()) {
+                    eprintln!("Failed to capture output for the dashboard: {}", e);
+                    unsafe { libc::close(terminal) };
+                    return None;
+                }
+            }
+            let _ = TERMINAL.set((terminal, output.clone()));
+            Some(Self { terminal, output })
+        }
+
+        pub fn spawn(self, sources: DashboardSources, shutdown: Arc<Notify>) {
+            // Ctrl-C would leave the terminal on the alternate screen; stop the normal way
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    shutdown.notify_one();
+                }
+            });
+            let started = std::thread::Builder::new()
+                .name("dashboard".to_string())
+                .spawn(move || self.run(sources));
+            if let Err(e) = started {
+                restore_terminal();
+                eprintln!("Failed to start the dashboard: {}", e);
+            }
+        }
+
+        fn run(self, sources: DashboardSources) {
+            let mut writer = terminal_file(self.terminal);
+            if let Err(e) = execute!(writer, EnterAlternateScreen, Hide) {
+                restore_terminal();
+                eprintln!("Failed to start the dashboard: {}", e);
+                return;
+            }
+            let mut terminal = match Terminal::new(CrosstermBackend::new(&*writer)) {
+                Ok(terminal) => terminal,
+                Err(e) => {
+                    restore_terminal();
+                    eprintln!("Failed to start the dashboard: {}", e);
+                    return;
+                }
+            };
+
+            let mut previous = sources.stats.totals();
+            let mut sampled = Instant::now();
+            let mut bandwidth: VecDeque<u64> = VecDeque::with_capacity(GRAPH_SECONDS);
+            loop {
+                std::thread::sleep(REFRESH);
+                let totals = sources.stats.totals();
+                let elapsed = sampled.elapsed().as_secs_f64();
+                sampled = Instant::now();
+                let rates: [f64; 4] = std::array::from_fn(|i| (totals[i] - previous[i]) as f64 / elapsed);
+                previous = totals;
+                let kbps = rates[2] * 8.0 / 1000.0;
+                if bandwidth.len() >= GRAPH_SECONDS {
+                    bandwidth.pop_front();
+                }
+                bandwidth.push_back(kbps as u64);
+
+                let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+                if output.restored {
+                    return;
+                }
+                let drawn = terminal.draw(|frame| draw(frame, &sources, rates, kbps, bandwidth.make_contiguous(), &output));
+                drop(output);
+                if let Err(e) = drawn {
+                    restore_terminal();
+                    eprintln!("Dashboard stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn row<'a>(label: &'a str, value: String, style: Style) -> Line<'a> {
+        Line::from(vec![Span::raw(format!("{:<12}", label)), Span::styled(value, style)])
+    }
+
+    fn draw(frame: &mut Frame, sources: &DashboardSources, rates: [f64; 4], kbps: f64, bandwidth: &[u64], output: &Output) {
+        let [summary, graph, log] =
+            Layout::vertical([Constraint::Length(10), Constraint::Length(6), Constraint::Min(3)]).areas(frame.area());
+
+        let (state, duration) = sources.status.snapshot();
+        let latest = sources.history.latest();
+        let normal = Style::default();
+        let warning = Style::default().fg(Color::Yellow);
+        let error = Style::default().fg(Color::Red);
+
+        let lines = vec![
+            row(
+                "State",
+                format!("{} for {}s", state.name(), duration.as_secs()),
+                if state.is_connected() { normal } else { warning },
+            ),
+            row(
+                "Frame rate",
+                format!("{:.1} fps captured, {:.1} fps sent, {:.1}/s dropped", rates[0], rates[1], rates[3]),
+                if rates[3] > 0.0 { warning } else { normal },
+            ),
+            row("Bandwidth", format!("{:.0} kbit/s", kbps), normal),
+            row("Queue", format!("{} frames", sources.queue_size.load(Ordering::Relaxed)), normal),
+            match &latest {
+                Some(sample) => row(
+                    "Congestion",
+                    format!("level {}{}", sample.level, if sample.congested { ", congested" } else { "" }),
+                    if sample.congested { warning } else { normal },
+                ),
+                None => row("Congestion", "no decisions yet".to_string(), normal),
+            },
+            match &latest {
+                Some(sample) => row("Stream", format!("{} at quality {}, {}", sample.resolution, sample.quality, sample.codec), normal),
+                None => row("Stream", "starting".to_string(), normal),
+            },
+            row("Reconnects", sources.status.reconnects().to_string(), normal),
+            match &output.last_error {
+                Some((message, at)) => row("Last error", format!("{}s ago: {}", at.elapsed().as_secs(), message), error),
+                None => row("Last error", "none".to_string(), normal),
+            },
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!(" Camera {} ", sources.camera_id))),
+            summary,
+        );
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(" Bandwidth, last {} seconds ", GRAPH_SECONDS)))
+                .data(bandwidth)
+                .style(Style::default().fg(Color::Cyan)),
+            graph,
+        );
+
+        // Newest lines at the bottom, as many as fit inside the border
+        let visible = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = output
+            .lines
+            .iter()
+            .skip(output.lines.len().saturating_sub(visible))
+            .map(|(line, is_error)| Line::styled(line.as_str(), if *is_error { error } else { normal }))
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Output ")), log);
+    }
+
+    // Put the terminal back and print the output the dashboard was showing, so whatever
+    // ended the process is still on screen. Call before exiting.
+    pub fn restore_terminal() {
+        let Some((fd, output)) = TERMINAL.get() else { return };
+        let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+        if output.restored {
+            return;
+        }
+        output.restored = true;
+        let mut terminal = terminal_file(*fd);
+        let _ = execute!(terminal, Show, LeaveAlternateScreen);
+        for (line, _) in &output.lines {
+            let _ = writeln!(terminal, "{}", line);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "dashboard", unix)))]
+mod unavailable {
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    use super::DashboardSources;
+
+    pub struct Dashboard;
+
+    impl Dashboard {
+        pub fn start() -> Option<Self> {
+            if cfg!(unix) {
+                eprintln!("This build has no dashboard; rebuild with --features dashboard");
+            } else {
+                eprintln!("The dashboard is only available on Unix");
+            }
+            None
+        }
+
+        pub fn spawn(self, _sources: DashboardSources, _shutdown: Arc<Notify>) {}
+    }
+
+    pub fn restore_terminal() {}
+}
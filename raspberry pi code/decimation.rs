@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecimationConfig {
+    // While the uplink is congested only every Nth frame is sent; 1 disables decimation
+    pub congested_keep_every: u32,
+}
+
+impl Default for DecimationConfig {
+    fn default() -> Self {
+        Self {
+            congested_keep_every: 2,
+        }
+    }
+}
+
+// Lowers the uplink frame rate without touching the encoder, since restarting
+// GStreamer to change the framerate interrupts the stream
+pub struct Decimator {
+    keep_every: u32,
+    count: u32,
+}
+
+impl Decimator {
+    pub fn new(config: &DecimationConfig) -> Self {
+        Self {
+            keep_every: config.congested_keep_every.max(1),
+            count: 0,
+        }
+    }
+
+    pub fn keep(&mut self, congested: bool) -> bool {
+        if !congested {
+            self.count = 0;
+            return true;
+        }
+        let keep = self.count.is_multiple_of(self.keep_every);
+        self.count += 1;
+        keep
+    }
+}
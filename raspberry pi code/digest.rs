@@ -0,0 +1,215 @@
+use chrono::{DateTime, Timelike};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+
+use crate::clock::TimeConfig;
+use crate::email::{self, EmailConfig};
+use crate::events::{CameraEvent, CameraEvents};
+use crate::snapshot::{self, LatestFrame};
+use crate::stats_db::StatsCounters;
+use crate::stream_state::StreamStatus;
+use crate::telegram::{self, TelegramConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DigestConfig {
+    // Local hour (0-23) the report on the past day goes out
+    pub hour: u32,
+    // Thumbnails of the most notable events, one per kind of event at most
+    pub max_thumbnails: usize,
+    pub thumbnail_width: u32,
+    // Only the digest: no email per event, and the Telegram bot starts muted (/arm
+    // unmutes it; with an alarm configured the alarm decides instead)
+    pub instead_of_alerts: bool,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self { hour: 8, max_thumbnails: 4, thumbnail_width: 320, instead_of_alerts: false }
+    }
+}
+
+// What the report is put together from, and the notifiers it is sent through
+pub struct DigestSources {
+    pub camera_id: String,
+    pub time: TimeConfig,
+    pub latest_frame: LatestFrame,
+    pub status: StreamStatus,
+    pub stats: StatsCounters,
+    pub email: Option<EmailConfig>,
+    pub telegram: Option<TelegramConfig>,
+}
+
+const SAMPLE_EVERY: Duration = Duration::from_secs(10);
+
+struct Highlight {
+    // Higher is more notable; the most notable event of each kind gets the thumbnail
+    weight: f32,
+    caption: String,
+    jpeg: Vec<u8>,
+}
+
+// One day's worth, reset after every report
+struct Day {
+    started: DateTime<Tz>,
+    counts: BTreeMap<&'static str, u32>,
+    by_hour: [u32; 24],
+    highlights: BTreeMap<&'static str, Highlight>,
+    connected: Duration,
+    sampled: Duration,
+    reconnects: u64,
+    totals: [u64; 4],
+}
+
+impl Day {
+    fn start(sources: &DigestSources) -> Self {
+        Self {
+            started: sources.time.now(),
+            counts: BTreeMap::new(),
+            by_hour: [0; 24],
+            highlights: BTreeMap::new(),
+            connected: Duration::ZERO,
+            sampled: Duration::ZERO,
+            reconnects: sources.status.reconnects(),
+            totals: sources.stats.totals(),
+        }
+    }
+
+    fn record(&mut self, sources: &DigestSources, event: &CameraEvent) {
+        let now = sources.time.now();
+        *self.counts.entry(event.kind()).or_default() += 1;
+        self.by_hour[now.hour() as usize] += 1;
+
+        let weight = match event {
+            CameraEvent::Motion { score } => *score,
+            CameraEvent::Sound { level_db, .. } => *level_db,
+            // The first one stands for the rest
+            _ => f32::MIN,
+        };
+        if self.highlights.get(event.kind()).is_some_and(|highlight| highlight.weight >= weight) {
+            return;
+        }
+        if let Some(jpeg) = sources.latest_frame.get() {
+            let caption = format!("{} {}", now.format("%H:%M"), event.describe());
+            // Scaled down when the report goes out, off the runtime's threads
+            self.highlights.insert(event.kind(), Highlight { weight, caption, jpeg });
+        }
+    }
+
+    fn report(&self, sources: &DigestSources) -> String {
+        let now = sources.time.now();
+        let mut lines = vec![format!(
+            "Camera {}, {} to {}",
+            sources.camera_id,
+            self.started.format("%Y-%m-%d %H:%M"),
+            now.format("%Y-%m-%d %H:%M %Z")
+        )];
+        lines.push(String::new());
+
+        let total: u32 = self.counts.values().sum();
+        if total == 0 {
+            lines.push("No events".to_string());
+        } else {
+            lines.push(format!("{} events", total));
+            for (kind, count) in &self.counts {
+                lines.push(format!("  {}: {}", kind, count));
+            }
+            let (busiest, count) = self.by_hour.iter().enumerate().max_by_key(|(hour, count)| (**count, std::cmp::Reverse(*hour))).unwrap();
+            lines.push(format!("Busiest hour {:02}:00 with {}", busiest, count));
+        }
+        lines.push(String::new());
+
+        let percent = if self.sampled.is_zero() { 0.0 } else { self.connected.as_secs_f64() * 100.0 / self.sampled.as_secs_f64() };
+        lines.push(format!(
+            "Connected {} of {} ({:.1}%), {} reconnects",
+            hours_minutes(self.connected),
+            hours_minutes(self.sampled),
+            percent,
+            sources.status.reconnects().saturating_sub(self.reconnects)
+        ));
+        let [captured, sent, bytes, dropped] = sources.stats.totals();
+        lines.push(format!(
+            "Frames captured {}, sent {} ({} MB), dropped {}",
+            captured.saturating_sub(self.totals[0]),
+            sent.saturating_sub(self.totals[1]),
+            bytes.saturating_sub(self.totals[2]) / (1024 * 1024),
+            dropped.saturating_sub(self.totals[3])
+        ));
+        lines.join("\n")
+    }
+
+    fn thumbnails(&self, config: &DigestConfig) -> Vec<(String, Vec<u8>)> {
+        let mut highlights: Vec<&Highlight> = self.highlights.values().collect();
+        highlights.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        highlights.into_iter().take(config.max_thumbnails).map(|highlight| (highlight.caption.clone(), highlight.jpeg.clone())).collect()
+    }
+}
+
+fn hours_minutes(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+async fn send(config: &DigestConfig, sources: &DigestSources, day: &Day) {
+    let report = day.report(sources);
+    let (highlights, width) = (day.thumbnails(config), config.thumbnail_width);
+    let thumbnails = tokio::task::spawn_blocking(move || {
+        highlights.into_iter().map(|(caption, jpeg)| (caption, snapshot::thumbnail(jpeg, width))).collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    let subject = format!("[{}] Daily summary for {}", sources.camera_id, sources.time.now().format("%Y-%m-%d"));
+    if let Some(email) = &sources.email {
+        match email::send_report(email, &subject, &report, &thumbnails).await {
+            Ok(()) => println!("Sent the daily digest to {}", email.to.join(", ")),
+            Err(e) => eprintln!("Failed to email the daily digest: {}", e),
+        }
+    }
+    if let Some(bot) = &sources.telegram {
+        match telegram::send_report(bot, &format!("{}\n\n{}", subject, report), &thumbnails).await {
+            Ok(()) => println!("Sent the daily digest to Telegram"),
+            Err(e) => eprintln!("Failed to send the daily digest to Telegram: {}", e),
+        }
+    }
+}
+
+// Counts events through the day and sends one report at the configured hour, instead
+// of or as well as the alert for each one
+pub fn spawn_digest(config: DigestConfig, sources: DigestSources, events: &CameraEvents) {
+    if sources.email.is_none() && sources.telegram.is_none() {
+        eprintln!("Daily digest needs email or Telegram configured to send it, leaving it off");
+        return;
+    }
+    let mut events = events.subscribe();
+    println!("Sending a daily digest at {:02}:00", config.hour);
+
+    tokio::spawn(async move {
+        let mut day = Day::start(&sources);
+        // Started after today's hour already went by: the first report is tomorrow's
+        let mut last_sent = Some(day.started).filter(|now| now.hour() >= config.hour).map(|now| now.date_naive());
+        let mut tick = interval(SAMPLE_EVERY);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => day.record(&sources, &event),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = tick.tick() => {
+                    day.sampled += SAMPLE_EVERY;
+                    if sources.status.get().is_connected() {
+                        day.connected += SAMPLE_EVERY;
+                    }
+                    let now = sources.time.now();
+                    if now.hour() == config.hour && last_sent != Some(now.date_naive()) {
+                        send(&config, &sources, &day).await;
+                        last_sent = Some(now.date_naive());
+                        day = Day::start(&sources);
+                    }
+                }
+            }
+        }
+    });
+}
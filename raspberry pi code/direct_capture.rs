@@ -0,0 +1,256 @@
+#[cfg(target_os = "linux")]
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use std::{io::Write, sync::OnceLock, time::Instant};
+use tokio::process::Command;
+
+use crate::capture_source::CaptureConfig;
+use crate::config;
+
+// Capture straight from a V4L2 device and encode JPEGs in Rust, for systems without
+// GStreamer (or a cross-compiled build that can't rely on one). Only the MJPEG uplink
+// stream comes out of it; recording, overlays and the other pipeline branches still
+// need GStreamer.
+
+pub const DEFAULT_DEVICE: &str = "/dev/video0";
+const FRAME_RATE: u32 = 15;
+
+fn device(capture: &CaptureConfig) -> String {
+    capture.device.clone().unwrap_or_else(|| DEFAULT_DEVICE.to_string())
+}
+
+// Whether gst-launch-1.0 is on the PATH; looked up once
+pub fn gstreamer_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        std::env::var_os("PATH")
+            .map(|path| std::env::split_paths(&path).any(|directory| directory.join("gst-launch-1.0").is_file()))
+            .unwrap_or(false)
+    })
+}
+
+// This binary again, as the capture child; it writes JPEGs to stdout like the
+// GStreamer pipeline's fdsink would
+pub fn command(capture: &CaptureConfig, width: u32, height: u32, quality: u32, frame_rate: Option<u32>) -> Command {
+    let program = std::env::current_exe().unwrap_or_else(|_| "rust_stream".into());
+    let mut command = Command::new(program);
+    command.args([
+        "--direct-capture".to_string(),
+        "--device".to_string(),
+        device(capture),
+        "--width".to_string(),
+        width.to_string(),
+        "--height".to_string(),
+        height.to_string(),
+        "--quality".to_string(),
+        quality.to_string(),
+        "--frame-rate".to_string(),
+        frame_rate.unwrap_or(FRAME_RATE).to_string(),
+    ]);
+    command
+}
+
+// --direct-capture: the child side. Runs until stdout goes away, i.e. the parent
+// killed or replaced it; returns the exit code.
+pub fn run_child() -> i32 {
+    let number = |flag: &str, default: u32| config::flag_value(flag).and_then(|v| v.parse().ok()).unwrap_or(default);
+    let device = config::flag_value("--device").unwrap_or_else(|| DEFAULT_DEVICE.to_string());
+    let (width, height, quality) = (number("--width", 1280), number("--height", 720), number("--quality", 70));
+    let frame_rate = number("--frame-rate", FRAME_RATE);
+
+    let mut stdout = std::io::stdout().lock();
+    match capture_frames(&device, width, height, quality, frame_rate, |jpeg| stdout.write_all(jpeg).and_then(|_| stdout.flush()).is_ok()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Direct capture from {} failed: {}", device, e);
+            1
+        }
+    }
+}
+
+// One JPEG, for stills and the self-test
+pub async fn capture_still(capture: &CaptureConfig, width: u32, height: u32, quality: u32) -> Result<Vec<u8>, String> {
+    let device = device(capture);
+    tokio::task::spawn_blocking(move || {
+        let mut still = None;
+        capture_frames(&device, width, height, quality, FRAME_RATE, |jpeg| {
+            still = Some(jpeg.to_vec());
+            false
+        })?;
+        still.ok_or_else(|| "camera delivered no frame".to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Frames per second over `frames` frames, including opening the device
+pub async fn measure_fps(capture: &CaptureConfig, width: u32, height: u32, frames: u32) -> Result<f64, String> {
+    let device = device(capture);
+    tokio::task::spawn_blocking(move || {
+        let started = Instant::now();
+        let mut remaining = frames;
+        capture_frames(&device, width, height, 85, FRAME_RATE, |_| {
+            remaining -= 1;
+            remaining > 0
+        })?;
+        Ok(frames as f64 / started.elapsed().as_secs_f64())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Capture and encode until `on_frame` returns false
+#[cfg(target_os = "linux")]
+pub fn capture_frames(
+    device: &str,
+    width: u32,
+    height: u32,
+    quality: u32,
+    frame_rate: u32,
+    mut on_frame: impl FnMut(&[u8]) -> bool,
+) -> Result<(), String> {
+    let mut camera = rscam::Camera::new(device).map_err(|e| e.to_string())?;
+    // Compressed frames fit many more pixels through USB 2
+    let formats: Vec<[u8; 4]> = camera.formats().filter_map(|format| format.ok().map(|format| format.format)).collect();
+    let format = [b"MJPG", b"YUYV"]
+        .into_iter()
+        .find(|wanted| formats.contains(*wanted))
+        .ok_or_else(|| format!("camera offers neither MJPG nor YUYV (has {})", fourccs(&formats)))?;
+    let resolution = pick_resolution(&camera, format, (width, height))?;
+    let interval = pick_interval(&camera, format, resolution, frame_rate.max(1));
+    camera
+        .start(&rscam::Config { interval, resolution, format, nbuffers: 4, ..Default::default() })
+        .map_err(|e| e.to_string())?;
+    eprintln!(
+        "Capturing {} at {}x{} from {} without GStreamer, sending {}x{}",
+        String::from_utf8_lossy(format), resolution.0, resolution.1, device, width, height
+    );
+
+    let quality = quality.clamp(1, 100) as u8;
+    let mut failures = 0;
+    loop {
+        let frame = camera.capture().map_err(|e| e.to_string())?;
+        match encode(&frame, format, resolution, (width, height), quality) {
+            Ok(jpeg) => {
+                failures = 0;
+                if !on_frame(&jpeg) {
+                    return Ok(());
+                }
+            }
+            // Webcams send the odd broken MJPEG frame; only give up when they all are
+            Err(e) => {
+                failures += 1;
+                if failures >= 30 {
+                    return Err(format!("no usable frames: {}", e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn capture_frames(
+    _device: &str,
+    _width: u32,
+    _height: u32,
+    _quality: u32,
+    _frame_rate: u32,
+    _on_frame: impl FnMut(&[u8]) -> bool,
+) -> Result<(), String> {
+    Err("capturing without GStreamer needs V4L2, which is Linux-only".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn fourccs(formats: &[[u8; 4]]) -> String {
+    formats.iter().map(|format| String::from_utf8_lossy(format).into_owned()).collect::<Vec<_>>().join(", ")
+}
+
+// The smallest mode covering the requested size, or the largest there is; frames are
+// scaled down to the requested size afterwards
+#[cfg(target_os = "linux")]
+fn pick_resolution(camera: &rscam::Camera, format: &[u8], wanted: (u32, u32)) -> Result<(u32, u32), String> {
+    match camera.resolutions(format).map_err(|e| e.to_string())? {
+        rscam::ResolutionInfo::Discretes(mut modes) => {
+            modes.sort_by_key(|(width, height)| width * height);
+            modes
+                .iter()
+                .find(|(width, height)| *width >= wanted.0 && *height >= wanted.1)
+                .or(modes.last())
+                .copied()
+                .ok_or_else(|| "camera reports no resolutions".to_string())
+        }
+        rscam::ResolutionInfo::Stepwise { min, max, step } => {
+            let fit = |wanted: u32, min: u32, max: u32, step: u32| {
+                let step = step.max(1);
+                (min + wanted.saturating_sub(min).div_ceil(step) * step).min(max)
+            };
+            Ok((fit(wanted.0, min.0, max.0, step.0), fit(wanted.1, min.1, max.1, step.1)))
+        }
+    }
+}
+
+// The frame interval closest to `frame_rate`
+#[cfg(target_os = "linux")]
+fn pick_interval(camera: &rscam::Camera, format: &[u8], resolution: (u32, u32), frame_rate: u32) -> (u32, u32) {
+    match camera.intervals(format, resolution) {
+        Ok(rscam::IntervalInfo::Discretes(intervals)) => intervals
+            .into_iter()
+            .filter(|(numerator, _)| *numerator > 0)
+            .min_by_key(|(numerator, denominator)| (denominator / numerator).abs_diff(frame_rate))
+            .unwrap_or((1, frame_rate)),
+        _ => (1, frame_rate),
+    }
+}
+
+// A camera frame as a JPEG of the requested size
+#[cfg(target_os = "linux")]
+fn encode(frame: &[u8], format: &[u8], size: (u32, u32), wanted: (u32, u32), quality: u8) -> Result<Vec<u8>, String> {
+    // Decoded as RGB or expanded to interleaved YCbCr, three bytes per pixel either way
+    let (pixels, size, color) = if format == b"MJPG" {
+        // Decoding also fills in the Huffman tables many webcams leave out
+        let mut decoder = jpeg_decoder::Decoder::new(frame);
+        let pixels = decoder.decode().map_err(|e| e.to_string())?;
+        let info = decoder.info().ok_or("missing JPEG header")?;
+        if info.pixel_format != jpeg_decoder::PixelFormat::RGB24 {
+            return Err(format!("unsupported pixel format {:?}", info.pixel_format));
+        }
+        (pixels, (info.width as u32, info.height as u32), ColorType::Rgb)
+    } else {
+        (yuyv_to_ycbcr(frame, size)?, size, ColorType::Ycbcr)
+    };
+
+    let (width, height) = (wanted.0.min(size.0), wanted.1.min(size.1));
+    let pixels = if (width, height) == size { pixels } else { scale_nearest(&pixels, size, (width, height)) };
+    let mut output = Vec::with_capacity(pixels.len() / 8);
+    let mut encoder = Encoder::new(&mut output, quality);
+    encoder.set_sampling_factor(SamplingFactor::R_4_2_0);
+    encoder.encode(&pixels, width as u16, height as u16, color).map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+// Y0 U Y1 V -> Y0 U V, Y1 U V
+#[cfg(target_os = "linux")]
+fn yuyv_to_ycbcr(frame: &[u8], (width, height): (u32, u32)) -> Result<Vec<u8>, String> {
+    let expected = (width * height * 2) as usize;
+    if frame.len() < expected {
+        return Err(format!("short YUYV frame: {} of {} bytes", frame.len(), expected));
+    }
+    let mut pixels = Vec::with_capacity(expected / 2 * 3);
+    for quad in frame[..expected].chunks_exact(4) {
+        pixels.extend_from_slice(&[quad[0], quad[1], quad[3], quad[2], quad[1], quad[3]]);
+    }
+    Ok(pixels)
+}
+
+// Nearest-neighbour downscale of three-byte pixels; cheap enough to keep up on a Pi
+#[cfg(target_os = "linux")]
+fn scale_nearest(pixels: &[u8], from: (u32, u32), to: (u32, u32)) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity((to.0 * to.1 * 3) as usize);
+    for y in 0..to.1 {
+        let row = (y * from.1 / to.1 * from.0) as usize;
+        for x in 0..to.0 {
+            let offset = (row + (x * from.0 / to.0) as usize) * 3;
+            scaled.extend_from_slice(&pixels[offset..offset + 3]);
+        }
+    }
+    scaled
+}
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::interval;
+
+use crate::events::{CameraEvent, CameraEvents};
+use crate::export;
+use crate::recording::RecordingConfig;
+use crate::storage::{self, StorageConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiskSpaceConfig {
+    // Oldest segments are deleted while less than this is free, ahead of retention
+    pub prune_below_mb: u64,
+    // Recording stops below this, so the disk never fills up under the rest of the
+    // system; the live stream carries on
+    pub pause_below_mb: u64,
+    // And starts again once this much is free
+    pub resume_above_mb: u64,
+    pub check_interval_seconds: u64,
+}
+
+impl Default for DiskSpaceConfig {
+    fn default() -> Self {
+        Self {
+            prune_below_mb: 2048,
+            pause_below_mb: 512,
+            resume_above_mb: 1024,
+            check_interval_seconds: 30,
+        }
+    }
+}
+
+// Space left for unprivileged writes on the filesystem holding `path`
+#[cfg(unix)]
+fn free_bytes(path: &str) -> Result<u64, String> {
+    let c_path = CString::new(path).map_err(|e| e.to_string())?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_bytes(_path: &str) -> Result<u64, String> {
+    Err("free space can only be measured on Unix".to_string())
+}
+
+fn free_mb(path: &str) -> Result<u64, String> {
+    free_bytes(path).map(|free| free / (1024 * 1024))
+}
+
+// Delete the oldest finished segments until enough is free, only recent ones are left,
+// or deleting one made no room, as when something else is filling the disk. Only
+// segments on the local disk can make room: all of them with local storage, otherwise
+// the ones still in the spool waiting for the backend.
+fn prune_oldest(recording: &RecordingConfig, config: &DiskSpaceConfig) -> usize {
+    let backend = storage::open(recording);
+    let segments = match export::list_segments(recording, backend.as_ref()) {
+        Ok(segments) => segments,
+        Err(e) => {
+            eprintln!("Disk space: failed to list segments: {}", e);
+            return 0;
+        }
+    };
+    // The one being recorded may still be growing
+    let Some((_, finished)) = segments.split_last() else {
+        return 0;
+    };
+    let local = matches!(recording.storage, StorageConfig::Local);
+    let mut deleted = 0;
+    for segment in finished.iter().filter(|segment| local || backend.is_spooled(&segment.name)) {
+        let Ok(before) = free_bytes(&recording.directory) else {
+            break;
+        };
+        if before / (1024 * 1024) >= config.prune_below_mb {
+            break;
+        }
+        match backend.delete(&segment.name) {
+            Ok(()) => {
+                deleted += 1;
+                if free_bytes(&recording.directory).is_ok_and(|after| after <= before) {
+                    eprintln!("Disk space: deleting {} freed nothing, leaving the rest", segment.name);
+                    break;
+                }
+            }
+            Err(e) => {
+                eprintln!("Disk space: failed to delete {}: {}", segment.name, e);
+                break;
+            }
+        }
+    }
+    deleted
+}
+
+// Whether recording is held off for lack of space. The capture loop restarts the
+// pipeline without the recording branch when it changes.
+#[derive(Clone)]
+pub struct DiskGuard {
+    paused: Arc<AtomicBool>,
+    changed: Arc<AtomicBool>,
+}
+
+impl DiskGuard {
+    pub fn recording_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // True once after recording was paused or resumed
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+// Watch the free space where segments are kept, prune early, and pause recording
+// rather than let the disk fill up. A tmpfs staging directory only holds the segment
+// being written, so it's the recording directory that fills up and that pruning frees.
+pub fn spawn_disk_monitor(recording: RecordingConfig, events: &CameraEvents) -> DiskGuard {
+    let config = recording.disk_space.clone();
+    let guard = DiskGuard {
+        paused: Arc::new(AtomicBool::new(false)),
+        changed: Arc::new(AtomicBool::new(false)),
+    };
+    let monitor = guard.clone();
+    let events = events.clone();
+
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(config.check_interval_seconds.max(1)));
+        loop {
+            check.tick().await;
+            let mut free = match free_mb(&recording.directory) {
+                Ok(free) => free,
+                Err(e) => {
+                    eprintln!("Disk space monitor stopped, failed to check {}: {}", recording.directory, e);
+                    return;
+                }
+            };
+            if free < config.prune_below_mb {
+                let (pruned_recording, pruned_config) = (recording.clone(), config.clone());
+                let deleted = tokio::task::spawn_blocking(move || prune_oldest(&pruned_recording, &pruned_config)).await.unwrap_or(0);
+                if deleted > 0 {
+                    println!("Disk space: {}MB free, deleted the {} oldest segments", free, deleted);
+                    free = free_mb(&recording.directory).unwrap_or(free);
+                }
+            }
+
+            let paused = monitor.recording_paused();
+            if !paused && free < config.pause_below_mb {
+                eprintln!("Disk space: only {}MB free, pausing recording; the live stream continues", free);
+                monitor.paused.store(true, Ordering::Relaxed);
+                monitor.changed.store(true, Ordering::Relaxed);
+                events.publish(CameraEvent::DiskSpace { free_mb: free });
+            } else if paused && free >= config.resume_above_mb.max(config.pause_below_mb) {
+                println!("Disk space: {}MB free again, resuming recording", free);
+                monitor.paused.store(false, Ordering::Relaxed);
+                monitor.changed.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    guard
+}
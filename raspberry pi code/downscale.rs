@@ -0,0 +1,65 @@
+use fast_image_resize::{images::Image, FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use jpeg_encoder::ColorType;
+use serde::Deserialize;
+
+use crate::jpeg::{self, JpegConfig, Pixels};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownscaleConfig {
+    // Width of the frames sent while the uplink is congested; the height keeps the aspect
+    pub congested_width: u32,
+    pub quality: u32,
+}
+
+impl Default for DownscaleConfig {
+    fn default() -> Self {
+        Self { congested_width: 320, quality: 50 }
+    }
+}
+
+// Makes a smaller JPEG from a frame in software, so a low-resolution copy doesn't need
+// the capture pipeline to change. Keeps its resize buffers from one frame to the next.
+pub struct Downscaler {
+    resizer: Resizer,
+    options: ResizeOptions,
+}
+
+impl Downscaler {
+    pub fn new() -> Self {
+        Self { resizer: Resizer::new(), options: ResizeOptions::new().resize_alg(ResizeAlg::Convolution(FilterType::Bilinear)) }
+    }
+
+    // `jpeg` at most `max_width` wide, or None when it already is
+    pub fn downscale(&mut self, jpeg: &[u8], max_width: u32, quality: u32) -> Result<Option<Vec<u8>>, String> {
+        let mut decoder = jpeg_decoder::Decoder::new(jpeg);
+        decoder.read_info().map_err(|e| e.to_string())?;
+        let info = decoder.info().ok_or("missing JPEG header")?;
+        if info.width as u32 <= max_width {
+            return Ok(None);
+        }
+        let width = max_width.max(16);
+        let height = (info.height as u32 * width / info.width as u32).max(1);
+        let (pixel_type, color) = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => (PixelType::U8x3, ColorType::Rgb),
+            jpeg_decoder::PixelFormat::L8 => (PixelType::U8, ColorType::Luma),
+            other => return Err(format!("unsupported pixel format {:?}", other)),
+        };
+
+        // The decoder does most of the work by scaling 1/2, 1/4 or 1/8 in the IDCT; the
+        // resizer only covers what's left
+        let (decoded_width, decoded_height) = decoder.scale(width as u16, height as u16).map_err(|e| e.to_string())?;
+        let data = decoder.decode().map_err(|e| e.to_string())?;
+        let data = if (decoded_width as u32, decoded_height as u32) == (width, height) {
+            data
+        } else {
+            let source = Image::from_vec_u8(decoded_width as u32, decoded_height as u32, data, pixel_type).map_err(|e| e.to_string())?;
+            let mut target = Image::new(width, height, pixel_type);
+            self.resizer.resize(&source, &mut target, &self.options).map_err(|e| e.to_string())?;
+            target.into_vec()
+        };
+
+        let pixels = Pixels { data, width: width as u16, height: height as u16, color };
+        jpeg::encode(&pixels, &JpegConfig::default(), quality).map(Some)
+    }
+}
@@ -0,0 +1,161 @@
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{sleep, timeout, Instant},
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// {"command": "echo_test", "sizes": [64, 16384], "count": 10}: the camera sends `count`
+// probes of each size, {"echo": {"id", "seq", "size", "payload"}}, and the server sends
+// back {"echo_reply": {"id", "seq"}} for each as soon as it reads it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EchoTestCommand {
+    pub id: Option<String>,
+    // Payload bytes per probe
+    pub sizes: Vec<usize>,
+    pub count: u32,
+    pub interval_ms: u64,
+    // A probe without a reply in this time counts as lost
+    pub timeout_ms: u64,
+}
+
+impl Default for EchoTestCommand {
+    fn default() -> Self {
+        Self { id: None, sizes: vec![64, 1024, 16384, 65536], count: 5, interval_ms: 200, timeout_ms: 2000 }
+    }
+}
+
+// Anything bigger would just be a frame-sized stall of the uplink
+const MAX_PROBE_BYTES: usize = 1024 * 1024;
+
+// Probes waiting for their reply, by (test id, sequence number)
+type Waiting = HashMap<(String, u64), oneshot::Sender<Instant>>;
+
+#[derive(Clone, Default)]
+pub struct EchoTests {
+    pending: Arc<Mutex<Waiting>>,
+}
+
+impl EchoTests {
+    // Called by the reader for every message from the server
+    pub fn handle_reply(&self, json: &serde_json::Value) {
+        let Some(reply) = json.get("echo_reply") else {
+            return;
+        };
+        let (Some(id), Some(seq)) = (reply["id"].as_str(), reply["seq"].as_u64()) else {
+            return;
+        };
+        if let Some(waiting) = self.pending.lock().unwrap().remove(&(id.to_string(), seq)) {
+            let _ = waiting.send(Instant::now());
+        }
+    }
+
+    fn expect(&self, id: &str, seq: u64) -> oneshot::Receiver<Instant> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert((id.to_string(), seq), tx);
+        rx
+    }
+
+    fn forget(&self, id: &str, seq: u64) {
+        self.pending.lock().unwrap().remove(&(id.to_string(), seq));
+    }
+}
+
+fn summary(values: &[f64]) -> serde_json::Value {
+    if values.is_empty() {
+        return serde_json::Value::Null;
+    }
+    let round = |value: f64| (value * 10.0).round() / 10.0;
+    let average = values.iter().sum::<f64>() / values.len() as f64;
+    json!({
+        "min": round(values.iter().copied().fold(f64::MAX, f64::min)),
+        "avg": round(average),
+        "max": round(values.iter().copied().fold(0.0, f64::max)),
+    })
+}
+
+fn load_average() -> Option<f64> {
+    std::fs::read_to_string("/proc/loadavg").ok()?.split_whitespace().next()?.parse().ok()
+}
+
+// Run the probes and send the result. Round trips that grow with the probe size point
+// at the uplink; a camera that is slow to get anything out shows up as scheduler lag
+// and load even for the small ones.
+pub async fn run_echo_test(command: EchoTestCommand, tests: EchoTests, camera_id: String, outgoing: mpsc::Sender<Message>) {
+    let id = command.id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    println!("Echo test {}: {} probes of {:?} bytes", id, command.count, command.sizes);
+
+    // How late a short sleep wakes up while the test runs: the time this process needs
+    // to get around to anything, the uplink included
+    let lag = Arc::new(Mutex::new(Vec::new()));
+    let running = Arc::new(AtomicBool::new(true));
+    let (lag_samples, lag_running) = (lag.clone(), running.clone());
+    tokio::spawn(async move {
+        while lag_running.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            sleep(Duration::from_millis(10)).await;
+            let late = started.elapsed().as_secs_f64() * 1000.0 - 10.0;
+            lag_samples.lock().unwrap().push(late.max(0.0));
+        }
+    });
+
+    let mut seq = 0u64;
+    let mut results = Vec::new();
+    for &size in &command.sizes {
+        let size = size.min(MAX_PROBE_BYTES);
+        let mut round_trips = Vec::new();
+        for _ in 0..command.count {
+            seq += 1;
+            let payload: String = rand::thread_rng().sample_iter(&Alphanumeric).take(size).map(char::from).collect();
+            let probe = json!({ "echo": { "id": id, "seq": seq, "size": size, "payload": payload } }).to_string();
+            let reply = tests.expect(&id, seq);
+            let sent = Instant::now();
+            if outgoing.send(Message::Text(probe)).await.is_err() {
+                tests.forget(&id, seq);
+                running.store(false, Ordering::Relaxed);
+                return;
+            }
+            match timeout(Duration::from_millis(command.timeout_ms), reply).await {
+                Ok(Ok(received)) => round_trips.push(received.duration_since(sent).as_secs_f64() * 1000.0),
+                _ => tests.forget(&id, seq),
+            }
+            sleep(Duration::from_millis(command.interval_ms)).await;
+        }
+        let lost = command.count as usize - round_trips.len();
+        results.push(json!({
+            "size": size,
+            "sent": command.count,
+            "received": round_trips.len(),
+            "loss_percent": (lost * 100) as f64 / command.count.max(1) as f64,
+            "rtt_ms": summary(&round_trips),
+        }));
+    }
+    running.store(false, Ordering::Relaxed);
+
+    let lag = summary(&lag.lock().unwrap());
+    println!("Echo test {} finished, scheduler lag {}", id, lag);
+    let result = json!({
+        "echo_result": {
+            "id": id,
+            "camera_id": camera_id,
+            "sizes": results,
+            "camera": {
+                "load_average": load_average(),
+                "cores": std::thread::available_parallelism().map(|cores| cores.get()).ok(),
+                "scheduler_lag_ms": lag,
+            }
+        }
+    });
+    let _ = outgoing.send(Message::Text(result.to_string())).await;
+}
@@ -0,0 +1,170 @@
+use lettre::{
+    message::{header::ContentType, Attachment, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::clock::TimeConfig;
+use crate::events::CameraEvents;
+use crate::snapshot::LatestFrame;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    // 465 uses implicit TLS, anything else STARTTLS
+    pub smtp_port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+    // At most one email per this many minutes
+    pub min_interval_minutes: u64,
+    // No emails between these local hours, e.g. [8, 18] while someone is home
+    pub quiet_hours: Option<[u32; 2]>,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            smtp_host: String::new(),
+            smtp_port: 587,
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+            to: Vec::new(),
+            min_interval_minutes: 10,
+            quiet_hours: None,
+        }
+    }
+}
+
+impl EmailConfig {
+    fn is_quiet_hour(&self, hour: u32) -> bool {
+        match self.quiet_hours {
+            Some([start, end]) if start <= end => hour >= start && hour < end,
+            Some([start, end]) => hour >= start || hour < end,
+            None => false,
+        }
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+        let builder = if self.smtp_port == 465 {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&self.smtp_host)
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.smtp_host)
+        }
+        .map_err(|e| e.to_string())?;
+        Ok(builder
+            .port(self.smtp_port)
+            .credentials(Credentials::new(self.username.clone(), self.password.clone()))
+            .build())
+    }
+}
+
+// Emails a snapshot of the latest frame for every event, within the rate limit and
+// outside quiet hours
+pub fn spawn_email_notifier(
+    config: EmailConfig,
+    time: TimeConfig,
+    camera_id: String,
+    events: &CameraEvents,
+    latest_frame: LatestFrame
+) {
+    let mut events = events.subscribe();
+
+    tokio::spawn(async move {
+        let transport = match config.transport() {
+            Ok(transport) => transport,
+            Err(e) => {
+                eprintln!("Email notifications disabled, SMTP setup failed: {}", e);
+                return;
+            }
+        };
+        let min_interval = Duration::from_secs(config.min_interval_minutes * 60);
+        let mut last_sent: Option<Instant> = None;
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            if config.is_quiet_hour(time.local_hour()) {
+                continue;
+            }
+            if last_sent.is_some_and(|at| at.elapsed() < min_interval) {
+                println!("Skipping email for event, rate limited: {}", event.describe());
+                continue;
+            }
+
+            match send_email(&transport, &config, &time, &camera_id, &event.describe(), latest_frame.get(), event.clip()).await {
+                Ok(()) => {
+                    println!("Sent event email to {}", config.to.join(", "));
+                    last_sent = Some(Instant::now());
+                }
+                Err(e) => eprintln!("Failed to send event email: {}", e),
+            }
+        }
+    });
+}
+
+// A report with images attached, e.g. the daily digest; not rate limited or held back
+// in quiet hours like the event emails
+pub async fn send_report(config: &EmailConfig, subject: &str, text: &str, images: &[(String, Vec<u8>)]) -> Result<(), String> {
+    let transport = config.transport()?;
+    let mut body = MultiPart::mixed().singlepart(SinglePart::plain(text.to_string()));
+    for (index, (_, jpeg)) in images.iter().enumerate() {
+        let content_type = ContentType::parse("image/jpeg").map_err(|e| e.to_string())?;
+        body = body.singlepart(Attachment::new(format!("event-{}.jpg", index + 1)).body(jpeg.clone(), content_type));
+    }
+
+    let mut builder = Message::builder()
+        .from(config.from.parse().map_err(|e| format!("invalid from address: {}", e))?)
+        .subject(subject);
+    for to in &config.to {
+        builder = builder.to(to.parse().map_err(|e| format!("invalid to address {}: {}", to, e))?);
+    }
+    let message = builder.multipart(body).map_err(|e| e.to_string())?;
+
+    transport.send(message).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn send_email(
+    transport: &AsyncSmtpTransport<Tokio1Executor>,
+    config: &EmailConfig,
+    time: &TimeConfig,
+    camera_id: &str,
+    event: &str,
+    snapshot: Option<Vec<u8>>,
+    sound: Option<&[u8]>
+) -> Result<(), String> {
+    let local_time = time.now().format("%Y-%m-%d %H:%M:%S %Z");
+    let summary = format!("Camera {} at {}\n\n{}\n", camera_id, local_time, event);
+
+    let mut body = MultiPart::mixed().singlepart(SinglePart::plain(summary));
+    if let Some(jpeg) = snapshot {
+        let content_type = ContentType::parse("image/jpeg").map_err(|e| e.to_string())?;
+        body = body.singlepart(Attachment::new("snapshot.jpg".to_string()).body(jpeg, content_type));
+    }
+    if let Some(wav) = sound {
+        let content_type = ContentType::parse("audio/wav").map_err(|e| e.to_string())?;
+        body = body.singlepart(Attachment::new("sound.wav".to_string()).body(wav.to_vec(), content_type));
+    }
+
+    let mut builder = Message::builder()
+        .from(config.from.parse().map_err(|e| format!("invalid from address: {}", e))?)
+        .subject(format!("[{}] {}", camera_id, event));
+    for to in &config.to {
+        builder = builder.to(to.parse().map_err(|e| format!("invalid to address {}: {}", to, e))?);
+    }
+    let message = builder.multipart(body).map_err(|e| e.to_string())?;
+
+    transport.send(message).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use serde::Deserialize;
+use std::{
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{process::Command, sync::mpsc, time::sleep};
+
+use crate::encoder::Codec;
+use crate::frame::FrameOutputs;
+use crate::resolution::Resolution;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EncodeProfilesConfig {
+    // Where the capture pipeline shares the scaled-down frames with the low profile's encoder
+    pub socket_path: String,
+    // Quality of the low profile; its resolution is the bottom rung of the ladder
+    pub low_quality: u32,
+}
+
+impl Default for EncodeProfilesConfig {
+    fn default() -> Self {
+        Self { socket_path: "/tmp/camera-low-profile".to_string(), low_quality: 40 }
+    }
+}
+
+impl EncodeProfilesConfig {
+    // Branch taken off right before the main encoder, so the low profile carries the same
+    // overlays; it is scaled here and encoded in a pipeline of its own
+    pub fn tee_args(&self, low: Resolution) -> Vec<String> {
+        let frame_bytes = low.width * low.height * 3 / 2;
+        vec![
+            "tee".into(),
+            "name=profiles".into(),
+            "!".into(),
+            "queue".into(),
+            "leaky=downstream".into(),
+            "!".into(),
+            "videoscale".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            format!("video/x-raw,format=I420,width={},height={}", low.width, low.height),
+            "!".into(),
+            "shmsink".into(),
+            format!("socket-path={}", self.socket_path),
+            format!("shm-size={}", frame_bytes * 4),
+            "wait-for-connection=false".into(),
+            "sync=false".into(),
+            "profiles.".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]
+    }
+
+    fn start_low(&self, low: Resolution, codec: Codec) -> std::io::Result<tokio::process::Child> {
+        let mut args = vec![
+            "shmsrc".to_string(),
+            format!("socket-path={}", self.socket_path),
+            "is-live=true".into(),
+            "do-timestamp=true".into(),
+            "!".into(),
+            format!("video/x-raw,format=I420,width={},height={},framerate=0/1", low.width, low.height),
+            "!".into(),
+        ];
+        args.extend(codec.pipeline_args(self.low_quality));
+        args.extend(["!".to_string(), "fdsink".to_string()]);
+        Command::new("gst-launch-1.0")
+            .args(&args)
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    High,
+    Low,
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::High => "high",
+            Profile::Low => "low",
+        }
+    }
+}
+
+// Which profile's frames go on to the uplink
+#[derive(Clone)]
+pub struct ProfileSwitch {
+    low: Arc<AtomicBool>,
+}
+
+impl ProfileSwitch {
+    pub fn active(&self) -> Profile {
+        if self.low.load(Ordering::Relaxed) {
+            Profile::Low
+        } else {
+            Profile::High
+        }
+    }
+
+    // True when this changed the selection
+    pub fn select(&self, profile: Profile) -> bool {
+        self.low.swap(profile == Profile::Low, Ordering::Relaxed) != (profile == Profile::Low)
+    }
+
+    // For the outputs of the pipeline that encodes `profile`
+    pub fn gate(&self, profile: Profile) -> ProfileGate {
+        ProfileGate { switch: self.clone(), profile }
+    }
+}
+
+// Set on the outputs of each profile's pipeline. The main pipeline's frames go everywhere
+// but the uplink while the low profile is selected; the low profile's only ever go to the
+// uplink, and only while selected.
+#[derive(Clone)]
+pub struct ProfileGate {
+    switch: ProfileSwitch,
+    profile: Profile,
+}
+
+impl ProfileGate {
+    pub fn forwarded(&self) -> bool {
+        self.switch.active() == self.profile
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.profile == Profile::Low
+    }
+}
+
+// The low profile's encoder, kept running next to the main pipeline so stepping down
+// for congestion is a switch of which frames are forwarded rather than a restart
+#[derive(Clone)]
+pub struct WarmStandby {
+    switch: ProfileSwitch,
+    restarted: mpsc::Sender<Codec>,
+}
+
+impl WarmStandby {
+    pub fn switch(&self) -> ProfileSwitch {
+        self.switch.clone()
+    }
+
+    // The main pipeline was restarted, maybe with another codec; the low profile follows
+    pub fn restarted(&self, codec: Codec) {
+        let _ = self.restarted.try_send(codec);
+    }
+}
+
+// `outputs` are the main stream's; the low profile goes out under the same stream id
+pub fn spawn_warm_standby(config: EncodeProfilesConfig, low: Resolution, codec: Codec, stream_id: Arc<str>, outputs: FrameOutputs) -> WarmStandby {
+    let switch = ProfileSwitch { low: Arc::new(AtomicBool::new(false)) };
+    let (restarted, mut restarted_rx) = mpsc::channel::<Codec>(4);
+    // Encoded at its own quality, without the main encoder's re-encode or size tracking
+    let outputs = FrameOutputs {
+        jpeg: None,
+        experiment: None,
+        complexity: None,
+        pipeline: None,
+        profile: Some(switch.gate(Profile::Low)),
+        ..outputs
+    };
+    println!("Keeping a {}x{} low profile encoded alongside the main stream", low.width, low.height);
+
+    tokio::spawn(async move {
+        let mut codec = codec;
+        let mut child: Option<tokio::process::Child> = None;
+        loop {
+            // Started again whenever it isn't running, as the shared memory only appears
+            // once the main pipeline is up
+            // Only MJPEG switches profiles, so there is nothing to keep warm for the others
+            let running = child.as_mut().is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+            if !running && codec == Codec::Mjpeg {
+                match config.start_low(low, codec) {
+                    Ok(mut started) => {
+                        let stdout = started.stdout.take().expect("Failed to capture low profile stdout");
+                        crate::process_frames(stdout, codec, stream_id.clone(), outputs.clone()).await;
+                        child = Some(started);
+                    }
+                    Err(e) => eprintln!("Failed to start the low profile encoder: {}", e),
+                }
+            }
+
+            tokio::select! {
+                // The same encoder picks up again from the restarted main pipeline by
+                // itself; only a codec change needs a new one
+                Some(new_codec) = restarted_rx.recv() => {
+                    if new_codec != codec {
+                        codec = new_codec;
+                        if let Some(mut child) = child.take() {
+                            let _ = child.kill().await;
+                        }
+                    }
+                }
+                _ = sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    });
+
+    WarmStandby { switch, restarted }
+}
@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+// Output codecs the camera can produce. MJPEG works everywhere; VP9 and AV1 give
+// much better quality per bit but need a capable encoder and a server that can decode them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Mjpeg = 0,
+    Vp9 = 1,
+    Av1 = 2,
+}
+
+impl Codec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Mjpeg => "mjpeg",
+            Codec::Vp9 => "vp9",
+            Codec::Av1 => "av1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mjpeg" => Some(Codec::Mjpeg),
+            "vp9" => Some(Codec::Vp9),
+            "av1" => Some(Codec::Av1),
+            _ => None,
+        }
+    }
+
+    // For storing the selected codec in an AtomicU8
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Codec::Vp9,
+            2 => Codec::Av1,
+            _ => Codec::Mjpeg,
+        }
+    }
+
+    // GStreamer elements that encode raw video at the given quality (20-90).
+    // VP9/AV1 output is wrapped in IVF so frame boundaries survive the pipe.
+    pub fn pipeline_args(self, quality: u32) -> Vec<String> {
+        match self {
+            Codec::Mjpeg => vec!["jpegenc".into(), format!("quality={}", quality)],
+            Codec::Vp9 => vec![
+                "vp9enc".into(),
+                "deadline=1".into(),
+                "cpu-used=8".into(),
+                "end-usage=cq".into(),
+                // cq-level runs 0 (best) to 63 (worst)
+                format!("cq-level={}", 63 - quality.min(100) * 63 / 100),
+                "!".into(),
+                "avmux_ivf".into(),
+            ],
+            Codec::Av1 => vec![
+                "svtav1enc".into(),
+                "preset=12".into(),
+                format!("crf={}", 63 - quality.min(100) * 63 / 100),
+                "!".into(),
+                "av1parse".into(),
+                "!".into(),
+                "avmux_ivf".into(),
+            ],
+        }
+    }
+}
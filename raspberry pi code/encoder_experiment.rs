@@ -0,0 +1,210 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::jpeg::{self, JpegConfig, Pixels, Subsampling};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncoderVariant {
+    // Fixed quality for this arm; unset follows the uplink quality like normal frames
+    pub quality: Option<u32>,
+    #[serde(flatten)]
+    pub jpeg: JpegConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alternation {
+    // A and B on alternate frames: both arms see the same scenes
+    Frame,
+    // A for a minute, then B; viewers get a steady picture rather than one that
+    // flickers between two settings
+    Minute,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EncoderExperimentConfig {
+    pub a: EncoderVariant,
+    pub b: EncoderVariant,
+    pub alternate: Alternation,
+    // Every this many frames of an arm is decoded again and compared with the source;
+    // that decode costs about as much as the encode
+    pub measure_every: u32,
+    // How often the comparison is printed
+    pub report_minutes: u64,
+}
+
+impl Default for EncoderExperimentConfig {
+    fn default() -> Self {
+        Self {
+            a: EncoderVariant { quality: Some(60), jpeg: JpegConfig::default() },
+            b: EncoderVariant { quality: Some(60), jpeg: JpegConfig { progressive: true, ..JpegConfig::default() } },
+            alternate: Alternation::Frame,
+            measure_every: 10,
+            report_minutes: 5,
+        }
+    }
+}
+
+impl EncoderExperimentConfig {
+    // jpegenc in the pipeline only makes the source both arms are encoded from, in
+    // the finer of their chroma layouts
+    pub fn pipeline_args(&self) -> Vec<String> {
+        let finest = [self.a.jpeg.subsampling, self.b.jpeg.subsampling]
+            .into_iter()
+            .max_by_key(|subsampling| match subsampling {
+                Subsampling::Yuv420 => 0,
+                Subsampling::Yuv422 => 1,
+                Subsampling::Yuv444 => 2,
+            })
+            .unwrap_or(Subsampling::Yuv420);
+        jpeg::reencode_source_args(finest)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct ArmStats {
+    frames: u64,
+    bytes: u64,
+    encode_us: u64,
+    measured: u64,
+    psnr_sum: f64,
+}
+
+impl ArmStats {
+    fn to_json(self) -> serde_json::Value {
+        let frames = self.frames.max(1);
+        json!({
+            "frames": self.frames,
+            "average_bytes": self.bytes / frames,
+            "encode_ms": (self.encode_us as f64 / frames as f64 / 100.0).round() / 10.0,
+            // Against the near-lossless source frame, so an upper bound on the real PSNR
+            "psnr_db": (self.measured > 0).then(|| (self.psnr_sum / self.measured as f64 * 10.0).round() / 10.0),
+        })
+    }
+}
+
+// Peak signal-to-noise ratio between two decodes of the same frame
+fn psnr(source: &[u8], encoded: &[u8]) -> f64 {
+    let (sum, count) = source
+        .iter()
+        .zip(encoded)
+        .fold((0u64, 0u64), |(sum, count), (&a, &b)| {
+            let difference = a as i64 - b as i64;
+            (sum + (difference * difference) as u64, count + 1)
+        });
+    let mse = sum as f64 / count.max(1) as f64;
+    if mse == 0.0 {
+        return 99.0;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+// Encodes uplink frames with two JPEG settings in turn and keeps size and quality
+// figures for each, to pick a quality ladder from numbers rather than by eye
+#[derive(Clone)]
+pub struct EncoderExperiment {
+    config: EncoderExperimentConfig,
+    quality: Arc<AtomicU32>,
+    started: Instant,
+    next_frame: Arc<AtomicU64>,
+    arms: Arc<Mutex<([ArmStats; 2], Instant)>>,
+}
+
+impl EncoderExperiment {
+    pub fn new(config: EncoderExperimentConfig, quality: Arc<AtomicU32>) -> Self {
+        println!("Encoder experiment: {} against {}", describe(&config.a), describe(&config.b));
+        Self {
+            config,
+            quality,
+            started: Instant::now(),
+            next_frame: Arc::new(AtomicU64::new(0)),
+            arms: Arc::new(Mutex::new(([ArmStats::default(); 2], Instant::now()))),
+        }
+    }
+
+    fn arm(&self, frame: u64) -> usize {
+        match self.config.alternate {
+            Alternation::Frame => (frame % 2) as usize,
+            Alternation::Minute => ((self.started.elapsed().as_secs() / 60) % 2) as usize,
+        }
+    }
+
+    pub fn encode(&self, source: &[u8]) -> Result<Vec<u8>, String> {
+        let frame = self.next_frame.fetch_add(1, Ordering::Relaxed);
+        let arm = self.arm(frame);
+        let variant = if arm == 0 { &self.config.a } else { &self.config.b };
+        let pixels = jpeg::decode(source)?;
+        let started = Instant::now();
+        let quality = variant.quality.unwrap_or_else(|| self.quality.load(Ordering::Relaxed));
+        let encoded = jpeg::encode(&pixels, &variant.jpeg, quality)?;
+        let encode_us = started.elapsed().as_micros() as u64;
+
+        let mut arms = self.arms.lock().unwrap();
+        let stats = &mut arms.0[arm];
+        stats.frames += 1;
+        stats.bytes += encoded.len() as u64;
+        stats.encode_us += encode_us;
+        let measure = stats.frames % self.config.measure_every.max(1) as u64 == 1;
+        drop(arms);
+
+        if measure {
+            if let Ok(Pixels { data, .. }) = jpeg::decode(&encoded) {
+                let mut arms = self.arms.lock().unwrap();
+                arms.0[arm].measured += 1;
+                arms.0[arm].psnr_sum += psnr(&pixels.data, &data);
+            }
+        }
+        self.report_if_due();
+        Ok(encoded)
+    }
+
+    fn report_if_due(&self) {
+        let mut arms = self.arms.lock().unwrap();
+        if arms.1.elapsed() < Duration::from_secs(self.config.report_minutes.max(1) * 60) {
+            return;
+        }
+        arms.1 = Instant::now();
+        let [a, b] = arms.0;
+        drop(arms);
+        let size = |arm: ArmStats| arm.bytes / arm.frames.max(1);
+        let quality = |arm: ArmStats| match arm.measured {
+            0 => "-".to_string(),
+            measured => format!("{:.1}dB", arm.psnr_sum / measured as f64),
+        };
+        println!(
+            "Encoder experiment: A {} bytes/frame at {}, B {} bytes/frame at {} ({} and {} frames)",
+            size(a), quality(a), size(b), quality(b), a.frames, b.frames
+        );
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let [a, b] = self.arms.lock().unwrap().0;
+        json!({
+            "a": { "settings": describe(&self.config.a), "results": a.to_json() },
+            "b": { "settings": describe(&self.config.b), "results": b.to_json() },
+        })
+    }
+}
+
+fn describe(variant: &EncoderVariant) -> String {
+    let mut settings = format!(
+        "quality {}, {}",
+        variant.quality.map_or("adaptive".to_string(), |quality| quality.to_string()),
+        variant.jpeg.subsampling.name()
+    );
+    if variant.jpeg.progressive {
+        settings.push_str(", progressive");
+    }
+    if let Some(interval) = variant.jpeg.restart_interval {
+        settings.push_str(&format!(", restart every {}", interval));
+    }
+    settings
+}
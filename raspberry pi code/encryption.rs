@@ -0,0 +1,414 @@
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use argon2::Argon2;
+use rand::{rngs::OsRng, RngCore};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+// File layout: MAGIC, salt, then chunks of nonce | ciphertext length (u32 LE) | ciphertext.
+// Each chunk is authenticated with its index and whether it is the last one, so chunks
+// can't be reordered and a truncated file is detected. Files are sealed and opened a
+// chunk at a time, so a segment never has to fit in memory.
+const MAGIC: &[u8; 8] = b"CAMENC1\0";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const CHUNK_SIZE: usize = 1024 * 1024;
+// AES-GCM tag
+const TAG_LEN: usize = 16;
+const CHUNK_HEADER_LEN: usize = NONCE_LEN + 4;
+pub const EXTENSION: &str = "enc";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // Key derived from this passphrase with Argon2id. Only for --decrypt on another
+    // machine: on the camera it would sit on the same card as the recordings it protects.
+    pub passphrase: Option<String>,
+    // File holding the passphrase, somewhere a stolen card doesn't take with it: removable
+    // media, or a systemd credential under $CREDENTIALS_DIRECTORY
+    pub passphrase_file: Option<String>,
+    // Program and arguments that print the 32-byte key as hex, e.g. tpm2_unseal for a key
+    // sealed in the TPM, a secure element tool, or a fetch from the server; takes
+    // precedence over a passphrase
+    pub key_command: Option<Vec<String>>,
+}
+
+fn parse_hex_key(text: &str) -> Result<[u8; 32], String> {
+    let text = text.trim();
+    if text.len() != 64 {
+        return Err(format!("expected 64 hex digits, got {}", text.len()));
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|e| e.to_string())?;
+    }
+    Ok(key)
+}
+
+// Resolves keys for the salts found in file headers; passphrase derivation is slow on
+// a Pi, so each salt is only derived once
+pub struct RecordingKeys {
+    config: EncryptionConfig,
+    keys: HashMap<[u8; SALT_LEN], [u8; 32]>,
+}
+
+impl RecordingKeys {
+    pub fn new(config: EncryptionConfig) -> Result<Self, String> {
+        if config.passphrase.is_none() && config.passphrase_file.is_none() && config.key_command.is_none() {
+            return Err("encryption needs a key_command, a passphrase_file or a passphrase".to_string());
+        }
+        Ok(Self { config, keys: HashMap::new() })
+    }
+
+    fn passphrase(&self) -> Result<String, String> {
+        match (&self.config.passphrase_file, &self.config.passphrase) {
+            (Some(path), _) => std::fs::read_to_string(path)
+                .map(|text| text.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| format!("{}: {}", path, e)),
+            (None, Some(passphrase)) => Ok(passphrase.clone()),
+            (None, None) => unreachable!("checked in new"),
+        }
+    }
+
+    fn key(&mut self, salt: &[u8; SALT_LEN]) -> Result<[u8; 32], String> {
+        if let Some(key) = self.keys.get(salt) {
+            return Ok(*key);
+        }
+        let key = match &self.config.key_command {
+            Some(command) => {
+                let (program, args) = command.split_first().ok_or("empty key_command")?;
+                let output = std::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .map_err(|e| format!("failed to run key command: {}", e))?;
+                if !output.status.success() {
+                    return Err(format!("key command exited with {}", output.status));
+                }
+                parse_hex_key(&String::from_utf8_lossy(&output.stdout))?
+            }
+            None => {
+                let mut key = [0u8; 32];
+                Argon2::default()
+                    .hash_password_into(self.passphrase()?.as_bytes(), salt, &mut key)
+                    .map_err(|e| e.to_string())?;
+                key
+            }
+        };
+        self.keys.insert(*salt, key);
+        Ok(key)
+    }
+
+    fn cipher(&mut self, salt: &[u8; SALT_LEN]) -> Result<Aes256Gcm, String> {
+        let key = self.key(salt)?;
+        Aes256Gcm::new_from_slice(&key).map_err(|e| e.to_string())
+    }
+}
+
+fn chunk_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_le_bytes());
+    aad[8] = last as u8;
+    aad
+}
+
+// Up to a chunk from `reader`; shorter only at the end
+fn read_chunk(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    reader.take(CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+// Seal everything `plain` yields into `out`. A chunk is read ahead to know which one is last.
+pub fn encrypt(mut plain: impl Read, mut out: impl Write, salt: &[u8; SALT_LEN], keys: &mut RecordingKeys) -> Result<(), String> {
+    let cipher = keys.cipher(salt)?;
+    out.write_all(MAGIC).and_then(|_| out.write_all(salt)).map_err(|e| e.to_string())?;
+
+    // An empty segment still gets one (empty) final chunk
+    let mut chunk = read_chunk(&mut plain).map_err(|e| e.to_string())?;
+    for index in 0.. {
+        let next = if chunk.len() == CHUNK_SIZE { read_chunk(&mut plain).map_err(|e| e.to_string())? } else { Vec::new() };
+        let last = next.is_empty();
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &chunk, aad: &chunk_aad(index, last) })
+            .map_err(|e| e.to_string())?;
+        out.write_all(&nonce)
+            .and_then(|_| out.write_all(&(sealed.len() as u32).to_le_bytes()))
+            .and_then(|_| out.write_all(&sealed))
+            .map_err(|e| e.to_string())?;
+        if last {
+            break;
+        }
+        chunk = next;
+    }
+    out.flush().map_err(|e| e.to_string())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The next chunk's nonce and length, or None at the end of the file
+fn read_chunk_header(reader: &mut impl Read) -> io::Result<Option<[u8; CHUNK_HEADER_LEN]>> {
+    let mut header = [0u8; CHUNK_HEADER_LEN];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(invalid("truncated recording")),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(header))
+}
+
+// Plaintext of an encrypted recording, decrypted a chunk at a time as it is read. The
+// header of the following chunk is read ahead, as its absence is what marks the last one.
+pub struct DecryptReader<R> {
+    inner: R,
+    cipher: Aes256Gcm,
+    index: u64,
+    next_header: Option<[u8; CHUNK_HEADER_LEN]>,
+    plain: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(mut inner: R, keys: &mut RecordingKeys) -> Result<Self, String> {
+        let mut header = [0u8; MAGIC.len() + SALT_LEN];
+        inner.read_exact(&mut header).map_err(|_| "not an encrypted recording".to_string())?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err("not an encrypted recording".to_string());
+        }
+        let salt: [u8; SALT_LEN] = header[MAGIC.len()..].try_into().unwrap();
+        let cipher = keys.cipher(&salt)?;
+        let next_header = read_chunk_header(&mut inner).map_err(|e| e.to_string())?;
+        if next_header.is_none() {
+            return Err("truncated recording".to_string());
+        }
+        Ok(Self { inner, cipher, index: 0, next_header, plain: Vec::new(), position: 0 })
+    }
+
+    // False once the last chunk has been read
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let Some(header) = self.next_header.take() else {
+            return Ok(false);
+        };
+        let len = u32::from_le_bytes(header[NONCE_LEN..].try_into().unwrap()) as usize;
+        if len > CHUNK_SIZE + TAG_LEN {
+            return Err(invalid("corrupted recording"));
+        }
+        let mut sealed = vec![0u8; len];
+        self.inner.read_exact(&mut sealed).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated recording"),
+            _ => e,
+        })?;
+        self.next_header = read_chunk_header(&mut self.inner)?;
+        let aad = chunk_aad(self.index, self.next_header.is_none());
+        self.plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(&header[..NONCE_LEN]), Payload { msg: &sealed, aad: &aad })
+            .map_err(|_| invalid("wrong key or corrupted recording"))?;
+        self.position = 0;
+        self.index += 1;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plain.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let read = buf.len().min(self.plain.len() - self.position);
+        buf[..read].copy_from_slice(&self.plain[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+// Plaintext size of a segment stored as `name` and `stored` bytes long, worked out from
+// the chunk layout without reading it; None if that can't be a whole encrypted file
+pub fn plain_len(name: &str, stored: u64) -> Option<u64> {
+    if plain_name(Path::new(name)).is_none() {
+        return Some(stored);
+    }
+    let body = stored.checked_sub((MAGIC.len() + SALT_LEN) as u64)?;
+    let overhead = (CHUNK_HEADER_LEN + TAG_LEN) as u64;
+    let chunks = body.div_ceil(CHUNK_SIZE as u64 + overhead).max(1);
+    body.checked_sub(chunks * overhead)
+}
+
+// "x.ts.enc" -> "x.ts"
+pub fn plain_name(path: &Path) -> Option<PathBuf> {
+    (path.extension()? == EXTENSION).then(|| path.with_extension(""))
+}
+
+// Read a segment, decrypting it if it is encrypted
+pub fn read_segment(path: &Path, keys: &mut Option<RecordingKeys>) -> Result<Box<dyn Read + Send>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    open_segment(&path.display().to_string(), Box::new(file), keys)
+}
+
+// Segment contents as stored under `name`, decrypted as they are read if the name says
+// it is encrypted
+pub fn open_segment(name: &str, data: Box<dyn Read + Send>, keys: &mut Option<RecordingKeys>) -> Result<Box<dyn Read + Send>, String> {
+    if plain_name(Path::new(name)).is_none() {
+        return Ok(data);
+    }
+    let keys = keys.as_mut().ok_or_else(|| format!("{} is encrypted but no key is configured", name))?;
+    let reader = DecryptReader::new(data, keys).map_err(|e| format!("{}: {}", name, e))?;
+    Ok(Box::new(reader))
+}
+
+// Encrypts finished segments for the segment finisher. One salt per run; the header
+// carries it so any run's files can be decrypted.
+pub struct SegmentEncryptor {
+    keys: RecordingKeys,
+    salt: [u8; SALT_LEN],
+}
+
+impl SegmentEncryptor {
+    pub fn new(config: EncryptionConfig) -> Result<Self, String> {
+        if config.key_command.is_none() && config.passphrase_file.is_none() {
+            return Err("a passphrase in the config file is on the same card as the recordings; use key_command or passphrase_file".to_string());
+        }
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut keys = RecordingKeys::new(config)?;
+        // Now rather than at the first segment, so a missing key stops the camera at startup
+        keys.key(&salt)?;
+        Ok(Self { keys, salt })
+    }
+
+    pub fn seal(&mut self, plain: impl Read, out: impl Write) -> Result<(), String> {
+        encrypt(plain, out, &self.salt, &mut self.keys)
+    }
+
+    // "x.ts" -> "x.ts.enc"
+    pub fn file_name(&self, plain: &str) -> String {
+        format!("{}.{}", plain, EXTENSION)
+    }
+}
+
+// `--decrypt <file or directory> [--output <directory>]`: write plaintext copies of
+// encrypted segments, by default next to the originals
+pub fn run_decrypt(config: Option<EncryptionConfig>, input: &str, output: Option<&str>) -> Result<usize, String> {
+    let mut keys = Some(RecordingKeys::new(config.ok_or("recording.encryption is not configured")?)?);
+    let input = Path::new(input);
+    let files: Vec<PathBuf> = if input.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(input)
+            .map_err(|e| e.to_string())?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| plain_name(path).is_some())
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![input.to_path_buf()]
+    };
+
+    for path in &files {
+        let mut plain = read_segment(path, &mut keys)?;
+        let name = plain_name(path).ok_or_else(|| format!("{} is not an encrypted segment", path.display()))?;
+        let target = match output {
+            Some(directory) => Path::new(directory).join(name.file_name().unwrap_or_default()),
+            None => name,
+        };
+        let copied = File::create(&target).and_then(|mut file| io::copy(&mut plain, &mut file));
+        if let Err(e) = copied {
+            // Nothing half-decrypted is left behind to pass for the whole segment
+            let _ = std::fs::remove_file(&target);
+            return Err(format!("{}: {}", target.display(), e));
+        }
+        println!("Decrypted {} -> {}", path.display(), target.display());
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; SALT_LEN] = [7; SALT_LEN];
+
+    // Keys as if already derived for SALT, so the tests don't wait on Argon2
+    fn keys(key: u8) -> RecordingKeys {
+        RecordingKeys { config: EncryptionConfig::default(), keys: HashMap::from([(SALT, [key; 32])]) }
+    }
+
+    fn seal(plain: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        encrypt(plain, &mut sealed, &SALT, &mut keys(1)).unwrap();
+        sealed
+    }
+
+    fn open(sealed: &[u8], key: u8) -> Result<Vec<u8>, String> {
+        let mut reader = DecryptReader::new(sealed, &mut keys(key))?;
+        let mut plain = Vec::new();
+        reader.read_to_end(&mut plain).map_err(|e| e.to_string())?;
+        Ok(plain)
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for len in [0, 1000, CHUNK_SIZE, 2 * CHUNK_SIZE + 5] {
+            let plain = sample(len);
+            let sealed = seal(&plain);
+            assert_eq!(open(&sealed, 1).unwrap(), plain, "{} bytes", len);
+            assert_eq!(plain_len("x.ts.enc", sealed.len() as u64), Some(len as u64), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn truncated_rejected() {
+        let sealed = seal(&sample(2 * CHUNK_SIZE + 5));
+        let header = MAGIC.len() + SALT_LEN;
+        let first_chunk = CHUNK_HEADER_LEN + CHUNK_SIZE + TAG_LEN;
+        // Cut in the middle of a chunk
+        assert!(open(&sealed[..sealed.len() - 3], 1).is_err());
+        // Cut cleanly after a whole chunk, which then wasn't sealed as the last one
+        assert!(open(&sealed[..header + first_chunk], 1).is_err());
+        // Nothing left but the file header
+        assert!(open(&sealed[..header], 1).is_err());
+    }
+
+    #[test]
+    fn tampered_rejected() {
+        let mut sealed = seal(&sample(2 * CHUNK_SIZE + 5));
+        let header = MAGIC.len() + SALT_LEN;
+        let chunk = CHUNK_HEADER_LEN + CHUNK_SIZE + TAG_LEN;
+
+        let mut flipped = sealed.clone();
+        flipped[header + CHUNK_HEADER_LEN + 100] ^= 1;
+        assert!(open(&flipped, 1).is_err());
+
+        // The first two chunks swapped; each is intact but in the wrong place
+        let (first, rest) = sealed[header..].split_at_mut(chunk);
+        first.swap_with_slice(&mut rest[..chunk]);
+        assert!(open(&sealed, 1).is_err());
+    }
+
+    #[test]
+    fn wrong_key_rejected() {
+        let sealed = seal(&sample(1000));
+        assert!(open(&sealed, 2).is_err());
+        assert!(open(b"plain MPEG-TS bytes, not sealed at all", 1).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// How frames are wrapped on the wire. JSON with base64 works with any server; CBOR
+// carries the JPEG bytes as-is in a binary message and saves about a third of the bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Envelope {
+    Json = 0,
+    Cbor = 1,
+}
+
+impl Envelope {
+    pub const SUPPORTED: [Envelope; 2] = [Envelope::Json, Envelope::Cbor];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Envelope::Json => "json",
+            Envelope::Cbor => "cbor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Envelope::Json),
+            "cbor" => Some(Envelope::Cbor),
+            _ => None,
+        }
+    }
+
+    // For storing the negotiated envelope in an AtomicU8
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Envelope::Cbor,
+            _ => Envelope::Json,
+        }
+    }
+}
+
+// Headroom for the envelope fields around the frame data in a chunk
+const CHUNK_OVERHEAD_BYTES: usize = 512;
+
+// Where a piece of a split frame belongs. The server collects `count` chunks with the
+// same frame_id and joins their data in index order.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChunkInfo {
+    pub frame_id: u64,
+    pub index: u32,
+    pub count: u32,
+    pub total_bytes: usize,
+}
+
+// Field names of frame messages. The relay server takes the standard names; the others
+// are for third-party ingestion endpoints that were written against other cameras.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldNaming {
+    // camera_id, stream_id, data, timestamp
+    #[default]
+    Standard,
+    // camera, stream, image, ts
+    Short,
+    // cameraId, streamId, image, timestamp
+    CamelCase,
+}
+
+// Base64 text in the JSON envelope, the bytes as they are in CBOR
+#[derive(Serialize)]
+#[serde(untagged)]
+enum FrameData<'a> {
+    Base64(Cow<'a, str>),
+    Bytes(#[serde(with = "serde_bytes")] &'a [u8]),
+}
+
+#[derive(Serialize)]
+struct StandardFrame<'a> {
+    camera_id: &'a str,
+    stream_id: &'a str,
+    data: FrameData<'a>,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<ChunkInfo>,
+}
+
+#[derive(Serialize)]
+struct ShortFrame<'a> {
+    camera: &'a str,
+    stream: &'a str,
+    image: FrameData<'a>,
+    ts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<ChunkInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CamelCaseFrame<'a> {
+    camera_id: &'a str,
+    stream_id: &'a str,
+    image: FrameData<'a>,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<ChunkInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum NamedFrame<'a> {
+    Standard(StandardFrame<'a>),
+    Short(ShortFrame<'a>),
+    CamelCase(CamelCaseFrame<'a>),
+}
+
+impl FieldNaming {
+    fn frame<'a>(
+        self,
+        camera_id: &'a str,
+        stream_id: &'a str,
+        data: FrameData<'a>,
+        timestamp: u64,
+        stats: Option<&'a serde_json::Value>,
+        chunk: Option<ChunkInfo>
+    ) -> NamedFrame<'a> {
+        match self {
+            FieldNaming::Standard => NamedFrame::Standard(StandardFrame { camera_id, stream_id, data, timestamp, stats, chunk }),
+            FieldNaming::Short => NamedFrame::Short(ShortFrame { camera: camera_id, stream: stream_id, image: data, ts: timestamp, stats, chunk }),
+            FieldNaming::CamelCase => NamedFrame::CamelCase(CamelCaseFrame { camera_id, stream_id, image: data, timestamp, stats, chunk }),
+        }
+    }
+}
+
+// What goes into a frame message, whatever the envelope
+#[derive(Clone, Copy)]
+pub struct FrameFields<'a> {
+    pub camera_id: &'a str,
+    pub stream_id: &'a str,
+    pub data: &'a [u8],
+    // The data already encoded, if a pipeline stage did that ahead of time
+    pub base64: Option<&'a str>,
+    pub timestamp: u64,
+    pub stats: &'a serde_json::Value,
+}
+
+// Wrap a frame in the negotiated envelope
+pub fn encode_frame(envelope: Envelope, naming: FieldNaming, frame: &FrameFields) -> Message {
+    encode(envelope, naming, *frame, Some(frame.stats), None)
+}
+
+// Like encode_frame, but frames that come out larger than max_message_bytes are split
+// into numbered chunks. Only the first chunk carries the stats.
+pub fn encode_frame_chunked(envelope: Envelope, naming: FieldNaming, frame: &FrameFields, frame_id: u64, max_message_bytes: usize) -> Vec<Message> {
+    let (data, stats) = (frame.data, frame.stats);
+    let whole = encode_frame(envelope, naming, frame);
+    if whole.len() <= max_message_bytes {
+        return vec![whole];
+    }
+
+    let stats_bytes = stats.to_string().len();
+    let budget = max_message_bytes.saturating_sub(CHUNK_OVERHEAD_BYTES + stats_bytes).max(1024);
+    // Base64 turns 3 bytes into 4
+    let chunk_bytes = match envelope {
+        Envelope::Json => budget / 4 * 3,
+        Envelope::Cbor => budget,
+    };
+    let count = data.len().div_ceil(chunk_bytes) as u32;
+    data.chunks(chunk_bytes)
+        .enumerate()
+        .map(|(index, piece)| {
+            let chunk = ChunkInfo { frame_id, index: index as u32, count, total_bytes: data.len() };
+            let stats = if index == 0 { Some(stats) } else { None };
+            encode(envelope, naming, FrameFields { data: piece, base64: None, ..*frame }, stats, Some(chunk))
+        })
+        .collect()
+}
+
+fn encode(envelope: Envelope, naming: FieldNaming, frame: FrameFields, stats: Option<&serde_json::Value>, chunk: Option<ChunkInfo>) -> Message {
+    let FrameFields { camera_id, stream_id, data, base64, timestamp, .. } = frame;
+    match envelope {
+        Envelope::Json => {
+            let data = FrameData::Base64(base64.map_or_else(|| Cow::Owned(BASE64_STANDARD.encode(data)), Cow::Borrowed));
+            let frame = naming.frame(camera_id, stream_id, data, timestamp, stats, chunk);
+            Message::Text(serde_json::to_string(&frame).expect("Frame fields always serialize"))
+        }
+        Envelope::Cbor => {
+            let frame = naming.frame(camera_id, stream_id, FrameData::Bytes(data), timestamp, stats, chunk);
+            let mut bytes = Vec::with_capacity(data.len() + 256);
+            ciborium::ser::into_writer(&frame, &mut bytes).expect("Serializing to a Vec can't fail");
+            Message::Binary(bytes)
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, broadcast::error::RecvError, mpsc, Notify},
+    time::sleep,
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::events::CameraEvents;
+use crate::maintenance::Maintenance;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EventQueueConfig {
+    // Events not yet acknowledged by the server, one JSON object per line
+    pub path: String,
+    // Oldest events are given up beyond this many, so a server that never acknowledges
+    // can't fill the disk
+    pub max_events: usize,
+    // Unacknowledged events are sent again after this long on the same connection
+    pub ack_timeout_seconds: u64,
+}
+
+impl Default for EventQueueConfig {
+    fn default() -> Self {
+        Self {
+            path: "event-queue.jsonl".to_string(),
+            max_events: 10_000,
+            ack_timeout_seconds: 30,
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+struct QueueState {
+    // Numbers are never reused, across restarts too, so the server can drop duplicates
+    next_seq: u64,
+    pending: VecDeque<serde_json::Value>,
+    // Lines in the file, given-up events included until the next rewrite
+    lines_on_disk: usize,
+}
+
+// Events wait here, on disk, until the server acknowledges them. Every connection
+// replays what is still pending, so an event is delivered at least once however long
+// the outage or however often the camera restarts.
+#[derive(Clone)]
+pub struct EventQueue {
+    config: EventQueueConfig,
+    state: Arc<Mutex<QueueState>>,
+    added: Arc<Notify>,
+    // Every event as queued, seq included, for the timeline
+    queued: broadcast::Sender<serde_json::Value>,
+}
+
+fn seq(record: &serde_json::Value) -> u64 {
+    record["seq"].as_u64().unwrap_or(0)
+}
+
+impl EventQueue {
+    // The file starts with {"next_seq": n}, followed by the pending events
+    fn load(config: EventQueueConfig) -> Self {
+        let mut state = QueueState { next_seq: 1, pending: VecDeque::new(), lines_on_disk: 0 };
+        if let Ok(text) = std::fs::read_to_string(&config.path) {
+            for line in text.lines() {
+                state.lines_on_disk += 1;
+                let Ok(record) = serde_json::from_str::<serde_json::Value>(line) else { continue };
+                if let Some(next) = record["next_seq"].as_u64() {
+                    state.next_seq = state.next_seq.max(next);
+                } else if seq(&record) > 0 {
+                    state.next_seq = state.next_seq.max(seq(&record) + 1);
+                    state.pending.push_back(record);
+                }
+            }
+        }
+        // The file may still hold events given up on before the last rewrite
+        let excess = state.pending.len().saturating_sub(config.max_events);
+        state.pending.drain(..excess);
+        if !state.pending.is_empty() {
+            println!("Event queue: {} events from before still to be delivered", state.pending.len());
+        }
+        let queue = Self { config, state: Arc::new(Mutex::new(state)), added: Arc::new(Notify::new()), queued: broadcast::channel(64).0 };
+        if excess > 0 {
+            println!("Event queue: gave up on {} events over max_events", excess);
+            queue.rewrite(&mut queue.state.lock().unwrap());
+        }
+        queue
+    }
+
+    fn push(&self, mut record: serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        record["seq"] = json!(state.next_seq);
+        state.next_seq += 1;
+        let appended = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .and_then(|mut file| writeln!(file, "{}", record));
+        match appended {
+            Ok(()) => state.lines_on_disk += 1,
+            Err(e) => eprintln!("Event queue: failed to write {}, the event is only kept in memory: {}", self.config.path, e),
+        }
+        state.pending.push_back(record.clone());
+        if state.pending.len() > self.config.max_events {
+            let dropped = state.pending.pop_front().map_or(0, |record| seq(&record));
+            eprintln!("Event queue full, giving up on event {}", dropped);
+            // Given-up events stay in the file until a rewrite; once they make up half of
+            // it, rewrite rather than let it grow with every event
+            if state.lines_on_disk > 2 * self.config.max_events {
+                self.rewrite(&mut state);
+            }
+        }
+        drop(state);
+        self.added.notify_one();
+        let _ = self.queued.send(record);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<serde_json::Value> {
+        self.queued.subscribe()
+    }
+
+    // The server has everything up to and including `acked`
+    pub fn ack(&self, acked: u64) {
+        let mut state = self.state.lock().unwrap();
+        let before = state.pending.len();
+        while state.pending.front().is_some_and(|record| seq(record) <= acked) {
+            state.pending.pop_front();
+        }
+        if state.pending.len() != before {
+            self.rewrite(&mut state);
+        }
+    }
+
+    // Leaves only what is still pending, written to the side first so a crash halfway
+    // loses nothing
+    fn rewrite(&self, state: &mut QueueState) {
+        let mut text = format!("{}\n", json!({ "next_seq": state.next_seq }));
+        for record in &state.pending {
+            text.push_str(&format!("{}\n", record));
+        }
+        let temporary = format!("{}.tmp", self.config.path);
+        match std::fs::write(&temporary, text).and_then(|_| std::fs::rename(&temporary, &self.config.path)) {
+            Ok(()) => state.lines_on_disk = state.pending.len() + 1,
+            Err(e) => eprintln!("Event queue: failed to rewrite {}: {}", self.config.path, e),
+        }
+    }
+
+    fn after(&self, sent: u64) -> Vec<serde_json::Value> {
+        self.state.lock().unwrap().pending.iter().filter(|record| seq(record) > sent).cloned().collect()
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.state.lock().unwrap().pending.front().map(seq)
+    }
+}
+
+// Queue every camera event for the server. Events during maintenance are queued too,
+// marked, like they are in the timeline.
+pub fn spawn_event_queue(config: EventQueueConfig, events: &CameraEvents, maintenance: Maintenance) -> EventQueue {
+    let queue = EventQueue::load(config);
+    let mut event_rx = events.subscribe();
+    let writer = queue.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match event_rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("Event queue fell behind, {} events were not queued", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let mut record = json!({
+                "kind": event.kind(),
+                "description": event.describe(),
+                "timestamp": event.timestamp_ms().unwrap_or_else(now_ms),
+                "has_clip": event.clip().is_some(),
+            });
+            if maintenance.covers(event.kind()) {
+                record["maintenance"] = json!(true);
+            }
+            writer.push(record);
+        }
+    });
+    queue
+}
+
+// Send the pending events over one connection as {"event": {...}}, oldest first, then
+// new ones as they come. The server answers {"event_ack": seq} for everything up to seq.
+pub async fn deliver(queue: EventQueue, camera_id: String, outgoing: mpsc::Sender<Message>) {
+    let ack_timeout = Duration::from_secs(queue.config.ack_timeout_seconds.max(1));
+    let mut sent = 0;
+    let mut unacked_since_last_check = None;
+    loop {
+        for mut record in queue.after(sent) {
+            let record_seq = seq(&record);
+            record["camera_id"] = json!(camera_id);
+            if outgoing.send(Message::Text(json!({ "event": record }).to_string())).await.is_err() {
+                return;
+            }
+            sent = record_seq;
+        }
+        tokio::select! {
+            _ = queue.added.notified() => {}
+            _ = sleep(ack_timeout) => {
+                // Nothing acknowledged for a whole timeout: go again from the oldest
+                let oldest = queue.oldest().filter(|oldest| *oldest <= sent);
+                if let Some(oldest) = oldest.filter(|_| oldest == unacked_since_last_check) {
+                    println!("Event queue: no ack for event {} yet, sending again", oldest);
+                    sent = oldest - 1;
+                }
+                unacked_since_last_check = oldest;
+            }
+            _ = outgoing.closed() => return,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::broadcast, time::interval};
+
+use crate::image_quality::SharedImageQuality;
+use crate::motion::MotionState;
+use crate::sound_events::SoundKind;
+
+// Something worth telling a person about
+#[derive(Debug, Clone)]
+pub enum CameraEvent {
+    // Motion started; score is the percentage of the image that changed
+    Motion { score: f32 },
+    // The image suddenly looks covered, defocused or blinded
+    Tamper { flag: &'static str },
+    // A loud or breaking-glass sound starting at timestamp_ms, with a WAV clip of it
+    Sound { kind: SoundKind, level_db: f32, timestamp_ms: u64, clip: Arc<Vec<u8>> },
+    // Recording paused because the disk is nearly full
+    DiskSpace { free_mb: u64 },
+    // The SoC temperature moved to another level and the stream was scaled to match
+    Thermal { temperature_c: f32, level: &'static str },
+}
+
+impl CameraEvent {
+    // Which detector raised it, as used in config files
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CameraEvent::Motion { .. } => "motion",
+            CameraEvent::Tamper { .. } => "tamper",
+            CameraEvent::Sound { kind, .. } => kind.name(),
+            CameraEvent::DiskSpace { .. } => "disk_space",
+            CameraEvent::Thermal { .. } => "thermal",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            CameraEvent::Motion { score } => format!("Motion detected ({:.1}% of the image changed)", score),
+            CameraEvent::Tamper { flag } => format!("Possible tampering: image is {}", flag),
+            CameraEvent::Sound { kind: SoundKind::LoudNoise, level_db, .. } => format!("Loud noise ({:.0} dBFS)", level_db),
+            CameraEvent::Sound { kind: SoundKind::GlassBreak, .. } => "Possible breaking glass".to_string(),
+            CameraEvent::DiskSpace { free_mb } => format!("Recording paused, only {}MB of disk space left", free_mb),
+            CameraEvent::Thermal { temperature_c, level } => format!("Camera is running {} at {:.0}°C", level, temperature_c),
+        }
+    }
+
+    // When it started, for events that are only raised some time after that
+    pub fn timestamp_ms(&self) -> Option<u64> {
+        match self {
+            CameraEvent::Sound { timestamp_ms, .. } => Some(*timestamp_ms),
+            _ => None,
+        }
+    }
+
+    // Audio of the event, as a WAV file
+    pub fn clip(&self) -> Option<&[u8]> {
+        match self {
+            CameraEvent::Sound { clip, .. } => Some(clip),
+            _ => None,
+        }
+    }
+}
+
+// Notifiers subscribe to this
+#[derive(Clone)]
+pub struct CameraEvents {
+    tx: broadcast::Sender<CameraEvent>,
+}
+
+impl CameraEvents {
+    pub fn from_sender(tx: broadcast::Sender<CameraEvent>) -> Self {
+        Self { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CameraEvent> {
+        self.tx.subscribe()
+    }
+
+    // For detectors that raise events themselves rather than through the monitor
+    pub fn publish(&self, event: CameraEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+// Turn the detector states into events on their rising edge
+pub fn spawn_event_monitor(motion: Option<MotionState>, image_quality: Option<SharedImageQuality>) -> CameraEvents {
+    let (tx, _) = broadcast::channel(16);
+    let events = CameraEvents::from_sender(tx.clone());
+
+    tokio::spawn(async move {
+        let mut motion_was_active = false;
+        let mut flags_seen: Vec<&'static str> = Vec::new();
+        let mut check = interval(Duration::from_secs(1));
+
+        loop {
+            check.tick().await;
+
+            if let Some(motion) = &motion {
+                let active = motion.is_active();
+                if active && !motion_was_active {
+                    let _ = tx.send(CameraEvent::Motion { score: motion.score() });
+                }
+                motion_was_active = active;
+            }
+            if let Some(flags) = image_quality.as_ref().and_then(|q| q.lock().unwrap().as_ref().map(|q| q.flags.clone())) {
+                for flag in &flags {
+                    if !flags_seen.contains(flag) {
+                        let _ = tx.send(CameraEvent::Tamper { flag });
+                    }
+                }
+                flags_seen = flags;
+            }
+        }
+    });
+
+    events
+}
@@ -0,0 +1,388 @@
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, SyncSender},
+        Arc,
+    },
+};
+use tokio::{process::Command, sync::mpsc};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::encryption::{self, RecordingKeys};
+use crate::recording::RecordingConfig;
+use crate::storage::{self, StorageBackend};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    // The recorded MPEG-TS segments joined as-is
+    Ts,
+    // Remuxed into MP4 without re-encoding
+    Mp4,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportClipCommand {
+    // Echoed back in progress messages so the server can match them up
+    pub id: Option<String>,
+    // Time range in UNIX millis
+    pub from: u64,
+    pub to: u64,
+    #[serde(default = "default_format")]
+    pub format: ClipFormat,
+    // Where the finished clip is PUT
+    pub upload_url: String,
+}
+
+fn default_format() -> ClipFormat {
+    ClipFormat::Mp4
+}
+
+// A recorded segment, by its name in the storage backend, and the time range it covers (UNIX millis)
+pub struct Segment {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+    // Size as stored when it was listed
+    pub bytes: u64,
+}
+
+// Segments are named "<local start time>-<index>.ts" (plus ".enc" once encrypted); each one
+// starts `segment_seconds` after the previous one and ends when it was last written to.
+pub fn list_segments(config: &RecordingConfig, backend: &dyn StorageBackend) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for object in backend.list()? {
+        let path = Path::new(&object.name);
+        let name = encryption::plain_name(path).unwrap_or_else(|| path.to_path_buf());
+        if name.extension().and_then(|e| e.to_str()) != Some("ts") {
+            continue;
+        }
+        let Some(stem) = name.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let Some((stamp, index)) = stem.rsplit_once('-') else {
+            continue;
+        };
+        let (Ok(started), Ok(index)) = (DateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S%z"), index.parse::<u64>()) else {
+            continue;
+        };
+        let start = started.timestamp_millis() as u64 + index * config.segment_seconds * 1000;
+        let end = object.modified_ms.max(start);
+        segments.push(Segment { name: object.name, start, end, bytes: object.bytes });
+    }
+    segments.sort_by_key(|s| s.start);
+    Ok(segments)
+}
+
+async fn remux_to_mp4(input: &Path, output: &Path) -> Result<(), String> {
+    let status = Command::new("gst-launch-1.0")
+        .args(&[
+            "-q".to_string(),
+            "filesrc".into(),
+            format!("location={}", input.display()),
+            "!".into(),
+            "tsdemux".into(),
+            "!".into(),
+            "h264parse".into(),
+            "!".into(),
+            "mp4mux".into(),
+            "!".into(),
+            "filesink".into(),
+            format!("location={}", output.display()),
+        ])
+        // Not left writing a file that has already been cleaned up
+        .kill_on_drop(true)
+        .status()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("remux exited with {}", status));
+    }
+    Ok(())
+}
+
+// Progress messages for an export, sent over the uplink under the command's id
+#[derive(Clone)]
+pub struct ExportProgress {
+    id: Option<String>,
+    camera_id: String,
+    outgoing: mpsc::Sender<Message>,
+}
+
+impl ExportProgress {
+    pub fn new(id: Option<String>, camera_id: String, outgoing: mpsc::Sender<Message>) -> Self {
+        Self { id, camera_id, outgoing }
+    }
+
+    pub async fn report(&self, stage: &str, extra: serde_json::Value) {
+        let _ = self.outgoing.send(Message::Text(self.message(stage, extra))).await;
+    }
+
+    // From a blocking task
+    pub fn report_blocking(&self, stage: &str, extra: serde_json::Value) {
+        let _ = self.outgoing.blocking_send(Message::Text(self.message(stage, extra)));
+    }
+
+    fn message(&self, stage: &str, extra: serde_json::Value) -> String {
+        let mut message = json!({
+            "export_progress": {
+                "id": self.id,
+                "camera_id": self.camera_id,
+                "stage": stage
+            }
+        });
+        if let (Some(target), Some(fields)) = (message["export_progress"].as_object_mut(), extra.as_object()) {
+            target.extend(fields.clone());
+        }
+        message.to_string()
+    }
+}
+
+// Cut the requested range out of the local recordings and upload it, reporting progress
+// over the uplink. Clips start and end on segment boundaries since we never re-encode.
+pub async fn export_clip(
+    config: RecordingConfig,
+    command: ExportClipCommand,
+    camera_id: String,
+    outgoing: mpsc::Sender<Message>
+) {
+    let progress = ExportProgress::new(command.id.clone(), camera_id.clone(), outgoing);
+    match run_export(&config, &command, &camera_id, &progress).await {
+        Ok(size) => {
+            println!("Exported clip {}-{} ({} bytes) to {}", command.from, command.to, size, command.upload_url);
+            progress.report("done", json!({ "bytes": size })).await;
+        }
+        Err(e) => {
+            eprintln!("Clip export failed: {}", e);
+            progress.report("failed", json!({ "error": e })).await;
+        }
+    }
+}
+
+// The segments overlapping [from, to), oldest first
+pub async fn find_segments(config: &RecordingConfig, from: u64, to: u64, progress: &ExportProgress) -> Result<Vec<Segment>, String> {
+    if to <= from {
+        return Err("empty time range".to_string());
+    }
+
+    let list_config = config.clone();
+    let segments: Vec<Segment> = tokio::task::spawn_blocking(move || list_segments(&list_config, storage::open(&list_config).as_ref()))
+        .await
+        .map_err(|e| e.to_string())??
+        .into_iter()
+        .filter(|s| s.start < to && s.end > from)
+        .collect();
+    let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+        return Err("no recordings in the requested range".to_string());
+    };
+    progress.report("collecting", json!({ "segments": segments.len(), "from": first.start, "to": last.end })).await;
+    Ok(segments)
+}
+
+// Opens segments for reading, decrypted as they are read. Calls block.
+pub struct SegmentReader {
+    backend: Arc<dyn StorageBackend>,
+    // Only resolved when a segment is actually encrypted
+    keys: Option<RecordingKeys>,
+}
+
+impl SegmentReader {
+    pub fn new(config: &RecordingConfig) -> Result<Self, String> {
+        let keys = match config.encryption.clone() {
+            Some(encryption) => Some(RecordingKeys::new(encryption)?),
+            None => None,
+        };
+        Ok(Self { backend: storage::open(config), keys })
+    }
+
+    pub fn open(&mut self, segment: &Segment) -> Result<Box<dyn Read + Send>, String> {
+        encryption::open_segment(&segment.name, self.backend.get(&segment.name)?, &mut self.keys)
+    }
+}
+
+enum Piece {
+    Data(Vec<u8>),
+    End,
+    Failed(String),
+}
+
+// A bounded pipe from a writer on one blocking thread to a request body read on
+// another, so an export is built and uploaded a piece at a time instead of in memory.
+// Only a finished writer ends the body; one that fails or goes away fails the upload,
+// so a server never gets a cut-off export that looks complete.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (tx, rx) = std::sync::mpsc::sync_channel(PIPE_PIECES);
+    (PipeWriter { tx }, PipeReader { rx, piece: Vec::new(), position: 0 })
+}
+
+const PIPE_PIECES: usize = 8;
+const PIPE_PIECE_BYTES: usize = 256 * 1024;
+
+pub struct PipeWriter {
+    tx: SyncSender<Piece>,
+}
+
+impl PipeWriter {
+    // Ends the body after `write` has written all of it
+    pub fn run(self, write: impl FnOnce(&mut dyn Write) -> Result<(), String>) {
+        let tx = self.tx.clone();
+        let mut buffered = BufWriter::with_capacity(PIPE_PIECE_BYTES, self);
+        let result = write(&mut buffered).and_then(|()| buffered.flush().map_err(|e| e.to_string()));
+        let _ = tx.send(match result {
+            Ok(()) => Piece::End,
+            Err(e) => Piece::Failed(e),
+        });
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.send(Piece::Data(buf.to_vec())).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct PipeReader {
+    rx: Receiver<Piece>,
+    piece: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.piece.len() {
+            match self.rx.recv() {
+                Ok(Piece::Data(data)) => (self.piece, self.position) = (data, 0),
+                Ok(Piece::End) => return Ok(0),
+                Ok(Piece::Failed(e)) => return Err(io::Error::other(e)),
+                Err(_) => return Err(io::Error::other("export stopped before it was finished")),
+            }
+        }
+        let read = buf.len().min(self.piece.len() - self.position);
+        buf[..read].copy_from_slice(&self.piece[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+// PUT a finished export where the server asked for it, streamed from `body`. Blocks,
+// and without the blocking client's default timeout, which a long upload would run into.
+pub fn upload(url: &str, content_type: &str, camera_id: &str, body: reqwest::blocking::Body) -> Result<(), String> {
+    let response = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .map_err(|e| e.to_string())?
+        .put(url)
+        .header("Content-Type", content_type)
+        .header("X-Camera-Id", camera_id)
+        .body(body)
+        .send()
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("server returned {}", response.status()));
+    }
+    Ok(())
+}
+
+// Each segment's contents in turn, which for MPEG-TS makes one playable stream; the
+// number of bytes written
+fn write_clip(config: &RecordingConfig, segments: &[Segment], out: &mut dyn Write, progress: &ExportProgress) -> Result<u64, String> {
+    let mut reader = SegmentReader::new(config)?;
+    let mut written = 0;
+    for (index, segment) in segments.iter().enumerate() {
+        written += io::copy(&mut reader.open(segment)?, out).map_err(|e| format!("{}: {}", segment.name, e))?;
+        progress.report_blocking("collecting", json!({ "percent": (index + 1) * 100 / segments.len() }));
+    }
+    Ok(written)
+}
+
+// Where a clip is put together for remuxing. It is decrypted by then, so not on the card:
+// the staging directory or /dev/shm, both usually a tmpfs that a crash or power cut
+// leaves nothing behind in.
+fn scratch_directory(config: &RecordingConfig) -> PathBuf {
+    match &config.wear.staging_directory {
+        Some(staging) => PathBuf::from(staging),
+        None if Path::new("/dev/shm").is_dir() => PathBuf::from("/dev/shm"),
+        None => std::env::temp_dir(),
+    }
+}
+
+// Files an export works with, removed however it ends
+struct ScratchFiles(Vec<PathBuf>);
+
+impl Drop for ScratchFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn run_export(
+    config: &RecordingConfig,
+    command: &ExportClipCommand,
+    camera_id: &str,
+    progress: &ExportProgress
+) -> Result<u64, String> {
+    let segments = find_segments(config, command.from, command.to, progress).await?;
+    let (url, camera_id) = (command.upload_url.clone(), camera_id.to_string());
+    let (config, clip_progress) = (config.clone(), progress.clone());
+
+    if command.format == ClipFormat::Ts {
+        // Uploaded as it is read; the size is only known at the end
+        progress.report("uploading", json!({})).await;
+        let (writer, reader) = pipe();
+        let written = Arc::new(AtomicU64::new(0));
+        let total = written.clone();
+        let clip = tokio::task::spawn_blocking(move || {
+            writer.run(|out| write_clip(&config, &segments, out, &clip_progress).map(|bytes| total.store(bytes, Ordering::Relaxed)))
+        });
+        let uploaded = tokio::task::spawn_blocking(move || upload(&url, "video/mp2t", &camera_id, reqwest::blocking::Body::new(reader)));
+        let uploaded = uploaded.await.map_err(|e| e.to_string())?;
+        let _ = clip.await;
+        uploaded?;
+        return Ok(written.load(Ordering::Relaxed));
+    }
+
+    // Remuxing needs the whole clip as a file first
+    let base = scratch_directory(&config).join(format!("export-{}-{}", command.from, command.to));
+    let ts_path = base.with_extension("ts.tmp");
+    let mp4_path = base.with_extension("mp4");
+    let _scratch = ScratchFiles(vec![ts_path.clone(), mp4_path.clone()]);
+    let clip_path = ts_path.clone();
+    let upload_progress = clip_progress.clone();
+    let clip = tokio::task::spawn_blocking(move || {
+        let mut file = BufWriter::new(File::create(&clip_path).map_err(|e| e.to_string())?);
+        write_clip(&config, &segments, &mut file, &clip_progress)?;
+        file.flush().map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    clip?;
+    progress.report("remuxing", json!({})).await;
+    let remuxed = remux_to_mp4(&ts_path, &mp4_path).await;
+    // Out of memory before the upload rather than after
+    let _ = tokio::fs::remove_file(&ts_path).await;
+    remuxed?;
+
+    let upload_path = mp4_path.clone();
+    let uploaded = tokio::task::spawn_blocking(move || {
+        let file = File::open(&upload_path).map_err(|e| e.to_string())?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        upload_progress.report_blocking("uploading", json!({ "bytes": size }));
+        upload(&url, "video/mp4", &camera_id, reqwest::blocking::Body::from(file)).map(|()| size)
+    })
+    .await
+    .map_err(|e| e.to_string());
+    uploaded?
+}
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+use std::{
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{process::Command, sync::mpsc, time::sleep};
+
+use crate::commands::PtzCommand;
+use crate::encoder::Codec;
+use crate::frame::FrameOutputs;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FisheyeConfig {
+    // Full sensor resolution shared with the view pipelines
+    pub source_width: u32,
+    pub source_height: u32,
+    // Position and size of the fisheye circle, as fractions of the image (dewarp element)
+    pub x_center: f64,
+    pub y_center: f64,
+    pub inner_radius: f64,
+    pub outer_radius: f64,
+    pub socket_path: String,
+    pub views: Vec<ViewConfig>,
+}
+
+impl Default for FisheyeConfig {
+    fn default() -> Self {
+        Self {
+            source_width: 1920,
+            source_height: 1080,
+            x_center: 0.5,
+            y_center: 0.5,
+            inner_radius: 0.1,
+            outer_radius: 0.5,
+            socket_path: "/tmp/camera-fisheye".to_string(),
+            views: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewConfig {
+    // Sent as the stream id of this view's frames
+    pub id: String,
+    // Degrees around the panorama
+    #[serde(default)]
+    pub pan: f64,
+    // 0.0 (outer edge of the circle) to 1.0 (inner edge)
+    #[serde(default = "default_tilt")]
+    pub tilt: f64,
+    // 1.0 shows the full panorama height
+    #[serde(default = "default_zoom")]
+    pub zoom: f64,
+    #[serde(default = "default_view_width")]
+    pub width: u32,
+    #[serde(default = "default_view_height")]
+    pub height: u32,
+    #[serde(default = "default_view_quality")]
+    pub quality: u32,
+}
+
+fn default_tilt() -> f64 { 0.5 }
+fn default_zoom() -> f64 { 1.0 }
+fn default_view_width() -> u32 { 640 }
+fn default_view_height() -> u32 { 480 }
+fn default_view_quality() -> u32 { 60 }
+
+impl FisheyeConfig {
+    // Elements added to the capture pipeline right after the source: the full-resolution
+    // image is shared over shared memory, and the main branch goes on to be scaled to the
+    // live resolution
+    pub fn tee_args(&self) -> Vec<String> {
+        let frame_bytes = self.source_width * self.source_height * 3;
+        vec![
+            "tee".into(),
+            "name=fisheye".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            "video/x-raw,format=RGB".into(),
+            "!".into(),
+            "shmsink".into(),
+            format!("socket-path={}", self.socket_path),
+            format!("shm-size={}", frame_bytes * 4),
+            "wait-for-connection=false".into(),
+            "sync=false".into(),
+            "fisheye.".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]
+    }
+
+    // Panorama size produced by the dewarp element, used to place the PTZ crop window
+    fn panorama_size(&self) -> (f64, f64) {
+        let outer = self.outer_radius * self.source_width as f64;
+        let inner = self.inner_radius * self.source_width as f64;
+        (2.0 * std::f64::consts::PI * outer, (outer - inner).max(1.0))
+    }
+
+    // videocrop margins (left, right, top, bottom) for a view
+    fn crop_for(&self, view: &ViewConfig) -> (u32, u32, u32, u32) {
+        let (pano_width, pano_height) = self.panorama_size();
+        let crop_height = (pano_height / view.zoom.max(1.0)).min(pano_height);
+        let crop_width = (crop_height * view.width as f64 / view.height as f64).min(pano_width);
+
+        let center_x = view.pan.rem_euclid(360.0) / 360.0 * pano_width;
+        let center_y = view.tilt.clamp(0.0, 1.0) * pano_height;
+        // The crop window can't wrap around the panorama seam, so it is clamped instead
+        let left = (center_x - crop_width / 2.0).clamp(0.0, pano_width - crop_width);
+        let top = (center_y - crop_height / 2.0).clamp(0.0, pano_height - crop_height);
+
+        (
+            left as u32,
+            (pano_width - left - crop_width) as u32,
+            top as u32,
+            (pano_height - top - crop_height) as u32,
+        )
+    }
+
+    fn start_view(&self, view: &ViewConfig) -> std::io::Result<tokio::process::Child> {
+        let (left, right, top, bottom) = self.crop_for(view);
+        println!("Starting virtual view {} (pan {:.0}, tilt {:.2}, zoom {:.1})", view.id, view.pan, view.tilt, view.zoom);
+
+        Command::new("gst-launch-1.0")
+            .args(&[
+                "shmsrc".to_string(),
+                format!("socket-path={}", self.socket_path),
+                "is-live=true".into(),
+                "do-timestamp=true".into(),
+                "!".into(),
+                format!("video/x-raw,format=RGB,width={},height={},framerate=0/1", self.source_width, self.source_height),
+                "!".into(),
+                "dewarp".into(),
+                format!("x-center={}", self.x_center),
+                format!("y-center={}", self.y_center),
+                format!("inner-radius={}", self.inner_radius),
+                format!("outer-radius={}", self.outer_radius),
+                "display=panorama".into(),
+                "!".into(),
+                "videocrop".into(),
+                format!("left={}", left),
+                format!("right={}", right),
+                format!("top={}", top),
+                format!("bottom={}", bottom),
+                "!".into(),
+                "videoscale".into(),
+                "!".into(),
+                "videoconvert".into(),
+                "!".into(),
+                format!("video/x-raw,width={},height={}", view.width, view.height),
+                "!".into(),
+                "jpegenc".into(),
+                format!("quality={}", view.quality),
+                "!".into(),
+                "fdsink".into(),
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+    }
+}
+
+// Run one pipeline per virtual view and restart them when they die or are moved.
+// Returns the sender for PTZ commands.
+pub fn spawn_virtual_views(
+    config: FisheyeConfig,
+    outputs: FrameOutputs
+) -> mpsc::Sender<PtzCommand> {
+    let (ptz_tx, mut ptz_rx) = mpsc::channel::<PtzCommand>(16);
+
+    tokio::spawn(async move {
+        let mut views = config.views.clone();
+        let mut children: Vec<Option<tokio::process::Child>> = views.iter().map(|_| None).collect();
+
+        loop {
+            // (Re)start any view whose pipeline isn't running
+            for (index, view) in views.iter().enumerate() {
+                let running = match children[index].as_mut() {
+                    Some(child) => matches!(child.try_wait(), Ok(None)),
+                    None => false,
+                };
+                if running {
+                    continue;
+                }
+                match config.start_view(view) {
+                    Ok(mut child) => {
+                        let stdout = child.stdout.take().expect("Failed to capture view stdout");
+                        crate::process_frames(
+                            stdout,
+                            Codec::Mjpeg,
+                            Arc::from(view.id.as_str()),
+                            outputs.clone()
+                        ).await;
+                        children[index] = Some(child);
+                    }
+                    Err(e) => eprintln!("Failed to start virtual view {}: {}", view.id, e),
+                }
+            }
+
+            tokio::select! {
+                Some(command) = ptz_rx.recv() => {
+                    let Some(index) = views.iter().position(|v| v.id == command.view) else {
+                        eprintln!("PTZ command for unknown view {}", command.view);
+                        continue;
+                    };
+                    let view = &mut views[index];
+                    view.pan = command.pan.unwrap_or(view.pan);
+                    view.tilt = command.tilt.unwrap_or(view.tilt);
+                    view.zoom = command.zoom.unwrap_or(view.zoom);
+
+                    // Restarted with the new crop on the next pass
+                    if let Some(mut child) = children[index].take() {
+                        let _ = child.kill().await;
+                    }
+                }
+                _ = sleep(Duration::from_secs(2)) => {}
+            }
+        }
+    });
+
+    ptz_tx
+}
@@ -0,0 +1,82 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+// Optional end-to-end backpressure: the server acknowledges frames and at most `window`
+// may be unacknowledged. While the window is full frames wait in the queue, whose
+// policy then drops them and feeds the congestion controller.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FlowControlConfig {
+    // Asked of the server: send {"ack": <frame_id>} after at least every this many frames
+    pub ack_every: u64,
+    pub window: u64,
+    // A full window with no ack for this long means the server stopped listening
+    pub ack_timeout_seconds: u64,
+}
+
+impl Default for FlowControlConfig {
+    fn default() -> Self {
+        Self {
+            ack_every: 10,
+            window: 30,
+            ack_timeout_seconds: 10,
+        }
+    }
+}
+
+impl FlowControlConfig {
+    pub fn ack_timeout(&self) -> Duration {
+        Duration::from_secs(self.ack_timeout_seconds)
+    }
+}
+
+// Acknowledgement state of one connection. Only enforced once the server has agreed
+// with {"frame_acks": true}, so servers without ack support are unaffected.
+#[derive(Clone)]
+pub struct AckWindow {
+    window: u64,
+    enabled: Arc<AtomicBool>,
+    // Frames up to (not including) this id are acknowledged
+    acked: Arc<AtomicU64>,
+    changed: Arc<Notify>,
+}
+
+impl AckWindow {
+    pub fn new(config: &FlowControlConfig) -> Self {
+        Self {
+            window: config.window,
+            enabled: Arc::new(AtomicBool::new(false)),
+            acked: Arc::new(AtomicU64::new(0)),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // Acks are cumulative
+    pub fn ack(&self, frame_id: u64) {
+        self.acked.fetch_max(frame_id + 1, Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    pub fn is_full(&self, next_frame_id: u64) -> bool {
+        self.is_enabled() && next_frame_id.saturating_sub(self.acked.load(Ordering::Relaxed)) >= self.window
+    }
+
+    pub async fn wait_for_ack(&self) {
+        self.changed.notified().await;
+    }
+}
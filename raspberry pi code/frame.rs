@@ -0,0 +1,294 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc;
+
+use crate::boost::ViewerBoost;
+use crate::capture_clock::{CaptureClock, FrameTimestamp};
+use crate::concealment::{Checked, Concealer, Concealment, ConcealmentConfig};
+use crate::cpu_budget::{CpuGovernor, Shed, Stage};
+use crate::decimation::{DecimationConfig, Decimator};
+use crate::downscale::{DownscaleConfig, Downscaler};
+use crate::encode_profiles::ProfileGate;
+use crate::encoder::Codec;
+use crate::frame_api::FrameHub;
+use crate::frame_pool::{FramePool, PooledFrame};
+use crate::encoder_experiment::EncoderExperiment;
+use crate::jpeg::JpegTuner;
+use crate::metadata::MetadataTap;
+use crate::motion::MotionState;
+use crate::pause::UplinkPause;
+use crate::pipeline::FramePipeline;
+use crate::privacy::PrivacyConfig;
+use crate::queue::{FrameSender, SendOutcome};
+use crate::scene_complexity::SceneComplexity;
+use crate::stats_db::StatsCounters;
+use crate::viewers::ViewerCount;
+use crate::watchdog::FrameWatchdog;
+
+// An encoded frame on its way to the uplink, plus what we know about it
+pub struct Frame {
+    pub data: PooledFrame,
+    // "main" for the camera itself, otherwise a virtual view id
+    pub stream_id: Arc<str>,
+    // Motion detector verdict when the frame was captured
+    pub motion: bool,
+    pub timestamp: FrameTimestamp,
+    // The data already base64-encoded for the JSON envelope, when a pipeline stage did it
+    pub base64: Option<String>,
+    // Set when the frame arrived damaged and what is sent was patched up or stood in for
+    pub concealment: Option<Concealment>,
+    // Decodes without the frames before it (MJPEG), so it can be dropped on the way
+    pub standalone: bool,
+}
+
+// Where extracted frames go, and what decides whether they are dropped
+#[derive(Clone)]
+pub struct FrameOutputs {
+    pub tx: FrameSender,
+    pub frame_pool: FramePool,
+    // Local consumers that get their own copy of every frame
+    pub local_sinks: Vec<mpsc::Sender<PooledFrame>>,
+    pub network_congested: Arc<AtomicBool>,
+    pub motion: Option<MotionState>,
+    pub decimation: DecimationConfig,
+    // Ticked for every extracted frame so a silent pipeline can be detected
+    pub watchdog: Option<FrameWatchdog>,
+    // Per-frame analytics saved alongside the recordings
+    pub metadata: Option<MetadataTap>,
+    // Capture timestamps; shared by every stream so they agree with each other
+    pub clock: CaptureClock,
+    // Frame counts for the statistics database
+    pub stats: StatsCounters,
+    // Re-encodes MJPEG frames when the settings need it
+    pub jpeg: Option<JpegTuner>,
+    // Alternates between two JPEG settings instead, while an experiment runs
+    pub experiment: Option<EncoderExperiment>,
+    // Encoded frame sizes for the congestion controller
+    pub complexity: Option<SceneComplexity>,
+    // Set while the server has paused the uplink
+    pub paused: UplinkPause,
+    // Skips frame dropping while an operator is watching
+    pub boost: ViewerBoost,
+    // Cuts the uplink down to motion frames while the server has nobody subscribed
+    pub viewers: ViewerCount,
+    // Extraction, processing and serialization on their own threads
+    pub pipeline: Option<FramePipeline>,
+    // Frame events for embedding applications
+    pub hub: Option<FrameHub>,
+    // Skips the optional re-encode while the CPU is over budget
+    pub governor: Option<CpuGovernor>,
+    // Checks extracted JPEGs and conceals damaged ones
+    pub concealment: Option<ConcealmentConfig>,
+    // Frames sent at a lower resolution while congested
+    pub downscale: Option<DownscaleConfig>,
+    // Set when a second encode profile is kept warm; only the selected one is forwarded
+    pub profile: Option<ProfileGate>,
+    // Metadata left off frames for the server, here rather than on the uplink task
+    pub privacy: Option<PrivacyConfig>,
+}
+
+// Everything that happens to an extracted frame before it is queued for the uplink:
+// re-encoding, accounting, local consumers and the decisions to drop it
+pub struct FrameProcessor {
+    outputs: FrameOutputs,
+    codec: Codec,
+    stream_id: Arc<str>,
+    decimator: Decimator,
+    concealer: Option<Concealer>,
+    downscaler: Option<Downscaler>,
+    downscaling: bool,
+    still_frames_dropped: u32,
+    // When the last still went out while nobody was watching
+    idle_still_sent: Option<std::time::Instant>,
+}
+
+impl FrameProcessor {
+    pub fn new(outputs: FrameOutputs, codec: Codec, stream_id: Arc<str>) -> Self {
+        let decimator = Decimator::new(&outputs.decimation);
+        // Only JPEG can be checked, or lose a frame without breaking the decoder
+        let concealer = outputs.concealment.clone().filter(|_| codec == Codec::Mjpeg).map(Concealer::new);
+        let downscaler = outputs.downscale.as_ref().filter(|_| codec == Codec::Mjpeg).map(|_| Downscaler::new());
+        Self { outputs, codec, stream_id, decimator, concealer, downscaler, downscaling: false, still_frames_dropped: 0, idle_still_sent: None }
+    }
+
+    // The frame to queue, or None when it is only for the local consumers. Decodes and
+    // encodes JPEGs, so it runs on a blocking thread.
+    pub fn process(&mut self, data: &[u8], timestamp: FrameTimestamp) -> Option<Frame> {
+        let FrameOutputs { frame_pool, local_sinks, network_congested, motion, watchdog, metadata, stats, jpeg, experiment, complexity, paused, boost, viewers, hub, governor, .. } = &self.outputs;
+        let codec = self.codec;
+        let gate = self.outputs.profile.as_ref();
+        let standby = gate.is_some_and(|gate| gate.is_standby());
+        if standby && gate.is_some_and(|gate| !gate.forwarded()) {
+            return None;
+        }
+        // Garbage from the sensor or a cut-off read never reaches anyone
+        let checked = self.concealer.as_mut().map_or(Checked::Intact, |concealer| concealer.check(data));
+        let concealment = match checked {
+            Checked::Intact => None,
+            Checked::Concealed(concealment) => Some(concealment),
+            Checked::Dropped => return None,
+        };
+        let data = match (&self.concealer, concealment) {
+            (Some(concealer), Some(concealment)) => concealer.concealed(concealment),
+            _ => data,
+        };
+        let has_motion = motion.as_ref().is_some_and(|m| m.is_active());
+        // The main pipeline has already given the standby profile's frame to everyone below
+        if !standby {
+            StatsCounters::add(&stats.frames_captured, 1);
+            if let Some(watchdog) = watchdog {
+                watchdog.tick();
+            }
+            if let Some(metadata) = metadata {
+                metadata.record(&self.stream_id, data.len(), timestamp);
+            }
+            if let Some(hub) = hub {
+                hub.publish(&self.stream_id, data, timestamp, has_motion);
+            }
+
+            // Local consumers (HLS) only understand JPEG and are never throttled by uplink congestion
+            if codec == Codec::Mjpeg {
+                for sink in local_sinks {
+                    if let Err(mpsc::error::TrySendError::Full(_)) = sink.try_send(frame_pool.acquire(data)) {
+                        println!("Local consumer falling behind, skipping frame");
+                    }
+                }
+            }
+        }
+
+        // The low profile's frames go out instead
+        if gate.is_some_and(|gate| !gate.forwarded()) {
+            return None;
+        }
+
+        // Nobody is watching; everything above still sees the frame
+        if paused.is_paused() {
+            return None;
+        }
+
+        let congested = network_congested.load(Ordering::Relaxed);
+
+        // Lower the uplink frame rate while congested; local consumers keep every frame.
+        // Inter-frame codecs can't lose frames without breaking the decoder.
+        if codec == Codec::Mjpeg && !self.decimator.keep(congested) {
+            return None;
+        }
+
+        // Connected but nobody has watched for the grace period, and no boost says otherwise.
+        // Like decimation, only JPEG can skip frames.
+        let unwatched = codec == Codec::Mjpeg && viewers.unwatched() && !boost.is_active();
+
+        // While congested or unwatched, frames without motion go first. Stills still go out
+        // (every 10th frame, or every idle_still_seconds unwatched) so the image isn't frozen.
+        if motion.is_some() && !has_motion && (congested || unwatched) {
+            let due = if unwatched {
+                let every = viewers.idle_still_every();
+                every.is_some_and(|every| self.idle_still_sent.is_none_or(|sent| sent.elapsed() >= every))
+            } else {
+                self.still_frames_dropped += 1;
+                self.still_frames_dropped.is_multiple_of(10)
+            };
+            if !due {
+                return None;
+            }
+            if unwatched {
+                self.idle_still_sent = Some(std::time::Instant::now());
+            }
+        }
+
+        // Progressive scans, restart markers and experiment arms need a second encode; only
+        // for frames that go out, everything above gets them as the camera encoded them
+        let reencoded;
+        let shed = governor.as_ref().is_some_and(|governor| governor.is_shed(Shed::Reencode));
+        let started = std::time::Instant::now();
+        let reencode = match (experiment, jpeg) {
+            _ if codec != Codec::Mjpeg || shed => None,
+            (Some(experiment), _) => Some(experiment.encode(data)),
+            (None, Some(tuner)) => Some(tuner.reencode(data)),
+            (None, None) => None,
+        };
+        if let (Some(governor), Some(_)) = (governor, &reencode) {
+            governor.record(Stage::Encode, started.elapsed());
+        }
+        let data = match reencode {
+            Some(Ok(encoded)) => {
+                reencoded = encoded;
+                &reencoded[..]
+            }
+            Some(Err(e)) => {
+                eprintln!("Failed to re-encode JPEG, sending it as-is: {}", e);
+                data
+            }
+            None => data,
+        };
+        // Sized as sent at the current quality, which is what the controller weighs
+        if let Some(complexity) = complexity.as_ref().filter(|_| !standby) {
+            complexity.record(data.len());
+        }
+
+        // A smaller copy goes out straight away; the pipeline may still step down later,
+        // after which there is nothing left to scale
+        let downscaled;
+        let data = match (&mut self.downscaler, &self.outputs.downscale) {
+            (Some(downscaler), Some(config)) if congested && !shed => {
+                let started = std::time::Instant::now();
+                let result = downscaler.downscale(data, config.congested_width, config.quality);
+                if let Some(governor) = governor {
+                    governor.record(Stage::Encode, started.elapsed());
+                }
+                match result {
+                    Ok(Some(smaller)) => {
+                        if !self.downscaling {
+                            println!("Network congested, sending frames scaled down to {} pixels wide", config.congested_width);
+                            self.downscaling = true;
+                        }
+                        downscaled = smaller;
+                        &downscaled[..]
+                    }
+                    Ok(None) => data,
+                    Err(e) => {
+                        eprintln!("Failed to scale frame down, sending it as-is: {}", e);
+                        data
+                    }
+                }
+            }
+            _ => {
+                self.downscaling = false;
+                data
+            }
+        };
+        let stripped = self.outputs.privacy.as_ref().and_then(|privacy| privacy.strip(data));
+        let data = stripped.as_deref().unwrap_or(data);
+
+        // Copy into a pooled buffer; the queue policy decides whether it is sent
+        Some(Frame {
+            data: frame_pool.acquire(data),
+            stream_id: self.stream_id.clone(),
+            motion: has_motion,
+            timestamp,
+            base64: None,
+            concealment,
+            standalone: self.codec == Codec::Mjpeg,
+        })
+    }
+}
+
+// Queue a frame for the uplink and account for it if the queue policy drops one
+pub async fn enqueue(tx: &FrameSender, stats: &StatsCounters, frame: Frame) {
+    match tx.send(frame).await {
+        SendOutcome::Queued => {}
+        SendOutcome::ReplacedOldest => {
+            StatsCounters::add(&stats.frames_dropped, 1);
+            println!("Queue full, dropped oldest frame");
+        }
+        SendOutcome::Dropped => {
+            StatsCounters::add(&stats.frames_dropped, 1);
+            println!("Network congested, skipping frame");
+        }
+        // The allocator's shares already say why
+        SendOutcome::OverShare => StatsCounters::add(&stats.frames_dropped, 1),
+        SendOutcome::Closed => eprintln!("Failed to send frame: uplink closed"),
+    }
+}
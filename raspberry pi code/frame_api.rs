@@ -0,0 +1,52 @@
+// Processed frames as events, kept apart from the transport, for stages that need to see
+// every frame before any uplink decisions (bursts)
+
+use futures_util::{stream, Stream};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::capture_clock::FrameTimestamp;
+
+// An encoded frame as it leaves the processing stage, before any uplink decisions
+#[derive(Debug, Clone)]
+pub struct FrameEvent {
+    pub stream_id: Arc<str>,
+    pub data: Arc<[u8]>,
+    pub timestamp: FrameTimestamp,
+    pub motion: bool,
+}
+
+// Publishes every processed frame to subscribers. Frames are only copied while something
+// is subscribed.
+#[derive(Clone)]
+pub struct FrameHub {
+    events: broadcast::Sender<FrameEvent>,
+}
+
+impl FrameHub {
+    pub fn new() -> Self {
+        // A slow subscriber misses frames instead of holding up capture
+        let (events, _) = broadcast::channel(8);
+        Self { events }
+    }
+
+    pub fn publish(&self, stream_id: &Arc<str>, data: &[u8], timestamp: FrameTimestamp, motion: bool) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.events.send(FrameEvent { stream_id: stream_id.clone(), data: Arc::from(data), timestamp, motion });
+    }
+
+    // Every frame from now on; frames a slow consumer falls behind on are skipped
+    pub fn frames(&self) -> impl Stream<Item = FrameEvent> {
+        stream::unfold(self.events.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+// Pool of reusable frame buffers. Buffers are bucketed by capacity (rounded up to
+// a power of two) so frames of similar size keep reusing the same allocations
+// instead of fragmenting the heap over days of uptime.
+#[derive(Clone)]
+pub struct FramePool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+struct PoolInner {
+    buckets: HashMap<usize, Vec<Vec<u8>>>,
+    max_per_bucket: usize,
+}
+
+impl FramePool {
+    pub fn new(max_per_bucket: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                buckets: HashMap::new(),
+                max_per_bucket,
+            })),
+        }
+    }
+
+    // Drop buffers sized for the old resolution and pre-allocate a few for the new one
+    pub fn prepare_for_resolution(&self, width: u32, height: u32, count: usize) {
+        // Compressed frames are well under half the raw pixel count in bytes
+        let capacity = bucket_capacity((width * height / 2) as usize);
+        let mut inner = self.inner.lock().unwrap();
+        inner.buckets.retain(|&key, _| key == capacity);
+
+        let max_per_bucket = inner.max_per_bucket;
+        let bucket = inner.buckets.entry(capacity).or_default();
+        while bucket.len() < count.min(max_per_bucket) {
+            bucket.push(Vec::with_capacity(capacity));
+        }
+    }
+
+    // Copy a frame into a pooled buffer; the buffer goes back to the pool when dropped
+    pub fn acquire(&self, data: &[u8]) -> PooledFrame {
+        let capacity = bucket_capacity(data.len());
+        let mut buffer = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.buckets.get_mut(&capacity).and_then(|bucket| bucket.pop())
+        }
+        .unwrap_or_else(|| Vec::with_capacity(capacity));
+
+        buffer.clear();
+        buffer.extend_from_slice(data);
+        PooledFrame { buffer, pool: self.clone() }
+    }
+
+    fn release(&self, buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        // Only keep buffers that still fit a bucket exactly
+        if !capacity.is_power_of_two() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let max_per_bucket = inner.max_per_bucket;
+        let bucket = inner.buckets.entry(capacity).or_default();
+        if bucket.len() < max_per_bucket {
+            bucket.push(buffer);
+        }
+    }
+}
+
+fn bucket_capacity(len: usize) -> usize {
+    len.max(4096).next_power_of_two()
+}
+
+// A frame backed by a pooled buffer
+pub struct PooledFrame {
+    buffer: Vec<u8>,
+    pool: FramePool,
+}
+
+impl Deref for PooledFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledFrame {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledFrame {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
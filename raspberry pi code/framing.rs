@@ -0,0 +1,78 @@
+// Splitting the encoder's byte stream into individual frames
+
+// Find complete JPEG frames (SOI..EOI) in `data`, calling `on_frame` for each one.
+// Returns how many bytes were consumed; anything after that is a partial frame.
+pub fn extract_jpeg_frames(data: &[u8], mut on_frame: impl FnMut(&[u8])) -> usize {
+    let mut position = 0;
+    while position + 4 < data.len() {
+        // Look for JPEG start marker
+        if data[position] == 0xFF && data[position + 1] == 0xD8 {
+            // Found start of JPEG, now look for end marker
+            let mut end_pos = position + 2;
+            let mut found_end = false;
+
+            while end_pos + 1 < data.len() {
+                if data[end_pos] == 0xFF && data[end_pos + 1] == 0xD9 {
+                    // Found end of JPEG (including the end marker)
+                    found_end = true;
+                    on_frame(&data[position..=end_pos + 1]);
+
+                    // Move position past this frame
+                    position = end_pos + 2;
+                    break;
+                }
+                end_pos += 1;
+            }
+
+            if !found_end {
+                // Didn't find the end marker yet, need more data
+                break;
+            }
+        } else {
+            // Not a start marker, move to next byte
+            position += 1;
+        }
+    }
+    position
+}
+
+const IVF_FRAME_HEADER_LEN: usize = 12;
+
+// Find complete frames in an IVF stream (used for VP9/AV1). The stream starts with a
+// file header which is skipped once; `header_seen` carries that across reads.
+pub fn extract_ivf_frames(data: &[u8], header_seen: &mut bool, mut on_frame: impl FnMut(&[u8])) -> usize {
+    let mut position = 0;
+
+    if !*header_seen {
+        if data.len() < 32 {
+            return 0;
+        }
+        if &data[..4] != b"DKIF" {
+            eprintln!("Encoder output is not an IVF stream");
+        }
+        // Header length is stored in the file header itself (normally 32). Wait until all
+        // of it is here, so the first frame is found wherever the reads happen to end.
+        position = u16::from_le_bytes([data[6], data[7]]) as usize;
+        if position > data.len() {
+            return 0;
+        }
+        *header_seen = true;
+    }
+
+    while position + IVF_FRAME_HEADER_LEN <= data.len() {
+        let frame_size = u32::from_le_bytes([
+            data[position],
+            data[position + 1],
+            data[position + 2],
+            data[position + 3],
+        ]) as usize;
+        let frame_start = position + IVF_FRAME_HEADER_LEN;
+        if frame_start + frame_size > data.len() {
+            // Need more data
+            break;
+        }
+        on_frame(&data[frame_start..frame_start + frame_size]);
+        position = frame_start + frame_size;
+    }
+    position.min(data.len())
+}
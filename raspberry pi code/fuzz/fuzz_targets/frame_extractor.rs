@@ -0,0 +1,51 @@
+// Arbitrary encoder output through the frame extraction process_frames does on every
+// read. Run with `cargo fuzz run frame_extractor` from this directory.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The binary has no library target, so the module under test is compiled in directly
+#[allow(dead_code)]
+#[path = "../../framing.rs"]
+mod framing;
+
+// The first byte picks the codec and how the stream is cut into reads; the rest is
+// the stream
+fuzz_target!(|input: &[u8]| {
+    let Some((&control, stream)) = input.split_first() else {
+        return;
+    };
+    let ivf = control & 0x80 != 0;
+    let read_size = (control & 0x7F) as usize + 1;
+
+    let extract = |data: &[u8], header_seen: &mut bool, frames: &mut Vec<Vec<u8>>| {
+        let mut on_frame = |frame: &[u8]| frames.push(frame.to_vec());
+        if ivf {
+            framing::extract_ivf_frames(data, header_seen, &mut on_frame)
+        } else {
+            framing::extract_jpeg_frames(data, &mut on_frame)
+        }
+    };
+
+    // Read by read, keeping the unconsumed tail the way process_frames does
+    let mut accumulated = Vec::new();
+    let mut header_seen = false;
+    let mut frames = Vec::new();
+    for read in stream.chunks(read_size) {
+        accumulated.extend_from_slice(read);
+        let position = extract(&accumulated, &mut header_seen, &mut frames);
+        assert!(position <= accumulated.len(), "consumed {} of {} bytes", position, accumulated.len());
+        accumulated.drain(..position);
+    }
+
+    for frame in &frames {
+        if !ivf {
+            assert!(frame.starts_with(&[0xFF, 0xD8]) && frame.ends_with(&[0xFF, 0xD9]), "frame without SOI and EOI");
+        }
+    }
+
+    // How the stream happened to be split into reads mustn't change what comes out
+    let mut whole = Vec::new();
+    extract(stream, &mut false, &mut whole);
+    assert_eq!(frames, whole, "frames depend on read boundaries");
+});
@@ -0,0 +1,69 @@
+// Arbitrary text through the checks the WebSocket reader applies to every server message
+// before acting on it: JSON parsing, V2 unwrapping, the known-keys check, the protocol
+// error reply, and the fields it reads by hand. Run with `cargo fuzz run server_message`
+// from this directory.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The binary has no library target, so the modules under test are compiled in directly.
+// Command deserialization isn't included: it's derived, and its command types pull in
+// most of the crate.
+#[allow(dead_code)]
+#[path = "../../protocol.rs"]
+mod protocol;
+#[allow(dead_code)]
+#[path = "../../protocol_errors.rs"]
+mod protocol_errors;
+#[allow(dead_code)]
+#[path = "../../resolution.rs"]
+mod resolution;
+#[allow(dead_code)]
+#[path = "../../tenancy.rs"]
+mod tenancy;
+
+use protocol::ProtocolVersion;
+use protocol_errors::{ProtocolErrorConfig, ProtocolErrors};
+use resolution::Resolution;
+use tenancy::TenancyConfig;
+
+fuzz_target!(|input: &[u8]| {
+    // WebSocket text frames are always UTF-8
+    let Ok(text) = std::str::from_utf8(input) else {
+        return;
+    };
+    let errors = ProtocolErrors::new(ProtocolErrorConfig::default());
+    let json = match serde_json::from_str::<serde_json::Value>(text).map(protocol::decode) {
+        Ok(json) if ProtocolErrors::is_understood(&json) => json,
+        Ok(_) => {
+            check_replies(errors.record("fuzz", "unknown message", text));
+            return;
+        }
+        Err(e) => {
+            check_replies(errors.record("fuzz", &format!("malformed JSON: {}", e), text));
+            return;
+        }
+    };
+
+    if let Some(number) = json.get("protocol_version").and_then(|v| v.as_u64()) {
+        let _ = ProtocolVersion::from_number(number);
+    }
+    // With and without a tenancy of our own, so both sides of every comparison are reached
+    let tenancy = TenancyConfig {
+        site_id: Some("site".to_string()),
+        group: Some("group".to_string()),
+        labels: [("zone".to_string(), "dock".to_string())].into_iter().collect(),
+    };
+    let _ = tenancy.addressed_to(&json, "fuzz");
+    let _ = TenancyConfig::default().addressed_to(&json, "fuzz");
+    if let Some(resolution) = json.get("network_feedback").and_then(|f| f.get("suggested_resolution")).and_then(|r| r.as_str()) {
+        let _ = Resolution::parse(resolution);
+    }
+});
+
+// What goes back to the server has to be JSON itself
+fn check_replies(replies: Vec<String>) {
+    for reply in replies {
+        assert!(serde_json::from_str::<serde_json::Value>(&reply).is_ok(), "reply isn't JSON: {}", reply);
+    }
+}
@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+// Publishes an H.264 stream to go2rtc, which restreams it over RTSP/WebRTC to Frigate
+// and other NVRs. In go2rtc the stream only needs an empty source entry, e.g.
+// `streams: { camera: }`, and Frigate reads rtsp://<go2rtc>:8554/camera.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Go2RtcConfig {
+    // RTSP publish address of the go2rtc stream
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_kbps: u32,
+}
+
+impl Default for Go2RtcConfig {
+    fn default() -> Self {
+        Self {
+            url: "rtsp://127.0.0.1:8554/camera".to_string(),
+            width: 1280,
+            height: 720,
+            bitrate_kbps: 2000,
+        }
+    }
+}
+
+impl Go2RtcConfig {
+    // Capture pipeline branch with its own encoder, so the NVR gets a steady stream
+    // whatever the uplink is doing. The publish reconnects whenever the pipeline restarts.
+    pub fn tee_args(&self) -> Vec<String> {
+        vec![
+            "tee".into(),
+            "name=go2rtc".into(),
+            "!".into(),
+            "queue".into(),
+            "leaky=downstream".into(),
+            "!".into(),
+            "videoscale".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            format!("video/x-raw,format=I420,width={},height={}", self.width, self.height),
+            "!".into(),
+            "x264enc".into(),
+            "tune=zerolatency".into(),
+            "speed-preset=superfast".into(),
+            format!("bitrate={}", self.bitrate_kbps),
+            "key-int-max=30".into(),
+            "!".into(),
+            "h264parse".into(),
+            "config-interval=-1".into(),
+            "!".into(),
+            "rtspclientsink".into(),
+            format!("location={}", self.url),
+            "protocols=tcp".into(),
+            "go2rtc.".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]
+    }
+}
@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use std::{process::Stdio, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command, sync::mpsc, time::sleep};
+
+use crate::frame_pool::PooledFrame;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HlsConfig {
+    // Directory for the .ts segments and playlist.m3u8
+    pub directory: String,
+    pub segment_seconds: u32,
+    // Number of segments kept in the playlist (older ones are deleted)
+    pub playlist_length: u32,
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            directory: "hls".to_string(),
+            segment_seconds: 4,
+            playlist_length: 10,
+        }
+    }
+}
+
+// Start the HLS writer and return the sender that JPEG frames should be pushed into.
+// Frames are piped into a separate GStreamer process that transcodes to H.264 and
+// lets hlssink2 handle segmenting and the playlist.
+pub fn spawn_hls_writer(config: HlsConfig) -> mpsc::Sender<PooledFrame> {
+    let (tx, mut rx) = mpsc::channel::<PooledFrame>(30);
+
+    tokio::spawn(async move {
+        if let Err(e) = std::fs::create_dir_all(&config.directory) {
+            eprintln!("Failed to create HLS directory {}: {}", config.directory, e);
+            return;
+        }
+
+        loop {
+            let mut child = match start_hls_pipeline(&config) {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Failed to start HLS pipeline: {}", e);
+                    return;
+                }
+            };
+            let mut stdin = child.stdin.take().expect("Failed to capture HLS pipeline stdin");
+            println!("HLS writer started in {}", config.directory);
+
+            let mut channel_closed = true;
+            while let Some(frame) = rx.recv().await {
+                if let Err(e) = stdin.write_all(&frame).await {
+                    eprintln!("HLS pipeline stopped accepting frames: {}", e);
+                    channel_closed = false;
+                    break;
+                }
+            }
+
+            drop(stdin);
+            let _ = child.kill().await;
+            if channel_closed {
+                println!("HLS writer shutting down");
+                break;
+            }
+
+            // Give the pipeline a moment before restarting it
+            sleep(Duration::from_secs(2)).await;
+        }
+    });
+
+    tx
+}
+
+fn start_hls_pipeline(config: &HlsConfig) -> std::io::Result<tokio::process::Child> {
+    Command::new("gst-launch-1.0")
+        .args([
+            "fdsrc",
+            "fd=0",
+            "do-timestamp=true",
+            "!",
+            "jpegparse",
+            "!",
+            "jpegdec",
+            "!",
+            "videoconvert",
+            "!",
+            "x264enc",
+            "tune=zerolatency",
+            "speed-preset=ultrafast",
+            "!",
+            "h264parse",
+            "!",
+            "hlssink2",
+            &format!("location={}/segment%05d.ts", config.directory),
+            &format!("playlist-location={}/playlist.m3u8", config.directory),
+            &format!("target-duration={}", config.segment_seconds),
+            &format!("playlist-length={}", config.playlist_length),
+            &format!("max-files={}", config.playlist_length + 2),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+}
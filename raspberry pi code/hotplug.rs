@@ -0,0 +1,249 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::sleep;
+
+use crate::capture_source::{CaptureSource, CaptureConfig};
+use crate::direct_capture;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HotplugConfig {
+    // Wait for an unplugged USB camera to come back rather than restarting capture over
+    // and over; only for V4L2 devices
+    pub enabled: bool,
+    // Given to udev after the device node reappears, to set its permissions and links
+    pub settle_ms: u64,
+    // How often the device node is checked between device events, or instead of them
+    // where they can't be received
+    pub poll_seconds: u64,
+}
+
+impl Default for HotplugConfig {
+    fn default() -> Self {
+        Self { enabled: true, settle_ms: 500, poll_seconds: 2 }
+    }
+}
+
+// Whether the camera's device node is there. A /dev/v4l/by-id/ path in `capture.device`
+// finds the same camera again even if it comes back under another /dev/videoN.
+#[derive(Clone)]
+pub struct CameraDevice {
+    path: Arc<str>,
+    present: Arc<AtomicBool>,
+    returned: Arc<AtomicBool>,
+}
+
+impl CameraDevice {
+    pub fn present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    // True once after the camera is plugged back in, when capture should start again
+    pub fn take_returned(&self) -> bool {
+        self.returned.swap(false, Ordering::Relaxed)
+    }
+
+    #[cfg(unix)]
+    fn node_exists(&self) -> bool {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata(&*self.path).is_ok_and(|metadata| metadata.file_type().is_char_device())
+    }
+
+    #[cfg(not(unix))]
+    fn node_exists(&self) -> bool {
+        std::path::Path::new(&*self.path).exists()
+    }
+
+    async fn refresh(&self, settle: Duration) {
+        let exists = self.node_exists();
+        if exists == self.present() {
+            return;
+        }
+        if exists {
+            sleep(settle).await;
+            if !self.node_exists() {
+                return;
+            }
+            println!("Camera {} is back", self.path);
+            self.present.store(true, Ordering::Relaxed);
+            self.returned.store(true, Ordering::Relaxed);
+        } else {
+            eprintln!("Camera {} was unplugged, waiting for it to come back", self.path);
+            self.present.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+// None for cameras that can't be unplugged, or that the capture element finds by name
+pub fn camera_device(config: &HotplugConfig, capture: &CaptureConfig) -> Option<CameraDevice> {
+    if !config.enabled || !matches!(capture.backend(), CaptureSource::V4l2 | CaptureSource::Direct) {
+        return None;
+    }
+    let path = capture.device.clone().unwrap_or_else(|| direct_capture::DEFAULT_DEVICE.to_string());
+    let device = CameraDevice {
+        path: Arc::from(path),
+        present: Arc::new(AtomicBool::new(true)),
+        returned: Arc::new(AtomicBool::new(false)),
+    };
+    device.present.store(device.node_exists(), Ordering::Relaxed);
+    if !device.present() {
+        eprintln!("Camera {} isn't plugged in yet", device.path);
+    }
+    Some(device)
+}
+
+// Follow the camera through unplugs and replugs. Run by the supervisor, which starts it
+// again if the event socket fails.
+pub async fn monitor(config: HotplugConfig, device: CameraDevice) {
+    let settle = Duration::from_millis(config.settle_ms);
+    let poll = Duration::from_secs(config.poll_seconds.max(1));
+
+    #[cfg(target_os = "linux")]
+    match uevents::UeventSocket::open() {
+        Ok(socket) => loop {
+            match tokio::time::timeout(poll, socket.video_event()).await {
+                Ok(Err(e)) => {
+                    eprintln!("Failed to read device events: {}", e);
+                    return;
+                }
+                // A device came or went, or it's time to look anyway
+                Ok(Ok(())) | Err(_) => device.refresh(settle).await,
+            }
+        },
+        Err(e) => eprintln!("No device events ({}), checking {} every {:?} instead", e, device.path, poll),
+    }
+
+    loop {
+        sleep(poll).await;
+        device.refresh(settle).await;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod uevents {
+    use std::{
+        io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+    use tokio::io::unix::AsyncFd;
+
+    // The kernel's device events, the ones udev itself acts on, read straight off netlink.
+    // The udev crate would link libudev, which the cross builds don't have (the same reason
+    // rscam is built without libv4l2), and only two fields of each event are needed here.
+    pub struct UeventSocket(AsyncFd<OwnedFd>);
+
+    impl UeventSocket {
+        pub fn open() -> io::Result<Self> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            // Group 1 is the kernel's broadcast; udev rebroadcasts on 2 in its own format
+            address.nl_groups = 1;
+            let bound = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if bound < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(AsyncFd::new(fd)?))
+        }
+
+        // Returns when a video4linux device is added or removed. Each message is
+        // "action@devpath" followed by NUL-separated KEY=value fields.
+        pub async fn video_event(&self) -> io::Result<()> {
+            let mut buffer = [0u8; 8192];
+            loop {
+                let mut guard = self.0.readable().await?;
+                let Ok(read) = guard.try_io(|fd| {
+                    let read = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+                    if read < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(read as usize)
+                    }
+                }) else {
+                    continue;
+                };
+                if is_video_plug(&buffer[..read?]) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Whether a uevent message is a video4linux device being added or removed
+    fn is_video_plug(message: &[u8]) -> bool {
+        let fields: Vec<&[u8]> = message.split(|byte| *byte == 0).collect();
+        let video = fields.contains(&&b"SUBSYSTEM=video4linux"[..]);
+        let plugged = fields.contains(&&b"ACTION=add"[..]) || fields.contains(&&b"ACTION=remove"[..]);
+        video && plugged
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::is_video_plug;
+
+        fn message(fields: &[&str]) -> Vec<u8> {
+            fields.iter().flat_map(|field| field.bytes().chain([0])).collect()
+        }
+
+        #[test]
+        fn camera_added_and_removed() {
+            for action in ["add", "remove"] {
+                let event = message(&[
+                    &format!("{}@/devices/platform/usb/1-1/1-1:1.0/video4linux/video0", action),
+                    &format!("ACTION={}", action),
+                    "DEVPATH=/devices/platform/usb/1-1/1-1:1.0/video4linux/video0",
+                    "SUBSYSTEM=video4linux",
+                    "DEVNAME=/dev/video0",
+                    "SEQNUM=2301",
+                ]);
+                assert!(is_video_plug(&event), "{}", action);
+            }
+        }
+
+        #[test]
+        fn other_actions_and_devices_ignored() {
+            let change = message(&["change@/devices/video4linux/video0", "ACTION=change", "SUBSYSTEM=video4linux"]);
+            assert!(!is_video_plug(&change));
+            let usb = message(&["add@/devices/platform/usb/1-1", "ACTION=add", "SUBSYSTEM=usb", "DEVTYPE=usb_device"]);
+            assert!(!is_video_plug(&usb));
+        }
+
+        #[test]
+        fn fields_match_whole() {
+            // A value that only starts with the subsystem name isn't the subsystem
+            let event = message(&["add@/devices/x", "ACTION=add", "SUBSYSTEM=video4linux2"]);
+            assert!(!is_video_plug(&event));
+            // The header names the action too, but only the ACTION field counts
+            let header_only = message(&["add@/devices/video4linux/video0", "SUBSYSTEM=video4linux"]);
+            assert!(!is_video_plug(&header_only));
+        }
+
+        #[test]
+        fn empty_and_unterminated() {
+            assert!(!is_video_plug(b""));
+            assert!(is_video_plug(b"ACTION=remove\0SUBSYSTEM=video4linux"));
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::{sleep_until, Instant};
+
+use crate::encoder::Codec;
+use crate::privacy::PrivacyConfig;
+use crate::queue::FrameReceiver;
+use crate::server_address::ProxyConfig;
+use crate::stats_db::StatsCounters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostFormat {
+    // multipart/form-data with a "frame" file part and camera_id, stream_id, codec and
+    // timestamp as text fields
+    Multipart,
+    // The frame as the request body, with the same details in X- headers
+    Raw,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpFallbackConfig {
+    // Endpoint the frames are POSTed to, e.g. "https://relay.example.com/ingest"
+    pub url: String,
+    pub format: PostFormat,
+    // Failed WebSocket connects in a row before switching to HTTP
+    pub after_failures: u32,
+    // Shortest time between two posted frames; the rest are skipped
+    pub min_interval_ms: u64,
+    // How long to post before trying the WebSocket again
+    pub retry_websocket_seconds: u64,
+    // Extra request headers, e.g. {"Authorization": "Bearer ..."}
+    pub headers: HashMap<String, String>,
+}
+
+impl Default for HttpFallbackConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            format: PostFormat::Multipart,
+            after_failures: 3,
+            min_interval_ms: 1000,
+            retry_websocket_seconds: 60,
+            headers: HashMap::new(),
+        }
+    }
+}
+
+fn content_type(codec: Codec) -> &'static str {
+    match codec {
+        Codec::Mjpeg => "image/jpeg",
+        Codec::Vp9 | Codec::Av1 => "application/octet-stream",
+    }
+}
+
+// Frames over plain HTTPS at a reduced rate, for networks whose firewall lets
+// requests through but blocks WebSocket upgrades
+#[derive(Clone)]
+pub struct HttpFallback {
+    config: HttpFallbackConfig,
+    client: reqwest::Client,
+    camera_id: String,
+    privacy: Option<PrivacyConfig>,
+    stats_counters: StatsCounters,
+}
+
+impl HttpFallback {
+    pub fn new(
+        config: HttpFallbackConfig,
+        proxy: Option<&ProxyConfig>,
+        camera_id: String,
+        privacy: Option<PrivacyConfig>,
+        stats_counters: StatsCounters
+    ) -> Result<Self, String> {
+        if config.url.is_empty() {
+            return Err("http_fallback.url is not set".to_string());
+        }
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(15));
+        // The HTTP proxy that tunnels the WebSocket carries the posts as well
+        if let Some(proxy) = proxy {
+            let mut http_proxy = reqwest::Proxy::all(&proxy.url).map_err(|e| format!("proxy {}: {}", proxy.url, e))?;
+            if let Some(username) = &proxy.username {
+                http_proxy = http_proxy.basic_auth(username, proxy.password.as_deref().unwrap_or(""));
+            }
+            builder = builder.proxy(http_proxy);
+        }
+        let client = builder.build().map_err(|e| e.to_string())?;
+        Ok(Self { config, client, camera_id, privacy, stats_counters })
+    }
+
+    // Whether enough WebSocket connects have failed in a row to fall back
+    pub fn should_take_over(&self, failures: u32) -> bool {
+        failures >= self.config.after_failures.max(1)
+    }
+
+    // Post frames until it is time to try the WebSocket again. Returns false once the
+    // frame source is gone.
+    pub async fn run(&self, rx: &mut FrameReceiver, codec: Codec) -> bool {
+        let retry_at = Instant::now() + Duration::from_secs(self.config.retry_websocket_seconds.max(5));
+        let min_interval = Duration::from_millis(self.config.min_interval_ms);
+        let mut last_post: Option<Instant> = None;
+        let mut failures_logged = false;
+        loop {
+            let frame = tokio::select! {
+                frame = rx.recv() => frame,
+                _ = sleep_until(retry_at) => return true,
+            };
+            let Some(frame) = frame else {
+                return false;
+            };
+            if last_post.is_some_and(|at| at.elapsed() < min_interval) {
+                continue;
+            }
+            last_post = Some(Instant::now());
+
+            let stripped = self.privacy.as_ref().and_then(|privacy| privacy.strip(&frame.data));
+            let data = stripped.unwrap_or_else(|| frame.data.to_vec());
+            let timestamp = self.privacy.as_ref().map_or(frame.timestamp.wall_ms, |privacy| privacy.timestamp(frame.timestamp.wall_ms));
+            let size = data.len() as u64;
+            match self.post(data, &frame.stream_id, codec, timestamp).await {
+                Ok(()) => {
+                    StatsCounters::add(&self.stats_counters.frames_sent, 1);
+                    StatsCounters::add(&self.stats_counters.bytes_sent, size);
+                    failures_logged = false;
+                }
+                // One line per run of failures rather than one per frame
+                Err(e) if !failures_logged => {
+                    eprintln!("HTTP fallback post failed: {}", e);
+                    failures_logged = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    async fn post(&self, data: Vec<u8>, stream_id: &str, codec: Codec, timestamp: u64) -> Result<(), String> {
+        let mut request = self.client.post(&self.config.url);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        request = match self.config.format {
+            PostFormat::Multipart => {
+                let part = reqwest::multipart::Part::bytes(data)
+                    .file_name(if codec == Codec::Mjpeg { "frame.jpg" } else { "frame.bin" })
+                    .mime_str(content_type(codec))
+                    .map_err(|e| e.to_string())?;
+                let form = reqwest::multipart::Form::new()
+                    .text("camera_id", self.camera_id.clone())
+                    .text("stream_id", stream_id.to_string())
+                    .text("codec", codec.name())
+                    .text("timestamp", timestamp.to_string())
+                    .part("frame", part);
+                request.multipart(form)
+            }
+            PostFormat::Raw => request
+                .header("Content-Type", content_type(codec))
+                .header("X-Camera-Id", &self.camera_id)
+                .header("X-Stream-Id", stream_id)
+                .header("X-Codec", codec.name())
+                .header("X-Timestamp", timestamp.to_string())
+                .body(data),
+        };
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
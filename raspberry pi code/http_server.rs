@@ -0,0 +1,91 @@
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::snapshot::{self, LatestFrame};
+use crate::stream_state::StreamStatus;
+
+// Minimal local HTTP server for health checks and the latest still
+pub async fn run_http_server(listen: String, status: StreamStatus, latest_frame: LatestFrame) {
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind HTTP server on {}: {}", listen, e);
+            return;
+        }
+    };
+    println!("HTTP server listening on {}", listen);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let status = status.clone();
+                let latest_frame = latest_frame.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, status, latest_frame).await {
+                        eprintln!("HTTP request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept HTTP connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, status: StreamStatus, latest_frame: LatestFrame) -> std::io::Result<()> {
+    let mut buffer = [0u8; 2048];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    // Current still for dashboards that don't want the whole stream; ?width=N for a thumbnail
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    if path == "/snapshot.jpg" {
+        let width = query.split('&').find_map(|pair| pair.strip_prefix("width=")).and_then(|width| width.parse().ok());
+        return match latest_frame.get() {
+            Some(jpeg) => {
+                let jpeg = match width {
+                    Some(width) => tokio::task::spawn_blocking(move || snapshot::thumbnail(jpeg, width)).await.map_err(std::io::Error::other)?,
+                    None => jpeg,
+                };
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                    jpeg.len()
+                );
+                stream.write_all(header.as_bytes()).await?;
+                stream.write_all(&jpeg).await
+            }
+            None => write_json(&mut stream, "503 Service Unavailable", json!({ "error": "no frame available" })).await,
+        };
+    }
+
+    let (code, body) = match path {
+        "/healthz" => {
+            let (state, duration) = status.snapshot();
+            let body = json!({
+                "state": state.name(),
+                "state_seconds": duration.as_secs(),
+            });
+            if state.is_connected() {
+                ("200 OK", body)
+            } else {
+                ("503 Service Unavailable", body)
+            }
+        }
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    };
+    write_json(&mut stream, code, body).await
+}
+
+pub async fn write_json(stream: &mut TcpStream, code: &str, body: serde_json::Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
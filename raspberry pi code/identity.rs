@@ -0,0 +1,154 @@
+use base64::prelude::*;
+use ed25519_dalek::{Signer, SigningKey};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{path::Path, time::Duration};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::server_address::{self, ProxyConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    // Device private key (32-byte ed25519 seed), created on first --provision
+    pub key_path: String,
+    // How long to wait for an operator to approve the enrollment code on the server
+    pub enrollment_timeout_seconds: u64,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            key_path: "camera.key".to_string(),
+            enrollment_timeout_seconds: 600,
+        }
+    }
+}
+
+// The device keypair that identifies this camera to the fleet
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+}
+
+impl DeviceIdentity {
+    // An existing key only; a new one wouldn't match what the server enrolled
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let seed: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "device key must be 32 bytes")
+        })?;
+        Ok(Self { signing_key: SigningKey::from_bytes(&seed) })
+    }
+
+    pub fn load_or_generate(path: &str) -> std::io::Result<Self> {
+        if Path::new(path).exists() {
+            return Self::load(path);
+        }
+
+        println!("Generating new device key at {}", path);
+        let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+        std::fs::write(path, signing_key.to_bytes())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(Self { signing_key })
+    }
+
+    pub fn public_key(&self) -> String {
+        BASE64_STANDARD.encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    pub fn fingerprint(&self) -> [u8; 32] {
+        Sha256::digest(self.signing_key.verifying_key().to_bytes()).into()
+    }
+
+    // Stable id derived from the public key
+    pub fn camera_id(&self) -> String {
+        let fingerprint = self.fingerprint();
+        let hex: String = fingerprint[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("camera-rust-{}", hex)
+    }
+
+    // Short code an operator compares against what the server shows, e.g. "K7QF-M2XA"
+    pub fn enrollment_code(&self) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+        let fingerprint = self.fingerprint();
+        let code: String = fingerprint[..8]
+            .iter()
+            .map(|b| ALPHABET[(*b as usize) % ALPHABET.len()] as char)
+            .collect();
+        format!("{}-{}", &code[..4], &code[4..])
+    }
+
+    pub fn sign(&self, data: &[u8]) -> String {
+        BASE64_STANDARD.encode(self.signing_key.sign(data).to_bytes())
+    }
+}
+
+// --provision: create the device key if needed, show the enrollment code/QR and
+// complete the challenge-response enrollment with the server
+pub async fn run_provisioning(config: IdentityConfig, server_url: &str, proxy: Option<&ProxyConfig>) -> Result<(), String> {
+    let identity = DeviceIdentity::load_or_generate(&config.key_path).map_err(|e| e.to_string())?;
+    let camera_id = identity.camera_id();
+    let public_key = identity.public_key();
+    let code = identity.enrollment_code();
+
+    println!("Camera ID:       {}", camera_id);
+    println!("Public key:      {}", public_key);
+    println!("Enrollment code: {}", code);
+    let qr_payload = json!({ "camera_id": camera_id, "public_key": public_key, "code": code }).to_string();
+    match qrcode::QrCode::new(qr_payload.as_bytes()) {
+        Ok(qr) => println!("{}", qr.render::<qrcode::render::unicode::Dense1x2>().build()),
+        Err(e) => eprintln!("Failed to render enrollment QR code: {}", e),
+    }
+
+    let (ws_stream, _) = server_address::connect(server_url, proxy).await?;
+    let (mut write, mut read) = ws_stream.split();
+
+    let enroll = json!({
+        "enroll": { "camera_id": camera_id, "public_key": public_key, "code": code }
+    }).to_string();
+    write.send(Message::Text(enroll)).await.map_err(|e| e.to_string())?;
+    println!("Waiting for an operator to approve code {} on the server...", code);
+
+    let wait = Duration::from_secs(config.enrollment_timeout_seconds);
+    timeout(wait, async {
+        while let Some(msg) = read.next().await {
+            let text = match msg {
+                Ok(Message::Text(text)) => text,
+                Ok(_) => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            let Ok(reply) = serde_json::from_str::<serde_json::Value>(&text) else {
+                continue;
+            };
+
+            // Prove we hold the private key for the public key we enrolled with
+            if let Some(challenge) = reply.get("challenge").and_then(|c| c.as_str()) {
+                let nonce = BASE64_STANDARD.decode(challenge).map_err(|e| e.to_string())?;
+                let response = json!({
+                    "challenge_response": { "camera_id": camera_id, "signature": identity.sign(&nonce) }
+                }).to_string();
+                write.send(Message::Text(response)).await.map_err(|e| e.to_string())?;
+                continue;
+            }
+
+            if let Some(enrolled) = reply.get("enrolled").and_then(|e| e.as_bool()) {
+                if enrolled {
+                    println!("Camera {} enrolled successfully", camera_id);
+                    return Ok(());
+                }
+                let reason = reply.get("reason").and_then(|r| r.as_str()).unwrap_or("no reason given");
+                return Err(format!("enrollment rejected: {}", reason));
+            }
+        }
+        Err("server closed the connection during enrollment".to_string())
+    })
+    .await
+    .map_err(|_| "timed out waiting for enrollment approval".to_string())?
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    process::{Child, Command},
+    sync::{broadcast::error::RecvError, mpsc},
+    time::{interval, sleep, Instant},
+};
+
+use crate::raw::{RawFrame, RawFrames};
+
+// How often the light level is measured
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IlluminatorOutput {
+    // An LED board switched on and off through libgpiod's gpioset
+    Gpio { chip: String, line: u32 },
+    // A dimmable board on a hardware PWM channel (dtoverlay=pwm on a Pi), through sysfs.
+    // With the Landlock sandbox, the chip's directory under /sys/devices has to be in
+    // sandbox.writable.
+    Pwm {
+        chip: u32,
+        channel: u32,
+        // The default 20kHz is fast enough not to show up as bands across a rolling shutter
+        #[serde(default = "default_period_ns")]
+        period_ns: u64,
+    },
+}
+
+fn default_period_ns() -> u64 {
+    50_000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IlluminatorConfig {
+    pub output: IlluminatorOutput,
+    // Average brightness (0-255) below which it counts as night
+    pub night_below: f64,
+    // Average brightness above which it counts as day again. The LEDs light the scene
+    // up themselves, so this has to be above what the camera sees with only them on.
+    pub day_above: f64,
+    // How long the light level has to stay past a threshold before switching
+    pub switch_after_seconds: u64,
+    // Highest PWM duty cycle the LEDs are driven at, in percent; also the default brightness
+    pub max_duty_percent: u32,
+    // Longest the LEDs stay lit in one go before they are switched off for rest_minutes
+    // to cool down; 0 leaves them on all night
+    pub max_on_minutes: u64,
+    pub rest_minutes: u64,
+}
+
+impl Default for IlluminatorConfig {
+    fn default() -> Self {
+        Self {
+            output: IlluminatorOutput::Gpio { chip: "gpiochip0".to_string(), line: 18 },
+            night_below: 35.0,
+            day_above: 100.0,
+            switch_after_seconds: 30,
+            max_duty_percent: 80,
+            max_on_minutes: 0,
+            rest_minutes: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IlluminatorMode {
+    // Follow the low-light detector
+    Auto,
+    On,
+    Off,
+}
+
+impl IlluminatorMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            IlluminatorMode::Auto => "auto",
+            IlluminatorMode::On => "on",
+            IlluminatorMode::Off => "off",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IlluminatorCommand {
+    pub mode: IlluminatorMode,
+    // Duty cycle in percent for a PWM board, capped at max_duty_percent
+    pub brightness: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IlluminatorState {
+    pub mode: IlluminatorMode,
+    // What the low-light detector last decided
+    pub night: bool,
+    // Duty cycle the LEDs are driven at, 0 when off
+    pub duty_percent: u32,
+    // Off until the rest after max_on_minutes is over
+    pub resting: bool,
+}
+
+// Overrides the illuminator from the protocol or the control socket
+#[derive(Clone)]
+pub struct IlluminatorHandle {
+    tx: mpsc::Sender<IlluminatorCommand>,
+    state: Arc<Mutex<IlluminatorState>>,
+}
+
+impl IlluminatorHandle {
+    pub async fn set(&self, command: IlluminatorCommand) {
+        let _ = self.tx.send(command).await;
+    }
+
+    pub fn state(&self) -> IlluminatorState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+// The LED board, holding whatever keeps the output at its level
+enum Driver {
+    // gpioset only holds the line while it runs
+    Gpio { chip: String, line: u32, holder: Option<Child> },
+    Pwm { directory: PathBuf, period_ns: u64 },
+}
+
+impl Driver {
+    async fn open(output: &IlluminatorOutput) -> Result<Self, String> {
+        match output {
+            IlluminatorOutput::Gpio { chip, line } => Ok(Driver::Gpio { chip: chip.clone(), line: *line, holder: None }),
+            IlluminatorOutput::Pwm { chip, channel, period_ns } => {
+                let chip_directory = PathBuf::from(format!("/sys/class/pwm/pwmchip{}", chip));
+                let directory = chip_directory.join(format!("pwm{}", channel));
+                if !directory.exists() {
+                    write(&chip_directory.join("export"), channel).await?;
+                    // udev fixes up the permissions of the new directory shortly after
+                    sleep(Duration::from_millis(500)).await;
+                }
+                // The duty cycle may never exceed the period, so clear it before changing that
+                write(&directory.join("duty_cycle"), 0).await?;
+                write(&directory.join("period"), period_ns).await?;
+                write(&directory.join("enable"), 1).await?;
+                Ok(Driver::Pwm { directory, period_ns: *period_ns })
+            }
+        }
+    }
+
+    async fn set(&mut self, duty_percent: u32) -> Result<(), String> {
+        match self {
+            Driver::Gpio { chip, line, holder } => {
+                if let Some(mut previous) = holder.take() {
+                    let _ = previous.kill().await;
+                }
+                let value = u32::from(duty_percent > 0);
+                let child = Command::new("gpioset")
+                    .args(["--mode=signal", chip.as_str(), &format!("{}={}", line, value)])
+                    .stdin(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| format!("failed to run gpioset: {}", e))?;
+                *holder = Some(child);
+                Ok(())
+            }
+            Driver::Pwm { directory, period_ns } => {
+                write(&directory.join("duty_cycle"), *period_ns * duty_percent as u64 / 100).await
+            }
+        }
+    }
+}
+
+async fn write(path: &std::path::Path, value: impl std::fmt::Display) -> Result<(), String> {
+    tokio::fs::write(path, value.to_string()).await.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn mean_luma(frame: &RawFrame) -> f64 {
+    let luma = frame.luma();
+    // Every 16th pixel is plenty for an average
+    let (sum, count) = luma.iter().step_by(16).fold((0u64, 0u64), |(sum, count), &value| (sum + value as u64, count + 1));
+    sum as f64 / count.max(1) as f64
+}
+
+// Switch the IR LEDs with the light level measured on the raw frames, unless the
+// server or a local client has overridden it
+pub fn spawn_illuminator(config: IlluminatorConfig, raw_frames: &RawFrames) -> IlluminatorHandle {
+    let (tx, mut rx) = mpsc::channel::<IlluminatorCommand>(8);
+    let max_duty = config.max_duty_percent.min(100);
+    let initial = IlluminatorState { mode: IlluminatorMode::Auto, night: false, duty_percent: 0, resting: false };
+    let handle = IlluminatorHandle { tx, state: Arc::new(Mutex::new(initial.clone())) };
+    let shared_state = handle.state.clone();
+    let mut frames = raw_frames.subscribe();
+
+    tokio::spawn(async move {
+        let mut driver = match Driver::open(&config.output).await {
+            Ok(driver) => driver,
+            Err(e) => {
+                eprintln!("IR illuminator disabled: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = driver.set(0).await {
+            eprintln!("Failed to switch the IR illuminator off: {}", e);
+        }
+
+        let switch_after = Duration::from_secs(config.switch_after_seconds);
+        let max_on = Duration::from_secs(config.max_on_minutes * 60);
+        let mut state = initial;
+        let mut brightness = max_duty;
+        let mut last_sample: Option<Instant> = None;
+        // When the light level first crossed the threshold for the other mode
+        let mut crossed_since: Option<Instant> = None;
+        let mut lit_since: Option<Instant> = None;
+        let mut resting_until: Option<Instant> = None;
+        // Rest periods end without a frame or command arriving
+        let mut tick = interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                Some(command) = rx.recv() => {
+                    if let Some(requested) = command.brightness {
+                        brightness = requested.min(max_duty);
+                    }
+                    println!("IR illuminator set to {} at {}%", command.mode.name(), brightness);
+                    state.mode = command.mode;
+                }
+                frame = frames.recv() => {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    };
+                    if last_sample.is_some_and(|at| at.elapsed() < SAMPLE_INTERVAL) {
+                        continue;
+                    }
+                    last_sample = Some(Instant::now());
+                    let level = mean_luma(&frame);
+                    let crossed = if state.night { level > config.day_above } else { level < config.night_below };
+                    if !crossed {
+                        crossed_since = None;
+                    } else if crossed_since.get_or_insert_with(Instant::now).elapsed() >= switch_after {
+                        crossed_since = None;
+                        state.night = !state.night;
+                        println!("Low light: {} (average brightness {:.0})", if state.night { "night" } else { "day" }, level);
+                    }
+                }
+                _ = tick.tick() => {}
+            }
+
+            let now = Instant::now();
+            if resting_until.is_some_and(|until| now >= until) {
+                resting_until = None;
+                println!("IR illuminator rest over");
+            }
+            let wanted = match state.mode {
+                IlluminatorMode::Auto => state.night,
+                IlluminatorMode::On => true,
+                IlluminatorMode::Off => false,
+            };
+            if wanted && !max_on.is_zero() && lit_since.is_some_and(|since| now - since >= max_on) {
+                println!("IR illuminator on for {} minutes, resting it for {}", config.max_on_minutes, config.rest_minutes);
+                resting_until = Some(now + Duration::from_secs(config.rest_minutes * 60));
+            }
+            state.resting = resting_until.is_some();
+            let duty = if wanted && !state.resting { brightness } else { 0 };
+
+            if duty != state.duty_percent {
+                if let Err(e) = driver.set(duty).await {
+                    eprintln!("IR illuminator disabled, failed to set it to {}%: {}", duty, e);
+                    return;
+                }
+                lit_since = match (duty > 0, lit_since) {
+                    (true, None) => Some(now),
+                    (true, since) => since,
+                    (false, _) => None,
+                };
+                state.duty_percent = duty;
+            }
+            *shared_state.lock().unwrap() = state.clone();
+        }
+
+        let _ = driver.set(0).await;
+    });
+
+    handle
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::raw::{RawFrame, RawFrames};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageQualityConfig {
+    pub interval_seconds: u64,
+    // Laplacian variance below this means the image is out of focus (or the lens is dirty)
+    pub blur_threshold: f64,
+    // Percentage of clipped pixels above which the image counts as over/under exposed
+    pub exposure_threshold_percent: f64,
+    // Estimated noise sigma above which the image counts as noisy
+    pub noise_threshold: f64,
+}
+
+impl Default for ImageQualityConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 30,
+            blur_threshold: 50.0,
+            exposure_threshold_percent: 25.0,
+            noise_threshold: 8.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageQuality {
+    pub sharpness: f64,
+    pub overexposed_percent: f64,
+    pub underexposed_percent: f64,
+    pub noise: f64,
+    // Problems worth flagging to an operator: "blurry", "overexposed", "underexposed", "noisy"
+    pub flags: Vec<&'static str>,
+}
+
+// Latest measurement, shared with the uplink for stats
+pub type SharedImageQuality = Arc<Mutex<Option<ImageQuality>>>;
+
+// Periodically analyze one raw frame
+pub fn spawn_image_quality_analyzer(config: ImageQualityConfig, raw_frames: &RawFrames) -> SharedImageQuality {
+    let latest: SharedImageQuality = Arc::new(Mutex::new(None));
+    let shared = latest.clone();
+    let mut frames = raw_frames.subscribe();
+
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(config.interval_seconds.max(1));
+        let mut last_analysis: Option<Instant> = None;
+
+        loop {
+            let frame = match frames.recv().await {
+                Ok(frame) => frame,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            if last_analysis.is_some_and(|at| at.elapsed() < interval) {
+                continue;
+            }
+            last_analysis = Some(Instant::now());
+
+            let config = config.clone();
+            let result = tokio::task::spawn_blocking(move || analyze(&frame, &config)).await;
+            let Ok(quality) = result else {
+                continue;
+            };
+            if !quality.flags.is_empty() {
+                println!("Image quality problems: {} (sharpness {:.1}, noise {:.1})",
+                        quality.flags.join(", "), quality.sharpness, quality.noise);
+            }
+            *shared.lock().unwrap() = Some(quality);
+        }
+    });
+
+    latest
+}
+
+fn analyze(frame: &RawFrame, config: &ImageQualityConfig) -> ImageQuality {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let luma = frame.luma();
+
+    let mut over = 0usize;
+    let mut under = 0usize;
+    for &value in &luma {
+        if value >= 250 {
+            over += 1;
+        } else if value <= 5 {
+            under += 1;
+        }
+    }
+
+    let mut laplacian_sum = 0f64;
+    let mut laplacian_sq_sum = 0f64;
+    let mut noise_sum = 0f64;
+    let mut samples = 0usize;
+    let at = |x: usize, y: usize| luma[y * width + x] as f64;
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            // Sharpness: variance of the 4-neighbour Laplacian
+            let laplacian = 4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1);
+            laplacian_sum += laplacian;
+            laplacian_sq_sum += laplacian * laplacian;
+
+            // Noise: Immerkaer's fast estimate using a mask that cancels out edges
+            let mask = 4.0 * at(x, y)
+                - 2.0 * (at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1))
+                + at(x - 1, y - 1) + at(x + 1, y - 1) + at(x - 1, y + 1) + at(x + 1, y + 1);
+            noise_sum += mask.abs();
+            samples += 1;
+        }
+    }
+
+    let samples_f = samples.max(1) as f64;
+    let mean = laplacian_sum / samples_f;
+    let sharpness = laplacian_sq_sum / samples_f - mean * mean;
+    let noise = noise_sum * (std::f64::consts::PI / 2.0).sqrt() / (6.0 * samples_f);
+    let total = luma.len().max(1) as f64;
+    let overexposed_percent = over as f64 * 100.0 / total;
+    let underexposed_percent = under as f64 * 100.0 / total;
+
+    let mut flags = Vec::new();
+    if sharpness < config.blur_threshold {
+        flags.push("blurry");
+    }
+    if overexposed_percent > config.exposure_threshold_percent {
+        flags.push("overexposed");
+    }
+    if underexposed_percent > config.exposure_threshold_percent {
+        flags.push("underexposed");
+    }
+    if noise > config.noise_threshold {
+        flags.push("noisy");
+    }
+
+    ImageQuality {
+        sharpness,
+        overexposed_percent,
+        underexposed_percent,
+        noise,
+        flags,
+    }
+}
@@ -0,0 +1,159 @@
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Subsampling {
+    #[serde(rename = "4:2:0")]
+    Yuv420,
+    // Twice the colour resolution horizontally; sharper edges on coloured detail
+    #[serde(rename = "4:2:2")]
+    Yuv422,
+    #[serde(rename = "4:4:4")]
+    Yuv444,
+}
+
+impl Subsampling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsampling::Yuv420 => "4:2:0",
+            Subsampling::Yuv422 => "4:2:2",
+            Subsampling::Yuv444 => "4:4:4",
+        }
+    }
+
+    // jpegenc keeps the chroma layout of its input
+    fn caps_format(self) -> &'static str {
+        match self {
+            Subsampling::Yuv420 => "I420",
+            Subsampling::Yuv422 => "Y42B",
+            Subsampling::Yuv444 => "Y444",
+        }
+    }
+
+    fn sampling_factor(self) -> SamplingFactor {
+        match self {
+            Subsampling::Yuv420 => SamplingFactor::R_4_2_0,
+            Subsampling::Yuv422 => SamplingFactor::R_4_2_2,
+            Subsampling::Yuv444 => SamplingFactor::R_4_4_4,
+        }
+    }
+}
+
+// MJPEG encoder tuning. Subsampling is applied in the pipeline; jpegenc can't write
+// progressive scans or restart markers, so those frames are re-encoded here, which
+// costs a decode and encode per frame.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JpegConfig {
+    pub subsampling: Subsampling,
+    // Coarse-to-fine scans: smaller files, and a cut-off frame still shows the whole image
+    pub progressive: bool,
+    // Restart marker every this many MCUs, so a corrupted byte only spoils a strip of the frame
+    pub restart_interval: Option<u16>,
+}
+
+impl Default for JpegConfig {
+    fn default() -> Self {
+        Self {
+            subsampling: Subsampling::Yuv420,
+            progressive: false,
+            restart_interval: None,
+        }
+    }
+}
+
+// jpegenc quality while frames are re-encoded, so the second pass loses little
+const REENCODE_SOURCE_QUALITY: u32 = 95;
+
+impl JpegConfig {
+    pub fn needs_reencode(&self) -> bool {
+        self.progressive || self.restart_interval.is_some()
+    }
+
+    // Caps and encoder in front of the MJPEG fdsink
+    pub fn pipeline_args(&self, quality: u32) -> Vec<String> {
+        if self.needs_reencode() {
+            return reencode_source_args(self.subsampling);
+        }
+        encoder_args(self.subsampling, quality)
+    }
+
+    // Settings reported with every MJPEG frame
+    pub fn stats(&self) -> serde_json::Value {
+        json!({
+            "subsampling": self.subsampling.name(),
+            "progressive": self.progressive,
+            "restart_interval": self.restart_interval
+        })
+    }
+}
+
+fn encoder_args(subsampling: Subsampling, quality: u32) -> Vec<String> {
+    vec![
+        format!("video/x-raw,format={}", subsampling.caps_format()),
+        "!".into(),
+        "jpegenc".into(),
+        format!("quality={}", quality),
+    ]
+}
+
+// jpegenc settings for frames that are re-encoded afterwards
+pub fn reencode_source_args(subsampling: Subsampling) -> Vec<String> {
+    encoder_args(subsampling, REENCODE_SOURCE_QUALITY)
+}
+
+// Decoded pixels of a frame, for encoding again
+pub struct Pixels {
+    pub data: Vec<u8>,
+    pub width: u16,
+    pub height: u16,
+    pub color: ColorType,
+}
+
+pub fn decode(jpeg: &[u8]) -> Result<Pixels, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(jpeg);
+    let data = decoder.decode().map_err(|e| e.to_string())?;
+    let info = decoder.info().ok_or("missing JPEG header")?;
+    let color = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => ColorType::Rgb,
+        jpeg_decoder::PixelFormat::L8 => ColorType::Luma,
+        other => return Err(format!("unsupported pixel format {:?}", other)),
+    };
+    Ok(Pixels { data, width: info.width, height: info.height, color })
+}
+
+pub fn encode(pixels: &Pixels, config: &JpegConfig, quality: u32) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(pixels.data.len() / 8);
+    let mut encoder = Encoder::new(&mut output, quality.clamp(1, 100) as u8);
+    encoder.set_sampling_factor(config.subsampling.sampling_factor());
+    encoder.set_progressive(config.progressive);
+    if let Some(interval) = config.restart_interval {
+        encoder.set_restart_interval(interval);
+    }
+    encoder.encode(&pixels.data, pixels.width, pixels.height, pixels.color).map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+// Re-encodes frames from jpegenc with the settings it can't produce, at the current
+// uplink quality
+#[derive(Clone)]
+pub struct JpegTuner {
+    config: JpegConfig,
+    quality: Arc<AtomicU32>,
+}
+
+impl JpegTuner {
+    // None when jpegenc's output can be used as-is
+    pub fn new(config: JpegConfig, quality: Arc<AtomicU32>) -> Option<Self> {
+        config.needs_reencode().then_some(Self { config, quality })
+    }
+
+    pub fn reencode(&self, jpeg: &[u8]) -> Result<Vec<u8>, String> {
+        encode(&decode(jpeg)?, &self.config, self.quality.load(Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::capture_source::CaptureConfig;
+use crate::stills;
+
+// Pinhole camera model from an OpenCV calibration
+#[derive(Debug, Clone, Deserialize)]
+pub struct LensConfig {
+    // Row-major 3x3 camera matrix: fx 0 cx / 0 fy cy / 0 0 1
+    pub camera_matrix: [f64; 9],
+    // k1 k2 p1 p2 [k3]
+    pub distortion: Vec<f64>,
+}
+
+impl LensConfig {
+    // GStreamer elements that undistort raw frames (OpenCV plugin), inserted ahead of any scaling
+    pub fn undistort_args(&self) -> Vec<String> {
+        vec![
+            "cameraundistort".into(),
+            format!("settings=\"{}\"", self.settings_xml().replace('"', "\\\"")),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+        ]
+    }
+
+    // Same serialization the cameracalibrate element produces
+    fn settings_xml(&self) -> String {
+        let join = |values: &[f64]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ");
+        format!(
+            "<?xml version=\"1.0\"?><opencv_storage>\
+             <cameraMatrix type_id=\"opencv-matrix\"><rows>3</rows><cols>3</cols><dt>d</dt><data>{}</data></cameraMatrix>\
+             <distCoeffs type_id=\"opencv-matrix\"><rows>{}</rows><cols>1</cols><dt>d</dt><data>{}</data></distCoeffs>\
+             </opencv_storage>",
+            join(&self.camera_matrix),
+            self.distortion.len(),
+            join(&self.distortion)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    pub directory: String,
+    pub frames: u32,
+    pub interval_seconds: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            directory: "calibration".to_string(),
+            frames: 20,
+            interval_seconds: 3,
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+// --calibrate: capture a series of stills while someone moves a checkerboard around
+// the field of view. The images are fed to OpenCV's calibrateCamera offline and the
+// result goes into the `lens` section of the config.
+pub async fn run_calibration(config: CalibrationConfig, capture: CaptureConfig) {
+    if let Err(e) = std::fs::create_dir_all(&config.directory) {
+        eprintln!("Failed to create calibration directory {}: {}", config.directory, e);
+        return;
+    }
+    println!("Capturing {} calibration frames into {}, one every {}s. Move the checkerboard between captures.",
+            config.frames, config.directory, config.interval_seconds);
+
+    let mut captured = 0;
+    for index in 0..config.frames {
+        sleep(Duration::from_secs(config.interval_seconds)).await;
+        match stills::capture_still(&capture, config.width, config.height, 95).await {
+            Ok(jpeg) => {
+                let path = format!("{}/checkerboard-{:03}.jpg", config.directory, index);
+                match std::fs::write(&path, jpeg) {
+                    Ok(_) => {
+                        captured += 1;
+                        println!("Captured {} ({}/{})", path, index + 1, config.frames);
+                    }
+                    Err(e) => eprintln!("Failed to write {}: {}", path, e),
+                }
+            }
+            Err(e) => eprintln!("Failed to capture calibration frame: {}", e),
+        }
+    }
+    println!("Calibration capture finished: {} frames saved", captured);
+}
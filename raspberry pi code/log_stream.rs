@@ -0,0 +1,197 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogStreamConfig {
+    // Let the server turn on log streaming; off by default since it needs stdout and stderr
+    // routed through a pipe
+    pub enabled: bool,
+    // Records sent per second at most, with bursts of up to a second's worth saved up
+    pub max_per_second: u32,
+    // Streaming turns itself off after this long unless the server asks for less
+    pub max_minutes: u64,
+}
+
+impl Default for LogStreamConfig {
+    fn default() -> Self {
+        Self { enabled: false, max_per_second: 20, max_minutes: 60 }
+    }
+}
+
+// Everything goes through println! (info) or eprintln! (warn), so those are the levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off = 0,
+    Warn = 1,
+    Info = 2,
+}
+
+impl LogLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            2 => LogLevel::Info,
+            1 => LogLevel::Warn,
+            _ => LogLevel::Off,
+        }
+    }
+}
+
+// {"command": "log_stream", "level": "info", "minutes": 10}: stream records at or above
+// `level` as {"log": {...}} messages until the time is up or the level is set to "off"
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogStreamCommand {
+    pub level: LogLevel,
+    pub minutes: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub timestamp_ms: u64,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+static RECORDS: OnceLock<broadcast::Sender<LogRecord>> = OnceLock::new();
+
+fn records() -> &'static broadcast::Sender<LogRecord> {
+    RECORDS.get_or_init(|| broadcast::channel(256).0)
+}
+
+// Called for every line written to stdout or stderr, by the output tee that only Unix has
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn publish(level: LogLevel, message: &str) {
+    let records = records();
+    if records.receiver_count() == 0 {
+        return;
+    }
+    let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+    let _ = records.send(LogRecord { timestamp_ms, level, message: message.to_string() });
+}
+
+// The level the server asked for on this connection; a new connection starts with
+// streaming off
+#[derive(Clone)]
+pub struct LogShipper {
+    config: LogStreamConfig,
+    level: Arc<AtomicU8>,
+    until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl LogShipper {
+    // Returns what the audit log records as the outcome
+    pub fn apply(&self, command: &LogStreamCommand) -> String {
+        if !self.config.enabled {
+            return "rejected: log streaming disabled".to_string();
+        }
+        let minutes = command.minutes.unwrap_or(self.config.max_minutes).min(self.config.max_minutes);
+        *self.until.lock().unwrap() = Some(Instant::now() + Duration::from_secs(minutes * 60));
+        self.level.store(command.level as u8, Ordering::Relaxed);
+        if command.level == LogLevel::Off {
+            println!("Log streaming stopped");
+            "stopped".to_string()
+        } else {
+            println!("Streaming {} logs to the server for {} minutes", command.level.name(), minutes);
+            format!("streaming {} for {} minutes", command.level.name(), minutes)
+        }
+    }
+
+    fn level(&self) -> LogLevel {
+        let expired = self.until.lock().unwrap().is_some_and(|until| Instant::now() >= until);
+        if expired && self.level.swap(LogLevel::Off as u8, Ordering::Relaxed) != LogLevel::Off as u8 {
+            println!("Log streaming time is up, stopped");
+        }
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+}
+
+// Forward log records to the server for as long as the connection lasts. Records over
+// the rate limit are counted, and the count goes out with the next record that fits.
+pub fn spawn_log_shipper(config: LogStreamConfig, camera_id: String, outgoing: mpsc::Sender<Message>) -> LogShipper {
+    let shipper = LogShipper {
+        config: config.clone(),
+        level: Arc::new(AtomicU8::new(LogLevel::Off as u8)),
+        until: Arc::new(Mutex::new(None)),
+    };
+    if !config.enabled {
+        return shipper;
+    }
+    let state = shipper.clone();
+    let mut records = records().subscribe();
+
+    tokio::spawn(async move {
+        let rate = config.max_per_second.max(1) as f64;
+        let mut tokens = rate;
+        let mut refilled = Instant::now();
+        let mut dropped: u64 = 0;
+        loop {
+            let record = tokio::select! {
+                record = records.recv() => match record {
+                    Ok(record) => record,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        if state.level() != LogLevel::Off {
+                            dropped += missed;
+                        }
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = outgoing.closed() => break,
+            };
+            let level = state.level();
+            if level == LogLevel::Off || record.level > level {
+                continue;
+            }
+
+            let now = Instant::now();
+            tokens = (tokens + now.duration_since(refilled).as_secs_f64() * rate).min(rate);
+            refilled = now;
+            if tokens < 1.0 {
+                dropped += 1;
+                continue;
+            }
+            tokens -= 1.0;
+
+            let mut message = json!({
+                "log": {
+                    "camera_id": camera_id,
+                    "timestamp": record.timestamp_ms,
+                    "level": record.level.name(),
+                    "message": record.message,
+                }
+            });
+            if dropped > 0 {
+                message["log"]["dropped"] = json!(dropped);
+            }
+            // Logs never hold up command replies
+            match outgoing.try_send(Message::Text(message.to_string())) {
+                Ok(()) => dropped = 0,
+                Err(mpsc::error::TrySendError::Full(_)) => dropped += 1,
+                Err(mpsc::error::TrySendError::Closed(_)) => break,
+            }
+        }
+    });
+
+    shipper
+}
@@ -1,105 +1,202 @@
 use tokio::process::Command;
+use tokio::io::{AsyncRead, AsyncReadExt};  // This is actually used in process_frames
+use tokio_tungstenite::tungstenite::protocol::Message;
 use base64::prelude::*;
-use tokio::io::AsyncReadExt;  // This is actually used in process_frames
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use futures_util::{SinkExt, StreamExt};
 use serde_json::json;
 use uuid::Uuid;
-use std::{sync::{Arc, atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}}, time::Duration};
-use tokio::{sync::mpsc, time::sleep};
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering}}, time::Duration};
+use tokio::{sync::{mpsc, Notify}, time::sleep};
 
-struct NetworkState {
-    is_congested: bool,
-    congestion_level: u8,       // 0-10 scale, higher means more congested
-    stability_counter: u32,     // counts stable measurements before allowing changes
-    last_resolution_change: std::time::Instant, // prevent rapid resolution changes
-}
+mod access;
+mod adaptation;
+mod alarm;
+mod audit;
+mod av_container;
+mod boost;
+mod burst;
+mod camera_controls;
+mod capture_clock;
+mod capture_source;
+mod clock;
+mod commands;
+mod concealment;
+mod config;
+mod cpu_budget;
+mod congestion_history;
+mod control_socket;
+mod crash;
+mod custody;
+mod dashboard;
+mod digest;
+mod echo_test;
+mod decimation;
+mod direct_capture;
+mod disk_space;
+mod downscale;
+mod email;
+mod encode_profiles;
+mod encoder;
+mod encoder_experiment;
+mod encryption;
+mod envelope;
+mod event_queue;
+mod events;
+mod export;
+mod fisheye;
+mod flow_control;
+mod frame;
+mod frame_api;
+mod frame_pool;
+mod framing;
+mod go2rtc;
+mod hls;
+mod hotplug;
+mod http_fallback;
+mod http_server;
+mod illuminator;
+mod identity;
+mod image_quality;
+mod jpeg;
+mod lens;
+mod log_stream;
+mod maintenance;
+mod metadata;
+mod motion;
+mod mqtt;
+mod overlay;
+mod pause;
+mod pipeline;
+mod presence;
+mod privacy;
+mod protocol;
+mod protocol_errors;
+mod queue;
+mod raw;
+mod raw_archive;
+mod recording;
+mod resolution;
+mod rest_api;
+mod scene_complexity;
+mod retention;
+mod rtsp_server;
+mod sandbox;
+mod self_test;
+mod server_address;
+mod simulation;
+mod snapshot;
+mod sound_events;
+mod stabilize;
+mod stats_db;
+mod stills;
+mod storage;
+mod stream_priority;
+mod stream_state;
+mod supervisor;
+mod synthetic_capture;
+mod telegram;
+mod tenancy;
+mod thermal;
+mod test_pattern;
+mod time_sync;
+mod timeline;
+mod uplink_allocator;
+mod viewers;
+mod virtual_input;
+mod watchdog;
+mod wear;
+mod web_ui;
 
-impl NetworkState {
-    fn new() -> Self {
-        Self { 
-            is_congested: false, 
-            congestion_level: 0,
-            stability_counter: 0,
-            last_resolution_change: std::time::Instant::now(),
-        }
-    }
+use access::AccessConfig;
+use alarm::AlarmHandle;
+use adaptation::AdaptationStrategy;
+use audit::AuditLog;
+use av_container::AudioTap;
+use camera_controls::SharedCameraControls;
+use capture_clock::{CaptureClock, FrameTimestamp};
+use capture_source::{CaptureBackend, CaptureSource, FrameExtractor};
+use commands::{PtzCommand, ServerCommand};
+use config::Config;
+use congestion_history::{CongestionHistory, CongestionSample};
+use control_socket::ControlContext;
+use custody::CustodySources;
+use dashboard::{Dashboard, DashboardSources};
+use digest::DigestSources;
+use echo_test::EchoTests;
+use event_queue::EventQueue;
+use encode_profiles::Profile;
+use encoder::Codec;
+use encoder_experiment::EncoderExperiment;
+use envelope::{Envelope, FieldNaming, FrameFields};
+use flow_control::{AckWindow, FlowControlConfig};
+use frame::{Frame, FrameOutputs, FrameProcessor};
+use frame_api::FrameHub;
+use frame_pool::{FramePool, PooledFrame};
+use identity::DeviceIdentity;
+use http_fallback::{HttpFallback, HttpFallbackConfig};
+use illuminator::IlluminatorHandle;
+use image_quality::SharedImageQuality;
+use jpeg::{JpegConfig, JpegTuner};
+use log_stream::LogStreamConfig;
+use maintenance::Maintenance;
+use boost::ViewerBoost;
+use burst::Bursts;
+use overlay::SharedOverlays;
+use pipeline::FramePipeline;
+use privacy::PrivacyConfig;
+use server_address::ProxyConfig;
+use pause::UplinkPause;
+use protocol::ProtocolVersion;
+use protocol_errors::ProtocolErrors;
+use queue::{FrameReceiver, FrameSender, SendOutcome};
+use recording::RecordingConfig;
+use snapshot::LatestFrame;
+use stats_db::{StatsCounters, StatsDb};
+use time_sync::TimeSync;
+use timeline::Timeline;
+use resolution::{Resolution, ResolutionConfig};
+use rest_api::RecentEvents;
+use scene_complexity::SceneComplexity;
+use stream_priority::StreamPriorities;
+use stream_state::{ReplacedAction, StreamState, StreamStatus};
+use supervisor::Supervisor;
+use telegram::TelegramConfig;
+use tenancy::TenancyConfig;
+use test_pattern::TestPatternConfig;
+use thermal::ThermalLevel;
+use viewers::ViewerCount;
+use virtual_input::{VirtualInput, VirtualInputConfig};
+use watchdog::FrameWatchdog;
 
-    // Update congestion state with hysteresis
-    fn update_congestion(&mut self, queue_size: u64, consecutive_failures: u32, server_congestion: bool) -> (bool, u32, u32) {
-        // Combine multiple congestion indicators
-        let new_congestion_indicators = 
-            (if queue_size > 20 { 2 } else if queue_size > 10 { 1 } else { 0 }) +
-            (if consecutive_failures > 3 { 3 } else if consecutive_failures > 0 { 1 } else { 0 }) +
-            (if server_congestion { 3 } else { 0 });
-        
-        // Gradually adjust congestion level (with inertia)
-        if new_congestion_indicators > (self.congestion_level as u32) {
-            self.congestion_level = (self.congestion_level + 1).min(10);
-        } else if new_congestion_indicators < (self.congestion_level as u32) && self.stability_counter > 5 {
-            self.congestion_level = self.congestion_level.saturating_sub(1);
-        }
-        
-        // Reset stability counter if indicators changed significantly
-        if (new_congestion_indicators as i32 - self.congestion_level as i32).abs() > 2 {
-            self.stability_counter = 0;
-        } else {
-            self.stability_counter += 1;
+// Update local metrics tracking
+fn track_failures(consecutive_failures: &mut u32, consecutive_successes: &mut u32, congested: bool) {
+    if congested {
+        *consecutive_failures = (*consecutive_failures + 1).min(10);
+        *consecutive_successes = 0;
+    } else {
+        *consecutive_successes = (*consecutive_successes + 1).min(30);
+        if *consecutive_failures > 0 {
+            *consecutive_failures -= 1;
         }
-        
-        // Determine if we should change resolution and quality based on congestion level
-        // and how long since the last change
-        let now = std::time::Instant::now();
-        let time_since_last_change = now.duration_since(self.last_resolution_change);
-        
-        let should_reduce = self.congestion_level > 6 && 
-                           time_since_last_change > Duration::from_secs(2) && 
-                           !self.is_congested;
-                           
-        let should_increase = self.congestion_level < 3 && 
-                              time_since_last_change > Duration::from_secs(15) && 
-                              self.is_congested && 
-                              self.stability_counter > 20;
-        
-        // Calculate target quality and resolution
-        let (width, height, quality) = if should_reduce || self.is_congested {
-            self.is_congested = true;
-            self.last_resolution_change = now;
-            (640, 480, 50 - self.congestion_level as u32 * 2)
-        } else if should_increase {
-            self.is_congested = false;
-            self.last_resolution_change = now;
-            (1280, 720, 70)
-        } else if self.is_congested {
-            // Maintain lower resolution but adjust quality based on current congestion
-            (640, 480, 50 - self.congestion_level as u32 * 2)
-        } else {
-            // Maintain higher resolution but adjust quality based on current congestion
-            (1280, 720, 70 - self.congestion_level as u32 * 3)
-        };
-        
-        // Log meaningful state changes
-        if should_reduce {
-            println!("Network congestion detected (level {}). Reducing resolution to {}x{}, quality to {}", 
-                    self.congestion_level, width, height, quality);
-        } else if should_increase {
-            println!("Network stable (level {}) for {} frames. Increasing resolution to {}x{}, quality to {}",
-                    self.congestion_level, self.stability_counter, width, height, quality);
-        }
-        
-        (self.is_congested, width, quality.max(20))
     }
 }
 
 // Define process_frames first so it's in scope when called
 async fn process_frames(
-    mut stdout: tokio::process::ChildStdout,
-    tx: mpsc::Sender<Vec<u8>>,
-    queue_size: Arc<AtomicU64>
+    mut stdout: impl AsyncRead + Unpin + Send + 'static,
+    codec: Codec,
+    stream_id: Arc<str>,
+    outputs: FrameOutputs
 ) {
+    // With a frame pipeline the rest happens on its threads; this task only extracts
+    let stages = outputs.pipeline.clone().map(|pipeline| pipeline.spawn(outputs.clone(), codec, stream_id.clone()));
     tokio::spawn(async move {
-        let mut accumulated_data = Vec::new();
+        let mut extractor = FrameExtractor::new(codec);
         let mut buffer = vec![0; 512 * 1024]; // 512KB buffer
+        let mut extracted: Vec<(PooledFrame, FrameTimestamp)> = Vec::new();
+        let clock = outputs.clock.clone();
+        let (tx, stats, frame_pool) = (outputs.tx.clone(), outputs.stats.clone(), outputs.frame_pool.clone());
+        let mut processor = FrameProcessor::new(outputs, codec, stream_id);
         
         loop {
             match stdout.read(&mut buffer).await {
@@ -108,77 +205,33 @@ async fn process_frames(
                     break;
                 },
                 Ok(bytes_read) => {
-                    // Append the new data to our accumulated buffer
-                    accumulated_data.extend_from_slice(&buffer[..bytes_read]);
+                    let extraction_started = std::time::Instant::now();
                     
-                    // Process all complete JPEG frames in the accumulated data
-                    let mut position = 0;
-                    while position + 4 < accumulated_data.len() {
-                        // Look for JPEG start marker
-                        if accumulated_data[position] == 0xFF && accumulated_data[position + 1] == 0xD8 {
-                            // Found start of JPEG, now look for end marker
-                            let mut end_pos = position + 2;
-                            let mut found_end = false;
-                            
-                            while end_pos + 1 < accumulated_data.len() {
-                                if accumulated_data[end_pos] == 0xFF && accumulated_data[end_pos + 1] == 0xD9 {
-                                    // Found end of JPEG
-                                    found_end = true;
-                                    
-                                    // Extract the complete JPEG frame (including the end marker)
-                                    let frame = accumulated_data[position..=end_pos+1].to_vec();
-                                    
-                                    // Get current queue size
-                                    let current_queue = queue_size.load(Ordering::Relaxed);
-                                    
-                                    // Only send if queue isn't too full
-                                    if current_queue < 50 {
-                                        // Send frame and update queue size
-                                        match tx.try_send(frame) {
-                                            Ok(_) => {
-                                                queue_size.fetch_add(1, Ordering::Relaxed);
-                                            },
-                                            Err(mpsc::error::TrySendError::Full(_)) => {
-                                                println!("Channel full, skipping frame");
-                                            },
-                                            Err(e) => {
-                                                eprintln!("Failed to send frame: {}", e);
-                                            }
-                                        }
-                                    } else {
-                                        // Skip frame if queue is too full
-                                        println!("Network congested, skipping frame");
-                                    }
-                                    
-                                    // Move position past this frame
-                                    position = end_pos + 2;
-                                    break;
-                                }
-                                end_pos += 1;
-                            }
-                            
-                            if !found_end {
-                                // Didn't find the end marker yet, need more data
-                                break;
-                            }
-                        } else {
-                            // Not a start marker, move to next byte
-                            position += 1;
+                    // Hand every frame this completes to the local consumers and queue it for the WebSocket task
+                    extractor.push(&buffer[..bytes_read], |data: &[u8]| {
+                        let timestamp = clock.now();
+                        match &stages {
+                            Some(stages) => stages.submit(data, timestamp),
+                            None => extracted.push((frame_pool.acquire(data), timestamp)),
                         }
+                    });
+                    if let Some(stages) = &stages {
+                        stages.extracted(extraction_started.elapsed());
                     }
                     
-                    // Keep only the unprocessed data
-                    if position > 0 {
-                        accumulated_data = accumulated_data[position..].to_vec();
-                    }
-                    
-                    // Safety measure: if accumulated buffer gets too large without finding complete frames,
-                    // clear part of it to avoid memory issues
-                    if accumulated_data.len() > 10 * 1024 * 1024 {  // 10MB limit
-                        println!("Buffer too large, discarding old data");
-                        // Keep the last 1MB which might contain a partial frame
-                        let keep_size = 1024 * 1024.min(accumulated_data.len());
-                        accumulated_data = accumulated_data[accumulated_data.len() - keep_size..].to_vec();
+                    // Re-encoding and scaling take milliseconds a frame; keep them off the runtime's threads
+                    if !extracted.is_empty() {
+                        let batch = std::mem::take(&mut extracted);
+                        let (returned, processed) = tokio::task::spawn_blocking(move || {
+                            let processed: Vec<Frame> = batch.iter().filter_map(|(data, timestamp)| processor.process(data, *timestamp)).collect();
+                            (processor, processed)
+                        })
+                        .await
+                        .expect("frame processing panicked");
+                        processor = returned;
+                        for frame in processed {
+                            frame::enqueue(&tx, &stats, frame).await;
+                        }
                     }
                 },
                 Err(e) => {
@@ -193,256 +246,1223 @@ async fn process_frames(
     });
 }
 
-async fn start_gstreamer(width: u32, height: u32, quality: u32) -> tokio::process::Child {
-    println!("Starting GStreamer with resolution {}x{} and quality {}", width, height, quality);
-    
-    Command::new("gst-launch-1.0")
-        .args(&[
-            "libcamerasrc",
-            "!",
-            &format!("video/x-raw,width={},height={}", width, height),
-            "!",
-            "videoconvert",
-            "!",
-            "jpegenc",
-            &format!("quality={}", quality),
-            "!",
-            "fdsink",
-        ])
-        .stdout(std::process::Stdio::piped())
-        .spawn()
-        .expect("Failed to start GStreamer with libcamerasrc")
+// Lens correction, straight off the sensor: the calibration is for the frames as
+// captured, before anything rotates or scales them
+fn undistort_args(config: &Config) -> Vec<String> {
+    match &config.lens {
+        Some(lens) => ["videoconvert".to_string(), "!".to_string()].into_iter().chain(lens.undistort_args()).collect(),
+        None => Vec::new(),
+    }
+}
+
+// The size frames are captured at when it doesn't follow the uplink: the fisheye sensor's,
+// so the virtual views can share it, the recording's, so the recording doesn't follow
+// the uplink down, or the top rung for a restream
+fn fixed_capture_size(config: &Config) -> Option<(u32, u32)> {
+    match (&config.fisheye, &config.recording) {
+        (Some(fisheye), _) => Some((fisheye.source_width, fisheye.source_height)),
+        (None, Some(recording)) => Some((recording.width, recording.height)),
+        (None, None) if config.go2rtc.is_some() || config.rtsp.is_some() => {
+            let high = config.resolution.high();
+            Some((high.width, high.height))
+        }
+        (None, None) => None,
+    }
+}
+
+// Recording and restreams run in a capture pipeline of their own, which hands its frames
+// to the uplink's over shared memory; restarts for quality, resolution, codec or overlays
+// then leave them be
+fn shares_capture(config: &Config, virtual_input: Option<&VirtualInput>) -> bool {
+    (config.recording.is_some() || config.go2rtc.is_some() || config.rtsp.is_some()) && (config.test_pattern.is_some() || virtual_input.is_some() || !config.capture.backend().without_gstreamer())
+}
+
+// From the source up to where the uplink's own processing starts, ending in "!": the
+// branches that run at capture resolution hang off here
+fn capture_args(width: u32, height: u32, frame_rate: Option<u32>, config: &Config, controls: &SharedCameraControls, virtual_input: Option<&VirtualInput>, record: bool) -> Vec<String> {
+    // Ask the source for a specific pixel format if one is configured, and a lower frame
+    // rate from a camera while the SoC runs hot; the sensor and every encoder then do less
+    let mut source_format = config.pixel_format
+        .map(|format| format!(",format={}", format.caps_name()))
+        .unwrap_or_default();
+    if let Some(frame_rate) = frame_rate.filter(|_| config.test_pattern.is_none() && virtual_input.is_none()) {
+        source_format.push_str(&format!(",framerate={}/1", frame_rate));
+    }
+    
+    let mut args = match (&config.test_pattern, virtual_input) {
+        (Some(test_pattern), _) => test_pattern.source_args(),
+        (None, Some(virtual_input)) => virtual_input.source_args(),
+        (None, None) => config.capture.source_args(None, Some(controls)),
+    };
+    args.push("!".to_string());
+    let (capture_width, capture_height) = fixed_capture_size(config).unwrap_or((width, height));
+    // Portrait-mounted sensors still capture landscape; rotate afterwards
+    if config.fisheye.is_none() && config.resolution.aspect_ratio.is_portrait() {
+        args.push(format!("video/x-raw{},width={},height={}", source_format, capture_height, capture_width));
+        args.push("!".to_string());
+        args.extend(undistort_args(config));
+        args.extend(["videoflip".to_string(), "method=clockwise".to_string(), "!".to_string()]);
+    } else {
+        args.push(format!("video/x-raw{},width={},height={}", source_format, capture_width, capture_height));
+        args.push("!".to_string());
+        args.extend(undistort_args(config));
+    }
+    if let Some(test_pattern) = &config.test_pattern {
+        args.extend(test_pattern.overlay_args());
+    }
+    // Still captured at the recording size while recording is paused, so pausing
+    // doesn't change what the uplink sees
+    if let Some(recording) = config.recording.as_ref().filter(|_| record) {
+        args.extend(recording.tee_args(&config.time));
+    }
+    // Restream for go2rtc/Frigate, scaled on its own branch, never following the uplink
+    if let Some(go2rtc) = &config.go2rtc {
+        args.extend(go2rtc.tee_args());
+    }
+    // H.264 for the embedded RTSP server, which shouldn't degrade with the WAN uplink
+    if let Some(rtsp) = &config.rtsp {
+        rtsp.remove_socket();
+        args.extend(rtsp.tee_args());
+    }
+    if let Some(fisheye) = &config.fisheye {
+        // A killed pipeline leaves its socket behind, which shmsink refuses to reuse
+        let _ = std::fs::remove_file(&fisheye.socket_path);
+        args.extend(fisheye.tee_args());
+    }
+    args
+}
+
+// From the capture resolution down to the encoded uplink stream
+fn uplink_args(width: u32, height: u32, quality: u32, codec: Codec, config: &Config, overlays: &SharedOverlays) -> Vec<String> {
+    let mut args = Vec::new();
+    if fixed_capture_size(config).is_some_and(|size| size != (width, height)) {
+        args.extend([
+            "videoscale".to_string(),
+            "!".to_string(),
+            format!("video/x-raw,width={},height={}", width, height),
+            "!".to_string(),
+        ]);
+    }
+    // Raw frames for local consumers, taken before encoding
+    if let Some(raw) = &config.raw {
+        let _ = std::fs::remove_file(&raw.socket_path);
+        args.extend(raw.tee_args("raw"));
+    }
+    // Reduced-rate feed for the detectors
+    if let Some(analytics) = &config.analytics {
+        let _ = std::fs::remove_file(&analytics.socket_path);
+        args.extend(analytics.raw_config().tee_args("analytics"));
+    }
+    args.extend(["videoconvert".to_string(), "!".to_string()]);
+    // Processing stages run on raw frames before encoding
+    if config.time.overlay {
+        args.extend(config.time.overlay_args());
+    }
+    // Server-driven annotations only go to the uplink, not the recording
+    args.extend(overlays.pipeline_args(width, height));
+    if let Some(profiles) = config.encode_profiles.as_ref().filter(|_| config.fisheye.is_none()) {
+        let _ = std::fs::remove_file(&profiles.socket_path);
+        args.extend(profiles.tee_args(config.resolution.low()));
+    }
+    match codec {
+        Codec::Mjpeg => match &config.encoder_experiment {
+            // Both arms are encoded from the same high-quality frame
+            Some(experiment) => args.extend(experiment.pipeline_args()),
+            None => args.extend(config.jpeg.pipeline_args(quality)),
+        },
+        _ => args.extend(codec.pipeline_args(quality)),
+    }
+    args.extend(["!".to_string(), "fdsink".to_string()]);
+    args
+}
+
+fn gst_launch(args: &[String], config: &Config) -> Command {
+    let mut command = Command::new("gst-launch-1.0");
+    command
+        .args(args)
+        .env("TZ", config.time.tz_env())
+        .kill_on_drop(true);
+    if let Some(user) = config.sandbox.child_user() {
+        sandbox::run_child_as(&mut command, user);
+    }
+    command
+}
+
+fn ensure_recording_directories(config: &Config) {
+    if let Some(recording) = &config.recording {
+        for directory in [recording.directory.as_str(), recording.write_directory()] {
+            if let Err(e) = std::fs::create_dir_all(directory) {
+                eprintln!("Failed to create recording directory {}: {}", directory, e);
+            }
+        }
+    }
+}
+
+// The capture pipeline, when it runs apart from the uplink's; None when the uplink
+// pipeline captures for itself
+async fn start_capture(
+    frame_rate: Option<u32>,
+    config: &Config,
+    controls: &SharedCameraControls,
+    virtual_input: Option<&VirtualInput>,
+    record: bool
+) -> Option<tokio::process::Child> {
+    if !shares_capture(config, virtual_input) {
+        return None;
+    }
+    let (width, height) = fixed_capture_size(config)?;
+    println!("Starting capture pipeline at {}x{}{}", width, height, if record { " with recording" } else { "" });
+    ensure_recording_directories(config);
+    let socket_path = &config.capture.socket_path;
+    let _ = std::fs::remove_file(socket_path);
+    let mut args = capture_args(width, height, frame_rate, config, controls, virtual_input, record);
+    args.extend([
+        "videoconvert".to_string(),
+        "!".to_string(),
+        "video/x-raw,format=I420".to_string(),
+        "!".to_string(),
+        "shmsink".to_string(),
+        format!("socket-path={}", socket_path),
+        format!("shm-size={}", width * height * 3 / 2 * 4),
+        "wait-for-connection=false".to_string(),
+        "sync=false".to_string(),
+    ]);
+    let mut command = gst_launch(&args, config);
+    if virtual_input.is_some() {
+        command.stdin(std::process::Stdio::piped());
+    }
+    let mut child = command.spawn().expect("Failed to start capture pipeline");
+    if let Some(virtual_input) = virtual_input {
+        virtual_input.attach(&mut child).await;
+    }
+    // The uplink pipeline gives up if the socket isn't there yet
+    for _ in 0..100 {
+        if std::path::Path::new(socket_path).exists() {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    Some(child)
+}
+
+// What a capture is started with; a change to any of it means starting it again
+#[derive(Clone, Copy)]
+struct CaptureSettings {
+    width: u32,
+    height: u32,
+    quality: u32,
+    codec: Codec,
+    frame_rate: Option<u32>,
+    record: bool,
 }
 
-async fn start_websocket_handler(
-    _tx: mpsc::Sender<Vec<u8>>,
-    mut rx: mpsc::Receiver<Vec<u8>>,
+async fn start_gstreamer(
+    settings: CaptureSettings,
+    config: &Config,
+    controls: &SharedCameraControls,
+    overlays: &SharedOverlays,
+    virtual_input: Option<&VirtualInput>
+) -> tokio::process::Child {
+    let CaptureSettings { width, height, quality, codec, frame_rate, record } = settings;
+    if config.capture.backend() == CaptureSource::Direct && config.test_pattern.is_none() && virtual_input.is_none() {
+        println!("Starting direct capture with resolution {}x{} and quality {}", width, height, quality);
+        let mut command = direct_capture::command(&config.capture, width, height, quality, frame_rate);
+        command.stdout(std::process::Stdio::piped()).kill_on_drop(true);
+        if let Some(user) = config.sandbox.child_user() {
+            sandbox::run_child_as(&mut command, user);
+        }
+        return command.spawn().expect("Failed to start direct capture");
+    }
+    if config.capture.backend() == CaptureSource::Synthetic && config.test_pattern.is_none() && virtual_input.is_none() {
+        println!("Starting synthetic capture with resolution {}x{} and quality {}", width, height, quality);
+        let mut command = synthetic_capture::command(&config.capture, width, height, quality);
+        command.stdout(std::process::Stdio::piped()).kill_on_drop(true);
+        return command.spawn().expect("Failed to start synthetic capture");
+    }
+    
+    println!("Starting GStreamer with resolution {}x{}, quality {} and codec {}", width, height, quality, codec.name());
+    
+    let shared = shares_capture(config, virtual_input);
+    let mut args = match fixed_capture_size(config).filter(|_| shared) {
+        // Frames from the capture pipeline, which is started first
+        Some((capture_width, capture_height)) => vec![
+            "shmsrc".to_string(),
+            format!("socket-path={}", config.capture.socket_path),
+            "is-live=true".to_string(),
+            "do-timestamp=true".to_string(),
+            "!".to_string(),
+            format!("video/x-raw,format=I420,width={},height={},framerate=0/1", capture_width, capture_height),
+            "!".to_string(),
+        ],
+        None => {
+            ensure_recording_directories(config);
+            capture_args(width, height, frame_rate, config, controls, virtual_input, record)
+        }
+    };
+    args.extend(uplink_args(width, height, quality, codec, config, overlays));
+    
+    let mut command = gst_launch(&args, config);
+    command.stdout(std::process::Stdio::piped());
+    if virtual_input.is_some() && !shared {
+        command.stdin(std::process::Stdio::piped());
+    }
+    let mut child = command.spawn().expect("Failed to start GStreamer");
+    if let Some(virtual_input) = virtual_input.filter(|_| !shared) {
+        virtual_input.attach(&mut child).await;
+    }
+    child
+}
+
+// Everything the uplink works with, shared with capture and the local control paths
+struct UplinkContext {
+    tx: FrameSender,
+    rx: FrameReceiver,
     quality: Arc<AtomicU32>,
     width: Arc<AtomicU32>,
     height: Arc<AtomicU32>,
     network_congested: Arc<AtomicBool>,
     queue_size: Arc<AtomicU64>,
-    _camera_id: String
-) {
-    // Generate a unique camera ID
-    let camera_id = generate_camera_id();
+    codec: Arc<AtomicU8>,
+    offered_codecs: Vec<Codec>,
+    resolutions: ResolutionConfig,
+    ptz_tx: Option<mpsc::Sender<PtzCommand>>,
+    camera_controls: SharedCameraControls,
+    overlays: SharedOverlays,
+    alarm: Option<AlarmHandle>,
+    illuminator: Option<IlluminatorHandle>,
+    status: StreamStatus,
+    image_quality: Option<SharedImageQuality>,
+    recording: Option<RecordingConfig>,
+    custody: Option<CustodySources>,
+    audit_log: AuditLog,
+    congestion_history: CongestionHistory,
+    latest_frame: LatestFrame,
+    max_message_bytes: usize,
+    stats_counters: StatsCounters,
+    stats_db: Option<StatsDb>,
+    jpeg: JpegConfig,
+    encoder_experiment: Option<EncoderExperiment>,
+    protocol_errors: ProtocolErrors,
+    flow_control: Option<FlowControlConfig>,
+    timeline: Option<Timeline>,
+    uplink_pause: UplinkPause,
+    viewer_boost: ViewerBoost,
+    viewers: ViewerCount,
+    bursts: Bursts,
+    maintenance: Maintenance,
+    server_url: String,
+    proxy: Option<ProxyConfig>,
+    http_fallback: Option<HttpFallbackConfig>,
+    frame_pipeline: Option<FramePipeline>,
+    privacy: Option<PrivacyConfig>,
+    field_naming: FieldNaming,
+    time_sync: Option<TimeSync>,
+    audio: Option<AudioTap>,
+    log_stream: LogStreamConfig,
+    event_queue: Option<EventQueue>,
+    on_replaced: ReplacedAction,
+    tenancy: TenancyConfig,
+    access: AccessConfig,
+    shutdown: Arc<Notify>,
+    camera_id: String,
+    identity: Option<Arc<DeviceIdentity>>,
+}
+
+async fn run_websocket_handler(context: UplinkContext) {
+    let UplinkContext {
+        tx,
+        mut rx,
+        quality,
+        width,
+        height,
+        network_congested,
+        queue_size,
+        codec,
+        offered_codecs,
+        resolutions,
+        ptz_tx,
+        camera_controls,
+        overlays,
+        alarm,
+        illuminator,
+        status,
+        image_quality,
+        recording,
+        custody,
+        audit_log,
+        congestion_history,
+        latest_frame,
+        max_message_bytes,
+        stats_counters,
+        stats_db,
+        jpeg,
+        encoder_experiment,
+        protocol_errors,
+        flow_control,
+        timeline,
+        uplink_pause,
+        viewer_boost,
+        viewers,
+        bursts,
+        maintenance,
+        server_url,
+        proxy,
+        http_fallback,
+        frame_pipeline,
+        privacy,
+        field_naming,
+        time_sync,
+        audio,
+        log_stream,
+        event_queue,
+        on_replaced,
+        tenancy,
+        access,
+        shutdown,
+        camera_id,
+        identity,
+    } = context;
+    // Tells this process apart from another one joining with the same camera id
+    let session = Uuid::new_v4().to_string();
+    let echo_tests = EchoTests::default();
+    let http_fallback = http_fallback.and_then(|config| {
+        HttpFallback::new(config, proxy.as_ref(), camera_id.clone(), privacy.clone(), stats_counters.clone())
+            .map_err(|e| eprintln!("HTTP fallback disabled: {}", e))
+            .ok()
+    });
+    let mut connect_failures: u32 = 0;
+    let mut on_fallback = false;
     let mut consecutive_failures = 0;
     let mut consecutive_successes = 0;
     
-    tokio::spawn(async move {
+    // One pass per connection. The loop only ends when the frame source goes away,
+    // so a dropped connection can never leave the process running without an uplink.
+    loop {
+        status.transition(StreamState::Connecting);
+        
         // Connect to the WebSocket server
-        let url = url::Url::parse("ws://100.78.140.50:3001").expect("Failed to parse URL");
-        match connect_async(url.clone()).await {
-            Ok((ws_stream, _)) => {
-                println!("Connected to WebSocket server");
-                
-                // Create a channel for communication between the two WebSocket tasks
-                let (pong_tx, mut pong_rx) = mpsc::channel::<Message>(10);
-                
-                let (mut write, mut read) = ws_stream.split();
-                
-                // Send join message
-                let join_message = json!({
-                    "join": camera_id,
-                    "capabilities": {
-                        "adaptive_quality": true,
-                        "min_quality": 20,
-                        "max_quality": 90,
-                        "resolutions": ["640x480", "1280x720"]
+        let ws_stream = match server_address::connect(&server_url, proxy.as_ref()).await {
+            Ok((ws_stream, _)) => ws_stream,
+            Err(e) => {
+                eprintln!("Failed to connect to WebSocket server: {}", e);
+                status.transition(StreamState::Reconnecting);
+                connect_failures += 1;
+                match &http_fallback {
+                    // Blocked rather than briefly down; get frames out some other way meanwhile
+                    Some(fallback) if fallback.should_take_over(connect_failures) => {
+                        if !on_fallback {
+                            println!("WebSocket unreachable, posting frames over HTTP until it is back");
+                            on_fallback = true;
+                        }
+                        if !fallback.run(&mut rx, Codec::from_u8(codec.load(Ordering::Relaxed))).await {
+                            println!("Frame source closed, stopping uplink");
+                            status.transition(StreamState::Idle);
+                            break;
+                        }
                     }
-                }).to_string();
-                
-                if let Err(e) = write.send(Message::Text(join_message)).await {
-                    eprintln!("Failed to send join message: {}", e);
-                    return;
+                    _ => sleep(Duration::from_secs(5)).await,
                 }
-                println!("Join message sent successfully");
-                
-                // Handle incoming messages (for server feedback)
-                let pong_tx_clone = pong_tx.clone();
-                let quality_clone = quality.clone();
-                let width_clone = width.clone();
-                let height_clone = height.clone();
-                let network_congested_clone = network_congested.clone();
-                
-                // Spawn a task to handle incoming messages
-                tokio::spawn(async move {
-                    while let Some(msg) = read.next().await {
-                        match msg {
-                            Ok(Message::Text(text)) => {
-                                // Parse server feedback for network conditions
-                                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
-                                    // Check if feedback contains network_feedback
-                                    if let Some(feedback) = json.get("network_feedback") {
-                                        // Explicitly set congestion state based on feedback
-                                        if let Some(congestion) = feedback.get("congested") {
-                                            if let Some(congested) = congestion.as_bool() {
-                                                // Update the congestion flag
-                                                network_congested_clone.store(congested, Ordering::Relaxed);
-                                                
-                                                // If server suggests quality change
-                                                if let Some(suggested_quality) = feedback.get("suggested_quality") {
-                                                    if let Some(q) = suggested_quality.as_u64() {
-                                                        quality_clone.store(q as u32, Ordering::Relaxed);
-                                                    }
-                                                }
-                                                
-                                                // If server suggests resolution change
-                                                if let Some(suggested_res) = feedback.get("suggested_resolution") {
-                                                    if let Some(res) = suggested_res.as_str() {
-                                                        if res == "640x480" {
-                                                            width_clone.store(640, Ordering::Relaxed);
-                                                            height_clone.store(480, Ordering::Relaxed);
-                                                        } else if res == "1280x720" {
-                                                            width_clone.store(1280, Ordering::Relaxed);
-                                                            height_clone.store(720, Ordering::Relaxed);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        } else {
-                                            // If "congested" field is missing, assume network is fine
-                                            network_congested_clone.store(false, Ordering::Relaxed);
+                continue;
+            }
+        };
+        println!("Connected to WebSocket server");
+        connect_failures = 0;
+        if on_fallback {
+            println!("Back on the WebSocket, HTTP fallback stopped");
+            on_fallback = false;
+        }
+        
+        // Create a channel for communication between the two WebSocket tasks
+        let (pong_tx, mut pong_rx) = mpsc::channel::<Message>(10);
+        
+        let (mut write, mut read) = ws_stream.split();
+        
+        // Send join message
+        let mut join_message = json!({
+            "join": camera_id,
+            "session": session,
+            "capabilities": {
+                "adaptive_quality": true,
+                "min_quality": 20,
+                "max_quality": 90,
+                "resolutions": resolutions.rungs().iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+                "codecs": offered_codecs.iter().map(|c| c.name()).collect::<Vec<_>>(),
+                "envelopes": Envelope::SUPPORTED.iter().map(|e| e.name()).collect::<Vec<_>>(),
+                "chunked_frames": { "max_message_bytes": max_message_bytes },
+                "pause": true,
+                "viewer_boost": true,
+                "viewer_count": true,
+                "burst_capture": true,
+                "time_sync": time_sync.is_some(),
+                "session_replaced": true,
+                "targeted_commands": true,
+                "echo_test": true,
+                "av_container": { "version": 1, "audio": audio.is_some() },
+                "log_stream": log_stream.enabled,
+                "event_acks": event_queue.is_some(),
+                "stream_priorities": true,
+                "signed_join": identity.is_some()
+            },
+            "frame_acks": flow_control.as_ref().map(|flow| json!({ "every": flow.ack_every, "window": flow.window })),
+            "protocol_versions": ProtocolVersion::SUPPORTED.iter().map(|v| *v as u8).collect::<Vec<_>>()
+        });
+        if let (Some(join), Some(site)) = (join_message.as_object_mut(), tenancy.join_fields().as_object()) {
+            join.extend(site.clone());
+        }
+        
+        if let Err(e) = write.send(Message::Text(join_message.to_string())).await {
+            eprintln!("Failed to send join message: {}", e);
+            status.transition(StreamState::Reconnecting);
+            sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+        println!("Join message sent successfully");
+        let previous = status.transition(StreamState::Joined);
+        report_transition(&mut write, &camera_id, previous, StreamState::Joined, ProtocolVersion::V1).await;
+        // A new connection streams until the server pauses it again; it may not remember
+        if uplink_pause.resume() {
+            println!("Resuming the paused stream for the new connection");
+        }
+        viewers.reset();
+        
+        // Handle incoming messages (for server feedback)
+        let quality_clone = quality.clone();
+        let width_clone = width.clone();
+        let height_clone = height.clone();
+        let network_congested_clone = network_congested.clone();
+        let codec_clone = codec.clone();
+        // Frame envelope is negotiated per connection and starts out as JSON
+        let frame_envelope = Arc::new(AtomicU8::new(Envelope::Json as u8));
+        let frame_envelope_clone = frame_envelope.clone();
+        let pipeline_clone = frame_pipeline.clone();
+        if let Some(pipeline) = &frame_pipeline {
+            pipeline.set_envelope(Envelope::Json);
+        }
+        // Chunking is off (0) until the server says which message size it accepts
+        let chunk_limit = Arc::new(AtomicUsize::new(0));
+        let chunk_limit_clone = chunk_limit.clone();
+        let mut next_frame_id: u64 = 0;
+        // Original format until the server picks a newer one
+        let protocol_version = Arc::new(AtomicU8::new(ProtocolVersion::V1 as u8));
+        let protocol_version_clone = protocol_version.clone();
+        let ack_window = flow_control.as_ref().map(AckWindow::new);
+        let ack_window_clone = ack_window.clone();
+        let mut window_full_since: Option<std::time::Instant> = None;
+        let ack_timeout = flow_control.as_ref().map(|flow| flow.ack_timeout()).unwrap_or_default();
+        let resolutions_clone = resolutions.clone();
+        let offered_codecs = offered_codecs.clone();
+        let ptz_tx = ptz_tx.clone();
+        let camera_controls = camera_controls.clone();
+        let overlays = overlays.clone();
+        let alarm = alarm.clone();
+        let reader_illuminator = illuminator.clone();
+        let recording = recording.clone();
+        let custody = custody.clone();
+        let camera_id_clone = camera_id.clone();
+        let audit_log = audit_log.clone();
+        let congestion_history = congestion_history.clone();
+        let latest_frame = latest_frame.clone();
+        let reader_privacy = privacy.clone();
+        let stats_db = stats_db.clone();
+        let timeline = timeline.clone();
+        let uplink_pause = uplink_pause.clone();
+        let viewer_boost = viewer_boost.clone();
+        let reader_viewers = viewers.clone();
+        let reader_bursts = bursts.clone();
+        let reader_maintenance = maintenance.clone();
+        let reader_status = status.clone();
+        let protocol_errors = protocol_errors.clone();
+        let reader_time_sync = time_sync.clone();
+        let reader_session = session.clone();
+        let reader_tenancy = tenancy.clone();
+        let reader_access = access.clone();
+        let reader_echo_tests = echo_tests.clone();
+        let reader_identity = identity.clone();
+        let reader_logs = log_stream::spawn_log_shipper(log_stream.clone(), camera_id.clone(), pong_tx.clone());
+        let reader_priorities = rx.priorities();
+        let uplink_allocator = rx.allocator();
+        // Whatever the last connection didn't get acknowledged goes out first
+        let reader_event_queue = event_queue.clone();
+        if let Some(queue) = &event_queue {
+            tokio::spawn(event_queue::deliver(queue.clone(), camera_id.clone(), pong_tx.clone()));
+        }
+        // Audio and video go out as container packets once the server asks for them
+        let av_container = Arc::new(AtomicBool::new(false));
+        let reader_av_container = av_container.clone();
+        if let Some(audio) = &audio {
+            let mut packets = audio.subscribe();
+            let (audio_tx, frame_tx, av_container) = (pong_tx.clone(), tx.clone(), av_container.clone());
+            let pool = FramePool::new(4);
+            tokio::spawn(async move {
+                loop {
+                    let packet = tokio::select! {
+                        packet = packets.recv() => match packet {
+                            Ok(packet) => packet,
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                        },
+                        _ = audio_tx.closed() => break,
+                    };
+                    if !av_container.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    // Through the frame queue, so audio counts towards congestion and the
+                    // uplink share like video, and is dropped the same way
+                    if let SendOutcome::Closed = frame_tx.send(packet.frame(&pool)).await {
+                        break;
+                    }
+                }
+            });
+        }
+        
+        // Spawn a task to handle incoming messages; it finishes when the server goes away,
+        // with true if that was because a newer session took over
+        let mut reader = tokio::spawn(async move {
+            while let Some(msg) = read.next().await {
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        // Parse server feedback for network conditions
+                        let json = match serde_json::from_str::<serde_json::Value>(&text).map(protocol::decode) {
+                            Ok(json) if ProtocolErrors::is_understood(&json) => Some(json),
+                            Ok(_) => {
+                                for reply in protocol_errors.record(&camera_id_clone, "unknown message", &text) {
+                                    let _ = pong_tx.send(Message::Text(reply)).await;
+                                }
+                                None
+                            }
+                            Err(e) => {
+                                for reply in protocol_errors.record(&camera_id_clone, &format!("malformed JSON: {}", e), &text) {
+                                    let _ = pong_tx.send(Message::Text(reply)).await;
+                                }
+                                None
+                            }
+                        };
+                        if let Some(json) = json {
+                            // Server picks the wire format for the rest of the connection
+                            if let Some(number) = json.get("protocol_version").and_then(|v| v.as_u64()) {
+                                match ProtocolVersion::from_number(number) {
+                                    Some(version) => {
+                                        println!("Server selected protocol version {}", number);
+                                        protocol_version_clone.store(version as u8, Ordering::Relaxed);
+                                    }
+                                    None => eprintln!("Server selected unsupported protocol version {}", number),
+                                }
+                            }
+                            
+                            // Server agrees to acknowledge frames, and acknowledges them
+                            if let Some(ack_window) = &ack_window_clone {
+                                if json.get("frame_acks").and_then(|a| a.as_bool()) == Some(true) {
+                                    println!("Server acknowledges frames, flow control enabled");
+                                    ack_window.enable();
+                                }
+                                if let Some(frame_id) = json.get("ack").and_then(|a| a.as_u64()) {
+                                    ack_window.ack(frame_id);
+                                }
+                            }
+                            
+                            // Answer to one of our clock offset measurements
+                            if let Some(time_sync) = &reader_time_sync {
+                                time_sync.handle_reply(&json);
+                            }
+                            reader_echo_tests.handle_reply(&json);
+                            
+                            // The server checks the join came from the enrolled device key,
+                            // the same way as at enrollment
+                            if let Some(challenge) = json.get("challenge").and_then(|c| c.as_str()) {
+                                match (&reader_identity, BASE64_STANDARD.decode(challenge)) {
+                                    (Some(identity), Ok(nonce)) => {
+                                        let response = json!({
+                                            "challenge_response": { "camera_id": camera_id_clone, "signature": identity.sign(&nonce) }
+                                        });
+                                        let _ = pong_tx.send(Message::Text(response.to_string())).await;
+                                    }
+                                    (None, _) => eprintln!("Server sent a join challenge, but this camera has no device key; run --provision"),
+                                    (_, Err(e)) => eprintln!("Server sent an unreadable join challenge: {}", e),
+                                }
+                            }
+                            
+                            // Another instance joined with our camera id and the server switched
+                            // to it. A notice naming some other session isn't about us.
+                            if let Some(replaced) = ReplacedAction::notice(&json, &reader_session) {
+                                eprintln!("Server says a newer session took over this camera: {}", replaced);
+                                audit_log.record(commands::issued_by(&json), "session_replaced", replaced, "applied");
+                                return true;
+                            }
+                            
+                            // The server couldn't make sense of something we sent
+                            if let Some(error) = json.get("protocol_error") {
+                                eprintln!("Server reported a protocol error: {}", error);
+                            }
+                            
+                            // Commands for another site or group are none of our business
+                            let command = commands::parse_command(&json).filter(|_| reader_tenancy.addressed_to(&json, &camera_id_clone));
+                            let role = reader_access.server_role(&json);
+                            let outcome = match command {
+                                Some(Ok(command)) if role < access::required_for_command(&command) => {
+                                    let required = access::required_for_command(&command);
+                                    eprintln!("Refusing {} from {}: needs {}, not {}", json["command"], commands::issued_by(&json), required.name(), role.name());
+                                    Some(format!("rejected: needs {}", required.name()))
+                                }
+                                Some(Ok(ServerCommand::Ptz(command))) => match &ptz_tx {
+                                    Some(ptz_tx) => {
+                                        let _ = ptz_tx.send(command).await;
+                                        Some("applied".to_string())
+                                    }
+                                    None => {
+                                        eprintln!("PTZ command received but no virtual views are configured");
+                                        Some("rejected: no virtual views configured".to_string())
+                                    }
+                                },
+                                Some(Ok(ServerCommand::Alarm(command))) => match &alarm {
+                                    Some(alarm) => {
+                                        alarm.set_mode(command.mode).await;
+                                        Some("applied".to_string())
+                                    }
+                                    None => {
+                                        eprintln!("Alarm command received but no alarm is configured");
+                                        Some("rejected: no alarm configured".to_string())
+                                    }
+                                },
+                                Some(Ok(ServerCommand::Illuminator(command))) => match &reader_illuminator {
+                                    Some(illuminator) => {
+                                        illuminator.set(command).await;
+                                        Some("applied".to_string())
+                                    }
+                                    None => {
+                                        eprintln!("Illuminator command received but no IR illuminator is configured");
+                                        Some("rejected: no illuminator configured".to_string())
+                                    }
+                                },
+                                Some(Ok(ServerCommand::CameraControls(command))) => {
+                                    println!("Applying camera controls: {:?}", command);
+                                    camera_controls.apply(command);
+                                    Some("applied".to_string())
+                                }
+                                Some(Ok(ServerCommand::Overlay(command))) => {
+                                    overlays.set(command);
+                                    Some("applied".to_string())
+                                }
+                                Some(Ok(ServerCommand::ClearOverlay(command))) => {
+                                    overlays.clear(command.id.as_deref());
+                                    Some("applied".to_string())
+                                }
+                                // Exports can take minutes, so they run alongside the stream
+                                Some(Ok(ServerCommand::ExportClip(command))) => match &recording {
+                                    Some(recording) => {
+                                        tokio::spawn(export::export_clip(
+                                            recording.clone(),
+                                            command,
+                                            camera_id_clone.clone(),
+                                            pong_tx.clone()
+                                        ));
+                                        Some("started".to_string())
+                                    }
+                                    None => {
+                                        eprintln!("Clip export requested but local recording is not configured");
+                                        Some("rejected: recording not configured".to_string())
+                                    }
+                                },
+                                Some(Ok(ServerCommand::BurstCapture(command))) => {
+                                    let current_codec = Codec::from_u8(codec_clone.load(Ordering::Relaxed));
+                                    Some(reader_bursts.schedule(command, current_codec, camera_id_clone.clone(), pong_tx.clone()))
+                                }
+                                Some(Ok(ServerCommand::CancelBurst(command))) => {
+                                    Some(reader_bursts.cancel(command, camera_id_clone.clone(), pong_tx.clone()))
+                                }
+                                Some(Ok(ServerCommand::EchoTest(command))) => {
+                                    tokio::spawn(echo_test::run_echo_test(
+                                        command,
+                                        reader_echo_tests.clone(),
+                                        camera_id_clone.clone(),
+                                        pong_tx.clone()
+                                    ));
+                                    Some("started".to_string())
+                                }
+                                Some(Ok(ServerCommand::LogStream(command))) => Some(reader_logs.apply(&command)),
+                                Some(Ok(ServerCommand::StreamPriorities(command))) => Some(reader_priorities.apply(&command)),
+                                Some(Ok(ServerCommand::CustodyExport(command))) => match &custody {
+                                    Some(sources) => {
+                                        tokio::spawn(custody::export_custody(
+                                            sources.clone(),
+                                            command,
+                                            camera_id_clone.clone(),
+                                            commands::issued_by(&json).to_string(),
+                                            pong_tx.clone()
+                                        ));
+                                        Some("started".to_string())
+                                    }
+                                    None => Some("rejected: recording not configured".to_string()),
+                                },
+                                Some(Ok(ServerCommand::AuditQuery(query))) => {
+                                    let _ = pong_tx.send(Message::Text(audit_log.query(&query).to_string())).await;
+                                    Some("answered".to_string())
+                                }
+                                Some(Ok(ServerCommand::DumpCongestionHistory)) => {
+                                    let _ = pong_tx.send(Message::Text(congestion_history.dump().to_string())).await;
+                                    Some("answered".to_string())
+                                }
+                                Some(Ok(ServerCommand::StatsQuery(query))) => match &stats_db {
+                                    Some(stats_db) => {
+                                        let _ = pong_tx.send(Message::Text(stats_db.query(&query).to_string())).await;
+                                        Some("answered".to_string())
+                                    }
+                                    None => Some("rejected: statistics database not configured".to_string()),
+                                },
+                                Some(Ok(ServerCommand::TimelineQuery(query))) => match &timeline {
+                                    Some(timeline) => {
+                                        let _ = pong_tx.send(Message::Text(timeline.query(&camera_id_clone, &query).to_string())).await;
+                                        Some("answered".to_string())
+                                    }
+                                    None => Some("rejected: timeline not enabled".to_string()),
+                                },
+                                Some(Ok(ServerCommand::PauseStream)) => {
+                                    if uplink_pause.pause() {
+                                        println!("Server paused the stream, nobody is watching");
+                                        if let Some(previous) = reader_status.transition(StreamState::Paused) {
+                                            let message = lifecycle_message(&camera_id_clone, previous, StreamState::Paused);
+                                            let _ = pong_tx.send(Message::Text(message)).await;
                                         }
+                                    }
+                                    Some("paused".to_string())
+                                }
+                                Some(Ok(ServerCommand::ResumeStream)) => {
+                                    if uplink_pause.resume() {
+                                        println!("Server resumed the stream");
+                                        if let Some(previous) = reader_status.transition(StreamState::Streaming) {
+                                            let message = lifecycle_message(&camera_id_clone, previous, StreamState::Streaming);
+                                            let _ = pong_tx.send(Message::Text(message)).await;
+                                        }
+                                    }
+                                    Some("resumed".to_string())
+                                }
+                                Some(Ok(ServerCommand::ViewerActive(command))) => {
+                                    let lasts = viewer_boost.boost(&command);
+                                    println!("Operator watching, boosting the stream for {}s", lasts.as_secs());
+                                    Some(format!("boosted for {}s", lasts.as_secs()))
+                                }
+                                Some(Ok(ServerCommand::Maintenance(command))) => {
+                                    let lasts = reader_maintenance.start(&command);
+                                    println!(
+                                        "Maintenance mode for {} minutes{}, detector alerts are suppressed",
+                                        lasts.as_secs() / 60,
+                                        command.reason.as_deref().map(|reason| format!(" ({})", reason)).unwrap_or_default()
+                                    );
+                                    Some(format!("maintenance for {}s", lasts.as_secs()))
+                                }
+                                Some(Ok(ServerCommand::EndMaintenance)) => {
+                                    if reader_maintenance.end() {
+                                        Some("ended".to_string())
                                     } else {
-                                        // If no network_feedback, assume network is fine
-                                        network_congested_clone.store(false, Ordering::Relaxed);
+                                        Some("ignored: not in maintenance mode".to_string())
                                     }
                                 }
-                            },
-                            Ok(Message::Ping(ping_data)) => {
-                                // Send a pong message via the channel
-                                let _ = pong_tx_clone.send(Message::Pong(ping_data)).await;
-                            },
-                            Err(e) => {
-                                eprintln!("Error receiving message: {}", e);
-                                break;
-                            },
-                            _ => {}
-                        }
-                    }
-                });
-                
-                // Spawn a task to process frames and handle pongs
-                tokio::spawn(async move {
-                    // Process and send frames 
-                    let capture_timestamp = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64;
-                    
-                    loop {
-                        tokio::select! {
-                            Some(pong_msg) = pong_rx.recv() => {
-                                if let Err(e) = write.send(pong_msg).await {
-                                    eprintln!("Failed to send pong: {}", e);
-                                    consecutive_failures += 1;
-                                    consecutive_successes = 0;
-                                } else {
-                                    consecutive_successes += 1;
-                                    if consecutive_successes > 4 {
-                                        // After 4 successful messages, assume network is good
-                                        network_congested.store(false, Ordering::Relaxed);
-                                        consecutive_failures = 0;
+                                Some(Ok(ServerCommand::Snapshot(command))) => {
+                                    let reply = latest_frame.response(&camera_id_clone, &command, reader_privacy.as_ref()).await;
+                                    let _ = pong_tx.send(Message::Text(reply.to_string())).await;
+                                    Some("answered".to_string())
+                                }
+                                Some(Err(e)) => {
+                                    for reply in protocol_errors.record(&camera_id_clone, &format!("invalid command: {}", e), &text) {
+                                        let _ = pong_tx.send(Message::Text(reply)).await;
+                                    }
+                                    Some(format!("invalid: {}", e))
+                                }
+                                None => None,
+                            };
+                            if let Some(outcome) = outcome {
+                                let what = json["command"].as_str().unwrap_or("unknown");
+                                audit_log.record(commands::issued_by(&json), what, &json, &outcome);
+                            }
+                            
+                            // Server picks one of the codecs we offered in the join message
+                            if let Some(name) = json.get("codec").and_then(|c| c.as_str()) {
+                                match Codec::from_name(name) {
+                                    Some(selected) if offered_codecs.contains(&selected) => {
+                                        println!("Server selected codec {}", name);
+                                        codec_clone.store(selected as u8, Ordering::Relaxed);
+                                        audit_log.record(commands::issued_by(&json), "codec", &json!({ "codec": name }), "applied");
+                                    }
+                                    _ => {
+                                        eprintln!("Server selected unsupported codec {}", name);
+                                        audit_log.record(commands::issued_by(&json), "codec", &json!({ "codec": name }), "rejected: unsupported");
                                     }
                                 }
                             }
-                            Some(frame) = rx.recv() => {
-                                queue_size.fetch_sub(1, Ordering::Relaxed);
-                                
-                                let current_width = width.load(Ordering::Relaxed);
-                                let current_height = height.load(Ordering::Relaxed);
-                                let current_quality = quality.load(Ordering::Relaxed);
-                                let current_queue = queue_size.load(Ordering::Relaxed);
-                                
-                                let encoded_frame = BASE64_STANDARD.encode(&frame);
-                                let payload = json!({
-                                    "camera_id": camera_id,
-                                    "data": encoded_frame,
-                                    "timestamp": capture_timestamp,
-                                    "stats": {
-                                        "resolution": format!("{}x{}", current_width, current_height),
-                                        "quality": current_quality
+                            
+                            // Server accepts binary frames in one of the envelopes we offered
+                            if let Some(name) = json.get("envelope").and_then(|e| e.as_str()) {
+                                match Envelope::from_name(name) {
+                                    Some(selected) => {
+                                        println!("Server selected {} frame envelope", name);
+                                        frame_envelope_clone.store(selected as u8, Ordering::Relaxed);
+                                        if let Some(pipeline) = &pipeline_clone {
+                                            pipeline.set_envelope(selected);
+                                        }
                                     }
-                                }).to_string();
-                                
-                                match write.send(Message::Text(payload)).await {
-                                    Ok(_) => {
-                                        // Frame sent successfully
-                                        consecutive_successes += 1;
-                                        consecutive_failures = 0;
+                                    None => eprintln!("Server selected unsupported envelope {}", name),
+                                }
+                            }
+                            
+                            if let (Some(queue), Some(seq)) = (&reader_event_queue, json.get("event_ack").and_then(|a| a.as_u64())) {
+                                queue.ack(seq);
+                            }
+                            
+                            // Server takes audio and video in the container from here on
+                            if let Some(name) = json.get("container").and_then(|c| c.as_str()) {
+                                if name == "av" {
+                                    println!("Server selected the A/V container");
+                                    reader_av_container.store(true, Ordering::Relaxed);
+                                } else {
+                                    eprintln!("Server selected unsupported container {}", name);
+                                }
+                            }
+                            
+                            // How many of the server's clients are subscribed to this camera
+                            if let Some(count) = json.get("viewers").and_then(|v| v.as_u64()) {
+                                reader_viewers.update(count.min(u32::MAX as u64) as u32);
+                            }
+                            
+                            // Server accepts chunked frames up to this message size
+                            if let Some(limit) = json.get("max_message_bytes").and_then(|m| m.as_u64()) {
+                                let limit = (limit as usize).min(max_message_bytes);
+                                println!("Splitting frames larger than {} bytes", limit);
+                                chunk_limit_clone.store(limit, Ordering::Relaxed);
+                            }
+                            
+                            // Check if feedback contains network_feedback
+                            if let Some(feedback) = json.get("network_feedback") {
+                                // Explicitly set congestion state based on feedback
+                                if let Some(congestion) = feedback.get("congested") {
+                                    if let Some(congested) = congestion.as_bool() {
+                                        // Update the congestion flag
+                                        network_congested_clone.store(congested, Ordering::Relaxed);
                                         
-                                        // If we have several successful sends, assume network is good
-                                        if consecutive_successes > 10 {
-                                            if network_congested.load(Ordering::Relaxed) {
-                                                network_congested.store(false, Ordering::Relaxed);
+                                        // If server suggests quality change
+                                        if let Some(suggested_quality) = feedback.get("suggested_quality") {
+                                            if let Some(q) = suggested_quality.as_u64() {
+                                                quality_clone.store(q as u32, Ordering::Relaxed);
                                             }
                                         }
-                                    },
-                                    Err(e) => {
-                                        eprintln!("Failed to send frame: {}", e);
-                                        consecutive_failures += 1;
-                                        consecutive_successes = 0;
-
-                                        // If we have several failures in a row, mark network as congested
-                                        if consecutive_failures > 3 {
-                                            network_congested.store(true, Ordering::Relaxed);
-                                        }
-                                        
-                                        // Connection might be down, retry after a delay
-                                        sleep(Duration::from_secs(5)).await;
                                         
-                                        // Try to reconnect
-                                        match connect_async(url.clone()).await {
-                                            Ok((new_ws_stream, _)) => {
-                                                let (new_write, _) = new_ws_stream.split();
-                                                write = new_write;
-                                                
-                                                // Send join message again
-                                                let rejoin_message = json!({
-                                                    "join": camera_id
-                                                }).to_string();
-                                                
-                                                if let Err(e) = write.send(Message::Text(rejoin_message)).await {
-                                                    eprintln!("Failed to send rejoin message: {}", e);
-                                                    break;
+                                        // If server suggests resolution change
+                                        if let Some(suggested_res) = feedback.get("suggested_resolution") {
+                                            // Only rungs of our own ladder are accepted
+                                            if let Some(res) = suggested_res.as_str().and_then(Resolution::parse) {
+                                                if resolutions_clone.contains(res) {
+                                                    width_clone.store(res.width, Ordering::Relaxed);
+                                                    height_clone.store(res.height, Ordering::Relaxed);
                                                 }
-                                            },
-                                            Err(e) => {
-                                                eprintln!("Failed to reconnect: {}", e);
-                                                break;
                                             }
                                         }
                                     }
-                                }
-                                
-                                // Dynamic delay based on network conditions
-                                let congestion_state = network_congested.load(Ordering::Relaxed);
-                                let delay = if congestion_state {
-                                    Duration::from_millis(100)  // More delay when congested
-                                } else {
-                                    Duration::from_millis(10)   // Less delay when network is good
-                                };
-                                
-                                // Backoff based on queue size too
-                                let queue_delay = if current_queue > 30 {
-                                    Duration::from_millis(50)  // Additional delay when queue is building up
                                 } else {
-                                    Duration::from_millis(0)   // No additional delay when queue is small
-                                };
-                                
-                                sleep(delay + queue_delay).await;
+                                    // If "congested" field is missing, assume network is fine
+                                    network_congested_clone.store(false, Ordering::Relaxed);
+                                }
+                            } else {
+                                // If no network_feedback, assume network is fine
+                                network_congested_clone.store(false, Ordering::Relaxed);
                             }
-                            else => break,
+                        }
+                    },
+                    Ok(Message::Ping(ping_data)) => {
+                        // Send a pong message via the channel
+                        let _ = pong_tx.send(Message::Pong(ping_data)).await;
+                    },
+                    Ok(Message::Binary(data)) => {
+                        // The server only ever sends text
+                        let reason = format!("unexpected binary message ({} bytes)", data.len());
+                        for reply in protocol_errors.record(&camera_id_clone, &reason, "") {
+                            let _ = pong_tx.send(Message::Text(reply)).await;
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error receiving message: {}", e);
+                        break;
+                    },
+                    _ => {}
+                }
+            }
+            false
+        });
+        
+        let mut replaced = false;
+        let mut time_sync_tick = tokio::time::interval(time_sync.as_ref().map_or(Duration::from_secs(60), |sync| sync.interval()));
+        let source_closed = loop {
+            tokio::select! {
+                // Stamped as late as possible, so only the network is in the round trip
+                _ = time_sync_tick.tick(), if time_sync.is_some() => {
+                    if let Some(time_sync) = &time_sync {
+                        let request = protocol::encode(ProtocolVersion::from_u8(protocol_version.load(Ordering::Relaxed)), Message::Text(time_sync.request()));
+                        if let Err(e) = write.send(request).await {
+                            eprintln!("Failed to send time sync request: {}", e);
                         }
                     }
-                });
-            },
-            Err(e) => {
-                eprintln!("Failed to connect to WebSocket server: {}", e);
+                }
+                Some(pong_msg) = pong_rx.recv() => {
+                    let pong_msg = protocol::encode(ProtocolVersion::from_u8(protocol_version.load(Ordering::Relaxed)), pong_msg);
+                    if let Err(e) = write.send(pong_msg).await {
+                        eprintln!("Failed to send pong: {}", e);
+                        consecutive_failures += 1;
+                        consecutive_successes = 0;
+                    } else {
+                        consecutive_successes += 1;
+                        if consecutive_successes > 4 {
+                            // After 4 successful messages, assume network is good
+                            network_congested.store(false, Ordering::Relaxed);
+                            consecutive_failures = 0;
+                        }
+                    }
+                }
+                // With acks enabled, only send while the window has room
+                _ = async {
+                    match &ack_window {
+                        Some(window) => window.wait_for_ack().await,
+                        None => std::future::pending().await,
+                    }
+                }, if window_full_since.is_some() => {
+                    // Any ack shows the server is still there
+                    window_full_since = ack_window
+                        .as_ref()
+                        .filter(|window| window.is_full(next_frame_id))
+                        .map(|_| std::time::Instant::now());
+                }
+                _ = sleep(window_full_since.map(|since| ack_timeout.saturating_sub(since.elapsed())).unwrap_or_default()), if window_full_since.is_some() => {
+                    eprintln!("No frame acks from the server for {:?}, reconnecting", ack_timeout);
+                    break false;
+                }
+                frame = rx.recv(), if window_full_since.is_none() => {
+                    let Some(frame) = frame else {
+                        break true;
+                    };
+                    // Audio queued for a connection that has gone, or before the server asked for the container
+                    let audio = &*frame.stream_id == av_container::AUDIO_STREAM;
+                    if audio && !av_container.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let current_width = width.load(Ordering::Relaxed);
+                    let current_height = height.load(Ordering::Relaxed);
+                    let current_quality = quality.load(Ordering::Relaxed);
+                    let current_queue = queue_size.load(Ordering::Relaxed);
+                    let current_codec = Codec::from_u8(codec.load(Ordering::Relaxed));
+                    
+                    let mut stats = json!({
+                        "resolution": format!("{}x{}", current_width, current_height),
+                        "quality": current_quality,
+                        "codec": current_codec.name(),
+                        "motion": frame.motion,
+                        "monotonic_ms": frame.timestamp.monotonic_ms,
+                        "queue": rx.policy().stats(current_queue)
+                    });
+                    if let Some(allocator) = &uplink_allocator {
+                        stats["uplink_share"] = allocator.stats(&frame.stream_id);
+                    }
+                    if let Some(count) = viewers.count() {
+                        stats["viewers"] = json!(count);
+                    }
+                    if let Some(concealment) = frame.concealment {
+                        stats["corrupted"] = json!(true);
+                        stats["concealment"] = json!(concealment.name());
+                    }
+                    if current_codec == Codec::Mjpeg && &*frame.stream_id == "main" {
+                        stats["jpeg"] = jpeg.stats();
+                        if let Some(experiment) = &encoder_experiment {
+                            stats["encoder_experiment"] = experiment.stats();
+                        }
+                    }
+                    if let Some(pipeline) = frame_pipeline.as_ref().filter(|_| &*frame.stream_id == "main") {
+                        stats["pipeline"] = pipeline.stats();
+                    }
+                    // What the server acknowledges
+                    if ack_window.as_ref().is_some_and(|window| window.is_enabled()) {
+                        stats["frame_id"] = json!(next_frame_id);
+                    }
+                    // Latest periodic image quality measurement, if any
+                    if let Some(latest) = image_quality.as_ref().and_then(|q| q.lock().unwrap().clone()) {
+                        stats["image_quality"] = serde_json::to_value(latest).unwrap_or_default();
+                    }
+                    if let Some(illuminator) = &illuminator {
+                        stats["illuminator"] = serde_json::to_value(illuminator.state()).unwrap_or_default();
+                    }
+                    if let Some(time_sync) = &time_sync {
+                        stats["time_sync"] = time_sync.stats();
+                    }
+                    if let Some(maintenance) = maintenance.stats() {
+                        stats["maintenance"] = maintenance;
+                    }
+                    // Identifying metadata stays on the device. Frame processing has already taken
+                    // it off the camera's frames, so this only finds anything to copy in frames
+                    // queued from elsewhere; a stripped frame needs a fresh base64.
+                    let stripped = privacy.as_ref().and_then(|privacy| privacy.strip(&frame.data));
+                    let data = stripped.as_deref().unwrap_or(&frame.data);
+                    let encoded = if stripped.is_some() { None } else { frame.base64.as_deref() };
+                    let timestamp = privacy.as_ref().map_or(frame.timestamp.wall_ms, |privacy| privacy.timestamp(frame.timestamp.wall_ms));
+                    if let Some(privacy) = &privacy {
+                        privacy.redact_stats(&mut stats);
+                    }
+                    // Frames over the negotiated message size go out in chunks
+                    let chunk_limit = chunk_limit.load(Ordering::Relaxed);
+                    let payloads = if audio {
+                        let timestamp = FrameTimestamp { wall_ms: timestamp, ..frame.timestamp };
+                        let header = av_container::audio_header(data.len() / 2);
+                        av_container::packets(av_container::PacketKind::Audio, 0, &frame.stream_id, timestamp, &header, data, chunk_limit)
+                    } else if av_container.load(Ordering::Relaxed) {
+                        let flags = if frame.motion { av_container::FLAG_MOTION } else { 0 }
+                            | if frame.concealment.is_some() { av_container::FLAG_CONCEALED } else { 0 };
+                        let timestamp = FrameTimestamp { wall_ms: timestamp, ..frame.timestamp };
+                        av_container::packets(av_container::PacketKind::Video, flags, &frame.stream_id, timestamp, &stats, data, chunk_limit)
+                    } else {
+                        let envelope = Envelope::from_u8(frame_envelope.load(Ordering::Relaxed));
+                        let fields = FrameFields { camera_id: &camera_id, stream_id: &frame.stream_id, data, base64: encoded, timestamp, stats: &stats };
+                        if chunk_limit > 0 {
+                            envelope::encode_frame_chunked(envelope, field_naming, &fields, next_frame_id, chunk_limit)
+                        } else {
+                            vec![envelope::encode_frame(envelope, field_naming, &fields)]
+                        }
+                    };
+                    // Audio carries no frame_id, so the server has nothing to acknowledge
+                    if !audio {
+                        next_frame_id += 1;
+                    }
+                    if ack_window.as_ref().is_some_and(|window| window.is_full(next_frame_id)) {
+                        window_full_since = Some(std::time::Instant::now());
+                    }
+                    
+                    let mut sent = Ok(());
+                    let version = ProtocolVersion::from_u8(protocol_version.load(Ordering::Relaxed));
+                    for payload in payloads {
+                        sent = write.send(protocol::encode_frame(version, payload)).await;
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                    
+                    match sent {
+                        Ok(_) => {
+                            // Frame sent successfully
+                            if !audio {
+                                StatsCounters::add(&stats_counters.frames_sent, 1);
+                            }
+                            StatsCounters::add(&stats_counters.bytes_sent, frame.data.len() as u64);
+                            consecutive_successes += 1;
+                            consecutive_failures = 0;
+                            
+                            // If we have several successful sends, assume network is good
+                            if consecutive_successes > 10 && network_congested.load(Ordering::Relaxed) {
+                                network_congested.store(false, Ordering::Relaxed);
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Failed to send frame: {}", e);
+                            consecutive_failures += 1;
+                            consecutive_successes = 0;
+                            
+                            // If we have several failures in a row, mark network as congested
+                            if consecutive_failures > 3 {
+                                network_congested.store(true, Ordering::Relaxed);
+                            }
+                            
+                            // Connection might be down, reconnect
+                            break false;
+                        }
+                    }
+                    
+                    // Streaming once frames flow, degraded while the network is congested
+                    let congestion_state = network_congested.load(Ordering::Relaxed);
+                    let next_state = match (status.get(), congestion_state) {
+                        (StreamState::Joined, _) | (StreamState::Degraded, false) => Some(StreamState::Streaming),
+                        (StreamState::Streaming, true) => Some(StreamState::Degraded),
+                        _ => None,
+                    };
+                    if let Some(next_state) = next_state {
+                        let previous = status.transition(next_state);
+                        report_transition(&mut write, &camera_id, previous, next_state, version).await;
+                    }
+                    
+                    // Dynamic delay based on network conditions
+                    let delay = if congestion_state {
+                        Duration::from_millis(100)  // More delay when congested
+                    } else {
+                        Duration::from_millis(10)   // Less delay when network is good
+                    };
+                    
+                    // Backoff based on queue size too
+                    let queue_delay = if rx.policy().needs_backoff(current_queue) {
+                        Duration::from_millis(50)  // Additional delay when queue is building up
+                    } else {
+                        Duration::from_millis(0)   // No additional delay when queue is small
+                    };
+                    
+                    sleep(delay + queue_delay).await;
+                }
+                finished = &mut reader => {
+                    replaced = matches!(finished, Ok(true));
+                    if !replaced {
+                        eprintln!("Server connection closed");
+                    }
+                    break false;
+                }
+            }
+        };
+        reader.abort();
+        
+        if replaced {
+            let version = ProtocolVersion::from_u8(protocol_version.load(Ordering::Relaxed));
+            let previous = status.transition(StreamState::Replaced);
+            report_transition(&mut write, &camera_id, previous, StreamState::Replaced, version).await;
+            let _ = write.send(Message::Close(None)).await;
+            match on_replaced {
+                ReplacedAction::KeepRecording => println!("Uplink stopped for good, still capturing locally"),
+                ReplacedAction::Exit => {
+                    println!("Uplink stopped for good, shutting down");
+                    shutdown.notify_one();
+                }
             }
+            // Frames keep coming for the local outputs; nobody upstream wants them
+            while rx.recv().await.is_some() {}
+            status.transition(StreamState::Idle);
+            break;
         }
-    });
+        
+        if source_closed {
+            println!("Frame source closed, stopping uplink");
+            status.transition(StreamState::Idle);
+            break;
+        }
+        
+        // Connection might be down, retry after a delay
+        status.transition(StreamState::Reconnecting);
+        sleep(Duration::from_secs(5)).await;
+    }
+}
+
+// Tell the server about a lifecycle transition while we still have a connection to it
+async fn report_transition<S>(write: &mut S, camera_id: &str, previous: Option<StreamState>, state: StreamState, version: ProtocolVersion)
+where
+    S: futures_util::Sink<Message> + Unpin,
+    S::Error: std::fmt::Display,
+{
+    let Some(previous) = previous else {
+        return;
+    };
+    let message = lifecycle_message(camera_id, previous, state);
+    if let Err(e) = write.send(protocol::encode(version, Message::Text(message))).await {
+        eprintln!("Failed to report state change: {}", e);
+    }
+}
+
+fn lifecycle_message(camera_id: &str, previous: StreamState, state: StreamState) -> String {
+    json!({
+        "lifecycle": {
+            "camera_id": camera_id,
+            "from": previous.name(),
+            "to": state.name(),
+            "timestamp": std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+        }
+    }).to_string()
 }
 
 /// Generate a unique camera ID using UUID
@@ -451,86 +1471,715 @@ fn generate_camera_id() -> String {
     format!("camera-rust-{}", camera_id)
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
+    supervisor::install_panic_hook();
+    // The capture child for cameras without GStreamer
+    if config::has_flag("--direct-capture") {
+        std::process::exit(direct_capture::run_child());
+    }
+    // The capture child that generates frames
+    if config::has_flag("--synthetic-capture") {
+        std::process::exit(synthetic_capture::run_child());
+    }
+    let mut config = Config::load();
+    // Stand-in video for development without camera hardware
+    if config::has_flag("--test-pattern") && config.test_pattern.is_none() {
+        config.test_pattern = Some(TestPatternConfig::default());
+    }
+    if config.test_pattern.is_some() {
+        println!("Using a test pattern instead of the camera");
+    }
+    // Video from another process over stdin or a Unix socket
+    if config::has_flag("--virtual-input") && config.virtual_input.is_none() {
+        config.virtual_input = Some(VirtualInputConfig::default());
+    }
+    // Capturing without GStreamer only produces MJPEG, so that's all the server is offered
+    if config.capture.backend().without_gstreamer() && config.test_pattern.is_none() && config.virtual_input.is_none() {
+        if config.capture.backend() == CaptureSource::Synthetic {
+            println!("Capturing generated frames without GStreamer; recording and pipeline features are unavailable");
+        } else {
+            println!("Capturing from V4L2 without GStreamer; recording and pipeline features are unavailable");
+        }
+        config.codecs = vec![Codec::Mjpeg];
+    }
+    
+    // An enrolled camera is named by its device key, so it keeps its id across restarts
+    // and the server can check who joins. Without one it joins under a new id each run.
+    let identity = match DeviceIdentity::load(&config.identity.key_path) {
+        Ok(identity) => Some(Arc::new(identity)),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Failed to load the device key from {}: {}", config.identity.key_path, e);
+            }
+            None
+        }
+    };
+    let camera_id = match &identity {
+        Some(identity) => identity.camera_id(),
+        None => generate_camera_id(),
+    };
+    println!("Camera ID: {}{}", camera_id, if identity.is_some() { "" } else { " (not provisioned)" });
+    if identity.is_none() && config.tenancy.is_set() && !config::has_flag("--provision") {
+        eprintln!("Site and group are set but the camera isn't provisioned; it joins under a new id each run, so the server can't keep it in its site across restarts");
+    }
+    
+    if config::has_flag("--provision") {
+        if let Err(e) = runtime().block_on(identity::run_provisioning(config.identity.clone(), &config.server_url, config.proxy.as_ref())) {
+            eprintln!("Provisioning failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    
+    if config::has_flag("--self-test") {
+        let ready = runtime().block_on(self_test::run_self_test(&config, &camera_id, &config.server_url));
+        std::process::exit(if ready { 0 } else { 1 });
+    }
+    
+    // Offline evaluation of the congestion controller; no camera or server involved
+    if let Some(script) = config::flag_value("--simulate") {
+        let report = config::flag_value("--report").unwrap_or_else(|| "simulation-report.json".to_string());
+        if let Err(e) = simulation::run_simulation(&config, &script, &report) {
+            eprintln!("Simulation failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    
+    // Plaintext copies of encrypted recordings, e.g. from an SD card read on another machine
+    if let Some(input) = config::flag_value("--decrypt") {
+        let encryption = config.recording.as_ref().and_then(|recording| recording.encryption.clone());
+        match encryption::run_decrypt(encryption, &input, config::flag_value("--output").as_deref()) {
+            Ok(count) => println!("Decrypted {} segments", count),
+            Err(e) => {
+                eprintln!("Decryption failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    
+    if config::has_flag("--calibrate") {
+        runtime().block_on(lens::run_calibration(config.calibration.clone(), config.capture.clone()));
+        return;
+    }
+    
+    // Stills mode replaces the live stream entirely
+    if config::has_flag("--stills") {
+        runtime().block_on(stills::run_stills_mode(config.stills.clone().unwrap_or_default(), config.capture.clone(), config.time.clone(), camera_id));
+        return;
+    }
+    
+    // Drop root before anything talks to the network. The recording and staging
+    // directories have to exist first so they can be allowed through Landlock. Landlock
+    // and no_new_privs only cover the thread that sets them and threads started after,
+    // so this comes before the dashboard, the log tee and the runtime start theirs.
+    let mut recording_directories = Vec::new();
+    if let Some(recording) = &config.recording {
+        recording_directories.push(recording.directory.clone());
+        recording_directories.extend(recording.wear.staging_directory.clone());
+    }
+    for directory in &recording_directories {
+        let _ = std::fs::create_dir_all(directory);
+    }
+    config.sandbox.apply(&recording_directories);
+    
+    // The dashboard takes over the output first so the crash reporter copies it from there
+    let dashboard = if config::has_flag("--dashboard") { Dashboard::start() } else { None };
+    
+    // Only a long-running camera gets crash reports; its output goes through the reporter
+    crash::install(&config.crash_reports, config.log_stream.enabled);
+    
+    runtime().block_on(run_camera(config, identity, camera_id, dashboard));
+}
+
+// For the async parts of main. The camera's own runtime is only built once the process
+// is sandboxed, see above.
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("failed to start the async runtime")
+}
+
+async fn run_camera(config: Config, identity: Option<Arc<DeviceIdentity>>, camera_id: String, dashboard: Option<Dashboard>) {
+    tokio::spawn(crash::upload_reports(config.crash_reports.clone(), camera_id.clone()));
     let quality = Arc::new(AtomicU32::new(70));
-    let resolution_width = Arc::new(AtomicU32::new(1280));
-    let resolution_height = Arc::new(AtomicU32::new(720));
+    let initial_resolution = config.resolution.high();
+    let resolution_width = Arc::new(AtomicU32::new(initial_resolution.width));
+    let resolution_height = Arc::new(AtomicU32::new(initial_resolution.height));
     let network_congested = Arc::new(AtomicBool::new(false));
     let queue_size = Arc::new(AtomicU64::new(0));
-    let mut network_state = NetworkState::new();
+    let codec = Arc::new(AtomicU8::new(Codec::Mjpeg as u8));
+    let frame_pool = FramePool::new(64);
+    let stream_status = StreamStatus::new();
+    let capture_watchdog = FrameWatchdog::new();
+    let camera_controls = SharedCameraControls::new(config.camera_controls.clone());
+    let overlays = SharedOverlays::new();
+    let uplink_pause = UplinkPause::new();
+    let viewer_boost = ViewerBoost::new(config.viewer_boost.clone());
+    let viewer_count = ViewerCount::new(config.viewers.clone());
+    let congestion_history = CongestionHistory::new(config.congestion_history_minutes);
+    let scene_complexity = SceneComplexity::new();
+    
+    let mut supervisor = Supervisor::new();
     
-    let camera_id = generate_camera_id();
-    println!("Generated camera ID: {}", camera_id);
-
     let quality_for_manager = quality.clone();
     let width_for_manager = resolution_width.clone();
     let height_for_manager = resolution_height.clone();
     let network_congested_for_manager = network_congested.clone();
     let queue_size_for_manager = queue_size.clone();
+    
+    let stream_priorities = StreamPriorities::default();
+    let stats_counters = StatsCounters::default();
+    let uplink_allocator = config.uplink_allocator.clone().map(|allocator| {
+        uplink_allocator::spawn_uplink_allocator(allocator, stream_priorities.clone(), stats_counters.clone(), network_congested.clone())
+    });
+    let (tx, rx) = queue::channel(config.queue.clone(), queue_size.clone(), stream_priorities, uplink_allocator);
+    
+    // Local consumers that get their own copy of every frame
+    let mut local_sinks = Vec::new();
+    if let Some(hls_config) = config.hls.clone() {
+        local_sinks.push(hls::spawn_hls_writer(hls_config));
+    }
+    
+    // Uncompressed frames for local processing; consumers subscribe to this
+    let raw_frames = config.raw.clone().map(|raw_config| raw::spawn_raw_reader(raw_config, frame_pool.clone()));
+    // Detectors prefer the reduced-rate analytics feed and fall back to the raw tap
+    let analytics_frames = config.analytics.as_ref().map(|analytics| raw::spawn_raw_reader(analytics.raw_config(), frame_pool.clone()));
+    let detector_frames = analytics_frames.or(raw_frames);
+    
+    // Sharpness/exposure/noise measurements from the raw frames
+    let image_quality = match (config.image_quality.clone(), &detector_frames) {
+        (Some(quality_config), Some(raw_frames)) => {
+            Some(image_quality::spawn_image_quality_analyzer(quality_config, raw_frames))
+        }
+        (Some(_), None) => {
+            eprintln!("Image quality analysis needs raw frames; add an \"analytics\" or \"raw\" section to the config");
+            None
+        }
+        _ => None,
+    };
+    
+    // The thermal monitor sheds through the governor too, budget or not
+    let cpu_governor = (config.cpu_budget.is_some() || config.thermal.is_some())
+        .then(|| cpu_budget::spawn_cpu_governor(config.cpu_budget.clone(), overlays.clone()));
+    // Motion verdict used to decide which frames to drop first when congested
+    let motion = match (config.motion.clone(), &detector_frames) {
+        (Some(motion_config), Some(raw_frames)) => Some(motion::spawn_motion_detector(motion_config, raw_frames, cpu_governor.clone())),
+        (Some(_), None) => {
+            eprintln!("Motion detection needs raw frames; add an \"analytics\" or \"raw\" section to the config");
+            None
+        }
+        _ => None,
+    };
+    
+    // IR LEDs switched with the light level, plus manual overrides
+    let illuminator = match (config.illuminator.clone(), &detector_frames) {
+        (Some(illuminator_config), Some(raw_frames)) => Some(illuminator::spawn_illuminator(illuminator_config, raw_frames)),
+        (Some(_), None) => {
+            eprintln!("The IR illuminator needs raw frames to measure the light; add an \"analytics\" or \"raw\" section to the config");
+            None
+        }
+        _ => None,
+    };
+    
+    // Latest live JPEG, used for notification snapshots
+    let (latest_frame_sink, latest_frame) = snapshot::spawn_latest_frame_sink();
+    local_sinks.push(latest_frame_sink);
+    
+    if let Some(rtsp) = config.rtsp.clone() {
+        rtsp_server::spawn_rtsp_server(rtsp);
+    }
+    
+    if let Some(listen) = config.http_listen.clone() {
+        let status = stream_status.clone();
+        let latest_frame = latest_frame.clone();
+        supervisor.spawn_restartable("http", move || http_server::run_http_server(listen.clone(), status.clone(), latest_frame.clone()));
+    }
+    
+    let audit_log = AuditLog::open(&config.audit);
+    // Motion/tamper events; with an alarm configured only the ones it lets through are alerted
+    let camera_events = events::spawn_event_monitor(motion.clone(), image_quality.clone());
+    let capture_clock = CaptureClock::new(config.recording.as_ref().map(|recording| format!("{}/clock-anchors.jsonl", recording.directory)));
+    // The microphone is read once, for the detector and the uplink both
+    let audio_tap = config.audio_uplink.as_ref().map(|audio| AudioTap::new(audio, capture_clock.clone()));
+    if config.sound_events.is_some() || audio_tap.is_some() {
+        let detect = config.sound_events.is_some().then_some(&camera_events);
+        sound_events::spawn_sound_detector(config.sound_events.clone().unwrap_or_default(), detect, audio_tap.clone());
+    }
+    // While technicians are on site their movements are still recorded and indexed,
+    // just not alerted
+    let maintenance = Maintenance::new(config.maintenance.clone());
+    let alertable_events = maintenance::filter_events(&camera_events, &maintenance);
+    let event_queue = config.event_queue.clone().map(|queue| event_queue::spawn_event_queue(queue, &camera_events, maintenance.clone()));
+    let (alarm, alerts) = match config.alarm.clone() {
+        Some(alarm_config) => {
+            let (alarm, alerts) = alarm::spawn_alarm(alarm_config, &alertable_events);
+            (Some(alarm), alerts)
+        }
+        None => (None, alertable_events.clone()),
+    };
+    watchdog::spawn_heartbeat(&config.watchdog, capture_watchdog.clone());
+    if let Some(recording) = &config.recording {
+        if let Some(retention) = recording.retention.clone() {
+            retention::spawn_retention(recording.clone(), retention, &camera_events);
+        }
+        wear::spawn_segment_finisher(recording.clone());
+        wear::spawn_wear_monitor(recording.clone(), config.time.clone());
+    }
+    let disk_guard = config.recording.clone().map(|recording| disk_space::spawn_disk_monitor(recording, &camera_events));
+    let raw_archive = config.raw_archive.clone().and_then(|archive| raw_archive::spawn_raw_archive(archive, &config.capture));
+    let thermal = config.thermal.clone().map(|thermal| thermal::spawn_thermal_monitor(thermal, cpu_governor.clone(), &camera_events));
+    let metadata_tap = match &config.recording {
+        Some(recording) if recording.metadata => Some(metadata::spawn_metadata_writer(
+            recording.directory.clone(),
+            config.time.clone(),
+            recording.wear.write_buffer_kb * 1024,
+            motion.clone(),
+            image_quality.clone()
+        )),
+        _ => None,
+    };
+    let timeline = match &config.recording {
+        Some(recording) if recording.timeline => Some(timeline::spawn_timeline(
+            recording.clone(),
+            motion.clone(),
+            &camera_events,
+            event_queue.as_ref(),
+            metadata_tap.clone(),
+            maintenance.clone()
+        )),
+        _ => None,
+    };
+    let custody_sources = config.recording.clone().map(|recording| CustodySources {
+        recording,
+        identity: config.identity.clone(),
+        timeline: timeline.clone(),
+        audit_log: audit_log.clone(),
+    });
+    // Per-minute aggregates kept on the device
+    let stats_db = config.stats_db.clone().and_then(|stats_config| {
+        stats_db::spawn_stats_db(stats_config, stats_counters.clone(), &camera_events)
+    });
+    // With only the digest wanted, events still reach it but aren't sent one by one
+    let digest_only = config.digest.as_ref().is_some_and(|digest| digest.instead_of_alerts);
+    if let Some(digest_config) = config.digest.clone() {
+        let sources = DigestSources {
+            camera_id: camera_id.clone(),
+            time: config.time.clone(),
+            latest_frame: latest_frame.clone(),
+            status: stream_status.clone(),
+            stats: stats_counters.clone(),
+            email: config.email.clone(),
+            telegram: config.telegram.clone(),
+        };
+        // Maintenance visits aren't counted, but disarmed hours are
+        digest::spawn_digest(digest_config, sources, &alertable_events);
+    }
+    if let Some(email_config) = config.email.clone().filter(|_| !digest_only) {
+        email::spawn_email_notifier(
+            email_config,
+            config.time.clone(),
+            camera_id.clone(),
+            &alerts,
+            latest_frame.clone()
+        );
+    }
+    if let Some(telegram_config) = config.telegram.clone() {
+        telegram::spawn_telegram_bot(
+            TelegramConfig { armed: telegram_config.armed && !digest_only, ..telegram_config },
+            camera_id.clone(),
+            &alerts,
+            latest_frame.clone(),
+            stream_status.clone(),
+            alarm.clone()
+        );
+    }
+    
+    let frame_pipeline = config.pipeline.clone().map(FramePipeline::new);
+    let time_sync = config.time_sync.clone().map(|sync_config| TimeSync::new(sync_config, capture_clock.clone()));
+    let encoder_experiment = config.encoder_experiment.clone().map(|experiment| EncoderExperiment::new(experiment, quality.clone()));
+    let frame_hub = FrameHub::new();
+    let bursts = Bursts::new(config.burst.clone(), capture_clock.clone(), frame_hub.clone());
+    let frame_outputs = FrameOutputs {
+        tx: tx.clone(),
+        frame_pool: frame_pool.clone(),
+        local_sinks,
+        network_congested: network_congested.clone(),
+        motion: motion.clone(),
+        decimation: config.decimation.clone(),
+        watchdog: Some(capture_watchdog.clone()),
+        stats: stats_counters.clone(),
+        jpeg: JpegTuner::new(config.jpeg.clone(), quality.clone()),
+        experiment: encoder_experiment.clone(),
+        // The bitrate target strategy estimates bitrates from measured frame sizes
+        complexity: (config.scene_complexity.is_some() || config.adaptation.strategy == AdaptationStrategy::BitrateTarget)
+            .then(|| scene_complexity.clone()),
+        paused: uplink_pause.clone(),
+        boost: viewer_boost.clone(),
+        viewers: viewer_count.clone(),
+        pipeline: frame_pipeline.clone(),
+        hub: Some(frame_hub),
+        governor: cpu_governor,
+        concealment: config.concealment.clone(),
+        downscale: config.downscale.clone(),
+        profile: None,
+        privacy: config.privacy.clone(),
+        clock: capture_clock,
+        metadata: metadata_tap,
+    };
+    
+    // Virtual PTZ views of a fisheye lens, each sent as its own stream
+    let ptz_tx = match config.fisheye.clone() {
+        Some(fisheye) if !fisheye.views.is_empty() => Some(fisheye::spawn_virtual_views(
+            fisheye,
+            // The views have their own pipelines; only the main capture feeds the watchdog
+            // and they encode at their own quality
+            FrameOutputs { local_sinks: Vec::new(), watchdog: None, metadata: None, jpeg: None, experiment: None, complexity: None, pipeline: None, ..frame_outputs.clone() }
+        )),
+        _ => None,
+    };
+    
+    // Clean exit on request from the control socket, or once a newer session took over
+    let shutdown = Arc::new(Notify::new());
+    // Fix: Use the original atomic references
+    supervisor.spawn_essential("uplink", run_websocket_handler(UplinkContext {
+        tx: tx.clone(),
+        rx,
+        quality: quality.clone(),
+        width: resolution_width.clone(),
+        height: resolution_height.clone(),
+        network_congested: network_congested.clone(),
+        queue_size: queue_size.clone(),
+        codec: codec.clone(),
+        offered_codecs: config.codecs.clone(),
+        resolutions: config.resolution.clone(),
+        ptz_tx,
+        camera_controls: camera_controls.clone(),
+        overlays: overlays.clone(),
+        alarm: alarm.clone(),
+        illuminator: illuminator.clone(),
+        status: stream_status.clone(),
+        image_quality,
+        recording: config.recording.clone(),
+        custody: custody_sources,
+        audit_log: audit_log.clone(),
+        congestion_history: congestion_history.clone(),
+        latest_frame: latest_frame.clone(),
+        max_message_bytes: config.max_message_bytes,
+        stats_counters: stats_counters.clone(),
+        stats_db,
+        jpeg: config.jpeg.clone(),
+        encoder_experiment,
+        protocol_errors: ProtocolErrors::new(config.protocol_errors.clone()),
+        flow_control: config.flow_control.clone(),
+        timeline,
+        uplink_pause: uplink_pause.clone(),
+        viewer_boost: viewer_boost.clone(),
+        viewers: viewer_count.clone(),
+        bursts: bursts.clone(),
+        maintenance: maintenance.clone(),
+        server_url: config.server_url.clone(),
+        proxy: config.proxy.clone(),
+        http_fallback: config.http_fallback.clone(),
+        frame_pipeline,
+        privacy: config.privacy.clone(),
+        field_naming: config.field_naming,
+        time_sync,
+        audio: audio_tap,
+        log_stream: config.log_stream.clone(),
+        event_queue,
+        on_replaced: config.on_session_replaced,
+        tenancy: config.tenancy.clone(),
+        access: config.access.clone(),
+        shutdown: shutdown.clone(),
+        camera_id: camera_id.clone(),
+        identity: identity.clone(),
+    }));
+
+    // Local clients, over the control socket or the REST API, can do the same things
+    let control_context = ControlContext {
+        camera_id: camera_id.clone(),
+        status: stream_status.clone(),
+        quality: quality.clone(),
+        width: resolution_width.clone(),
+        height: resolution_height.clone(),
+        codec: codec.clone(),
+        queue_size: queue_size.clone(),
+        congested: network_congested.clone(),
+        paused: uplink_pause.clone(),
+        viewers: viewer_count.clone(),
+        latest_frame: latest_frame.clone(),
+        camera_controls: camera_controls.clone(),
+        overlays: overlays.clone(),
+        alarm: alarm.clone(),
+        illuminator: illuminator.clone(),
+        audit_log: audit_log.clone(),
+        shutdown: shutdown.clone(),
+    };
+    if let Some(path) = config.control_socket.clone() {
+        let context = control_context.clone();
+        supervisor.spawn_restartable("control", move || control_socket::run_control_socket(path.clone(), context.clone()));
+    }
+    if config.rest_api.as_ref().is_some_and(|rest| rest.token.is_empty()) {
+        eprintln!("REST API needs a token, leaving it off");
+    } else if let Some(rest_config) = config.rest_api.clone() {
+        let events = RecentEvents::spawn(&camera_events, rest_config.recent_events);
+        let recording = config.recording.clone();
+        let context = control_context.clone();
+        supervisor.spawn_restartable("rest_api", move || {
+            rest_api::run_rest_api(rest_config.clone(), context.clone(), recording.clone(), events.clone())
+        });
+    }
+    let camera_device = hotplug::camera_device(&config.hotplug, &config.capture);
+    if let Some(device) = camera_device.clone() {
+        let hotplug = config.hotplug.clone();
+        supervisor.spawn_restartable("hotplug", move || hotplug::monitor(hotplug.clone(), device.clone()));
+    }
+    if let Some(dashboard) = dashboard {
+        dashboard.spawn(
+            DashboardSources {
+                camera_id: camera_id.clone(),
+                status: stream_status.clone(),
+                stats: stats_counters.clone(),
+                queue_size: queue_size.clone(),
+                history: congestion_history.clone(),
+            },
+            shutdown.clone()
+        );
+    }
 
-    let process_manager = tokio::spawn(async move {
+    let virtual_input = match (config.virtual_input.clone(), config.stabilization.clone()) {
+        (Some(input), _) => Some(VirtualInput::spawn(input)),
+        // The stabilizer captures from the camera itself and feeds the pipeline steadied frames
+        (None, Some(stabilization))
+            if config.test_pattern.is_none() && config.fisheye.is_none() && !config.capture.backend().without_gstreamer() =>
+        {
+            Some(stabilize::spawn_stabilizer(stabilization, config.capture.clone(), camera_controls.clone(), config.sandbox.clone()))
+        }
+        (None, Some(_)) => {
+            eprintln!("Stabilization needs GStreamer capture from the camera and doesn't work with fisheye views, leaving it off");
+            None
+        }
+        (None, None) => None,
+    };
+    // Direct and synthetic capture have no GStreamer pipeline to branch the low profile off
+    let no_pipeline = config.capture.backend().without_gstreamer() && config.test_pattern.is_none() && virtual_input.is_none();
+    let warm_standby = match config.encode_profiles.clone() {
+        Some(profiles) if config.fisheye.is_none() && !no_pipeline => Some(encode_profiles::spawn_warm_standby(
+            profiles,
+            config.resolution.low(),
+            Codec::from_u8(codec.load(Ordering::Relaxed)),
+            Arc::from("main"),
+            frame_outputs.clone(),
+        )),
+        Some(_) => {
+            eprintln!("Encode profiles need a GStreamer capture pipeline and don't work with fisheye views, leaving them off");
+            None
+        }
+        None => None,
+    };
+    let main_outputs = FrameOutputs {
+        profile: warm_standby.as_ref().map(|standby| standby.switch().gate(Profile::High)),
+        ..frame_outputs.clone()
+    };
+    supervisor.spawn_essential("capture", async move {
         let mut current_quality = quality_for_manager.load(Ordering::Relaxed);
+        // What the main pipeline encodes at while the low profile takes the step downs
+        let standby_quality = current_quality;
         let mut current_width = width_for_manager.load(Ordering::Relaxed);
         let mut current_height = height_for_manager.load(Ordering::Relaxed);
-        let mut gstreamer_process = start_gstreamer(current_width, current_height, current_quality).await;
-        let mut network_state = NetworkState::new();
+        let mut current_codec = Codec::from_u8(codec.load(Ordering::Relaxed));
+        frame_pool.prepare_for_resolution(current_width, current_height, 8);
+        let recording_allowed = || !disk_guard.as_ref().is_some_and(|guard| guard.recording_paused());
+        let frame_rate = || thermal.as_ref().and_then(|thermal| thermal.framerate_cap(viewer_boost.warm_framerate()));
+        let mut current_frame_rate = frame_rate();
+        let mut capture_process = start_capture(frame_rate(), &config, &camera_controls, virtual_input.as_ref(), recording_allowed()).await;
+        let mut gstreamer_process = start_gstreamer(
+            CaptureSettings { width: current_width, height: current_height, quality: current_quality, codec: current_codec, frame_rate: frame_rate(), record: recording_allowed() },
+            &config,
+            &camera_controls,
+            &overlays,
+            virtual_input.as_ref()
+        ).await;
+        let mut network_state = adaptation::policy(&config.adaptation, config.resolution.clone(), std::time::Instant::now());
+        println!("Adapting to the network with the {} strategy", network_state.name());
         let mut consecutive_failures: u32 = 0;
         let mut consecutive_successes: u32 = 0;
+        let mut stall_restarts: u32 = 0;
+        let stall_timeout = Duration::from_secs(config.watchdog.stall_seconds);
+        let main_stream_id: Arc<str> = Arc::from("main");
+        let mut capture_started = std::time::Instant::now();
+        let mut waiting_for_camera = false;
     
-        let mut stdout = gstreamer_process.stdout.take().expect("Failed to capture GStreamer stdout");
-        let (tx, rx) = mpsc::channel::<Vec<u8>>(60);
-    
-        let tx_clone = tx.clone();
-        
-        // Fix: Use the original atomic references
-        start_websocket_handler(
-            tx_clone,
-            rx,
-            quality_for_manager.clone(),
-            width_for_manager.clone(),
-            height_for_manager.clone(),
-            network_congested_for_manager.clone(),
-            queue_size_for_manager.clone(),
-            camera_id.clone()
-        ).await;
-        
-        process_frames(stdout, tx.clone(), queue_size_for_manager.clone()).await;
+        if let Some(governor) = &main_outputs.governor {
+            governor.watch_pipeline(gstreamer_process.id());
+        }
+        let mut stdout = gstreamer_process.take_output().expect("Failed to capture GStreamer stdout");
+        process_frames(stdout, current_codec, main_stream_id.clone(), main_outputs.clone()).await;
         
         loop {
             // Get current metrics
             let queue_size_now = queue_size_for_manager.load(Ordering::Relaxed);
             let server_congestion = network_congested_for_manager.load(Ordering::Relaxed);
             
-            // Update local metrics tracking
-            if server_congestion || queue_size_now > 15 {
-                consecutive_failures = (consecutive_failures + 1).min(10);
-                consecutive_successes = 0;
-            } else {
-                consecutive_successes = (consecutive_successes + 1).min(30);
-                if consecutive_failures > 0 {
-                    consecutive_failures -= 1;
+            track_failures(&mut consecutive_failures, &mut consecutive_successes, server_congestion || config.queue.is_backed_up(queue_size_now));
+            
+            // Nothing to restart while the camera is unplugged; the stall watchdog would
+            // only go on to power-cycle it
+            if camera_device.as_ref().is_some_and(|device| !device.present()) {
+                if !waiting_for_camera {
+                    let _ = gstreamer_process.kill().await;
+                    if let Some(process) = capture_process.as_mut() {
+                        let _ = process.kill().await;
+                    }
+                    waiting_for_camera = true;
                 }
+                capture_started = std::time::Instant::now();
+                sleep(network_state.check_interval()).await;
+                continue;
             }
+            waiting_for_camera = false;
             
-            // Get resolution and quality recommendations from network state
-            let (is_congested, recommended_width, recommended_quality) = 
-                network_state.update_congestion(queue_size_now, consecutive_failures, server_congestion);
+            // How detailed the scene is decides how much congestion the high resolution is worth
+            let bits_per_pixel = scene_complexity.bits_per_pixel(current_width, current_height, current_quality, current_codec);
+            let relative_complexity = config.scene_complexity.as_ref().and_then(|complexity_config| {
+                let relative = bits_per_pixel? / complexity_config.reference_bits_per_pixel;
+                network_state.set_scene_complexity(relative, complexity_config);
+                Some(relative)
+            });
             
-            // Calculate recommended height based on width (16:9 or 4:3 aspect ratio)
-            let recommended_height = if recommended_width == 1280 { 720 } else { 480 };
+            // Get resolution and quality recommendations from network state
+            // The network reader stores the server's suggestions in the shared settings
+            let signals = adaptation::Signals {
+                queue_size: queue_size_now,
+                consecutive_failures,
+                server_congested: server_congestion,
+                suggested_quality: quality_for_manager.load(Ordering::Relaxed),
+                suggested_resolution: Resolution {
+                    width: width_for_manager.load(Ordering::Relaxed),
+                    height: height_for_manager.load(Ordering::Relaxed),
+                },
+                bits_per_pixel,
+                codec: current_codec,
+            };
+            let (is_congested, recommended_resolution, recommended_quality) = 
+                network_state.update(std::time::Instant::now(), &signals);
+            // A warm SoC gets the low rung whatever the network could carry
+            let recommended_resolution = match &thermal {
+                Some(thermal) if thermal.level() >= ThermalLevel::Warm => {
+                    let low = config.resolution.low();
+                    if low.width * low.height < recommended_resolution.width * recommended_resolution.height { low } else { recommended_resolution }
+                }
+                _ => recommended_resolution,
+            };
+            // A burst gets the top rung, from early enough that the restart is over before it starts.
+            // Not over a congested link: the burst then captures at whatever the uplink can carry.
+            let burst_due = bursts.due() && !is_congested;
+            let recommended_resolution = if burst_due { config.resolution.high() } else { recommended_resolution };
+            // The server may have negotiated a different codec
+            let selected_codec = Codec::from_u8(codec.load(Ordering::Relaxed));
+            // With the low profile kept warm every step down is a switch to it, and the
+            // main pipeline stays on the top rung. Only for MJPEG: other codecs' frames
+            // depend on the ones before, which came from the other encoder.
+            let standby = warm_standby.as_ref().filter(|_| selected_codec == Codec::Mjpeg);
+            if let (Some(warm_standby), None) = (&warm_standby, standby) {
+                if warm_standby.switch().select(Profile::High) {
+                    println!("Switched back to the high encode profile for {}", selected_codec.name());
+                }
+            }
+            let (recommended_resolution, uplink_resolution) = match standby {
+                Some(standby) => {
+                    let profile = if recommended_resolution == config.resolution.high() { Profile::High } else { Profile::Low };
+                    if standby.switch().select(profile) {
+                        println!("Switched to the {} encode profile", profile.name());
+                    }
+                    let uplink = if profile == Profile::Low { config.resolution.low() } else { config.resolution.high() };
+                    (config.resolution.high(), uplink)
+                }
+                None => (recommended_resolution, recommended_resolution),
+            };
+            let recommended_width = recommended_resolution.width;
+            let recommended_height = recommended_resolution.height;
+            // Spend the headroom on an operator who is watching, but never while congested
+            // The profile switch already is the step down, so congestion alone doesn't restart
+            let recommended_quality = if standby.is_some() { standby_quality } else { recommended_quality };
+            let recommended_quality = if burst_due {
+                bursts.quality()
+            } else if is_congested {
+                recommended_quality
+            } else {
+                viewer_boost.apply(recommended_quality, std::time::Instant::now())
+            };
             
             // Update atomic values for other threads
             network_congested_for_manager.store(is_congested, Ordering::Relaxed);
             
+            // Optional restarts wait for a burst being captured to end, which they would lose
+            // frames from; what changed meanwhile is picked up once it is over
+            let capturing_burst = bursts.capturing();
+            // Camera controls and overlays only take effect on a restart
+            let controls_changed = !capturing_burst && camera_controls.take_changed();
+            let overlays_changed = !capturing_burst && overlays.take_changed();
+            // Inter-frame codecs need a fresh keyframe after frames were held back
+            let resumed = !capturing_burst && uplink_pause.take_resumed() && selected_codec != Codec::Mjpeg;
+            // A RAW still needs the camera to itself for a moment
+            let raw_due = !capturing_burst && raw_archive.as_ref().is_some_and(|archive| archive.take_due());
+            // Recording was paused for disk space, or can start again
+            let recording_changed = disk_guard.as_ref().is_some_and(|guard| guard.take_changed());
+            // The frame rate and overlays come down or back up with the thermal level
+            let thermal_changed = thermal.as_ref().is_some_and(|thermal| thermal.take_changed());
+            // A boost starting or running out while warm moves the frame rate cap
+            let frame_rate_changed = !capturing_burst && frame_rate() != current_frame_rate;
+            // The camera was plugged back in
+            let replugged = camera_device.as_ref().is_some_and(|device| device.take_returned());
+            
             // Check if we need to change GStreamer settings
-            let significant_change = recommended_quality.abs_diff(current_quality) > 5 || 
+            let significant_change = (!capturing_burst && (
+                                    recommended_quality.abs_diff(current_quality) > 5 || 
                                     recommended_width != current_width || 
-                                    recommended_height != current_height;
+                                    recommended_height != current_height ||
+                                    selected_codec != current_codec ||
+                                    controls_changed ||
+                                    overlays_changed ||
+                                    raw_due ||
+                                    frame_rate_changed ||
+                                    resumed)) ||
+                                    recording_changed ||
+                                    thermal_changed ||
+                                    replugged;
+            
+            congestion_history.record(CongestionSample {
+                timestamp: CongestionHistory::timestamp(),
+                queue_depth: queue_size_now,
+                consecutive_failures,
+                server_congested: server_congestion,
+                level: network_state.level(),
+                congested: is_congested,
+                resolution: format!("{}x{}", uplink_resolution.width, uplink_resolution.height),
+                quality: recommended_quality,
+                codec: selected_codec.name(),
+                pipeline_restarted: significant_change,
+                scene_complexity: relative_complexity,
+            });
+            
+            // libcamera can wedge without exiting; stdout just goes quiet
+            // Give a freshly started pipeline the full timeout before its first frame
+            let stalled_for = capture_watchdog.stalled_for().min(capture_started.elapsed());
+            let stalled = stalled_for >= stall_timeout;
+            if stalled {
+                stall_restarts += 1;
+                eprintln!("No frames from the camera for {:?}, restarting capture (attempt {})", stalled_for, stall_restarts);
+                if stall_restarts >= config.watchdog.restarts_before_power_cycle {
+                    watchdog::power_cycle_camera(&config.watchdog).await;
+                    stall_restarts = 0;
+                }
+            } else if capture_watchdog.stalled_for() < capture_started.elapsed() {
+                // Frames have flowed since the last restart
+                stall_restarts = 0;
+            }
                                     
-            if significant_change {
-                println!("Adjusting camera: Quality={}, Resolution={}x{}, Queue={}, Congestion={}", 
-                        recommended_quality, recommended_width, recommended_height, queue_size_now, is_congested);
+            if significant_change || stalled {
+                println!("Adjusting camera: Quality={}, Resolution={}x{}, Codec={}, Queue={}, Congestion={}", 
+                        recommended_quality, recommended_width, recommended_height, selected_codec.name(), queue_size_now, is_congested);
                         
                 // Update atomic values
                 quality_for_manager.store(recommended_quality, Ordering::Relaxed);
@@ -538,27 +2187,59 @@ async fn main() {
                 height_for_manager.store(recommended_height, Ordering::Relaxed);
                 
                 // Restart GStreamer with new settings
+                frame_pool.prepare_for_resolution(recommended_width, recommended_height, 8);
                 let _ = gstreamer_process.kill().await;
-                gstreamer_process = start_gstreamer(recommended_width, recommended_height, recommended_quality).await;
-                stdout = gstreamer_process.stdout.take().expect("Failed to capture GStreamer stdout");
-                process_frames(stdout, tx.clone(), queue_size_for_manager.clone()).await;
+                // A capture pipeline of its own, with the recording and restreams, only
+                // restarts for what changes the camera or the recording
+                let capture_changed = stalled || controls_changed || recording_changed || thermal_changed || frame_rate_changed || raw_due || replugged;
+                current_frame_rate = frame_rate();
+                if let Some(process) = capture_process.as_mut().filter(|_| capture_changed) {
+                    let _ = process.kill().await;
+                }
+                if let Some(archive) = raw_archive.as_ref().filter(|_| raw_due) {
+                    archive.capture(&config.time).await;
+                }
+                if capture_changed && capture_process.is_some() {
+                    capture_process = start_capture(frame_rate(), &config, &camera_controls, virtual_input.as_ref(), recording_allowed()).await;
+                }
+                gstreamer_process = start_gstreamer(
+                    CaptureSettings { width: recommended_width, height: recommended_height, quality: recommended_quality, codec: selected_codec, frame_rate: frame_rate(), record: recording_allowed() },
+                    &config,
+                    &camera_controls,
+                    &overlays,
+                    virtual_input.as_ref()
+                ).await;
+                if let Some(governor) = &main_outputs.governor {
+                    governor.watch_pipeline(gstreamer_process.id());
+                }
+                stdout = gstreamer_process.take_output().expect("Failed to capture GStreamer stdout");
+                process_frames(stdout, selected_codec, main_stream_id.clone(), main_outputs.clone()).await;
+                if let Some(standby) = &warm_standby {
+                    standby.restarted(selected_codec);
+                }
+                capture_started = std::time::Instant::now();
+                scene_complexity.reset();
                 
                 // Update current values
+                current_codec = selected_codec;
                 current_quality = recommended_quality;
                 current_width = recommended_width;
                 current_height = recommended_height;
             }
             
-            // Check less frequently when stable
-            let check_interval = if network_state.stability_counter > 15 {
-                Duration::from_secs(5)
-            } else {
-                Duration::from_secs(2)
-            };
-            
-            sleep(check_interval).await;
+            sleep(network_state.check_interval()).await;
         }
     });
     
-    let _ = process_manager.await;
+    let exit_code = tokio::select! {
+        stopped = supervisor.run() => {
+            eprintln!("Essential task {} stopped, shutting down", stopped);
+            1
+        }
+        _ = shutdown.notified() => 0,
+    };
+    supervisor.shutdown().await;
+    crash::drain_output();
+    dashboard::restore_terminal();
+    std::process::exit(exit_code);
 }
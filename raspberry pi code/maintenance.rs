@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{sleep_until, Instant},
+};
+
+use crate::events::CameraEvents;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    // How long a maintenance command lasts without its own "minutes"
+    pub default_minutes: u64,
+    // Longest window a single command can open, so a forgotten one still ends
+    pub max_minutes: u64,
+    // Event kinds held back from the alarm and notifiers meanwhile
+    pub suppress: Vec<String>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            default_minutes: 60,
+            max_minutes: 480,
+            suppress: ["motion", "tamper", "loud_noise", "glass_break"].map(String::from).to_vec(),
+        }
+    }
+}
+
+// {"command": "maintenance", "minutes": 30, "reason": "window cleaning"}
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceCommand {
+    pub minutes: Option<u64>,
+    pub reason: Option<String>,
+}
+
+struct Window {
+    until: Instant,
+    reason: Option<String>,
+    suppressed: u64,
+}
+
+// Watch-only mode for cleaners and technicians on site: the camera keeps streaming
+// and recording, but what they set off doesn't raise alarms
+#[derive(Clone)]
+pub struct Maintenance {
+    config: MaintenanceConfig,
+    window: Arc<Mutex<Option<Window>>>,
+}
+
+impl Maintenance {
+    pub fn new(config: MaintenanceConfig) -> Self {
+        Self { config, window: Arc::new(Mutex::new(None)) }
+    }
+
+    // Start or replace the window; returns how long it lasts
+    pub fn start(&self, command: &MaintenanceCommand) -> Duration {
+        let minutes = command.minutes.unwrap_or(self.config.default_minutes).min(self.config.max_minutes);
+        let lasts = Duration::from_secs(minutes * 60);
+        let until = Instant::now() + lasts;
+        *self.window.lock().unwrap() = Some(Window { until, reason: command.reason.clone(), suppressed: 0 });
+
+        let window = self.window.clone();
+        tokio::spawn(async move {
+            sleep_until(until).await;
+            let mut window = window.lock().unwrap();
+            // Unless it was ended or extended in the meantime
+            if window.as_ref().is_some_and(|current| current.until == until) {
+                let suppressed = window.take().map_or(0, |ended| ended.suppressed);
+                println!("Maintenance mode expired, {} events were suppressed", suppressed);
+            }
+        });
+        lasts
+    }
+
+    // True if maintenance mode was on
+    pub fn end(&self) -> bool {
+        match self.window.lock().unwrap().take() {
+            Some(ended) => {
+                println!("Maintenance mode ended, {} events were suppressed", ended.suppressed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.window.lock().unwrap().as_ref().is_some_and(|window| Instant::now() < window.until)
+    }
+
+    // Whether events of this kind are being held back right now
+    pub fn covers(&self, kind: &str) -> bool {
+        self.is_active() && self.config.suppress.iter().any(|suppressed| suppressed == kind)
+    }
+
+    // Like covers, and counts the event for the stats
+    fn suppresses(&self, kind: &str) -> bool {
+        let covered = self.covers(kind);
+        if covered {
+            if let Some(window) = self.window.lock().unwrap().as_mut() {
+                window.suppressed += 1;
+            }
+        }
+        covered
+    }
+
+    // Sent with the frame stats while the mode is on
+    pub fn stats(&self) -> Option<serde_json::Value> {
+        let window = self.window.lock().unwrap();
+        let window = window.as_ref().filter(|window| Instant::now() < window.until)?;
+        Some(json!({
+            "remaining_seconds": window.until.saturating_duration_since(Instant::now()).as_secs(),
+            "reason": window.reason,
+            "suppressed_events": window.suppressed,
+        }))
+    }
+}
+
+// The detector events with the suppressed ones taken out, for the alarm and notifiers.
+// Recording-side consumers keep the full stream and mark what was suppressed.
+pub fn filter_events(events: &CameraEvents, maintenance: &Maintenance) -> CameraEvents {
+    let (tx, _) = broadcast::channel(16);
+    let filtered = CameraEvents::from_sender(tx.clone());
+    let mut event_rx = events.subscribe();
+    let maintenance = maintenance.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match event_rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            if maintenance.suppresses(event.kind()) {
+                println!("Maintenance mode, not alerting: {}", event.describe());
+                continue;
+            }
+            let _ = tx.send(event);
+        }
+    });
+    filtered
+}
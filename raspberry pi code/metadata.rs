@@ -0,0 +1,99 @@
+use serde_json::json;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::capture_clock::FrameTimestamp;
+use crate::clock::TimeConfig;
+use crate::image_quality::SharedImageQuality;
+use crate::motion::MotionState;
+
+// Collects per-frame analytics next to the video so they can be correlated with
+// recordings later. Lines go to "<directory>/metadata-<local date>.jsonl".
+#[derive(Clone)]
+pub struct MetadataTap {
+    tx: mpsc::Sender<serde_json::Value>,
+    motion: Option<MotionState>,
+    image_quality: Option<SharedImageQuality>,
+}
+
+impl MetadataTap {
+    // Called for every frame extracted from the capture pipeline
+    pub fn record(&self, stream_id: &str, frame_bytes: usize, timestamp: FrameTimestamp) {
+        let mut sample = json!({
+            // Both clocks, so samples line up with the recording even across NTP corrections
+            "timestamp": timestamp.wall_ms,
+            "monotonic_ms": timestamp.monotonic_ms,
+            "stream_id": stream_id,
+            "bytes": frame_bytes,
+        });
+        if let Some(motion) = &self.motion {
+            sample["motion"] = json!(motion.is_active());
+            sample["motion_score"] = json!(motion.score());
+        }
+        if let Some(quality) = self.image_quality.as_ref().and_then(|q| q.lock().unwrap().clone()) {
+            sample["exposure"] = json!({
+                "overexposed_percent": quality.overexposed_percent,
+                "underexposed_percent": quality.underexposed_percent,
+            });
+            sample["sharpness"] = json!(quality.sharpness);
+        }
+        // Losing a sample is better than stalling the capture
+        let _ = self.tx.try_send(sample);
+    }
+
+    // A timeline line (a motion interval or an event) among the samples, so the sidecar
+    // is the one index kept next to the recordings
+    pub fn mark(&self, line: serde_json::Value) {
+        if self.tx.try_send(line).is_err() {
+            eprintln!("Metadata writer falling behind, a timeline entry was not saved");
+        }
+    }
+}
+
+pub fn spawn_metadata_writer(
+    directory: String,
+    time: TimeConfig,
+    // Lines are written to the card in blocks of this size rather than one per frame
+    buffer_bytes: usize,
+    motion: Option<MotionState>,
+    image_quality: Option<SharedImageQuality>
+) -> MetadataTap {
+    let (tx, mut rx) = mpsc::channel::<serde_json::Value>(256);
+
+    tokio::spawn(async move {
+        let mut current_date = String::new();
+        let mut file: Option<BufWriter<tokio::fs::File>> = None;
+
+        while let Some(sample) = rx.recv().await {
+            // Roll over to a new file at local midnight
+            let date = time.now().format("%Y%m%d").to_string();
+            if date != current_date || file.is_none() {
+                if let Some(mut previous) = file.take() {
+                    let _ = previous.flush().await;
+                }
+                let path = format!("{}/metadata-{}.jsonl", directory, date);
+                file = match OpenOptions::new().create(true).append(true).open(&path).await {
+                    Ok(file) => Some(BufWriter::with_capacity(buffer_bytes, file)),
+                    Err(e) => {
+                        eprintln!("Failed to open metadata file {}: {}", path, e);
+                        None
+                    }
+                };
+                current_date = date;
+            }
+
+            if let Some(writer) = file.as_mut() {
+                let line = format!("{}\n", sample);
+                if let Err(e) = writer.write_all(line.as_bytes()).await {
+                    eprintln!("Failed to write metadata: {}", e);
+                    file = None;
+                }
+            }
+        }
+    });
+
+    MetadataTap { tx, motion, image_quality }
+}
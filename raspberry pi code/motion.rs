@@ -0,0 +1,116 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::cpu_budget::{CpuGovernor, Stage};
+use crate::raw::RawFrames;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MotionConfig {
+    // Brightness change (0-255) for a sampled pixel to count as changed
+    pub pixel_threshold: u8,
+    // Share of sampled pixels that must change for the frame to count as motion
+    pub min_changed_percent: f64,
+    // Keep reporting motion this long after the last detection
+    pub hold_seconds: u64,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            pixel_threshold: 25,
+            min_changed_percent: 1.0,
+            hold_seconds: 2,
+        }
+    }
+}
+
+// The detector's current verdict, shared with the frame pipeline
+#[derive(Clone)]
+pub struct MotionState {
+    last_motion_ms: Arc<AtomicU64>,
+    // Percentage of changed pixels in the latest frame, stored as f32 bits
+    score: Arc<AtomicU32>,
+    hold_ms: u64,
+}
+
+impl MotionState {
+    pub fn is_active(&self) -> bool {
+        let last = self.last_motion_ms.load(Ordering::Relaxed);
+        last != 0 && now_ms().saturating_sub(last) <= self.hold_ms
+    }
+
+    pub fn score(&self) -> f32 {
+        f32::from_bits(self.score.load(Ordering::Relaxed))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// Frame-differencing motion detector on the raw frame tap
+pub fn spawn_motion_detector(config: MotionConfig, raw_frames: &RawFrames, governor: Option<CpuGovernor>) -> MotionState {
+    let state = MotionState {
+        last_motion_ms: Arc::new(AtomicU64::new(0)),
+        score: Arc::new(AtomicU32::new(0)),
+        hold_ms: config.hold_seconds * 1000,
+    };
+    let last_motion_ms = state.last_motion_ms.clone();
+    let score = state.score.clone();
+    let mut frames = raw_frames.subscribe();
+
+    tokio::spawn(async move {
+        let mut previous: Vec<u8> = Vec::new();
+        let mut received: u64 = 0;
+
+        loop {
+            let frame = match frames.recv().await {
+                Ok(frame) => frame,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            received += 1;
+            let keep_every = governor.as_ref().map_or(1, |governor| governor.detector_keep_every());
+            if !received.is_multiple_of(keep_every as u64) {
+                continue;
+            }
+            let started = Instant::now();
+
+            // Every 4th pixel in both directions is plenty for whole-scene motion
+            let width = frame.width as usize;
+            let luma = frame.luma();
+            let sampled: Vec<u8> = luma
+                .chunks(width.max(1))
+                .step_by(4)
+                .flat_map(|row| row.iter().step_by(4).copied())
+                .collect();
+
+            if previous.len() == sampled.len() {
+                let changed = sampled
+                    .iter()
+                    .zip(&previous)
+                    .filter(|(a, b)| a.abs_diff(**b) > config.pixel_threshold)
+                    .count();
+                let changed_percent = changed as f64 * 100.0 / sampled.len().max(1) as f64;
+                score.store((changed_percent as f32).to_bits(), Ordering::Relaxed);
+                if changed_percent >= config.min_changed_percent {
+                    last_motion_ms.store(now_ms(), Ordering::Relaxed);
+                }
+            }
+            previous = sampled;
+            if let Some(governor) = &governor {
+                governor.record(Stage::Detect, started.elapsed());
+            }
+        }
+    });
+
+    state
+}
@@ -0,0 +1,369 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{io, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time::{interval, sleep, timeout, Instant, MissedTickBehavior},
+};
+
+use crate::alarm::{AlarmHandle, AlarmMode, AlarmState};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    // host:port of the broker. Plain TCP, for a broker on the local network.
+    pub broker: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // The state is published to <topic>/state, commands are taken from <topic>/set and
+    // <topic>/availability says whether the camera is connected
+    pub topic: String,
+    // Announce the alarm to Home Assistant under this prefix; unset to leave it out
+    pub discovery_prefix: Option<String>,
+    pub keep_alive_seconds: u16,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker: "127.0.0.1:1883".to_string(),
+            client_id: "security-camera".to_string(),
+            username: None,
+            password: None,
+            topic: "security_camera/alarm".to_string(),
+            discovery_prefix: Some("homeassistant".to_string()),
+            keep_alive_seconds: 60,
+        }
+    }
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Nothing the camera subscribes to comes near this; a length past it means a broken stream
+const MAX_PACKET_BYTES: usize = 64 * 1024;
+
+// MQTT 3.1.1 packet types, the top four bits of the first byte
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+
+// The states and commands of Home Assistant's MQTT alarm panel, so it works with no
+// templates on that side
+fn state_payload(state: AlarmState) -> &'static str {
+    match state {
+        AlarmState::Disarmed | AlarmState::Armed(AlarmMode::Disarmed) => "disarmed",
+        AlarmState::Arming(_) => "arming",
+        AlarmState::Armed(AlarmMode::ArmedHome) => "armed_home",
+        AlarmState::Armed(AlarmMode::ArmedAway) => "armed_away",
+        AlarmState::Pending(_) => "pending",
+        AlarmState::Triggered(_) => "triggered",
+    }
+}
+
+fn command_mode(payload: &[u8]) -> Option<AlarmMode> {
+    match payload {
+        b"DISARM" => Some(AlarmMode::Disarmed),
+        b"ARM_HOME" => Some(AlarmMode::ArmedHome),
+        b"ARM_AWAY" => Some(AlarmMode::ArmedAway),
+        _ => None,
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+// The fixed header: type and flags, then the length of the rest seven bits at a time
+fn packet(first: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut out = vec![first];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    out.extend(body);
+    out
+}
+
+// Clean session, with a retained "offline" will so subscribers see the camera drop off
+fn connect_packet(config: &MqttConfig) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, b"MQTT");
+    body.push(4);
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&config.keep_alive_seconds.to_be_bytes());
+    put_string(&mut body, config.client_id.as_bytes());
+    put_string(&mut body, format!("{}/availability", config.topic).as_bytes());
+    put_string(&mut body, b"offline");
+    for credential in [&config.username, &config.password].into_iter().flatten() {
+        put_string(&mut body, credential.as_bytes());
+    }
+    packet(CONNECT << 4, body)
+}
+
+// At most once; a missed state is put right by the next one, and retained for whoever
+// subscribes later
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH << 4 | retain as u8, body)
+}
+
+fn subscribe_packet(packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    put_string(&mut body, topic.as_bytes());
+    body.push(0);
+    packet(SUBSCRIBE << 4 | 0x02, body)
+}
+
+// The first byte of a packet and everything after its length
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let first = reader.read_u8().await?;
+    let mut length = 0usize;
+    for shift in 0..4 {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << (7 * shift);
+        if byte & 0x80 == 0 {
+            if length > MAX_PACKET_BYTES {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} byte packet", length)));
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await?;
+            return Ok((first, body));
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "packet length runs past four bytes"))
+}
+
+// Topic and payload of a PUBLISH, skipping the packet id that QoS 1 and 2 add
+fn parse_publish(first: u8, body: &[u8]) -> Option<(&str, &[u8])> {
+    let length = u16::from_be_bytes(body.get(..2)?.try_into().ok()?) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + length)?).ok()?;
+    let rest = &body[2 + length..];
+    let payload = if (first >> 1) & 0x03 > 0 { rest.get(2..)? } else { rest };
+    Some((topic, payload))
+}
+
+fn connack_reason(code: u8) -> &'static str {
+    match code {
+        1 => "unsupported protocol version",
+        2 => "client id rejected",
+        3 => "server unavailable",
+        4 => "bad username or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+// Keep the alarm on the broker: its state published as it changes, and arm/disarm
+// commands taken from the command topic. Reconnects for as long as the alarm runs.
+pub async fn run_alarm_mqtt(config: MqttConfig, alarm: AlarmHandle) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match session(&config, &alarm, &mut backoff).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("MQTT connection to {} failed: {}; retrying in {:?}", config.broker, e, backoff),
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Ok once the alarm has stopped
+async fn session(config: &MqttConfig, alarm: &AlarmHandle, backoff: &mut Duration) -> io::Result<()> {
+    let timed_out = |what: &str| io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what));
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(&config.broker)).await.map_err(|_| timed_out("connecting"))??;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(&connect_packet(config)).await?;
+    let (first, body) = timeout(CONNECT_TIMEOUT, read_packet(&mut reader)).await.map_err(|_| timed_out("CONNACK"))??;
+    if first >> 4 != CONNACK || body.len() < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected CONNACK"));
+    }
+    if body[1] != 0 {
+        return Err(io::Error::other(format!("broker refused the connection: {}", connack_reason(body[1]))));
+    }
+    *backoff = Duration::from_secs(1);
+    println!("MQTT connected to {}", config.broker);
+
+    let state_topic = format!("{}/state", config.topic);
+    let command_topic = format!("{}/set", config.topic);
+    let availability_topic = format!("{}/availability", config.topic);
+    writer.write_all(&subscribe_packet(1, &command_topic)).await?;
+    if let Some(prefix) = &config.discovery_prefix {
+        let object_id: String = config.client_id.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        let discovery = json!({
+            "name": "Alarm",
+            "unique_id": format!("{}_alarm", object_id),
+            "state_topic": state_topic,
+            "command_topic": command_topic,
+            "availability_topic": availability_topic,
+            "code_arm_required": false,
+            "supported_features": ["arm_home", "arm_away"],
+            "device": { "identifiers": [object_id], "name": config.client_id },
+        });
+        let topic = format!("{}/alarm_control_panel/{}/alarm/config", prefix, object_id);
+        writer.write_all(&publish_packet(&topic, discovery.to_string().as_bytes(), true)).await?;
+    }
+    writer.write_all(&publish_packet(&availability_topic, b"online", true)).await?;
+    let mut changes = alarm.changes();
+    let mut published = state_payload(*changes.borrow_and_update());
+    writer.write_all(&publish_packet(&state_topic, published.as_bytes(), true)).await?;
+
+    // Read on its own task, as a read cut short by the select below would lose bytes
+    let (packet_tx, mut packets) = mpsc::channel(8);
+    let reading = tokio::spawn(async move {
+        loop {
+            let packet = read_packet(&mut reader).await;
+            let failed = packet.is_err();
+            if packet_tx.send(packet).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let keep_alive = Duration::from_secs(config.keep_alive_seconds.max(1) as u64);
+    let mut ping = interval(keep_alive);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping.tick().await;
+    let mut last_heard = Instant::now();
+
+    let result = async {
+        loop {
+            tokio::select! {
+                packet = packets.recv() => {
+                    let (first, body) = packet.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))??;
+                    last_heard = Instant::now();
+                    // Acknowledgements and ping responses need nothing more
+                    let Some((topic, payload)) = (first >> 4 == PUBLISH).then(|| parse_publish(first, &body)).flatten() else {
+                        continue;
+                    };
+                    if topic != command_topic {
+                        continue;
+                    }
+                    match command_mode(payload) {
+                        Some(mode) => {
+                            println!("MQTT set alarm to {}", mode.name());
+                            alarm.set_mode(mode).await;
+                        }
+                        None => eprintln!("Ignoring MQTT alarm command {:?}", String::from_utf8_lossy(payload)),
+                    }
+                }
+                changed = changes.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    // The alarm's own states are finer than the panel's
+                    let state = state_payload(*changes.borrow_and_update());
+                    if state != published {
+                        writer.write_all(&publish_packet(&state_topic, state.as_bytes(), true)).await?;
+                        published = state;
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > keep_alive * 2 {
+                        return Err(timed_out("keep-alive"));
+                    }
+                    writer.write_all(&[PINGREQ << 4, 0]).await?;
+                }
+            }
+        }
+    }
+    .await;
+    reading.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length() {
+        for (length, encoded) in [(0, vec![0x00]), (127, vec![0x7f]), (128, vec![0x80, 0x01]), (16_383, vec![0xff, 0x7f]), (16_384, vec![0x80, 0x80, 0x01])] {
+            let bytes = packet(0x30, vec![0; length]);
+            assert_eq!(&bytes[1..1 + encoded.len()], &encoded[..], "{}", length);
+            assert_eq!(bytes.len(), 1 + encoded.len() + length);
+        }
+    }
+
+    #[test]
+    fn connect_with_credentials() {
+        let config = MqttConfig {
+            client_id: "cam".to_string(),
+            username: Some("u".to_string()),
+            password: Some("p".to_string()),
+            topic: "t".to_string(),
+            ..MqttConfig::default()
+        };
+        let mut expected = vec![0x10, 46, 0, 4, b'M', b'Q', b'T', b'T', 4, 0xe6, 0, 60];
+        for field in [&b"cam"[..], b"t/availability", b"offline", b"u", b"p"] {
+            expected.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            expected.extend_from_slice(field);
+        }
+        assert_eq!(connect_packet(&config), expected);
+    }
+
+    #[test]
+    fn subscribe_and_publish() {
+        assert_eq!(subscribe_packet(1, "a/set"), [0x82, 10, 0, 1, 0, 5, b'a', b'/', b's', b'e', b't', 0]);
+        assert_eq!(publish_packet("a", b"on", true), [0x31, 5, 0, 1, b'a', b'o', b'n']);
+        assert_eq!(publish_packet("a", b"on", false)[0], 0x30);
+    }
+
+    #[tokio::test]
+    async fn read_back_publish() {
+        let bytes = publish_packet("security_camera/alarm/set", b"ARM_AWAY", false);
+        let (first, body) = read_packet(&mut &bytes[..]).await.unwrap();
+        assert_eq!(parse_publish(first, &body), Some(("security_camera/alarm/set", &b"ARM_AWAY"[..])));
+        assert_eq!(command_mode(b"ARM_AWAY"), Some(AlarmMode::ArmedAway));
+    }
+
+    #[test]
+    fn publish_with_packet_id() {
+        // QoS 1 from the broker puts a packet id between topic and payload
+        let body = [0, 1, b'a', 0, 7, b'D', b'I', b'S', b'A', b'R', b'M'];
+        assert_eq!(parse_publish(0x32, &body), Some(("a", &b"DISARM"[..])));
+        assert_eq!(parse_publish(0x30, &body[..2]), None);
+    }
+
+    #[tokio::test]
+    async fn bad_lengths() {
+        let too_long = [0x30, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(read_packet(&mut &too_long[..]).await.is_err());
+        let oversized = [0x30, 0x80, 0x80, 0x10];
+        assert!(read_packet(&mut &oversized[..]).await.is_err());
+        let cut_off = [0x30, 5, 0, 1];
+        assert!(read_packet(&mut &cut_off[..]).await.is_err());
+    }
+
+    #[test]
+    fn panel_states() {
+        assert_eq!(state_payload(AlarmState::Armed(AlarmMode::ArmedHome)), "armed_home");
+        assert_eq!(state_payload(AlarmState::Pending(AlarmMode::ArmedAway)), "pending");
+        assert_eq!(state_payload(AlarmState::Arming(AlarmMode::ArmedAway)), "arming");
+        assert_eq!(command_mode(b"disarm"), None);
+    }
+}
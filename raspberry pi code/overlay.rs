@@ -0,0 +1,166 @@
+use serde::Deserialize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+// Where the rendered overlay is written for rsvgoverlay to pick up
+const OVERLAY_PATH: &str = "/tmp/camera-overlay.svg";
+
+// Positions and sizes are fractions of the frame (0.0 - 1.0) so they survive resolution changes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OverlayShape {
+    // A text banner, e.g. "RECORDING"
+    Text {
+        text: String,
+        x: f32,
+        y: f32,
+        #[serde(default = "default_text_size")]
+        size: f32,
+    },
+    Box {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    Arrow {
+        from: [f32; 2],
+        to: [f32; 2],
+    },
+}
+
+fn default_text_size() -> f32 { 0.05 }
+fn default_color() -> String { "#ff0000".to_string() }
+
+// {"command": "overlay", "id": "...", "kind": "text" | "box" | "arrow", ...}
+#[derive(Debug, Clone, Deserialize)]
+pub struct OverlayCommand {
+    pub id: String,
+    #[serde(default = "default_color")]
+    pub color: String,
+    #[serde(flatten)]
+    pub shape: OverlayShape,
+}
+
+// {"command": "clear_overlay", "id": "..."}; without an id everything is cleared
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClearOverlayCommand {
+    pub id: Option<String>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl OverlayCommand {
+    fn to_svg(&self, width: f32, height: f32) -> String {
+        let color = escape(&self.color);
+        match &self.shape {
+            OverlayShape::Text { text, x, y, size } => format!(
+                r#"<text x="{}" y="{}" font-family="sans-serif" font-weight="bold" font-size="{}" fill="{}" stroke="black" stroke-width="1">{}</text>"#,
+                x * width, y * height, size * height, color, escape(text)
+            ),
+            OverlayShape::Box { x, y, width: w, height: h } => format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+                x * width, y * height, w * width, h * height, color, (height / 200.0).max(2.0)
+            ),
+            OverlayShape::Arrow { from, to } => {
+                let (x1, y1, x2, y2) = (from[0] * width, from[1] * height, to[0] * width, to[1] * height);
+                // Arrow head: two short strokes angled back from the tip
+                let angle = (y2 - y1).atan2(x2 - x1);
+                let head = height / 30.0;
+                let spread = std::f32::consts::PI / 7.0;
+                let (hx1, hy1) = (x2 - head * (angle - spread).cos(), y2 - head * (angle - spread).sin());
+                let (hx2, hy2) = (x2 - head * (angle + spread).cos(), y2 - head * (angle + spread).sin());
+                format!(
+                    r#"<polyline points="{},{} {},{}" fill="none" stroke="{c}" stroke-width="{w}"/><polyline points="{},{} {},{} {},{}" fill="none" stroke="{c}" stroke-width="{w}"/>"#,
+                    x1, y1, x2, y2, hx1, hy1, x2, y2, hx2, hy2,
+                    c = color, w = (height / 200.0).max(2.0)
+                )
+            }
+        }
+    }
+}
+
+// Overlays pushed by the server, drawn on outgoing frames until cleared. The pipeline
+// has to be restarted to pick up changes, like the camera controls.
+#[derive(Clone)]
+pub struct SharedOverlays {
+    items: Arc<Mutex<Vec<OverlayCommand>>>,
+    changed: Arc<AtomicBool>,
+    // Left off while the CPU is over budget; they come back as they were
+    suspended: Arc<AtomicBool>,
+    drawn: Arc<AtomicBool>,
+}
+
+impl SharedOverlays {
+    pub fn new() -> Self {
+        Self {
+            items: Arc::new(Mutex::new(Vec::new())),
+            changed: Arc::new(AtomicBool::new(false)),
+            suspended: Arc::new(AtomicBool::new(false)),
+            drawn: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Adding an overlay with an existing id replaces it
+    pub fn set(&self, overlay: OverlayCommand) {
+        let mut items = self.items.lock().unwrap();
+        items.retain(|item| item.id != overlay.id);
+        items.push(overlay);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self, id: Option<&str>) {
+        let mut items = self.items.lock().unwrap();
+        match id {
+            Some(id) => items.retain(|item| item.id != id),
+            None => items.clear(),
+        }
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    // Takes effect when the pipeline next restarts for some other reason
+    pub fn suspend(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+    }
+
+    // Whether the running pipeline draws any; set when its arguments were built
+    pub fn drawn(&self) -> bool {
+        self.drawn.load(Ordering::Relaxed)
+    }
+
+    // True once after every change
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+
+    // Pipeline elements that draw the current overlays, or nothing when there are none
+    pub fn pipeline_args(&self, width: u32, height: u32) -> Vec<String> {
+        let items = self.items.lock().unwrap();
+        self.drawn.store(false, Ordering::Relaxed);
+        if items.is_empty() || self.suspended.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+
+        let shapes: String = items.iter().map(|item| item.to_svg(width as f32, height as f32)).collect();
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">{}</svg>"#,
+            width, height, shapes
+        );
+        if let Err(e) = std::fs::write(OVERLAY_PATH, svg) {
+            eprintln!("Failed to write overlay {}: {}", OVERLAY_PATH, e);
+            return Vec::new();
+        }
+        self.drawn.store(true, Ordering::Relaxed);
+        vec![
+            "rsvgoverlay".into(),
+            format!("location={}", OVERLAY_PATH),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+        ]
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// The server pauses the uplink while nobody is watching. Capture, detectors, the
+// recording and local consumers keep every frame; only the uplink stops.
+#[derive(Clone)]
+pub struct UplinkPause {
+    paused: Arc<AtomicBool>,
+    resumed: Arc<AtomicBool>,
+}
+
+impl UplinkPause {
+    pub fn new() -> Self {
+        Self {
+            paused: Arc::new(AtomicBool::new(false)),
+            resumed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // True if the stream was running
+    pub fn pause(&self) -> bool {
+        !self.paused.swap(true, Ordering::Relaxed)
+    }
+
+    // True if the stream was paused
+    pub fn resume(&self) -> bool {
+        let was_paused = self.paused.swap(false, Ordering::Relaxed);
+        if was_paused {
+            self.resumed.store(true, Ordering::Relaxed);
+        }
+        was_paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // True once after every resume
+    pub fn take_resumed(&self) -> bool {
+        self.resumed.swap(false, Ordering::Relaxed)
+    }
+}
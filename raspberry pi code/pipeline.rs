@@ -0,0 +1,184 @@
+use base64::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+use crate::capture_clock::FrameTimestamp;
+use crate::encoder::Codec;
+use crate::envelope::Envelope;
+use crate::frame::{self, Frame, FrameOutputs, FrameProcessor};
+use crate::frame_pool::{FramePool, PooledFrame};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    // Frames that may wait in front of the processing and serialization stages. A full
+    // processing stage drops new frames; a full serialization stage holds processing up.
+    pub stage_capacity: usize,
+    // Base64-encode frames for the JSON envelope before they are queued, so the uplink
+    // task only has to write them
+    pub pre_encode: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stage_capacity: 8,
+            pre_encode: true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct StageCounters {
+    waiting: AtomicUsize,
+    processed: AtomicU64,
+    dropped: AtomicU64,
+    busy_us: AtomicU64,
+}
+
+impl StageCounters {
+    fn worked(&self, frames: u64, busy: Duration) {
+        self.processed.fetch_add(frames, Ordering::Relaxed);
+        self.busy_us.fetch_add(busy.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn stats(&self, capacity: Option<usize>) -> serde_json::Value {
+        let mut stats = json!({
+            "processed": self.processed.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "busy_ms": self.busy_us.load(Ordering::Relaxed) / 1000,
+        });
+        if let Some(capacity) = capacity {
+            stats["waiting"] = json!(self.waiting.load(Ordering::Relaxed));
+            stats["capacity"] = json!(capacity);
+        }
+        stats
+    }
+}
+
+// Frame extraction, processing (re-encoding, accounting, drop decisions) and
+// serialization for the uplink, each on its own thread so high frame rates aren't
+// limited by one core. Counters survive pipeline restarts.
+#[derive(Clone)]
+pub struct FramePipeline {
+    config: PipelineConfig,
+    extraction: Arc<StageCounters>,
+    processing: Arc<StageCounters>,
+    serialization: Arc<StageCounters>,
+    // Pre-encoding only pays off while the server takes the JSON envelope
+    json_envelope: Arc<AtomicBool>,
+}
+
+impl FramePipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            extraction: Arc::default(),
+            processing: Arc::default(),
+            serialization: Arc::default(),
+            json_envelope: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    // Follows the envelope negotiated for the current connection
+    pub fn set_envelope(&self, envelope: Envelope) {
+        self.json_envelope.store(envelope == Envelope::Json, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        json!({
+            "extraction": self.extraction.stats(None),
+            "processing": self.processing.stats(Some(self.config.stage_capacity)),
+            "serialization": self.serialization.stats(Some(self.config.stage_capacity)),
+        })
+    }
+
+    // Start the processing and serialization threads for one capture pipeline. They
+    // stop once the returned stages are dropped and everything in flight is queued.
+    pub fn spawn(&self, outputs: FrameOutputs, codec: Codec, stream_id: Arc<str>) -> PipelineStages {
+        let capacity = self.config.stage_capacity.max(1);
+        let (extracted_tx, mut extracted_rx) = mpsc::channel::<(PooledFrame, FrameTimestamp)>(capacity);
+        let (processed_tx, mut processed_rx) = mpsc::channel::<Frame>(capacity);
+        let runtime = tokio::runtime::Handle::current();
+        let (tx, stats, pool) = (outputs.tx.clone(), outputs.stats.clone(), outputs.frame_pool.clone());
+
+        let pipeline = self.clone();
+        let serialization_name = format!("serialize-{}", stream_id);
+        let processing = std::thread::Builder::new()
+            .name(format!("process-{}", stream_id))
+            .spawn(move || {
+                let mut processor = FrameProcessor::new(outputs, codec, stream_id);
+                while let Some((data, timestamp)) = extracted_rx.blocking_recv() {
+                    pipeline.processing.waiting.fetch_sub(1, Ordering::Relaxed);
+                    let started = Instant::now();
+                    let frame = processor.process(&data, timestamp);
+                    drop(data);
+                    pipeline.processing.worked(1, started.elapsed());
+                    let Some(frame) = frame else { continue };
+                    pipeline.serialization.waiting.fetch_add(1, Ordering::Relaxed);
+                    if processed_tx.blocking_send(frame).is_err() {
+                        pipeline.serialization.waiting.fetch_sub(1, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            });
+
+        let pipeline = self.clone();
+        let serialization = std::thread::Builder::new()
+            .name(serialization_name)
+            .spawn(move || {
+                while let Some(mut frame) = processed_rx.blocking_recv() {
+                    pipeline.serialization.waiting.fetch_sub(1, Ordering::Relaxed);
+                    let started = Instant::now();
+                    if pipeline.config.pre_encode && pipeline.json_envelope.load(Ordering::Relaxed) {
+                        frame.base64 = Some(BASE64_STANDARD.encode(&frame.data[..]));
+                    }
+                    pipeline.serialization.worked(1, started.elapsed());
+                    // Waits here when the queue policy is to block
+                    runtime.block_on(frame::enqueue(&tx, &stats, frame));
+                }
+            });
+
+        if let Err(e) = processing.and(serialization) {
+            eprintln!("Failed to start frame pipeline threads: {}", e);
+        }
+        PipelineStages { extracted_tx, pool, pipeline: self.clone() }
+    }
+}
+
+// The extraction end of a running pipeline
+pub struct PipelineStages {
+    extracted_tx: mpsc::Sender<(PooledFrame, FrameTimestamp)>,
+    pool: FramePool,
+    pipeline: FramePipeline,
+}
+
+impl PipelineStages {
+    // Hand an extracted frame to the processing thread; skipped when it is behind, as
+    // waiting here would back the camera pipe up instead
+    pub fn submit(&self, data: &[u8], timestamp: FrameTimestamp) {
+        self.pipeline.extraction.processed.fetch_add(1, Ordering::Relaxed);
+        // Counted before sending so the processing thread never sees it below zero
+        self.pipeline.processing.waiting.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.extracted_tx.try_send((self.pool.acquire(data), timestamp)) {
+            self.pipeline.processing.waiting.fetch_sub(1, Ordering::Relaxed);
+            if let mpsc::error::TrySendError::Full(_) = e {
+                self.pipeline.processing.dropped.fetch_add(1, Ordering::Relaxed);
+                println!("Frame processing falling behind, skipping frame");
+            }
+        }
+    }
+
+    // Time spent scanning the camera output for frames
+    pub fn extracted(&self, busy: Duration) {
+        self.pipeline.extraction.worked(0, busy);
+    }
+}
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+use crate::alarm::{AlarmHandle, AlarmMode};
+use crate::http_server::write_json;
+use crate::rest_api::token_matches;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PersonBehavior {
+    // Mode to switch to when this person gets home; unset leaves the alarm alone
+    pub on_arrive: Option<AlarmMode>,
+    // Whether the house counts as occupied while this person is there. Off for e.g. a
+    // dog walker whose phone shouldn't keep the alarm from arming.
+    pub counts_as_home: bool,
+}
+
+impl Default for PersonBehavior {
+    fn default() -> Self {
+        Self { on_arrive: Some(AlarmMode::Disarmed), counts_as_home: true }
+    }
+}
+
+// Arrivals and departures posted by a phone companion app or a home automation
+// geofence, as {"identity": "alice-phone", "present": true}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    // Only reachable from the camera itself while there is no token
+    pub listen: String,
+    // Required as "Authorization: Bearer <token>" when set
+    pub token: Option<String>,
+    // Identities the camera acts on; anything else is logged and ignored
+    pub people: HashMap<String, PersonBehavior>,
+    // Mode set once the last person who counts has left
+    pub when_everyone_leaves: AlarmMode,
+    // How long the house has to stay empty first, so a phone dropping off the network
+    // for a moment doesn't arm it
+    pub away_delay_seconds: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8091".to_string(),
+            token: None,
+            people: HashMap::new(),
+            when_everyone_leaves: AlarmMode::ArmedAway,
+            away_delay_seconds: 120,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PresenceUpdate {
+    identity: String,
+    present: bool,
+}
+
+// Listen for presence updates and arm or disarm the alarm as people come and go.
+// Only changes act on the alarm, so it can still be set by hand in between.
+pub async fn run_presence_listener(mut config: PresenceConfig, alarm: AlarmHandle) {
+    // Anyone who can reach it can disarm the camera, so not the network without a token
+    let local = config.listen.parse::<SocketAddr>().is_ok_and(|address| address.ip().is_loopback()) || config.listen.starts_with("localhost:");
+    if config.token.is_none() && !local {
+        let port = config.listen.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).unwrap_or(8091);
+        eprintln!("Presence updates have no token; listening on localhost instead of {}", config.listen);
+        config.listen = SocketAddr::from(([127, 0, 0, 1], port)).to_string();
+    }
+    let listener = match TcpListener::bind(&config.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Presence arming disabled, failed to bind {}: {}", config.listen, e);
+            return;
+        }
+    };
+    println!("Presence updates accepted on {}", config.listen);
+
+    let home = Arc::new(Mutex::new(HashSet::<String>::new()));
+    let (update_tx, update_rx) = mpsc::channel::<PresenceUpdate>(16);
+    tokio::spawn(apply_updates(config.clone(), alarm, home.clone(), update_rx));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let token = config.token.clone();
+                let home = home.clone();
+                let update_tx = update_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, token, home, update_tx).await {
+                        eprintln!("Presence request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept presence connection: {}", e),
+        }
+    }
+}
+
+async fn apply_updates(
+    config: PresenceConfig,
+    alarm: AlarmHandle,
+    home: Arc<Mutex<HashSet<String>>>,
+    mut updates: mpsc::Receiver<PresenceUpdate>
+) {
+    // Set while the house is empty and waiting out away_delay_seconds
+    let mut arm_at: Option<Instant> = None;
+    loop {
+        let timer = async move {
+            match arm_at {
+                Some(at) => sleep_until(at).await,
+                None => std::future::pending::<()>().await,
+            }
+        };
+        tokio::select! {
+            update = updates.recv() => {
+                let Some(update) = update else { return };
+                let Some(behavior) = config.people.get(&update.identity) else {
+                    println!("Presence: ignoring unknown identity {}", update.identity);
+                    continue;
+                };
+                let (changed, occupied) = {
+                    let mut home = home.lock().unwrap();
+                    let changed = if update.present {
+                        home.insert(update.identity.clone())
+                    } else {
+                        home.remove(&update.identity)
+                    };
+                    let occupied = home.iter().any(|identity| config.people.get(identity).is_some_and(|person| person.counts_as_home));
+                    (changed, occupied)
+                };
+                if !changed {
+                    continue;
+                }
+                println!("Presence: {} {}", update.identity, if update.present { "arrived" } else { "left" });
+                if update.present {
+                    if occupied {
+                        arm_at = None;
+                    }
+                    if let Some(mode) = behavior.on_arrive {
+                        println!("Presence set alarm to {}", mode.name());
+                        alarm.set_mode(mode).await;
+                    }
+                } else if !occupied && behavior.counts_as_home {
+                    arm_at = Some(Instant::now() + Duration::from_secs(config.away_delay_seconds));
+                }
+            }
+            _ = timer => {
+                arm_at = None;
+                println!("Presence: everyone has left, setting alarm to {}", config.when_everyone_leaves.name());
+                alarm.set_mode(config.when_everyone_leaves).await;
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    token: Option<String>,
+    home: Arc<Mutex<HashSet<String>>>,
+    update_tx: mpsc::Sender<PresenceUpdate>
+) -> std::io::Result<()> {
+    // Headers and a small JSON body; read until Content-Length is covered
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 2048];
+    let (head, body) = loop {
+        let read = stream.read(&mut buffer).await?;
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = header(head, "content-length").and_then(|value| value.parse::<usize>().ok()).unwrap_or(0);
+            if body.len() >= length || read == 0 {
+                break (head.to_string(), body.to_string());
+            }
+        }
+        if read == 0 || request.len() > 16 * 1024 {
+            return write_json(&mut stream, "400 Bad Request", json!({ "error": "incomplete request" })).await;
+        }
+    };
+
+    if let Some(token) = &token {
+        let given = header(&head, "authorization").and_then(|value| value.strip_prefix("Bearer ")).unwrap_or("");
+        if !token_matches(given.as_bytes(), token.as_bytes()) {
+            return write_json(&mut stream, "401 Unauthorized", json!({ "error": "bad token" })).await;
+        }
+    }
+
+    let mut request_line = head.split_whitespace();
+    let (method, path) = (request_line.next().unwrap_or(""), request_line.next().unwrap_or("/"));
+    let (code, reply) = match (method, path) {
+        ("POST", "/presence") => match serde_json::from_str::<PresenceUpdate>(&body) {
+            Ok(update) => {
+                let _ = update_tx.send(update).await;
+                ("200 OK", json!({ "ok": true }))
+            }
+            Err(e) => ("400 Bad Request", json!({ "error": e.to_string() })),
+        },
+        ("GET", "/presence") => {
+            let mut home: Vec<_> = home.lock().unwrap().iter().cloned().collect();
+            home.sort();
+            ("200 OK", json!({ "home": home }))
+        }
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    };
+    write_json(&mut stream, code, reply).await
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
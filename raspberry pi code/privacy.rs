@@ -0,0 +1,92 @@
+use serde::Deserialize;
+
+// What is left out of frames and stats sent to the server. Local recordings, HLS and the
+// metadata sidecar always keep everything.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    // Drop EXIF/XMP (GPS position, camera serial, capture time) and comment segments from JPEGs
+    pub strip_jpeg_metadata: bool,
+    // Round frame and snapshot timestamps down to this many milliseconds; 0 keeps them exact
+    pub timestamp_granularity_ms: u64,
+    // Fields removed from the stats sent with every frame
+    pub redact_stats: Vec<String>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            strip_jpeg_metadata: true,
+            timestamp_granularity_ms: 1000,
+            redact_stats: vec!["monotonic_ms".to_string()],
+        }
+    }
+}
+
+impl PrivacyConfig {
+    pub fn timestamp(&self, wall_ms: u64) -> u64 {
+        match self.timestamp_granularity_ms {
+            0 => wall_ms,
+            granularity => wall_ms / granularity * granularity,
+        }
+    }
+
+    pub fn redact_stats(&self, stats: &mut serde_json::Value) {
+        if let Some(stats) = stats.as_object_mut() {
+            for field in &self.redact_stats {
+                stats.remove(field);
+            }
+        }
+    }
+
+    // The JPEG without its metadata segments, or None when there is nothing to strip
+    pub fn strip(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if self.strip_jpeg_metadata {
+            strip_jpeg_metadata(data)
+        } else {
+            None
+        }
+    }
+}
+
+// Copy a JPEG without its APP1-APP15 and COM segments. APP0 (JFIF) stays since some
+// decoders want it. Returns None for anything that isn't a well-formed JPEG header or
+// has nothing to remove.
+pub fn strip_jpeg_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut position = 2;
+    let mut stripped = false;
+
+    while position + 4 <= data.len() {
+        if data[position] != 0xFF {
+            return None;
+        }
+        let marker = data[position + 1];
+        // Fill byte before a marker
+        if marker == 0xFF {
+            position += 1;
+            continue;
+        }
+        // Start of scan: the rest is entropy-coded data and the end marker
+        if marker == 0xDA {
+            out.extend_from_slice(&data[position..]);
+            return stripped.then_some(out);
+        }
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        let end = position + 2 + length;
+        if length < 2 || end > data.len() {
+            return None;
+        }
+        if (0xE1..=0xEF).contains(&marker) || marker == 0xFE {
+            stripped = true;
+        } else {
+            out.extend_from_slice(&data[position..end]);
+        }
+        position = end;
+    }
+    None
+}
@@ -0,0 +1,76 @@
+use serde_json::json;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// Wire formats the camera can speak. V1 is the original ad-hoc format where the
+// top-level keys say what a message is; V2 wraps everything as {"type": ..., "payload": ...}.
+// The join message lists both and the server picks one with {"protocol_version": n};
+// servers that never answer get V1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V1 = 1,
+    V2 = 2,
+}
+
+impl ProtocolVersion {
+    pub const SUPPORTED: [ProtocolVersion; 2] = [ProtocolVersion::V1, ProtocolVersion::V2];
+
+    pub fn from_number(number: u64) -> Option<Self> {
+        match number {
+            1 => Some(ProtocolVersion::V1),
+            2 => Some(ProtocolVersion::V2),
+            _ => None,
+        }
+    }
+
+    // For storing the negotiated version in an AtomicU8
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            2 => ProtocolVersion::V2,
+            _ => ProtocolVersion::V1,
+        }
+    }
+}
+
+// Typed messages whose payload sits under a key of the same name in V1
+const KEYED_TYPES: [&str; 2] = ["network_feedback", "protocol_error"];
+
+// Server message in the V1 shape the handler works with. Typed messages are accepted
+// whatever was negotiated, so a server can switch over before the camera does.
+pub fn decode(json: serde_json::Value) -> serde_json::Value {
+    let (Some(kind), Some(payload)) = (json.get("type").and_then(|t| t.as_str()), json.get("payload")) else {
+        return json;
+    };
+    if KEYED_TYPES.contains(&kind) {
+        json!({ kind: payload })
+    } else {
+        // Commands and negotiation fields are flat in V1
+        payload.clone()
+    }
+}
+
+// Control message in the negotiated format. V1 messages are objects with a single key
+// naming them, which becomes the type in V2.
+pub fn encode(version: ProtocolVersion, message: Message) -> Message {
+    let Message::Text(text) = &message else {
+        return message;
+    };
+    if version == ProtocolVersion::V1 {
+        return message;
+    }
+    let Ok(serde_json::Value::Object(fields)) = serde_json::from_str::<serde_json::Value>(text) else {
+        return message;
+    };
+    if fields.len() != 1 {
+        return message;
+    }
+    let (kind, payload) = fields.into_iter().next().unwrap();
+    Message::Text(json!({ "type": kind, "payload": payload }).to_string())
+}
+
+// Frames are too big to parse again, so the JSON envelope is wrapped as text
+pub fn encode_frame(version: ProtocolVersion, message: Message) -> Message {
+    match (version, message) {
+        (ProtocolVersion::V2, Message::Text(text)) => Message::Text(format!(r#"{{"type":"frame","payload":{}}}"#, text)),
+        (_, message) => message,
+    }
+}
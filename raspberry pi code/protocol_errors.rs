@@ -0,0 +1,114 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Top-level keys the camera understands in server messages
+const KNOWN_KEYS: [&str; 17] = [
+    "command", "codec", "envelope", "max_message_bytes", "network_feedback", "issued_by", "protocol_error", "protocol_version",
+    "frame_acks", "ack", "time_sync_reply", "session_replaced", "echo_reply", "container", "event_ack", "viewers", "challenge",
+];
+// Longest excerpt of a bad message kept or echoed
+const SAMPLE_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProtocolErrorConfig {
+    // This many bad messages within a minute usually means camera and server disagree on the protocol
+    pub alert_per_minute: usize,
+    // Recent bad messages kept for the alert
+    pub samples: usize,
+}
+
+impl Default for ProtocolErrorConfig {
+    fn default() -> Self {
+        Self {
+            alert_per_minute: 10,
+            samples: 5,
+        }
+    }
+}
+
+struct ErrorState {
+    total: u64,
+    recent: VecDeque<Instant>,
+    samples: VecDeque<serde_json::Value>,
+    last_alert: Option<Instant>,
+}
+
+// Server messages we couldn't make sense of: counted, sampled to the log, answered with
+// a structured error, and alerted on when they keep coming
+#[derive(Clone)]
+pub struct ProtocolErrors {
+    config: ProtocolErrorConfig,
+    state: Arc<Mutex<ErrorState>>,
+}
+
+fn excerpt(text: &str) -> String {
+    text.chars().take(SAMPLE_CHARS).collect()
+}
+
+impl ProtocolErrors {
+    pub fn new(config: ProtocolErrorConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(ErrorState {
+                total: 0,
+                recent: VecDeque::new(),
+                samples: VecDeque::new(),
+                last_alert: None,
+            })),
+        }
+    }
+
+    // Whether a decoded message has anything in it we act on
+    pub fn is_understood(json: &serde_json::Value) -> bool {
+        json.as_object().is_some_and(|fields| fields.keys().any(|key| KNOWN_KEYS.contains(&key.as_str())))
+    }
+
+    // Records a bad message and returns the messages to send back: the error reply, and an
+    // alert if the rate is over the limit
+    pub fn record(&self, camera_id: &str, reason: &str, received: &str) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.total += 1;
+        state.recent.push_back(now);
+        while state.recent.front().is_some_and(|at| now.duration_since(*at) > Duration::from_secs(60)) {
+            state.recent.pop_front();
+        }
+
+        let sample = json!({ "reason": reason, "received": excerpt(received) });
+        // Log the first few and then an occasional one, so a flood doesn't fill the journal
+        if state.total <= 10 || state.total.is_multiple_of(100) {
+            eprintln!("Protocol error #{}: {} ({})", state.total, reason, excerpt(received));
+        }
+        state.samples.push_back(sample.clone());
+        while state.samples.len() > self.config.samples {
+            state.samples.pop_front();
+        }
+
+        let mut replies = vec![json!({ "protocol_error": sample }).to_string()];
+
+        let alerted_recently = state.last_alert.is_some_and(|at| now.duration_since(at) < Duration::from_secs(600));
+        if state.recent.len() >= self.config.alert_per_minute && !alerted_recently {
+            state.last_alert = Some(now);
+            eprintln!(
+                "{} protocol errors in the last minute; the server may speak a different protocol version",
+                state.recent.len()
+            );
+            replies.push(json!({
+                "alert": {
+                    "kind": "protocol_errors",
+                    "camera_id": camera_id,
+                    "errors_last_minute": state.recent.len(),
+                    "errors_total": state.total,
+                    "samples": state.samples
+                }
+            }).to_string());
+        }
+        replies
+    }
+}
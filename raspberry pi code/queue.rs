@@ -0,0 +1,252 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::Notify;
+
+use crate::frame::Frame;
+use crate::stream_priority::StreamPriorities;
+use crate::uplink_allocator::UplinkAllocator;
+
+// What happens to a new frame once the queue is above the high watermark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueStrategy {
+    // Keep what is queued and skip the new frame
+    DropNew,
+    // Evict the oldest queued frame (motion frames last) to make room; favours latency
+    DropOld,
+    // Wait for the uplink to catch up; the capture pipeline backs up behind it
+    Block,
+}
+
+impl QueueStrategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueStrategy::DropNew => "drop_new",
+            QueueStrategy::DropOld => "drop_old",
+            QueueStrategy::Block => "block",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QueuePolicy {
+    // Hard limit on frames waiting for the uplink; only motion frames may go past the high watermark
+    pub capacity: usize,
+    // Above this the strategy decides what happens to new frames
+    pub high_watermark: usize,
+    // Below this the queue counts as healthy when judging congestion
+    pub low_watermark: usize,
+    pub strategy: QueueStrategy,
+}
+
+impl Default for QueuePolicy {
+    fn default() -> Self {
+        Self {
+            capacity: 60,
+            high_watermark: 50,
+            low_watermark: 15,
+            strategy: QueueStrategy::DropNew,
+        }
+    }
+}
+
+impl QueuePolicy {
+    pub fn is_backed_up(&self, depth: u64) -> bool {
+        depth > self.low_watermark as u64
+    }
+
+    // Halfway to the high watermark the uplink starts pacing itself
+    pub fn needs_backoff(&self, depth: u64) -> bool {
+        depth > ((self.low_watermark + self.high_watermark) / 2) as u64
+    }
+
+    pub fn stats(&self, depth: u64) -> serde_json::Value {
+        json!({
+            "strategy": self.strategy.name(),
+            "capacity": self.capacity,
+            "high_watermark": self.high_watermark,
+            "low_watermark": self.low_watermark,
+            "depth": depth
+        })
+    }
+}
+
+pub enum SendOutcome {
+    Queued,
+    // Queued after evicting the oldest frame
+    ReplacedOldest,
+    Dropped,
+    // The stream is over its share of the uplink
+    OverShare,
+    // The uplink is gone
+    Closed,
+}
+
+struct Shared {
+    frames: Mutex<VecDeque<Frame>>,
+    policy: QueuePolicy,
+    // Queue depth, shared with whoever needs to watch it
+    depth: Arc<AtomicU64>,
+    priorities: StreamPriorities,
+    allocator: Option<UplinkAllocator>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    available: Notify,
+    space: Notify,
+}
+
+// Frame queue between the capture pipelines and the uplink
+pub fn channel(
+    policy: QueuePolicy,
+    depth: Arc<AtomicU64>,
+    priorities: StreamPriorities,
+    allocator: Option<UplinkAllocator>,
+) -> (FrameSender, FrameReceiver) {
+    let shared = Arc::new(Shared {
+        frames: Mutex::new(VecDeque::with_capacity(policy.capacity)),
+        policy,
+        depth,
+        priorities,
+        allocator,
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        available: Notify::new(),
+        space: Notify::new(),
+    });
+    (FrameSender { shared: shared.clone() }, FrameReceiver { shared })
+}
+
+pub struct FrameSender {
+    shared: Arc<Shared>,
+}
+
+impl Clone for FrameSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Wake the receiver so it sees the source is gone
+            self.shared.available.notify_one();
+        }
+    }
+}
+
+impl FrameSender {
+    pub async fn send(&self, frame: Frame) -> SendOutcome {
+        let shared = &self.shared;
+        let policy = &shared.policy;
+        if let Some(allocator) = &shared.allocator {
+            // Inter-frame codecs can't lose frames without breaking the decoder; their
+            // bitrate control keeps them to their share instead
+            if !allocator.admit(&frame.stream_id, frame.data.len(), frame.motion || !frame.standalone) {
+                return SendOutcome::OverShare;
+            }
+        }
+        loop {
+            if !shared.receiver_alive.load(Ordering::Relaxed) {
+                return SendOutcome::Closed;
+            }
+            let space = shared.space.notified();
+            {
+                let mut frames = shared.frames.lock().unwrap();
+                let limit = if frame.motion { policy.capacity } else { policy.high_watermark };
+                if frames.len() < limit.min(policy.capacity) {
+                    frames.push_back(frame);
+                    shared.depth.store(frames.len() as u64, Ordering::Relaxed);
+                    shared.available.notify_one();
+                    return SendOutcome::Queued;
+                }
+
+                // A stream the server ranks higher takes the place of the oldest frame of
+                // the lowest-ranked one (motion frames last), whatever the strategy
+                if let Some(priorities) = shared.priorities.snapshot() {
+                    let rank = |stream: &str| priorities.get(stream).copied().unwrap_or(0);
+                    let own = rank(&frame.stream_id);
+                    let lowest = frames
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, queued)| rank(&queued.stream_id) < own)
+                        .min_by_key(|(index, queued)| (rank(&queued.stream_id), queued.motion, *index))
+                        .map(|(index, _)| index);
+                    if let Some(index) = lowest {
+                        frames.remove(index);
+                        frames.push_back(frame);
+                        shared.available.notify_one();
+                        return SendOutcome::ReplacedOldest;
+                    }
+                }
+
+                match policy.strategy {
+                    QueueStrategy::DropNew => return SendOutcome::Dropped,
+                    QueueStrategy::DropOld => {
+                        let oldest = frames.iter().position(|f| !f.motion).unwrap_or(0);
+                        frames.remove(oldest);
+                        frames.push_back(frame);
+                        shared.available.notify_one();
+                        return SendOutcome::ReplacedOldest;
+                    }
+                    QueueStrategy::Block => {}
+                }
+            }
+            space.await;
+        }
+    }
+}
+
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Relaxed);
+        self.shared.space.notify_waiters();
+    }
+}
+
+impl FrameReceiver {
+    // None once every sender is gone and the queue is drained
+    pub async fn recv(&mut self) -> Option<Frame> {
+        let shared = &self.shared;
+        loop {
+            let available = shared.available.notified();
+            {
+                let mut frames = shared.frames.lock().unwrap();
+                if let Some(frame) = frames.pop_front() {
+                    shared.depth.store(frames.len() as u64, Ordering::Relaxed);
+                    shared.space.notify_one();
+                    return Some(frame);
+                }
+                if shared.senders.load(Ordering::Acquire) == 0 {
+                    return None;
+                }
+            }
+            available.await;
+        }
+    }
+
+    pub fn policy(&self) -> &QueuePolicy {
+        &self.shared.policy
+    }
+
+    pub fn priorities(&self) -> StreamPriorities {
+        self.shared.priorities.clone()
+    }
+
+    pub fn allocator(&self) -> Option<UplinkAllocator> {
+        self.shared.allocator.clone()
+    }
+}
@@ -0,0 +1,239 @@
+use serde::Deserialize;
+use std::{process::Stdio, sync::Arc, time::Duration};
+use tokio::{io::AsyncReadExt, process::Command, sync::broadcast, time::sleep};
+
+use crate::frame_pool::{FramePool, PooledFrame};
+
+// Raw pixel formats we can ask the source for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PixelFormat {
+    Nv12,
+    Yuy2,
+    I420,
+    Gray8,
+    Rgb,
+}
+
+impl PixelFormat {
+    // Name used in GStreamer caps
+    pub fn caps_name(self) -> &'static str {
+        match self {
+            PixelFormat::Nv12 => "NV12",
+            PixelFormat::Yuy2 => "YUY2",
+            PixelFormat::I420 => "I420",
+            PixelFormat::Gray8 => "GRAY8",
+            PixelFormat::Rgb => "RGB",
+        }
+    }
+
+    pub fn frame_size(self, width: u32, height: u32) -> usize {
+        let pixels = (width * height) as usize;
+        match self {
+            PixelFormat::Nv12 | PixelFormat::I420 => pixels * 3 / 2,
+            PixelFormat::Yuy2 => pixels * 2,
+            PixelFormat::Gray8 => pixels,
+            PixelFormat::Rgb => pixels * 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RawConfig {
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    // Frames per second to publish; every captured frame when unset
+    pub frame_rate: Option<u32>,
+    pub socket_path: String,
+}
+
+impl Default for RawConfig {
+    fn default() -> Self {
+        Self {
+            format: PixelFormat::Nv12,
+            width: 640,
+            height: 360,
+            frame_rate: None,
+            socket_path: "/tmp/camera-raw".to_string(),
+        }
+    }
+}
+
+// Low-rate, low-resolution feed for the detectors, sized for typical model inputs, so
+// analytics never take full-rate frames away from the encoder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+    pub socket_path: String,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            format: PixelFormat::Rgb,
+            width: 416,
+            height: 416,
+            frame_rate: 2,
+            socket_path: "/tmp/camera-analytics".to_string(),
+        }
+    }
+}
+
+impl AnalyticsConfig {
+    // The feed is a raw tap like any other, just scaled and rate limited
+    pub fn raw_config(&self) -> RawConfig {
+        RawConfig {
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            frame_rate: Some(self.frame_rate),
+            socket_path: self.socket_path.clone(),
+        }
+    }
+}
+
+impl RawConfig {
+    fn caps(&self) -> String {
+        format!("video/x-raw,format={},width={},height={}", self.format.caps_name(), self.width, self.height)
+    }
+
+    // Capture pipeline branch that publishes raw frames over shared memory. `name` tells
+    // the tees apart when there is more than one.
+    pub fn tee_args(&self, name: &str) -> Vec<String> {
+        let frame_bytes = self.format.frame_size(self.width, self.height);
+        let mut args: Vec<String> = vec![
+            "tee".into(),
+            format!("name={}", name),
+            "!".into(),
+            "queue".into(),
+            "leaky=downstream".into(),
+            "!".into(),
+        ];
+        // Drop frames before scaling so the skipped ones cost nothing
+        if self.frame_rate.is_some() {
+            args.extend(["videorate".into(), "drop-only=true".into(), "!".into()]);
+        }
+        let caps = match self.frame_rate {
+            Some(frame_rate) => format!("{},framerate={}/1", self.caps(), frame_rate),
+            None => self.caps(),
+        };
+        args.extend([
+            "videoscale".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            caps,
+            "!".into(),
+            "shmsink".into(),
+            format!("socket-path={}", self.socket_path),
+            format!("shm-size={}", frame_bytes * 4),
+            "wait-for-connection=false".into(),
+            "sync=false".into(),
+            format!("{}.", name),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]);
+        args
+    }
+}
+
+// An uncompressed frame as delivered by the source
+pub struct RawFrame {
+    pub format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: PooledFrame,
+}
+
+impl RawFrame {
+    // Brightness channel of the frame, one byte per pixel
+    pub fn luma(&self) -> Vec<u8> {
+        let pixels = (self.width * self.height) as usize;
+        let data: &[u8] = &self.data;
+        match self.format {
+            // Planar formats store the full-resolution Y plane first
+            PixelFormat::Nv12 | PixelFormat::I420 | PixelFormat::Gray8 => data[..pixels.min(data.len())].to_vec(),
+            PixelFormat::Yuy2 => data.iter().step_by(2).copied().collect(),
+            PixelFormat::Rgb => data
+                .chunks_exact(3)
+                .map(|p| ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8)
+                .collect(),
+        }
+    }
+}
+
+// Raw frames for local processing without a JPEG round-trip. Subscribers that fall
+// behind miss frames rather than slowing capture down.
+#[derive(Clone)]
+pub struct RawFrames {
+    sender: broadcast::Sender<Arc<RawFrame>>,
+}
+
+impl RawFrames {
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<RawFrame>> {
+        self.sender.subscribe()
+    }
+}
+
+// Read raw frames back from the capture pipeline's shared memory branch
+pub fn spawn_raw_reader(config: RawConfig, frame_pool: FramePool) -> RawFrames {
+    let (sender, _) = broadcast::channel(4);
+    let frames = RawFrames { sender: sender.clone() };
+
+    tokio::spawn(async move {
+        let frame_size = config.format.frame_size(config.width, config.height);
+        let mut buffer = vec![0u8; frame_size];
+
+        loop {
+            let child = Command::new("gst-launch-1.0")
+                .args(&[
+                    "-q".to_string(),
+                    "shmsrc".into(),
+                    format!("socket-path={}", config.socket_path),
+                    "is-live=true".into(),
+                    "!".into(),
+                    format!("{},framerate=0/1", config.caps()),
+                    "!".into(),
+                    "fdsink".into(),
+                ])
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn();
+            let mut child = match child {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Failed to start raw frame reader: {}", e);
+                    return;
+                }
+            };
+            let mut stdout = child.stdout.take().expect("Failed to capture raw reader stdout");
+
+            // Raw frames have a fixed size, so no parsing is needed
+            while stdout.read_exact(&mut buffer).await.is_ok() {
+                if sender.receiver_count() == 0 {
+                    continue;
+                }
+                let frame = RawFrame {
+                    format: config.format,
+                    width: config.width,
+                    height: config.height,
+                    data: frame_pool.acquire(&buffer),
+                };
+                let _ = sender.send(Arc::new(frame));
+            }
+
+            // The capture pipeline restarted; reconnect once its socket is back
+            let _ = child.kill().await;
+            sleep(Duration::from_secs(1)).await;
+        }
+    });
+
+    frames
+}
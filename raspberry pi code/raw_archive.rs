@@ -0,0 +1,157 @@
+use serde::Deserialize;
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    process::Command,
+    time::{interval_at, timeout, Instant},
+};
+
+use crate::capture_source::{CaptureSource, CaptureConfig};
+use crate::clock::TimeConfig;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RawArchiveConfig {
+    pub directory: String,
+    pub interval_minutes: u64,
+    // Oldest stills are deleted once the directory holds more than this
+    pub max_mb: u64,
+    // rpicam-still on current Raspberry Pi OS, libcamera-still on older releases
+    pub still_command: String,
+    // Time the still app gives auto exposure and white balance before the capture
+    pub settle_ms: u64,
+    // A still app that hasn't finished by then, settle time included, is killed so the
+    // stream can start again
+    pub timeout_seconds: u64,
+}
+
+impl Default for RawArchiveConfig {
+    fn default() -> Self {
+        Self {
+            directory: "/var/lib/camera/raw".to_string(),
+            interval_minutes: 60,
+            max_mb: 2048,
+            still_command: "rpicam-still".to_string(),
+            settle_ms: 1000,
+            timeout_seconds: 15,
+        }
+    }
+}
+
+// Sensor-native stills next to the stream: a DNG of the unprocessed Bayer data plus the
+// still app's JPEG of the same exposure, named by local capture time. The camera can
+// only be opened once, so the capture loop stops the pipeline for each one and the
+// stream pauses for a second or two.
+#[derive(Clone)]
+pub struct RawArchive {
+    config: RawArchiveConfig,
+    due: Arc<AtomicBool>,
+}
+
+impl RawArchive {
+    // True once each interval, when the capture loop should take the next still
+    pub fn take_due(&self) -> bool {
+        self.due.swap(false, Ordering::Relaxed)
+    }
+
+    // Run while the pipeline is stopped
+    pub async fn capture(&self, time: &TimeConfig) {
+        let directory = PathBuf::from(&self.config.directory);
+        if let Err(e) = std::fs::create_dir_all(&directory) {
+            eprintln!("Raw archive: failed to create {}: {}", directory.display(), e);
+            return;
+        }
+        // The still app writes the DNG next to the JPEG, with the extension swapped
+        let jpeg = directory.join(format!("{}.jpg", time.file_stamp()));
+        // Dropping the command on timeout kills it
+        let limit = Duration::from_millis(self.config.settle_ms) + Duration::from_secs(self.config.timeout_seconds.max(1));
+        let output = Command::new(&self.config.still_command)
+            .arg("--nopreview")
+            .arg("--raw")
+            .args(["--timeout", &self.config.settle_ms.max(1).to_string()])
+            .arg("--output")
+            .arg(&jpeg)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .output();
+        let Ok(output) = timeout(limit, output).await else {
+            eprintln!("Raw archive: {} didn't finish within {:?}, killed it", self.config.still_command, limit);
+            return;
+        };
+        match output {
+            Ok(output) if output.status.success() => {
+                let dng = jpeg.with_extension("dng");
+                match std::fs::metadata(&dng) {
+                    Ok(metadata) => println!("Raw archive: saved {} ({} bytes)", dng.display(), metadata.len()),
+                    Err(_) => eprintln!("Raw archive: {} wrote no DNG", self.config.still_command),
+                }
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                eprintln!("Raw archive: {} exited with {}: {}", self.config.still_command, output.status, stderr.lines().last().unwrap_or(""));
+            }
+            Err(e) => eprintln!("Raw archive: failed to run {}: {}", self.config.still_command, e),
+        }
+
+        let config = self.config.clone();
+        let _ = tokio::task::spawn_blocking(move || prune(&config)).await;
+    }
+}
+
+// Delete the oldest files until the directory fits in max_mb again
+fn prune(config: &RawArchiveConfig) {
+    let entries = match std::fs::read_dir(&config.directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Raw archive: failed to list {}: {}", config.directory, e);
+            return;
+        }
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    files.sort();
+    let limit = config.max_mb * 1024 * 1024;
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    for (_, size, path) in files {
+        if total <= limit {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => total -= size,
+            Err(e) => eprintln!("Raw archive: failed to delete {}: {}", path.display(), e),
+        }
+    }
+}
+
+// None when the camera isn't one the still app can open
+pub fn spawn_raw_archive(config: RawArchiveConfig, capture: &CaptureConfig) -> Option<RawArchive> {
+    if capture.backend() != CaptureSource::Libcamera {
+        eprintln!("Raw archive disabled, RAW stills need a libcamera camera");
+        return None;
+    }
+    println!("Raw archive: a DNG still every {} minutes in {}", config.interval_minutes, config.directory);
+    let archive = RawArchive { config: config.clone(), due: Arc::new(AtomicBool::new(false)) };
+    let due = archive.due.clone();
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.interval_minutes.max(1) * 60);
+        let mut timer = interval_at(Instant::now() + period, period);
+        loop {
+            timer.tick().await;
+            due.store(true, Ordering::Relaxed);
+        }
+    });
+    Some(archive)
+}
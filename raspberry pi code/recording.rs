@@ -0,0 +1,101 @@
+use serde::Deserialize;
+
+use crate::clock::TimeConfig;
+use crate::disk_space::DiskSpaceConfig;
+use crate::encryption::EncryptionConfig;
+use crate::retention::RetentionConfig;
+use crate::storage::StorageConfig;
+use crate::wear::WearConfig;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    // Directory for the recorded .ts segments
+    pub directory: String,
+    // Recording resolution; the camera captures at this size and the uplink is scaled down from it
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_kbps: u32,
+    pub segment_seconds: u64,
+    // Write per-frame analytics (motion, exposure) to a JSONL sidecar in the same directory
+    pub metadata: bool,
+    // How long segments are kept, by what happened in them; kept forever when unset
+    pub retention: Option<RetentionConfig>,
+    // Encrypt finished segments so a stolen SD card doesn't leak footage. Needs
+    // wear.staging_directory, where segments are recorded until they are sealed.
+    pub encryption: Option<EncryptionConfig>,
+    // A segment that hasn't been written to for this long is finished and can be
+    // encrypted or moved out of staging
+    pub finished_after_seconds: u64,
+    // Staging, write accounting and wear warnings for SD cards
+    pub wear: WearConfig,
+    // Where finished segments are kept: local disk, a mounted share, S3 or WebDAV
+    pub storage: StorageConfig,
+    // Index segments, motion intervals and events for timeline_query; the last two are
+    // saved in the metadata sidecar
+    pub timeline: bool,
+    // Free space thresholds for pruning early and pausing the recording
+    pub disk_space: DiskSpaceConfig,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            directory: "recordings".to_string(),
+            width: 1920,
+            height: 1080,
+            bitrate_kbps: 4000,
+            segment_seconds: 300,
+            metadata: true,
+            retention: None,
+            encryption: None,
+            finished_after_seconds: 30,
+            wear: WearConfig::default(),
+            storage: StorageConfig::default(),
+            timeline: true,
+            disk_space: DiskSpaceConfig::default(),
+        }
+    }
+}
+
+impl RecordingConfig {
+    // Where the pipeline writes segments while they are being recorded
+    pub fn write_directory(&self) -> &str {
+        self.wear.staging_directory.as_deref().unwrap_or(&self.directory)
+    }
+
+    // Capture pipeline branch that encodes the full-resolution image to disk with its own
+    // encoder settings, so uplink congestion never lowers the recording quality
+    pub fn tee_args(&self, time: &TimeConfig) -> Vec<String> {
+        // Segment numbers restart with every pipeline, so prefix them to avoid overwriting
+        let started = time.file_stamp();
+        vec![
+            "tee".into(),
+            "name=record".into(),
+            "!".into(),
+            // A slow encoder drops recording frames instead of stalling the uplink
+            "queue".into(),
+            "leaky=downstream".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            "x264enc".into(),
+            "tune=zerolatency".into(),
+            "speed-preset=superfast".into(),
+            format!("bitrate={}", self.bitrate_kbps),
+            "key-int-max=60".into(),
+            "!".into(),
+            "h264parse".into(),
+            "!".into(),
+            // MPEG-TS stays playable when the pipeline is killed mid-segment
+            "splitmuxsink".into(),
+            "muxer-factory=mpegtsmux".into(),
+            format!("location={}/{}-%05d.ts", self.write_directory(), started),
+            format!("max-size-time={}", self.segment_seconds * 1_000_000_000),
+            "record.".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]
+    }
+}
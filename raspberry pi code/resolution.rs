@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    // Parse "1280x720"
+    pub fn parse(text: &str) -> Option<Self> {
+        let (width, height) = text.split_once('x')?;
+        Some(Self {
+            width: width.trim().parse().ok()?,
+            height: height.trim().parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+// Native aspect ratio of the camera as mounted, e.g. "16:9", "4:3" or "9:16" for portrait
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AspectRatio {
+    pub horizontal: u32,
+    pub vertical: u32,
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (horizontal, vertical) = text.split_once(':').ok_or_else(|| format!("invalid aspect ratio {}", text))?;
+        let horizontal: u32 = horizontal.trim().parse().map_err(|_| format!("invalid aspect ratio {}", text))?;
+        let vertical: u32 = vertical.trim().parse().map_err(|_| format!("invalid aspect ratio {}", text))?;
+        if horizontal == 0 || vertical == 0 {
+            return Err(format!("invalid aspect ratio {}", text));
+        }
+        Ok(Self { horizontal, vertical })
+    }
+}
+
+impl AspectRatio {
+    pub fn is_portrait(self) -> bool {
+        self.vertical > self.horizontal
+    }
+
+    // Height for a given width, rounded to an even number as encoders expect
+    pub fn height_for(self, width: u32) -> u32 {
+        let height = (width as u64 * self.vertical as u64 / self.horizontal as u64) as u32;
+        (height + 1) & !1
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResolutionConfig {
+    pub aspect_ratio: AspectRatio,
+    // Output widths from best to worst. The congestion controller moves between the first
+    // and last rung; the server may suggest any of them.
+    pub ladder: Vec<u32>,
+}
+
+impl Default for ResolutionConfig {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: AspectRatio { horizontal: 16, vertical: 9 },
+            ladder: vec![1280, 640],
+        }
+    }
+}
+
+impl ResolutionConfig {
+    pub fn rungs(&self) -> Vec<Resolution> {
+        self.ladder
+            .iter()
+            .map(|&width| Resolution { width, height: self.aspect_ratio.height_for(width) })
+            .collect()
+    }
+
+    // Used while the network is healthy
+    pub fn high(&self) -> Resolution {
+        let width = self.ladder.first().copied().unwrap_or(1280);
+        Resolution { width, height: self.aspect_ratio.height_for(width) }
+    }
+
+    // Used while congested
+    pub fn low(&self) -> Resolution {
+        let width = self.ladder.last().copied().unwrap_or(640);
+        Resolution { width, height: self.aspect_ratio.height_for(width) }
+    }
+
+    pub fn contains(&self, resolution: Resolution) -> bool {
+        self.rungs().contains(&resolution)
+    }
+}
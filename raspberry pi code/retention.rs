@@ -0,0 +1,164 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+
+use crate::events::CameraEvents;
+use crate::export;
+use crate::recording::RecordingConfig;
+use crate::storage::{self, StorageBackend};
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    // Segments nothing happened in
+    pub continuous_days: u64,
+    // Segments with motion in them
+    pub motion_days: u64,
+    // Segments with a tamper event in them
+    pub tamper_days: u64,
+    // Segments this close to an event count as part of it
+    pub event_padding_seconds: u64,
+    pub check_interval_minutes: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            continuous_days: 7,
+            motion_days: 30,
+            tamper_days: 365,
+            event_padding_seconds: 30,
+            check_interval_minutes: 60,
+        }
+    }
+}
+
+struct IndexedEvent {
+    timestamp: u64,
+    kind: String,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn index_path(config: &RecordingConfig) -> String {
+    format!("{}/events.jsonl", config.directory)
+}
+
+fn append_event(config: &RecordingConfig, kind: &str) {
+    let line = json!({ "timestamp": now_ms(), "kind": kind });
+    let path = index_path(config);
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        eprintln!("Failed to index event in {}: {}", path, e);
+    }
+}
+
+fn load_events(config: &RecordingConfig) -> Vec<IndexedEvent> {
+    let Ok(contents) = std::fs::read_to_string(index_path(config)) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|entry| {
+            Some(IndexedEvent {
+                timestamp: entry["timestamp"].as_u64()?,
+                kind: entry["kind"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+// Delete the segments whose rule has expired, and index entries nothing needs any more
+fn prune(recording: &RecordingConfig, backend: &dyn StorageBackend, config: &RetentionConfig) {
+    let segments = match export::list_segments(recording, backend) {
+        Ok(segments) => segments,
+        Err(e) => {
+            eprintln!("Retention check failed to list {}: {}", backend.describe(), e);
+            return;
+        }
+    };
+    let events = load_events(recording);
+    let now = now_ms();
+    let padding = config.event_padding_seconds * 1000;
+
+    let mut deleted = 0;
+    for segment in &segments {
+        let overlaps = |kind: &str| {
+            events.iter().any(|event| {
+                event.kind == kind && event.timestamp + padding >= segment.start && event.timestamp <= segment.end + padding
+            })
+        };
+        // The longest rule that applies wins
+        let keep_days = if overlaps("tamper") {
+            config.tamper_days
+        } else if overlaps("motion") {
+            config.motion_days
+        } else {
+            config.continuous_days
+        };
+        if now.saturating_sub(segment.end) > keep_days * DAY_MS {
+            match backend.delete(&segment.name) {
+                Ok(()) => deleted += 1,
+                Err(e) => eprintln!("Failed to delete {}: {}", segment.name, e),
+            }
+        }
+    }
+    if deleted > 0 {
+        println!("Retention: deleted {} expired segments", deleted);
+    }
+
+    // No segment older than the longest rule is left, so neither are its events needed
+    let horizon = now.saturating_sub(config.continuous_days.max(config.motion_days).max(config.tamper_days) * DAY_MS);
+    if events.iter().any(|event| event.timestamp < horizon) {
+        let kept: String = events
+            .iter()
+            .filter(|event| event.timestamp >= horizon)
+            .map(|event| format!("{}\n", json!({ "timestamp": event.timestamp, "kind": event.kind })))
+            .collect();
+        if let Err(e) = std::fs::write(index_path(recording), kept) {
+            eprintln!("Failed to rewrite event index: {}", e);
+        }
+    }
+}
+
+// Index detector events next to the recordings and prune the directory periodically
+pub fn spawn_retention(recording: RecordingConfig, config: RetentionConfig, events: &CameraEvents) {
+    let mut event_rx = events.subscribe();
+    let index_config = recording.clone();
+    tokio::spawn(async move {
+        loop {
+            match event_rx.recv().await {
+                Ok(event) => append_event(&index_config, event.kind()),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let backend = storage::open(&recording);
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(config.check_interval_minutes.max(1) * 60));
+        loop {
+            check.tick().await;
+            let recording = recording.clone();
+            let config = config.clone();
+            let backend = backend.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || prune(&recording, backend.as_ref(), &config)).await {
+                eprintln!("Retention check failed: {}", e);
+            }
+        }
+    });
+}
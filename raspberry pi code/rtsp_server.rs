@@ -0,0 +1,126 @@
+use serde::Deserialize;
+
+// Where the capture pipeline hands encoded H.264 to the RTSP server
+const RTSP_SOCKET: &str = "/tmp/camera-rtsp";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RtspConfig {
+    pub port: u16,
+    // Served as rtsp://<camera>:<port><mount>
+    pub mount: String,
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_kbps: u32,
+}
+
+impl Default for RtspConfig {
+    fn default() -> Self {
+        Self {
+            port: 8554,
+            mount: "/stream".to_string(),
+            width: 1280,
+            height: 720,
+            bitrate_kbps: 2000,
+        }
+    }
+}
+
+impl RtspConfig {
+    // Capture pipeline branch that encodes H.264 for the RTSP server, independent of
+    // the uplink codec and quality
+    pub fn tee_args(&self) -> Vec<String> {
+        vec![
+            "tee".into(),
+            "name=rtsp".into(),
+            "!".into(),
+            "queue".into(),
+            "leaky=downstream".into(),
+            "!".into(),
+            "videoscale".into(),
+            "!".into(),
+            "videoconvert".into(),
+            "!".into(),
+            format!("video/x-raw,format=I420,width={},height={}", self.width, self.height),
+            "!".into(),
+            "x264enc".into(),
+            "tune=zerolatency".into(),
+            "speed-preset=superfast".into(),
+            format!("bitrate={}", self.bitrate_kbps),
+            "key-int-max=30".into(),
+            "!".into(),
+            "h264parse".into(),
+            "config-interval=-1".into(),
+            "!".into(),
+            "video/x-h264,stream-format=byte-stream,alignment=au".into(),
+            "!".into(),
+            "shmsink".into(),
+            format!("socket-path={}", RTSP_SOCKET),
+            format!("shm-size={}", 8 * 1024 * 1024),
+            "wait-for-connection=false".into(),
+            "sync=false".into(),
+            "rtsp.".into(),
+            "!".into(),
+            "queue".into(),
+            "!".into(),
+        ]
+    }
+
+    // Launch line for each RTSP session, reading the capture branch back
+    #[cfg_attr(not(feature = "rtsp"), allow(dead_code))]
+    fn media_launch(&self) -> String {
+        format!(
+            "( shmsrc socket-path={} is-live=true do-timestamp=true ! \
+             video/x-h264,stream-format=byte-stream,alignment=au ! h264parse ! \
+             rtph264pay name=pay0 pt=96 config-interval=1 )",
+            RTSP_SOCKET
+        )
+    }
+
+    // A killed pipeline leaves its socket behind, which shmsink refuses to reuse
+    pub fn remove_socket(&self) {
+        let _ = std::fs::remove_file(RTSP_SOCKET);
+    }
+}
+
+// Runs gst-rtsp-server on its own thread with a GLib main loop, alongside the
+// WebSocket uplink. All clients share one media, so extra viewers cost no encoding.
+#[cfg(feature = "rtsp")]
+pub fn spawn_rtsp_server(config: RtspConfig) {
+    use gstreamer_rtsp_server::prelude::*;
+    use gstreamer_rtsp_server::{RTSPMediaFactory, RTSPServer};
+
+    std::thread::spawn(move || {
+        if let Err(e) = gstreamer::init() {
+            eprintln!("RTSP server disabled, GStreamer failed to initialise: {}", e);
+            return;
+        }
+        let main_loop = gstreamer::glib::MainLoop::new(None, false);
+        let server = RTSPServer::new();
+        server.set_service(&config.port.to_string());
+
+        let factory = RTSPMediaFactory::new();
+        factory.set_launch(&config.media_launch());
+        factory.set_shared(true);
+        let Some(mounts) = server.mount_points() else {
+            eprintln!("RTSP server disabled, no mount points");
+            return;
+        };
+        mounts.add_factory(&config.mount, factory);
+
+        let _source = match server.attach(None) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Failed to start RTSP server on port {}: {}", config.port, e);
+                return;
+            }
+        };
+        println!("RTSP server at rtsp://0.0.0.0:{}{}", config.port, config.mount);
+        main_loop.run();
+    });
+}
+
+#[cfg(not(feature = "rtsp"))]
+pub fn spawn_rtsp_server(_config: RtspConfig) {
+    eprintln!("RTSP output is configured but this build has no RTSP support; rebuild with --features rtsp");
+}
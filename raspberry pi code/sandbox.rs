@@ -0,0 +1,202 @@
+#[cfg(target_os = "linux")]
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI,
+};
+use serde::Deserialize;
+#[cfg(unix)]
+use std::ffi::CString;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    // Run the whole process as this user once started (it needs the "video" group)
+    pub run_as: Option<String>,
+    // Run only the capture pipelines as this user; ignored when run_as is set
+    pub child_user: Option<String>,
+    // Restrict writes to the recording directories, /tmp and `writable` with Landlock.
+    // Reads and program execution stay unrestricted; children inherit the restriction.
+    // State files in the working directory (event queue, stats, identity key) and camera
+    // device nodes opened read-write (/dev/video0, /dev/media0) have to be listed in `writable`.
+    pub landlock: bool,
+    pub writable: Vec<String>,
+    // Carry on unrestricted when Landlock can't be applied instead of exiting
+    pub best_effort: bool,
+}
+
+// A user resolved up front, groups included, since nothing may allocate or read the user
+// database between fork and exec
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct ResolvedUser {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+}
+
+// Without Unix users there is nobody to switch to
+#[cfg(not(unix))]
+#[derive(Clone)]
+pub struct ResolvedUser;
+
+#[cfg(unix)]
+fn resolve_user(name: &str) -> Result<ResolvedUser, String> {
+    let c_name = CString::new(name).map_err(|_| format!("invalid user name {}", name))?;
+    // getpwnam returns a pointer into static storage; copy out what we need right away
+    let entry = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("no such user {}", name));
+    }
+    let (uid, gid) = unsafe { ((*entry).pw_uid, (*entry).pw_gid) };
+    // Supplementary groups, retried with the count it asks for when there are more
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let found = unsafe { libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if found >= 0 {
+            groups.truncate(count as usize);
+            break;
+        }
+        if count as usize <= groups.len() {
+            return Err(format!("can't list the groups of {}", name));
+        }
+        groups.resize(count as usize, 0);
+    }
+    Ok(ResolvedUser { uid, gid, groups })
+}
+
+// Supplementary groups (video, gpio) first, then the group, then the user, so the
+// process can't get root back; no_new_privs stops setuid binaries from regaining it
+#[cfg(unix)]
+fn switch_user(user: &ResolvedUser) -> std::io::Result<()> {
+    unsafe {
+        if libc::setgroups(user.groups.len() as _, user.groups.as_ptr()) != 0
+            || libc::setgid(user.gid) != 0
+            || libc::setuid(user.uid) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+        #[cfg(target_os = "linux")]
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn resolve_user(name: &str) -> Result<ResolvedUser, String> {
+    Err(format!("can't run as {} without Unix users", name))
+}
+
+#[cfg(not(unix))]
+fn switch_user(_user: &ResolvedUser) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+// Never root, so run_as and child_user are left alone
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+impl SandboxConfig {
+    // Apply the process-wide restrictions; called once at startup before any task runs
+    pub fn apply(&self, recording_directories: &[String]) {
+        if let Some(name) = &self.run_as {
+            if !is_root() {
+                eprintln!("Sandbox: not running as root, staying the current user instead of {}", name);
+            } else {
+                match resolve_user(name).and_then(|user| switch_user(&user).map_err(|e| e.to_string())) {
+                    Ok(()) => println!("Sandbox: running as {}", name),
+                    Err(e) => {
+                        // Carrying on as root is exactly what this is meant to prevent
+                        eprintln!("Sandbox: failed to switch to {}: {}", name, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = recording_directories;
+        #[cfg(not(target_os = "linux"))]
+        if self.landlock {
+            self.landlock_failed("Landlock is only available on Linux");
+        }
+        #[cfg(target_os = "linux")]
+        if self.landlock {
+            let mut writable: Vec<String> = vec!["/tmp".into()];
+            writable.extend(recording_directories.iter().cloned());
+            writable.extend(self.writable.iter().cloned());
+            // Landlock rules need the path to exist
+            writable.retain(|path| {
+                let exists = std::path::Path::new(path).exists();
+                if !exists {
+                    eprintln!("Sandbox: {} does not exist, not allowing writes to it", path);
+                }
+                exists
+            });
+            match restrict_writes(&writable) {
+                Ok(RulesetStatus::FullyEnforced) => println!("Sandbox: writes restricted to {}", writable.join(", ")),
+                Ok(RulesetStatus::PartiallyEnforced) => println!("Sandbox: writes partly restricted (older kernel)"),
+                Ok(RulesetStatus::NotEnforced) => self.landlock_failed("Landlock is not supported by this kernel"),
+                Err(e) => self.landlock_failed(&format!("failed to apply Landlock rules: {}", e)),
+            }
+        }
+    }
+
+    // Like a failed run_as, running unrestricted when Landlock was asked for is what
+    // it is meant to prevent, so only best_effort lets startup continue
+    fn landlock_failed(&self, reason: &str) {
+        if self.best_effort {
+            eprintln!("Sandbox: {}, writes are not restricted", reason);
+        } else {
+            eprintln!("Sandbox: {}; set sandbox.best_effort to run without it", reason);
+            std::process::exit(1);
+        }
+    }
+
+    // User for the capture pipelines, when they should run as someone else
+    pub fn child_user(&self) -> Option<ResolvedUser> {
+        if self.run_as.is_some() || !is_root() {
+            return None;
+        }
+        let name = self.child_user.as_ref()?;
+        match resolve_user(name) {
+            Ok(user) => Some(user),
+            Err(e) => {
+                eprintln!("Sandbox: capture pipelines stay root: {}", e);
+                None
+            }
+        }
+    }
+}
+
+// Drop to the given user in the child before it execs
+#[cfg(unix)]
+pub fn run_child_as(command: &mut tokio::process::Command, user: ResolvedUser) {
+    unsafe {
+        command.pre_exec(move || switch_user(&user));
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run_child_as(_command: &mut tokio::process::Command, _user: ResolvedUser) {}
+
+#[cfg(target_os = "linux")]
+fn restrict_writes(writable: &[String]) -> Result<RulesetStatus, String> {
+    let abi = ABI::V2;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/"], AccessFs::from_read(abi))))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(writable, AccessFs::from_all(abi))))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(|e| e.to_string())?;
+    Ok(status.ruleset)
+}
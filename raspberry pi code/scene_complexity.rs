@@ -0,0 +1,75 @@
+use serde::Deserialize;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::encoder::Codec;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SceneComplexityConfig {
+    // Bits per pixel (normalised to quality 100) of an ordinary scene
+    pub reference_bits_per_pixel: f64,
+    // Relative complexity below which the controller tolerates more congestion before stepping down
+    pub simple_below: f64,
+    // Relative complexity above which it steps down sooner
+    pub complex_above: f64,
+}
+
+impl Default for SceneComplexityConfig {
+    fn default() -> Self {
+        Self {
+            reference_bits_per_pixel: 2.0,
+            simple_below: 0.5,
+            complex_above: 1.5,
+        }
+    }
+}
+
+// How much smaller a frame gets as quality drops; quality 0 still costs about a fifth
+pub fn quality_scale(quality: u32) -> f64 {
+    0.2 + 0.8 * quality.min(100) as f64 / 100.0
+}
+
+// Inter-frame codecs only send what changed
+pub fn codec_factor(codec: Codec) -> f64 {
+    match codec {
+        Codec::Mjpeg => 1.0,
+        Codec::Vp9 | Codec::Av1 => 0.1,
+    }
+}
+
+// Average encoded frame size, as a proxy for how much detail and movement the scene has.
+// A dark static scene encodes small at any resolution; a busy one doesn't.
+#[derive(Clone)]
+pub struct SceneComplexity {
+    // f64 bits; 0 until the first frame
+    average_bytes: Arc<AtomicU64>,
+}
+
+impl SceneComplexity {
+    pub fn new() -> Self {
+        Self { average_bytes: Arc::new(AtomicU64::new(0)) }
+    }
+
+    pub fn record(&self, frame_bytes: usize) {
+        let previous = f64::from_bits(self.average_bytes.load(Ordering::Relaxed));
+        let average = if previous == 0.0 { frame_bytes as f64 } else { previous * 0.95 + frame_bytes as f64 * 0.05 };
+        self.average_bytes.store(average.to_bits(), Ordering::Relaxed);
+    }
+
+    // Frame sizes change with the encoder settings, so start over after a restart
+    pub fn reset(&self) {
+        self.average_bytes.store(0, Ordering::Relaxed);
+    }
+
+    // Bits per pixel the scene would take at quality 100 with MJPEG
+    pub fn bits_per_pixel(&self, width: u32, height: u32, quality: u32, codec: Codec) -> Option<f64> {
+        let average = f64::from_bits(self.average_bytes.load(Ordering::Relaxed));
+        if average == 0.0 || width == 0 || height == 0 {
+            return None;
+        }
+        Some(average * 8.0 / (width * height) as f64 / quality_scale(quality) / codec_factor(codec))
+    }
+}
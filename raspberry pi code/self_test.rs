@@ -0,0 +1,167 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use std::{
+    io::Write,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+use tokio::{process::Command, time::timeout};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::capture_source::{CaptureSource, CaptureConfig};
+use crate::direct_capture;
+use crate::config::Config;
+use crate::resolution::Resolution;
+use crate::server_address::{self, ProxyConfig};
+use crate::stills;
+
+// Frames captured per ladder rung when measuring FPS
+const FPS_SAMPLE_FRAMES: u32 = 30;
+// Written to the recording directory to measure disk speed
+const DISK_TEST_BYTES: usize = 32 * 1024 * 1024;
+
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: String,
+    verdict: Verdict,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, verdict: Verdict, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), verdict, detail: detail.into() }
+    }
+}
+
+// --self-test: check the camera, every ladder rung, the server and the disk, then
+// print a readiness report. Returns false if anything failed.
+pub async fn run_self_test(config: &Config, camera_id: &str, server_url: &str) -> bool {
+    println!("Running self-test for camera {}", camera_id);
+    let mut checks = Vec::new();
+
+    let high = config.resolution.high();
+    checks.push(match stills::capture_still(&config.capture, high.width, high.height, 85).await {
+        Ok(jpeg) => Check::new("capture", Verdict::Pass, format!("{} {} byte JPEG", high, jpeg.len())),
+        Err(e) => Check::new("capture", Verdict::Fail, e),
+    });
+
+    for rung in config.resolution.rungs() {
+        checks.push(match measure_fps(&config.capture, rung).await {
+            Ok(fps) if fps >= 15.0 => Check::new(format!("fps {}", rung), Verdict::Pass, format!("{:.1} fps", fps)),
+            Ok(fps) => Check::new(format!("fps {}", rung), Verdict::Warn, format!("only {:.1} fps", fps)),
+            Err(e) => Check::new(format!("fps {}", rung), Verdict::Fail, e),
+        });
+    }
+
+    checks.push(match check_server(camera_id, server_url, config.proxy.as_ref()).await {
+        Ok(detail) => Check::new("server", Verdict::Pass, detail),
+        Err(e) => Check::new("server", Verdict::Fail, e),
+    });
+
+    if let Some(recording) = &config.recording {
+        // Recording needs to keep up with its bitrate with plenty of headroom
+        let needed = recording.bitrate_kbps as f64 / 8.0 / 1024.0;
+        checks.push(match measure_disk_speed(&recording.directory) {
+            Ok(speed) if speed >= needed * 4.0 => Check::new("disk", Verdict::Pass, format!("{:.1} MB/s", speed)),
+            Ok(speed) => Check::new("disk", Verdict::Warn, format!("{:.1} MB/s for {:.1} MB/s of recording", speed, needed)),
+            Err(e) => Check::new("disk", Verdict::Fail, e),
+        });
+    }
+
+    println!("\nReadiness report");
+    let mut ready = true;
+    for check in &checks {
+        let label = match check.verdict {
+            Verdict::Pass => "PASS",
+            Verdict::Warn => "WARN",
+            Verdict::Fail => {
+                ready = false;
+                "FAIL"
+            }
+        };
+        println!("  [{}] {:<16} {}", label, check.name, check.detail);
+    }
+    println!("{}", if ready { "Camera is ready" } else { "Camera is NOT ready" });
+    ready
+}
+
+async fn measure_fps(capture: &CaptureConfig, resolution: Resolution) -> Result<f64, String> {
+    if capture.backend() == CaptureSource::Direct {
+        return direct_capture::measure_fps(capture, resolution.width, resolution.height, FPS_SAMPLE_FRAMES).await;
+    }
+    // Paced by its own config, so there's nothing to measure
+    if capture.backend() == CaptureSource::Synthetic {
+        return Ok(capture.synthetic.frame_rate as f64);
+    }
+    let started = Instant::now();
+    let mut args = vec!["-q".to_string()];
+    args.extend(capture.source_args(Some(FPS_SAMPLE_FRAMES), None));
+    args.extend([
+        "!".into(),
+        format!("video/x-raw,width={},height={}", resolution.width, resolution.height),
+        "!".into(),
+        "videoconvert".into(),
+        "!".into(),
+        "jpegenc".into(),
+        "!".into(),
+        "fakesink".into(),
+    ]);
+    let status = Command::new("gst-launch-1.0")
+        .args(&args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("GStreamer exited with {}", status));
+    }
+    // Includes pipeline startup, so this errs on the low side
+    Ok(FPS_SAMPLE_FRAMES as f64 / started.elapsed().as_secs_f64())
+}
+
+async fn check_server(camera_id: &str, server_url: &str, proxy: Option<&ProxyConfig>) -> Result<String, String> {
+    let started = Instant::now();
+    let (ws_stream, _) = timeout(Duration::from_secs(10), server_address::connect(server_url, proxy))
+        .await
+        .map_err(|_| "timed out connecting".to_string())??;
+    let connect_time = started.elapsed();
+    let (mut write, mut read) = ws_stream.split();
+
+    let join = json!({ "join": camera_id, "self_test": true }).to_string();
+    write.send(Message::Text(join)).await.map_err(|e| e.to_string())?;
+
+    // Any answer means the server accepted us; a close means it didn't
+    let reply = timeout(Duration::from_secs(5), read.next()).await;
+    let _ = write.send(Message::Close(None)).await;
+    match reply {
+        Ok(Some(Ok(Message::Close(frame)))) => Err(format!("server closed the connection: {:?}", frame)),
+        Ok(Some(Err(e))) => Err(e.to_string()),
+        Ok(None) => Err("server closed the connection".to_string()),
+        Ok(Some(Ok(_))) => Ok(format!("joined, connected in {:?}", connect_time)),
+        Err(_) => Ok(format!("connected in {:?}, no reply to join", connect_time)),
+    }
+}
+
+// Sequential write speed in MB/s, synced to disk
+fn measure_disk_speed(directory: &str) -> Result<f64, String> {
+    std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    let path = std::path::Path::new(directory).join(".self-test");
+    let data = vec![0x5Au8; DISK_TEST_BYTES];
+
+    let started = Instant::now();
+    let result = std::fs::File::create(&path).and_then(|mut file| {
+        file.write_all(&data)?;
+        file.sync_all()
+    });
+    let elapsed = started.elapsed();
+    let _ = std::fs::remove_file(&path);
+    result.map_err(|e| e.to_string())?;
+
+    Ok(DISK_TEST_BYTES as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64())
+}
@@ -0,0 +1,246 @@
+use base64::prelude::*;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{
+    client_async,
+    tungstenite::handshake::client::Response,
+    MaybeTlsStream, WebSocketStream,
+};
+use url::{Host, Url};
+
+// RFC 8305 recommends 250ms between connection attempts
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+// A proxy that accepts the connection and then says nothing mustn't hold up reconnects
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type ServerStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Proxy the uplink goes through, for networks without direct internet access
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyConfig {
+    // http://proxy:3128 (CONNECT tunnel) or socks5://proxy:1080
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    fn credentials(&self) -> Option<(&str, &str)> {
+        Some((self.username.as_deref()?, self.password.as_deref().unwrap_or("")))
+    }
+}
+
+// Addresses for the URL's host, resolved fresh every time so a relay that moves
+// (load balancer, new IP) is followed on the next reconnect
+async fn resolve(url: &Url) -> Result<Vec<SocketAddr>, String> {
+    let port = url.port_or_known_default().ok_or("server URL has no port")?;
+    let addresses: Vec<SocketAddr> = match url.host().ok_or("server URL has no host")? {
+        Host::Ipv4(ip) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Host::Domain(name) => tokio::net::lookup_host((name, port))
+            .await
+            .map_err(|e| format!("failed to resolve {}: {}", name, e))?
+            .collect(),
+    };
+    if addresses.is_empty() {
+        return Err("server name resolved to no addresses".to_string());
+    }
+
+    // Alternate the families, IPv6 first, so one broken family doesn't stall the other
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(|a| a.is_ipv6());
+    v6.reverse();
+    v4.reverse();
+    let mut ordered = Vec::new();
+    while !v6.is_empty() || !v4.is_empty() {
+        ordered.extend(v6.pop());
+        ordered.extend(v4.pop());
+    }
+    Ok(ordered)
+}
+
+async fn attempt(address: SocketAddr) -> (SocketAddr, Result<TcpStream, String>) {
+    let result = match timeout(ATTEMPT_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    };
+    (address, result)
+}
+
+// Ask an HTTP proxy to open a raw tunnel to host:port
+async fn http_connect(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> Result<(), String> {
+    let target = format!("{}:{}", host, port);
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+    if let Some((username, password)) = proxy.credentials() {
+        let token = BASE64_STANDARD.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+
+    // Read the response headers byte by byte so nothing after them is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err("proxy response too long".to_string());
+        }
+        let byte = stream.read_u8().await.map_err(|e| format!("proxy closed the connection: {}", e))?;
+        response.push(byte);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("proxy refused the tunnel: {}", status_line)),
+    }
+}
+
+// SOCKS5 (RFC 1928) with optional username/password authentication (RFC 1929). The
+// host name is passed through so the proxy resolves it.
+async fn socks5_connect(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> Result<(), String> {
+    let io = |e: std::io::Error| format!("SOCKS proxy: {}", e);
+    let methods: &[u8] = if proxy.credentials().is_some() { &[0x00, 0x02] } else { &[0x00] };
+    let mut greeting = vec![0x05, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await.map_err(io)?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await.map_err(io)?;
+    if choice[0] != 0x05 {
+        return Err(format!("not a SOCKS5 proxy (version {})", choice[0]));
+    }
+
+    match choice[1] {
+        0x00 => {}
+        0x02 => {
+            let (username, password) = proxy.credentials().ok_or("SOCKS proxy wants credentials")?;
+            if username.len() > 255 || password.len() > 255 {
+                return Err("SOCKS credentials too long".to_string());
+            }
+            let mut auth = vec![0x01, username.len() as u8];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await.map_err(io)?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await.map_err(io)?;
+            if status[1] != 0x00 {
+                return Err("SOCKS proxy rejected the credentials".to_string());
+            }
+        }
+        _ => return Err("SOCKS proxy accepts none of our authentication methods".to_string()),
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err("host name too long for SOCKS".to_string());
+            }
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await.map_err(io)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.map_err(io)?;
+    if reply[0] != 0x05 {
+        return Err(format!("not a SOCKS5 reply (version {})", reply[0]));
+    }
+    if reply[1] != 0x00 {
+        return Err(format!("SOCKS proxy failed to connect (reply {})", reply[1]));
+    }
+    // Skip the bound address the proxy reports
+    let address_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await.map_err(io)? as usize,
+        other => return Err(format!("SOCKS proxy sent address type {}", other)),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await.map_err(io)?;
+    Ok(())
+}
+
+// Happy Eyeballs: start with the first address, start the next one whenever an attempt
+// fails or the delay passes without an answer, and keep whichever connects first
+async fn connect_tcp(addresses: Vec<SocketAddr>) -> Result<TcpStream, String> {
+    let mut remaining = addresses.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut errors = Vec::new();
+
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(address) => attempts.push(attempt(address)),
+                None => return Err(errors.join(", ")),
+            }
+        }
+        tokio::select! {
+            Some((address, result)) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    errors.push(format!("{}: {}", address, e));
+                    if let Some(address) = remaining.next() {
+                        attempts.push(attempt(address));
+                    }
+                }
+            },
+            _ = sleep(ATTEMPT_DELAY), if remaining.len() > 0 => {
+                if let Some(address) = remaining.next() {
+                    attempts.push(attempt(address));
+                }
+            }
+        }
+    }
+}
+
+// Open the WebSocket to the server over whichever of its addresses answers first, or
+// through the proxy when one is configured
+pub async fn connect(server_url: &str, proxy: Option<&ProxyConfig>) -> Result<(ServerStream, Response), String> {
+    let url = Url::parse(server_url).map_err(|e| format!("invalid server URL {}: {}", server_url, e))?;
+    // Built without a TLS backend, like connect_async before it
+    if url.scheme() != "ws" {
+        return Err(format!("unsupported server URL scheme {}", url.scheme()));
+    }
+    let stream = match proxy {
+        None => connect_tcp(resolve(&url).await?).await?,
+        Some(proxy) => {
+            let proxy_url = Url::parse(&proxy.url).map_err(|e| format!("invalid proxy URL {}: {}", proxy.url, e))?;
+            let mut stream = connect_tcp(resolve(&proxy_url).await?).await?;
+            let host = url.host_str().ok_or("server URL has no host")?;
+            let port = url.port_or_known_default().ok_or("server URL has no port")?;
+            let tunnel = async {
+                match proxy_url.scheme() {
+                    "http" => http_connect(&mut stream, proxy, host, port).await,
+                    "socks5" | "socks5h" => socks5_connect(&mut stream, proxy, host, port).await,
+                    other => Err(format!("unsupported proxy scheme {}", other)),
+                }
+            };
+            let tunnel = timeout(HANDSHAKE_TIMEOUT, tunnel).await.unwrap_or_else(|_| Err("proxy handshake timed out".to_string()));
+            tunnel.map_err(|e| format!("via {}: {}", proxy.url, e))?;
+            stream
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    client_async(url.as_str(), MaybeTlsStream::Plain(stream)).await.map_err(|e| e.to_string())
+}
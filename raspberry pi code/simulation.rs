@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::adaptation::{self, Signals};
+use crate::config::Config;
+use crate::encoder::Codec;
+use crate::scene_complexity::{codec_factor, quality_scale};
+use crate::resolution::Resolution;
+use crate::track_failures;
+
+// One stretch of constant network conditions
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetworkStep {
+    pub seconds: u64,
+    pub bandwidth_kbps: u64,
+    #[serde(default)]
+    pub loss_percent: f64,
+    // What the server reports; by default it complains once loss goes over 5%
+    pub server_congested: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SimulationScript {
+    pub frame_rate: u32,
+    pub codec: Codec,
+    // Encoded size of a frame at quality 100; lower qualities scale down linearly to a fifth of it
+    pub bits_per_pixel: f64,
+    pub network: Vec<NetworkStep>,
+}
+
+impl Default for SimulationScript {
+    fn default() -> Self {
+        Self {
+            frame_rate: 15,
+            codec: Codec::Mjpeg,
+            bits_per_pixel: 2.0,
+            network: Vec::new(),
+        }
+    }
+}
+
+impl SimulationScript {
+    // Synthetic frame size for the current encoder settings
+    fn frame_bytes(&self, width: u32, height: u32, quality: u32) -> f64 {
+        (width * height) as f64 * self.bits_per_pixel * quality_scale(quality) * codec_factor(self.codec) / 8.0
+    }
+
+    fn step_at(&self, elapsed: u64) -> Option<&NetworkStep> {
+        let mut end = 0;
+        for step in &self.network {
+            end += step.seconds;
+            if elapsed < end {
+                return Some(step);
+            }
+        }
+        None
+    }
+}
+
+// --simulate <script.json>: run the congestion controller against a scripted network on a
+// virtual clock and write every decision to --report (default simulation-report.json).
+// The same script and config always give the same report.
+pub fn run_simulation(config: &Config, script_path: &str, report_path: &str) -> Result<(), String> {
+    let contents = std::fs::read_to_string(script_path).map_err(|e| format!("failed to read {}: {}", script_path, e))?;
+    let script: SimulationScript = serde_json::from_str(&contents).map_err(|e| format!("invalid script {}: {}", script_path, e))?;
+
+    let start = Instant::now();
+    let mut network_state = adaptation::policy(&config.adaptation, config.resolution.clone(), start);
+    let mut consecutive_failures: u32 = 0;
+    let mut consecutive_successes: u32 = 0;
+    let high = config.resolution.high();
+    let (mut width, mut height, mut quality) = (high.width, high.height, 70);
+    // Frames waiting for the uplink; fractional so slow links drain partial frames
+    let mut backlog: f64 = 0.0;
+    let mut elapsed: u64 = 0;
+
+    let mut ticks = Vec::new();
+    let mut restarts = 0;
+    let mut frames_sent: f64 = 0.0;
+    let mut frames_dropped: f64 = 0.0;
+    let mut seconds_at: BTreeMap<String, u64> = BTreeMap::new();
+    let mut quality_seconds: u64 = 0;
+
+    while let Some(step) = script.step_at(elapsed) {
+        let interval = network_state.check_interval().as_secs();
+
+        // Frames produced and sent since the last check
+        let frame_bytes = script.frame_bytes(width, height, quality);
+        let produced = script.frame_rate as f64 * interval as f64;
+        let usable_bytes = step.bandwidth_kbps as f64 * 1000.0 / 8.0 * (1.0 - step.loss_percent / 100.0) * interval as f64;
+        let drained = (usable_bytes / frame_bytes).min(backlog + produced);
+        frames_sent += drained;
+        backlog += produced - drained;
+        let capacity = config.queue.capacity as f64;
+        if backlog > capacity {
+            // Block stalls capture instead, which loses the frames just the same
+            frames_dropped += backlog - capacity;
+            backlog = capacity;
+        }
+
+        let queue_depth = backlog as u64;
+        let server_congested = step.server_congested.unwrap_or(step.loss_percent > 5.0);
+        track_failures(&mut consecutive_failures, &mut consecutive_successes, server_congested || config.queue.is_backed_up(queue_depth));
+
+        elapsed += interval;
+        let now = start + Duration::from_secs(elapsed);
+        // The script has no server suggestions; the server-driven strategy holds still
+        let signals = Signals {
+            queue_size: queue_depth,
+            consecutive_failures,
+            server_congested,
+            suggested_quality: quality,
+            suggested_resolution: Resolution { width, height },
+            bits_per_pixel: Some(script.bits_per_pixel),
+            codec: script.codec,
+        };
+        let (congested, resolution, recommended_quality) = network_state.update(now, &signals);
+
+        // Same rule the capture manager uses to decide on a pipeline restart
+        let restart = recommended_quality.abs_diff(quality) > 5 || resolution.width != width || resolution.height != height;
+        if restart {
+            restarts += 1;
+            width = resolution.width;
+            height = resolution.height;
+            quality = recommended_quality;
+        }
+
+        *seconds_at.entry(format!("{}x{}", width, height)).or_default() += interval;
+        quality_seconds += quality as u64 * interval;
+        ticks.push(json!({
+            "time": elapsed,
+            "bandwidth_kbps": step.bandwidth_kbps,
+            "loss_percent": step.loss_percent,
+            "queue_depth": queue_depth,
+            "consecutive_failures": consecutive_failures,
+            "server_congested": server_congested,
+            "level": network_state.level(),
+            "congested": congested,
+            "resolution": format!("{}x{}", width, height),
+            "quality": quality,
+            "restart": restart
+        }));
+    }
+
+    let report = json!({
+        "script": script_path,
+        "codec": script.codec.name(),
+        "queue_strategy": config.queue.strategy.name(),
+        "adaptation": network_state.name(),
+        "summary": {
+            "seconds": elapsed,
+            "restarts": restarts,
+            "frames_sent": frames_sent.round() as u64,
+            "frames_dropped": frames_dropped.round() as u64,
+            "seconds_at_resolution": seconds_at,
+            "mean_quality": if elapsed > 0 { quality_seconds as f64 / elapsed as f64 } else { 0.0 }
+        },
+        "ticks": ticks
+    });
+
+    let text = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    std::fs::write(report_path, text).map_err(|e| format!("failed to write {}: {}", report_path, e))?;
+    println!(
+        "Simulated {}s: {} restarts, {} frames dropped, report written to {}",
+        elapsed, restarts, frames_dropped.round(), report_path
+    );
+    Ok(())
+}
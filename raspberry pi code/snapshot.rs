@@ -0,0 +1,109 @@
+use base64::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+use crate::downscale::Downscaler;
+use crate::frame_pool::PooledFrame;
+use crate::privacy::PrivacyConfig;
+
+// {"command": "snapshot", "id": "..."}; the id is echoed back to match up the reply.
+// With "width" the reply is a thumbnail no wider than that.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotCommand {
+    pub id: Option<String>,
+    pub width: Option<u32>,
+}
+
+const THUMBNAIL_QUALITY: u32 = 70;
+
+struct Snapshot {
+    jpeg: Vec<u8>,
+    // UNIX millis it arrived at
+    timestamp: u64,
+}
+
+// The most recent JPEG from the main capture, for notifications and snapshots
+#[derive(Clone)]
+pub struct LatestFrame {
+    latest: Arc<Mutex<Option<Snapshot>>>,
+}
+
+impl LatestFrame {
+    pub fn get(&self) -> Option<Vec<u8>> {
+        self.get_with_timestamp().map(|(jpeg, _)| jpeg)
+    }
+
+    pub fn get_with_timestamp(&self) -> Option<(Vec<u8>, u64)> {
+        self.latest.lock().unwrap().as_ref().map(|snapshot| (snapshot.jpeg.clone(), snapshot.timestamp))
+    }
+
+    // Reply to a snapshot command, redacted like the frames when privacy is configured
+    pub async fn response(&self, camera_id: &str, command: &SnapshotCommand, privacy: Option<&PrivacyConfig>) -> serde_json::Value {
+        match self.get_with_timestamp() {
+            Some((jpeg, timestamp)) => {
+                let timestamp = privacy.map_or(timestamp, |privacy| privacy.timestamp(timestamp));
+                // Decoding and encoding again; not on the runtime's threads
+                let (privacy, width) = (privacy.cloned(), command.width);
+                let jpeg = tokio::task::spawn_blocking(move || {
+                    let jpeg = match &privacy {
+                        Some(privacy) => privacy.strip(&jpeg).unwrap_or(jpeg),
+                        None => jpeg,
+                    };
+                    match width {
+                        Some(width) => thumbnail(jpeg, width),
+                        None => jpeg,
+                    }
+                })
+                .await
+                .unwrap_or_default();
+                json!({
+                    "snapshot": {
+                        "id": command.id,
+                        "camera_id": camera_id,
+                        "timestamp": timestamp,
+                        "data": BASE64_STANDARD.encode(jpeg)
+                    }
+                })
+            }
+            None => json!({
+                "snapshot": {
+                    "id": command.id,
+                    "camera_id": camera_id,
+                    "error": "no frame available"
+                }
+            }),
+        }
+    }
+}
+
+// The frame scaled down to at most `width` wide, or as it is if that fails
+pub fn thumbnail(jpeg: Vec<u8>, width: u32) -> Vec<u8> {
+    match Downscaler::new().downscale(&jpeg, width, THUMBNAIL_QUALITY) {
+        Ok(thumbnail) => thumbnail.unwrap_or(jpeg),
+        Err(e) => {
+            eprintln!("Failed to make a thumbnail, sending the full frame: {}", e);
+            jpeg
+        }
+    }
+}
+
+// Returns a local sink that keeps the latest frame around
+pub fn spawn_latest_frame_sink() -> (mpsc::Sender<PooledFrame>, LatestFrame) {
+    let (tx, mut rx) = mpsc::channel::<PooledFrame>(2);
+    let latest = LatestFrame { latest: Arc::new(Mutex::new(None)) };
+    let shared = latest.clone();
+
+    tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+            *shared.latest.lock().unwrap() = Some(Snapshot { jpeg: frame.to_vec(), timestamp });
+        }
+    });
+
+    (tx, latest)
+}
@@ -0,0 +1,271 @@
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::AsyncReadExt,
+    process::Command,
+    time::{sleep, Instant},
+};
+
+use crate::av_container::AudioTap;
+use crate::events::{CameraEvent, CameraEvents};
+
+pub const SAMPLE_RATE: u32 = 16_000;
+// 20ms analysis frames
+const FRAME_SAMPLES: usize = 320;
+// How much of a sound after its onset the glass break check looks at
+const CLASSIFY_FRAMES: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundKind {
+    LoudNoise,
+    GlassBreak,
+}
+
+impl SoundKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundKind::LoudNoise => "loud_noise",
+            SoundKind::GlassBreak => "glass_break",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SoundEventConfig {
+    // ALSA device such as "hw:1,0"; unset lets GStreamer pick a microphone
+    pub device: Option<String>,
+    // Level in dBFS above which a sound is a loud noise
+    pub loud_threshold_db: f32,
+    // Look for breaking glass: a sudden, mostly high-pitched sound. A heuristic on the
+    // onset and spectrum rather than a trained model, so expect the odd false alarm
+    // from keys or cutlery close to the microphone.
+    pub glass_break: bool,
+    // How far above the background level a sound has to jump, within 20ms, to count as
+    // a sudden onset for the glass break check
+    pub onset_db: f32,
+    // Audio kept from before the sound starts and the length of the attached clip
+    pub pre_roll_seconds: f32,
+    pub clip_seconds: f32,
+    // Fewest seconds between two events of the same kind
+    pub min_interval_seconds: u64,
+}
+
+impl Default for SoundEventConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            loud_threshold_db: -20.0,
+            glass_break: true,
+            onset_db: 20.0,
+            pre_roll_seconds: 1.0,
+            clip_seconds: 4.0,
+            min_interval_seconds: 30,
+        }
+    }
+}
+
+// The microphone as 16kHz mono 16-bit PCM on stdout
+fn audio_command(config: &SoundEventConfig) -> Command {
+    let mut command = Command::new("gst-launch-1.0");
+    command.arg("-q");
+    match &config.device {
+        Some(device) => command.args(["alsasrc".to_string(), format!("device={}", device)]),
+        None => command.arg("autoaudiosrc"),
+    };
+    command.args(["!", "audioconvert", "!", "audioresample", "!"]);
+    command.arg(format!("audio/x-raw,format=S16LE,rate={},channels=1", SAMPLE_RATE));
+    command.args(["!", "fdsink", "fd=1"]);
+    command.stdout(Stdio::piped()).kill_on_drop(true);
+    command
+}
+
+// Level of a frame in dBFS and how much of its energy is high-pitched. The ratio of the
+// first difference's energy to the signal's is (2 sin(pi f / rate))^2 for a tone at f,
+// which passes 1 at a sixth of the sample rate (2.7kHz here).
+fn analyze(frame: &[i16]) -> (f32, f32) {
+    let mut energy = 0f64;
+    let mut difference_energy = 0f64;
+    let mut previous = frame[0] as f64;
+    for &sample in frame {
+        let sample = sample as f64;
+        energy += sample * sample;
+        difference_energy += (sample - previous) * (sample - previous);
+        previous = sample;
+    }
+    let rms = (energy / frame.len() as f64).sqrt() / 32768.0;
+    let level = 20.0 * rms.max(1e-6).log10();
+    let high_ratio = if energy > 0.0 { difference_energy / energy } else { 0.0 };
+    (level as f32, high_ratio as f32)
+}
+
+// A sound being recorded after it triggered
+struct Capture {
+    started_ms: u64,
+    samples: Vec<i16>,
+    // Where the pre-roll ends and the sound starts
+    onset: usize,
+    remaining: usize,
+    peak_db: f32,
+    sudden: bool,
+    // High-pitch ratios of the loud frames right after the onset
+    high_ratios: Vec<f32>,
+}
+
+impl Capture {
+    fn kind(&self, config: &SoundEventConfig) -> Option<SoundKind> {
+        let high_ratio = self.high_ratios.iter().sum::<f32>() / self.high_ratios.len().max(1) as f32;
+        if config.glass_break && self.sudden && self.high_ratios.len() >= 3 && high_ratio > 1.0 {
+            Some(SoundKind::GlassBreak)
+        } else if self.peak_db >= config.loud_threshold_db {
+            Some(SoundKind::LoudNoise)
+        } else {
+            None
+        }
+    }
+}
+
+struct Detector {
+    config: SoundEventConfig,
+    // Slow average of the level while nothing is happening
+    background_db: Option<f32>,
+    pre_roll: VecDeque<i16>,
+    capture: Option<Capture>,
+    last_event: Vec<(SoundKind, Instant)>,
+}
+
+impl Detector {
+    fn new(config: SoundEventConfig) -> Self {
+        Self { config, background_db: None, pre_roll: VecDeque::new(), capture: None, last_event: Vec::new() }
+    }
+
+    fn pre_roll_samples(&self) -> usize {
+        (self.config.pre_roll_seconds.max(0.0) * SAMPLE_RATE as f32) as usize
+    }
+
+    // Feed one frame; returns an event once a clip is complete
+    fn push(&mut self, frame: &[i16]) -> Option<CameraEvent> {
+        let (level, high_ratio) = analyze(frame);
+        let background = *self.background_db.get_or_insert(level);
+
+        if self.capture.is_none() {
+            let sudden = level - background >= self.config.onset_db;
+            if level >= self.config.loud_threshold_db || (self.config.glass_break && sudden) {
+                let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                let clip_samples = (self.config.clip_seconds.max(0.1) * SAMPLE_RATE as f32) as usize;
+                let samples: Vec<i16> = self.pre_roll.drain(..).collect();
+                self.capture = Some(Capture {
+                    started_ms: now_ms,
+                    remaining: clip_samples.saturating_sub(samples.len()),
+                    onset: samples.len(),
+                    samples,
+                    peak_db: level,
+                    sudden,
+                    high_ratios: Vec::new(),
+                });
+            } else {
+                self.background_db = Some(background * 0.98 + level * 0.02);
+                self.pre_roll.extend(frame);
+                let excess = self.pre_roll.len().saturating_sub(self.pre_roll_samples());
+                self.pre_roll.drain(..excess);
+                return None;
+            }
+        }
+
+        let capture = self.capture.as_mut()?;
+        capture.samples.extend_from_slice(frame);
+        capture.remaining = capture.remaining.saturating_sub(frame.len());
+        capture.peak_db = capture.peak_db.max(level);
+        let frames_in = (capture.samples.len() - capture.onset) / FRAME_SAMPLES;
+        // Only frames well above the background say anything about the sound itself
+        if frames_in <= CLASSIFY_FRAMES && level - background >= 6.0 {
+            capture.high_ratios.push(high_ratio);
+        }
+        if capture.remaining > 0 {
+            return None;
+        }
+
+        let capture = self.capture.take()?;
+        let kind = capture.kind(&self.config)?;
+        let min_interval = Duration::from_secs(self.config.min_interval_seconds);
+        if self.last_event.iter().any(|(last_kind, at)| *last_kind == kind && at.elapsed() < min_interval) {
+            return None;
+        }
+        self.last_event.retain(|(last_kind, _)| *last_kind != kind);
+        self.last_event.push((kind, Instant::now()));
+        Some(CameraEvent::Sound {
+            kind,
+            level_db: capture.peak_db,
+            timestamp_ms: capture.started_ms,
+            clip: Arc::new(wav(&capture.samples)),
+        })
+    }
+}
+
+// 16-bit mono PCM in a WAV container
+fn wav(samples: &[i16]) -> Vec<u8> {
+    let data_bytes = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_bytes as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_bytes).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_bytes.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+// Listen on the microphone and publish loud_noise/glass_break events with a clip of
+// the sound attached, when given events to publish to, and hand the audio to the
+// uplink's tap. The audio pipeline is restarted if it exits.
+pub fn spawn_sound_detector(config: SoundEventConfig, events: Option<&CameraEvents>, audio: Option<AudioTap>) {
+    let events = events.cloned();
+    tokio::spawn(async move {
+        let mut detector = Detector::new(config.clone());
+        loop {
+            let mut child = match audio_command(&config).spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Sound detection disabled, failed to start the audio pipeline: {}", e);
+                    return;
+                }
+            };
+            println!("Listening for sounds on {}", config.device.as_deref().unwrap_or("the default microphone"));
+            let mut stdout = child.stdout.take().expect("audio pipeline stdout is piped");
+            let mut packetizer = audio.as_ref().map(|audio| audio.packetizer());
+            let mut buffer = [0u8; FRAME_SAMPLES * 2];
+            let mut frame = [0i16; FRAME_SAMPLES];
+            while stdout.read_exact(&mut buffer).await.is_ok() {
+                for (sample, bytes) in frame.iter_mut().zip(buffer.chunks_exact(2)) {
+                    *sample = i16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                if let Some(packetizer) = &mut packetizer {
+                    packetizer.push(&frame);
+                }
+                let Some(events) = &events else { continue };
+                if let Some(event) = detector.push(&frame) {
+                    println!("{}", event.describe());
+                    events.publish(event);
+                }
+            }
+            let _ = child.kill().await;
+            eprintln!("Audio pipeline stopped, restarting in 5 seconds");
+            sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
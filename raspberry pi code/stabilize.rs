@@ -0,0 +1,212 @@
+use serde::Deserialize;
+use std::{process::Stdio, time::Duration};
+use tokio::{io::AsyncReadExt, process::Command, time::sleep};
+
+use crate::camera_controls::SharedCameraControls;
+use crate::capture_source::CaptureConfig;
+use crate::sandbox::SandboxConfig;
+use crate::virtual_input::{VirtualInput, VirtualInputConfig, VirtualInputFormat};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StabilizationConfig {
+    // 0 only steadies the quickest shakes, close to 1 holds the view almost still;
+    // deliberate pans are followed either way, more slowly with higher values
+    pub strength: f64,
+    // Border cropped off each side to make room for the correction, in percent of the
+    // frame; also the largest shake that can be taken out
+    pub margin_percent: u32,
+    // Fixed capture size and rate; the pipeline scales the stabilized frames from there
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+}
+
+impl Default for StabilizationConfig {
+    fn default() -> Self {
+        Self { strength: 0.8, margin_percent: 8, width: 1280, height: 720, frame_rate: 15 }
+    }
+}
+
+impl StabilizationConfig {
+    // Sizes rounded to even numbers so the chroma planes line up
+    fn margins(&self) -> (u32, u32) {
+        let margin = |size: u32| (size * self.margin_percent.min(25) / 100) & !1;
+        (margin(self.width), margin(self.height))
+    }
+
+    fn output_size(&self) -> (u32, u32) {
+        let (margin_x, margin_y) = self.margins();
+        (self.width - 2 * margin_x, self.height - 2 * margin_y)
+    }
+}
+
+// Row or column sums of the luma, with their mean taken out so exposure changes don't
+// look like motion
+fn profile(sums: Vec<f64>) -> Vec<f64> {
+    let mean = sums.iter().sum::<f64>() / sums.len().max(1) as f64;
+    sums.into_iter().map(|sum| sum - mean).collect()
+}
+
+// How far the content moved between two profiles, within +-range
+fn best_shift(previous: &[f64], current: &[f64], range: i64) -> i64 {
+    let length = current.len() as i64;
+    let mut best = (0, f64::MAX);
+    for shift in -range..=range {
+        let (start, end) = (shift.max(0), (length + shift).min(length));
+        if end - start < length / 2 {
+            continue;
+        }
+        let cost = (start..end)
+            .map(|i| (current[i as usize] - previous[(i - shift) as usize]).abs())
+            .sum::<f64>()
+            / (end - start) as f64;
+        if cost < best.1 {
+            best = (shift, cost);
+        }
+    }
+    best.0
+}
+
+// Global-motion compensation: the frame-to-frame translation is estimated from the
+// luma projections, and the crop window is moved to cancel whatever the smoothed
+// camera path doesn't explain
+struct Stabilizer {
+    config: StabilizationConfig,
+    previous: Option<(Vec<f64>, Vec<f64>)>,
+    // Where the camera has moved to since the start, and that path smoothed
+    position: (f64, f64),
+    smoothed: (f64, f64),
+}
+
+impl Stabilizer {
+    fn new(config: StabilizationConfig) -> Self {
+        Self { config, previous: None, position: (0.0, 0.0), smoothed: (0.0, 0.0) }
+    }
+
+    // An I420 frame of the capture size in, a cropped I420 frame of output_size out
+    fn process(&mut self, frame: &[u8]) -> Vec<u8> {
+        let (width, height) = (self.config.width as usize, self.config.height as usize);
+        let luma = &frame[..width * height];
+        // Every other pixel is plenty for the profiles
+        let mut rows = vec![0f64; height];
+        let mut columns = vec![0f64; width];
+        for (y, row) in luma.chunks_exact(width).enumerate().step_by(2) {
+            for (x, &value) in row.iter().enumerate().step_by(2) {
+                rows[y] += value as f64;
+                columns[x] += value as f64;
+            }
+        }
+        // The skipped rows and columns would match nothing; drop them
+        let rows = profile(rows.into_iter().step_by(2).collect());
+        let columns = profile(columns.into_iter().step_by(2).collect());
+
+        let (margin_x, margin_y) = self.config.margins();
+        if let Some((previous_rows, previous_columns)) = &self.previous {
+            let dx = best_shift(previous_columns, &columns, (margin_x / 2) as i64) * 2;
+            let dy = best_shift(previous_rows, &rows, (margin_y / 2) as i64) * 2;
+            self.position.0 += dx as f64;
+            self.position.1 += dy as f64;
+        }
+        self.previous = Some((rows, columns));
+        let smoothing = self.config.strength.clamp(0.0, 0.98);
+        self.smoothed.0 = self.smoothed.0 * smoothing + self.position.0 * (1.0 - smoothing);
+        self.smoothed.1 = self.smoothed.1 * smoothing + self.position.1 * (1.0 - smoothing);
+
+        // Move the crop with the shake, as far as the margin allows; even offsets keep
+        // the chroma aligned
+        let offset = |jitter: f64, margin: u32| ((margin as f64 + jitter).clamp(0.0, 2.0 * margin as f64) as usize) & !1;
+        let offset_x = offset(self.position.0 - self.smoothed.0, margin_x);
+        let offset_y = offset(self.position.1 - self.smoothed.1, margin_y);
+        crop_i420(frame, (width, height), (offset_x, offset_y), self.config.output_size())
+    }
+}
+
+fn crop_i420(frame: &[u8], (width, height): (usize, usize), (x, y): (usize, usize), (out_width, out_height): (u32, u32)) -> Vec<u8> {
+    let (out_width, out_height) = (out_width as usize, out_height as usize);
+    let mut output = Vec::with_capacity(out_width * out_height * 3 / 2);
+    let luma_bytes = width * height;
+    let chroma_bytes = luma_bytes / 4;
+    let planes = [
+        (&frame[..luma_bytes], width, x, y, out_width, out_height),
+        (&frame[luma_bytes..luma_bytes + chroma_bytes], width / 2, x / 2, y / 2, out_width / 2, out_height / 2),
+        (&frame[luma_bytes + chroma_bytes..luma_bytes + 2 * chroma_bytes], width / 2, x / 2, y / 2, out_width / 2, out_height / 2),
+    ];
+    for (plane, stride, x, y, plane_width, plane_height) in planes {
+        for row in plane.chunks_exact(stride).skip(y).take(plane_height) {
+            output.extend_from_slice(&row[x..x + plane_width]);
+        }
+    }
+    output
+}
+
+// Capture from the camera at the fixed size, stabilize, and feed the result into the
+// regular pipeline the way a raw virtual input would be
+pub fn spawn_stabilizer(
+    config: StabilizationConfig,
+    capture: CaptureConfig,
+    controls: SharedCameraControls,
+    sandbox: SandboxConfig,
+) -> VirtualInput {
+    let (out_width, out_height) = config.output_size();
+    let input = VirtualInput::fed_internally(VirtualInputConfig {
+        source: "stabilizer".to_string(),
+        format: VirtualInputFormat::Raw,
+        width: out_width,
+        height: out_height,
+        pixel_format: "I420".to_string(),
+        frame_rate: config.frame_rate,
+    });
+    let output = input.clone();
+
+    tokio::spawn(async move {
+        let frame_bytes = (config.width * config.height * 3 / 2) as usize;
+        let mut stabilizer = Stabilizer::new(config.clone());
+        loop {
+            let mut command = Command::new("gst-launch-1.0");
+            command.arg("-q").args(capture.source_args(None, Some(&controls))).args([
+                "!".to_string(),
+                format!("video/x-raw,width={},height={},framerate={}/1", config.width, config.height, config.frame_rate),
+                "!".to_string(),
+                "videoconvert".to_string(),
+                "!".to_string(),
+                "video/x-raw,format=I420".to_string(),
+                "!".to_string(),
+                "fdsink".to_string(),
+            ]);
+            command.stdout(Stdio::piped()).kill_on_drop(true);
+            if let Some(user) = sandbox.child_user() {
+                crate::sandbox::run_child_as(&mut command, user);
+            }
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Stabilization stopped, failed to start the capture: {}", e);
+                    return;
+                }
+            };
+            println!(
+                "Stabilizing {}x{} capture, sending {}x{} on",
+                config.width, config.height, out_width, out_height
+            );
+            let mut stdout = child.stdout.take().expect("capture stdout is piped");
+            let mut frame = vec![0u8; frame_bytes];
+            while stdout.read_exact(&mut frame).await.is_ok() {
+                // A few milliseconds per frame; keep it off the runtime's threads
+                let (returned, stabilized) = tokio::task::spawn_blocking(move || {
+                    let stabilized = stabilizer.process(&frame);
+                    ((stabilizer, frame), stabilized)
+                })
+                .await
+                .expect("stabilizer panicked");
+                (stabilizer, frame) = returned;
+                output.write(&stabilized).await;
+            }
+            let _ = child.kill().await;
+            eprintln!("Stabilization capture stopped, restarting in 5 seconds");
+            sleep(Duration::from_secs(5)).await;
+        }
+    });
+
+    input
+}
@@ -0,0 +1,195 @@
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+
+use crate::events::CameraEvents;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsDbConfig {
+    pub path: String,
+    // Rows older than this are deleted
+    pub retention_days: u64,
+}
+
+impl Default for StatsDbConfig {
+    fn default() -> Self {
+        Self {
+            path: "stats.db".to_string(),
+            retention_days: 90,
+        }
+    }
+}
+
+// {"command": "stats_query", "from": ..., "to": ...}; times in UNIX millis
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatsQueryCommand {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    // Most recent minutes first, at most this many
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    // A day of minutes
+    1440
+}
+
+// Running totals since start, bumped by the capture and uplink tasks. Readers keep
+// their own previous values to get rates, so more than one can sample them.
+#[derive(Clone, Default)]
+pub struct StatsCounters {
+    pub frames_captured: Arc<AtomicU64>,
+    pub frames_sent: Arc<AtomicU64>,
+    pub bytes_sent: Arc<AtomicU64>,
+    pub frames_dropped: Arc<AtomicU64>,
+}
+
+impl StatsCounters {
+    pub fn add(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> [u64; 4] {
+        [&self.frames_captured, &self.frames_sent, &self.bytes_sent, &self.frames_dropped]
+            .map(|counter| counter.load(Ordering::Relaxed))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// Per-minute health aggregates in a local SQLite file, for long-term reports without
+// any central infrastructure
+#[derive(Clone)]
+pub struct StatsDb {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl StatsDb {
+    fn open(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS minute_stats (
+                minute INTEGER PRIMARY KEY,
+                frames_captured INTEGER NOT NULL,
+                frames_sent INTEGER NOT NULL,
+                bytes_sent INTEGER NOT NULL,
+                frames_dropped INTEGER NOT NULL,
+                events INTEGER NOT NULL,
+                fps REAL NOT NULL,
+                uptime_seconds INTEGER NOT NULL
+            )",
+        )?;
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    pub fn query(&self, query: &StatsQueryCommand) -> serde_json::Value {
+        let connection = self.connection.lock().unwrap();
+        let result = connection
+            .prepare(
+                "SELECT minute, frames_captured, frames_sent, bytes_sent, frames_dropped, events, fps, uptime_seconds
+                 FROM minute_stats WHERE minute >= ?1 AND minute <= ?2 ORDER BY minute DESC LIMIT ?3",
+            )
+            .and_then(|mut statement| {
+                let rows = statement.query_map(
+                    params![
+                        query.from.unwrap_or(0) as i64,
+                        query.to.unwrap_or(i64::MAX as u64) as i64,
+                        query.limit as i64
+                    ],
+                    |row| {
+                        Ok(json!({
+                            "minute": row.get::<_, i64>(0)?,
+                            "frames_captured": row.get::<_, i64>(1)?,
+                            "frames_sent": row.get::<_, i64>(2)?,
+                            "bytes_sent": row.get::<_, i64>(3)?,
+                            "frames_dropped": row.get::<_, i64>(4)?,
+                            "events": row.get::<_, i64>(5)?,
+                            "fps": row.get::<_, f64>(6)?,
+                            "uptime_seconds": row.get::<_, i64>(7)?
+                        }))
+                    },
+                )?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            });
+        match result {
+            Ok(rows) => json!({ "stats_history": { "minutes": rows } }),
+            Err(e) => json!({ "stats_history": { "error": e.to_string() } }),
+        }
+    }
+
+    fn insert(&self, minute: u64, row: [u64; 5], fps: f64, uptime_seconds: u64, retention_days: u64) -> rusqlite::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO minute_stats VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![minute as i64, row[0] as i64, row[1] as i64, row[2] as i64, row[3] as i64, row[4] as i64, fps, uptime_seconds as i64],
+        )?;
+        let cutoff = minute.saturating_sub(retention_days * 24 * 60 * 60 * 1000);
+        connection.execute("DELETE FROM minute_stats WHERE minute < ?1", params![cutoff as i64])?;
+        Ok(())
+    }
+}
+
+// Writes one row per minute from the counters and the detector events
+pub fn spawn_stats_db(config: StatsDbConfig, counters: StatsCounters, events: &CameraEvents) -> Option<StatsDb> {
+    let db = match StatsDb::open(&config.path) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Statistics database disabled, failed to open {}: {}", config.path, e);
+            return None;
+        }
+    };
+    let writer = db.clone();
+    let mut event_rx = events.subscribe();
+
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let mut ticker = interval(Duration::from_secs(60));
+        // The first tick fires immediately
+        ticker.tick().await;
+        let mut events: u64 = 0;
+        let mut events_open = true;
+        let mut previous = counters.totals();
+
+        loop {
+            tokio::select! {
+                event = event_rx.recv(), if events_open => match event {
+                    Ok(_) => events += 1,
+                    Err(RecvError::Lagged(missed)) => events += missed,
+                    // Keep writing the other numbers
+                    Err(RecvError::Closed) => events_open = false,
+                },
+                _ = ticker.tick() => {
+                    let totals = counters.totals();
+                    let [captured, sent, bytes, dropped] = std::array::from_fn(|i| totals[i] - previous[i]);
+                    previous = totals;
+                    let row = [captured, sent, bytes, dropped, std::mem::take(&mut events)];
+                    let fps = row[0] as f64 / 60.0;
+                    let minute = now_ms() / 60_000 * 60_000;
+                    let uptime = started.elapsed().as_secs();
+                    let writer = writer.clone();
+                    let retention_days = config.retention_days;
+                    let result = tokio::task::spawn_blocking(move || writer.insert(minute, row, fps, uptime, retention_days)).await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => eprintln!("Failed to write statistics: {}", e),
+                        Err(e) => eprintln!("Statistics writer failed: {}", e),
+                    }
+                }
+            }
+        }
+    });
+
+    Some(db)
+}
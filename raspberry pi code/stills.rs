@@ -0,0 +1,148 @@
+use serde::Deserialize;
+use std::{process::Stdio, time::Duration};
+use tokio::{process::Command, time::sleep};
+
+use crate::capture_source::{CaptureSource, CaptureConfig};
+use crate::direct_capture;
+use crate::clock::TimeConfig;
+use crate::synthetic_capture;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StillsConfig {
+    // Upload URL; `{timestamp}` is replaced with the capture time in UNIX millis and
+    // `{local_time}` with the capture time in the configured timezone
+    pub endpoint: String,
+    // "put" or "post"
+    pub method: String,
+    pub interval_minutes: u64,
+    pub width: u32,
+    pub height: u32,
+    pub quality: u32,
+    // Only capture between these local hours, e.g. [6, 20]; wraps past midnight when start > end
+    pub active_hours: Option<[u32; 2]>,
+}
+
+impl Default for StillsConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            method: "put".to_string(),
+            interval_minutes: 10,
+            width: 1920,
+            height: 1080,
+            quality: 95,
+            active_hours: None,
+        }
+    }
+}
+
+impl StillsConfig {
+    fn is_active_hour(&self, hour: u32) -> bool {
+        match self.active_hours {
+            Some([start, end]) if start <= end => hour >= start && hour < end,
+            Some([start, end]) => hour >= start || hour < end,
+            None => true,
+        }
+    }
+}
+
+// Stills mode: no live stream, just one full-quality JPEG uploaded every interval
+pub async fn run_stills_mode(config: StillsConfig, capture: CaptureConfig, time: TimeConfig, camera_id: String) {
+    if config.endpoint.is_empty() {
+        eprintln!("Stills mode needs stills.endpoint in the config");
+        return;
+    }
+    println!("Stills mode: uploading a {}x{} still every {} minutes to {}",
+            config.width, config.height, config.interval_minutes, config.endpoint);
+
+    let client = reqwest::Client::new();
+    loop {
+        if !config.is_active_hour(time.local_hour()) {
+            sleep(Duration::from_secs(config.interval_minutes.max(1) * 60)).await;
+            continue;
+        }
+        
+        match capture_still(&capture, config.width, config.height, config.quality).await {
+            Ok(jpeg) => {
+                if let Err(e) = upload_still(&client, &config, &time, &camera_id, jpeg).await {
+                    eprintln!("Failed to upload still: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to capture still: {}", e),
+        }
+
+        sleep(Duration::from_secs(config.interval_minutes.max(1) * 60)).await;
+    }
+}
+
+// Capture a single frame with a one-shot pipeline
+pub async fn capture_still(capture: &CaptureConfig, width: u32, height: u32, quality: u32) -> Result<Vec<u8>, String> {
+    if capture.backend() == CaptureSource::Direct {
+        return direct_capture::capture_still(capture, width, height, quality).await;
+    }
+    if capture.backend() == CaptureSource::Synthetic {
+        return Ok(synthetic_capture::still(capture, width, height, quality));
+    }
+    let mut args = vec!["-q".to_string()];
+    args.extend(capture.source_args(Some(1), None));
+    args.extend([
+        "!".to_string(),
+        format!("video/x-raw,width={},height={}", width, height),
+        "!".to_string(),
+        "videoconvert".to_string(),
+        "!".to_string(),
+        "jpegenc".to_string(),
+        format!("quality={}", quality),
+        "!".to_string(),
+        "fdsink".to_string(),
+    ]);
+    let output = Command::new("gst-launch-1.0")
+        .args(&args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() || output.stdout.len() < 4 || output.stdout[..2] != [0xFF, 0xD8] {
+        return Err(format!("GStreamer exited with {} and {} bytes of output", output.status, output.stdout.len()));
+    }
+    Ok(output.stdout)
+}
+
+async fn upload_still(
+    client: &reqwest::Client,
+    config: &StillsConfig,
+    time: &TimeConfig,
+    camera_id: &str,
+    jpeg: Vec<u8>
+) -> Result<(), String> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let url = config.endpoint
+        .replace("{timestamp}", &timestamp.to_string())
+        .replace("{local_time}", &time.file_stamp());
+    let size = jpeg.len();
+
+    let request = if config.method.eq_ignore_ascii_case("post") {
+        client.post(&url)
+    } else {
+        client.put(&url)
+    };
+    let response = request
+        .header("Content-Type", "image/jpeg")
+        .header("X-Camera-Id", camera_id)
+        .body(jpeg)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("server returned {}", response.status()));
+    }
+    println!("Uploaded {} byte still to {}", size, url);
+    Ok(())
+}
@@ -0,0 +1,546 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::recording::RecordingConfig;
+use crate::wear;
+
+// Where finished recording segments end up. Segments are always recorded locally
+// first; the segment finisher hands them to the backend once they are complete.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StorageConfig {
+    // recording.directory on the local disk
+    #[default]
+    Local,
+    // An SMB/NFS share mounted by the OS. Nothing is written while it isn't mounted,
+    // so an outage doesn't fill the card through the empty mount point.
+    Mounted { path: String },
+    S3(S3Config),
+    Webdav(WebDavConfig),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    // e.g. https://s3.eu-west-1.amazonaws.com or a MinIO server; path-style addressing
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // Prepended to segment names, e.g. "front-door/"
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebDavConfig {
+    // Collection the segments go into, e.g. https://nas.local/remote.php/dav/files/cam/recordings
+    pub url: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+pub struct StoredObject {
+    pub name: String,
+    // When the object was last written, in UNIX millis
+    pub modified_ms: u64,
+    // Size as stored, encrypted or not
+    pub bytes: u64,
+}
+
+// Calls block; use them from spawn_blocking
+pub trait StorageBackend: Send + Sync {
+    fn describe(&self) -> String;
+    // Store a finished segment from the file at `source`, streamed rather than read into
+    // memory. A backend on the same filesystem may move the file into place instead.
+    // Backends that can keep `modified` do, others record the upload time, which is a
+    // little after the segment ended.
+    fn put(&self, name: &str, source: &Path, modified: SystemTime) -> Result<(), String>;
+    // Read as it arrives rather than held whole
+    fn get(&self, name: &str) -> Result<Box<dyn Read + Send>, String>;
+    fn delete(&self, name: &str) -> Result<(), String>;
+    fn list(&self) -> Result<Vec<StoredObject>, String>;
+    // Whether segments can stay where the pipeline recorded them
+    fn is_recording_directory(&self, _directory: &str) -> bool {
+        false
+    }
+    // Whether the segment is still in the spool, waiting to be stored
+    fn is_spooled(&self, _name: &str) -> bool {
+        false
+    }
+}
+
+pub fn open(recording: &RecordingConfig) -> Arc<dyn StorageBackend> {
+    let backend = open_backend(recording);
+    if backend.is_recording_directory(recording.write_directory()) {
+        return backend;
+    }
+    Arc::new(Spooled { backend, spool: PathBuf::from(recording.write_directory()) })
+}
+
+fn open_backend(recording: &RecordingConfig) -> Arc<dyn StorageBackend> {
+    match &recording.storage {
+        StorageConfig::Local => Arc::new(LocalStorage {
+            directory: PathBuf::from(&recording.directory),
+            direct_io: recording.wear.direct_io,
+            require_mount: false,
+        }),
+        StorageConfig::Mounted { path } => Arc::new(LocalStorage {
+            directory: PathBuf::from(path),
+            direct_io: false,
+            require_mount: true,
+        }),
+        StorageConfig::S3(config) => Arc::new(S3Storage { config: config.clone() }),
+        StorageConfig::Webdav(config) => Arc::new(WebDavStorage { config: config.clone() }),
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+struct LocalStorage {
+    directory: PathBuf,
+    direct_io: bool,
+    require_mount: bool,
+}
+
+#[cfg(unix)]
+fn same_device(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev()
+}
+
+// No device numbers to compare; a share that is there counts as mounted
+#[cfg(not(unix))]
+fn same_device(_a: &std::fs::Metadata, _b: &std::fs::Metadata) -> bool {
+    false
+}
+
+impl LocalStorage {
+    // A mount point lives on a different device than its parent directory
+    fn check_mounted(&self) -> Result<(), String> {
+        if !self.require_mount {
+            return Ok(());
+        }
+        let parent = self.directory.parent().unwrap_or(Path::new("/"));
+        let (Ok(share), Ok(parent)) = (std::fs::metadata(&self.directory), std::fs::metadata(parent)) else {
+            return Err(format!("{} is not available", self.directory.display()));
+        };
+        if same_device(&share, &parent) {
+            return Err(format!("nothing is mounted at {}", self.directory.display()));
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn describe(&self) -> String {
+        self.directory.display().to_string()
+    }
+
+    fn put(&self, name: &str, source: &Path, modified: SystemTime) -> Result<(), String> {
+        self.check_mounted()?;
+        let target = self.directory.join(name);
+        // Only a rename within one filesystem; from a tmpfs staging directory or onto a
+        // share it is one sequential copy
+        if std::fs::rename(source, &target).is_ok() {
+            return File::options().write(true).open(&target).and_then(|file| file.set_modified(modified)).map_err(|e| e.to_string());
+        }
+        wear::copy_file(source, &target, self.direct_io, modified).map_err(|e| e.to_string())
+    }
+
+    fn get(&self, name: &str) -> Result<Box<dyn Read + Send>, String> {
+        let file = File::open(self.directory.join(name)).map_err(|e| format!("{}: {}", name, e))?;
+        Ok(Box::new(file))
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        self.check_mounted()?;
+        std::fs::remove_file(self.directory.join(name)).map_err(|e| format!("{}: {}", name, e))
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        self.check_mounted()?;
+        let mut objects = Vec::new();
+        for entry in std::fs::read_dir(&self.directory).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let Some(name) = entry.file_name().to_str().map(String::from) else { continue };
+            let metadata = entry.metadata().map_err(|e| e.to_string())?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified_ms = metadata.modified().map(millis).unwrap_or(0);
+            objects.push(StoredObject { name, modified_ms, bytes: metadata.len() });
+        }
+        Ok(objects)
+    }
+
+    fn is_recording_directory(&self, directory: &str) -> bool {
+        !self.require_mount && self.directory == Path::new(directory)
+    }
+}
+
+// RFC 3986 encoding; S3 signatures depend on exactly this
+fn encode(text: &str, keep_slash: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b'/' if keep_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Contents of every <name> element, whatever namespace prefix the server uses.
+// S3 and WebDAV responses are simple enough not to need a real XML parser.
+fn xml_values<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find('>') else { break };
+        let tag = &rest[..close];
+        let local = tag.split_whitespace().next().unwrap_or("");
+        let local = local.rsplit(':').next().unwrap_or(local);
+        if local != name || tag.starts_with('/') || tag.ends_with('/') {
+            continue;
+        }
+        let body = &rest[close + 1..];
+        let end = body
+            .match_indices("</")
+            .find(|(at, _)| {
+                let closing = &body[at + 2..];
+                let closing = &closing[..closing.find('>').unwrap_or(closing.len())];
+                closing.rsplit(':').next() == Some(name)
+            })
+            .map(|(at, _)| at);
+        let Some(end) = end else { break };
+        values.push(&body[..end]);
+        rest = &body[end..];
+    }
+    values
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+struct S3Storage {
+    config: S3Config,
+}
+
+impl S3Storage {
+    // Signed with AWS Signature Version 4. A client per call: blocking clients can't be
+    // dropped on the async runtime, and segments are only stored every few minutes.
+    // With a body, it is streamed from the file; the signature needs its hash, which is
+    // worked out by reading it once beforehand
+    fn request(&self, method: reqwest::Method, key: &str, query: &[(&str, &str)], body: Option<&Path>) -> Result<reqwest::blocking::Response, String> {
+        let endpoint = url::Url::parse(&self.config.endpoint).map_err(|e| e.to_string())?;
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err("S3 endpoint has no host".to_string()),
+        };
+        let path = format!("/{}/{}", self.config.bucket, encode(key, true));
+        let mut query: Vec<(String, String)> = query.iter().map(|(k, v)| (encode(k, false), encode(v, false))).collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let (payload_hash, body) = match body {
+            Some(path) => {
+                let mut hasher = Sha256::new();
+                File::open(path).and_then(|mut file| std::io::copy(&mut file, &mut hasher)).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                (hex(&hasher.finalize()), reqwest::blocking::Body::from(file))
+            }
+            None => (hex(&Sha256::digest(b"")), reqwest::blocking::Body::from(Vec::new())),
+        };
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, query, host, payload_hash, amz_date, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&Sha256::digest(canonical.as_bytes())));
+        let mut key = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), &date);
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part);
+        }
+        let signature = hex(&hmac(&key, &to_sign));
+
+        let url = format!("{}{}{}{}", self.config.endpoint.trim_end_matches('/'), path, if query.is_empty() { "" } else { "?" }, query);
+        let response = reqwest::blocking::Client::new()
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                    self.config.access_key, scope, signature
+                ),
+            )
+            .body(body)
+            .send()
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("S3 returned {}", response.status()));
+        }
+        Ok(response)
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.config.bucket, self.config.prefix)
+    }
+
+    fn put(&self, name: &str, source: &Path, _modified: SystemTime) -> Result<(), String> {
+        self.request(reqwest::Method::PUT, &format!("{}{}", self.config.prefix, name), &[], Some(source))?;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Box<dyn Read + Send>, String> {
+        let response = self.request(reqwest::Method::GET, &format!("{}{}", self.config.prefix, name), &[], None)?;
+        Ok(Box::new(response))
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        self.request(reqwest::Method::DELETE, &format!("{}{}", self.config.prefix, name), &[], None)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.config.prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let xml = self.request(reqwest::Method::GET, "", &query, None)?.text().map_err(|e| e.to_string())?;
+            for entry in xml_values(&xml, "Contents") {
+                let (Some(key), Some(modified)) = (xml_values(entry, "Key").first().copied(), xml_values(entry, "LastModified").first().copied()) else {
+                    continue;
+                };
+                let Some(name) = key.strip_prefix(self.config.prefix.as_str()) else { continue };
+                let modified_ms = DateTime::parse_from_rfc3339(modified).map(|t| t.timestamp_millis() as u64).unwrap_or(0);
+                let bytes = xml_values(entry, "Size").first().and_then(|size| size.parse().ok()).unwrap_or(0);
+                objects.push(StoredObject { name: name.to_string(), modified_ms, bytes });
+            }
+            token = match xml_values(&xml, "IsTruncated").first() {
+                Some(&"true") => xml_values(&xml, "NextContinuationToken").first().map(|t| t.to_string()),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+}
+
+struct WebDavStorage {
+    config: WebDavConfig,
+}
+
+impl WebDavStorage {
+    fn request(&self, method: &str, name: &str) -> Result<reqwest::blocking::RequestBuilder, String> {
+        self.request_url(method, format!("{}/{}", self.config.url.trim_end_matches('/'), encode(name, false)))
+    }
+
+    fn request_url(&self, method: &str, url: String) -> Result<reqwest::blocking::RequestBuilder, String> {
+        let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+        let mut builder = reqwest::blocking::Client::new().request(method, url);
+        if !self.config.username.is_empty() {
+            builder = builder.basic_auth(&self.config.username, Some(&self.config.password));
+        }
+        Ok(builder)
+    }
+
+    // MKCOL only creates one level, so the collection's missing parents are created first,
+    // from the top down. Levels that exist answer 405, and ones above the account's own
+    // files may refuse; only the collection itself has to work out.
+    fn make_collections(&self) -> Result<(), String> {
+        let url = url::Url::parse(&self.config.url).map_err(|e| e.to_string())?;
+        let levels: Vec<&str> = url.path_segments().map(|segments| segments.filter(|s| !s.is_empty()).collect()).unwrap_or_default();
+        for depth in 1..=levels.len() {
+            let mut level = url.clone();
+            level.set_path(&format!("/{}/", levels[..depth].join("/")));
+            let status = self.request_url("MKCOL", level.to_string())?.send().map_err(|e| e.to_string())?.status();
+            if depth == levels.len() && !status.is_success() && status != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+                return Err(format!("WebDAV server refused to create {}: {}", level, status));
+            }
+        }
+        Ok(())
+    }
+
+    fn send(builder: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response, String> {
+        let response = builder.send().map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("WebDAV server returned {}", response.status()));
+        }
+        Ok(response)
+    }
+}
+
+impl StorageBackend for WebDavStorage {
+    fn describe(&self) -> String {
+        self.config.url.clone()
+    }
+
+    fn put(&self, name: &str, source: &Path, _modified: SystemTime) -> Result<(), String> {
+        let body = || File::open(source).map(reqwest::blocking::Body::from).map_err(|e| format!("{}: {}", source.display(), e));
+        let response = self.request("PUT", name)?.body(body()?).send().map_err(|e| e.to_string())?;
+        // The collection may not exist yet; create it and try once more
+        if response.status() == reqwest::StatusCode::CONFLICT || response.status() == reqwest::StatusCode::NOT_FOUND {
+            self.make_collections()?;
+            Self::send(self.request("PUT", name)?.body(body()?))?;
+        } else if !response.status().is_success() {
+            return Err(format!("WebDAV server returned {}", response.status()));
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Box<dyn Read + Send>, String> {
+        Ok(Box::new(Self::send(self.request("GET", name)?)?))
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        Self::send(self.request("DELETE", name)?)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let body = r#"<?xml version="1.0"?><d:propfind xmlns:d="DAV:"><d:prop><d:getlastmodified/><d:getcontentlength/><d:resourcetype/></d:prop></d:propfind>"#;
+        let xml = Self::send(self.request("PROPFIND", "")?.header("Depth", "1").header("Content-Type", "application/xml").body(body))?
+            .text()
+            .map_err(|e| e.to_string())?;
+
+        let mut objects = Vec::new();
+        for entry in xml_values(&xml, "response") {
+            // Skips the collection itself and any subdirectories
+            if entry.contains("collection/>") || entry.contains("collection />") {
+                continue;
+            }
+            let (Some(href), Some(modified)) = (xml_values(entry, "href").first().copied(), xml_values(entry, "getlastmodified").first().copied()) else {
+                continue;
+            };
+            let name = decode(href.trim_end_matches('/').rsplit('/').next().unwrap_or(""));
+            let modified_ms = DateTime::parse_from_rfc2822(modified).map(|t| t.timestamp_millis() as u64).unwrap_or(0);
+            let bytes = xml_values(entry, "getcontentlength").first().and_then(|size| size.parse().ok()).unwrap_or(0);
+            objects.push(StoredObject { name, modified_ms, bytes });
+        }
+        Ok(objects)
+    }
+}
+
+// Segments the finisher hasn't stored yet, e.g. while S3 is unreachable, stay where they
+// were recorded. Listing and reading go through that spool too, so exports, retention and
+// disk space pruning see footage that is still waiting for the backend.
+struct Spooled {
+    backend: Arc<dyn StorageBackend>,
+    spool: PathBuf,
+}
+
+impl Spooled {
+    fn spooled(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = Vec::new();
+        for entry in std::fs::read_dir(&self.spool).map_err(|e| format!("{}: {}", self.spool.display(), e))? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let Some(name) = entry.file_name().to_str().map(String::from) else { continue };
+            if Path::new(&name).extension().and_then(|e| e.to_str()) != Some("ts") {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else { continue };
+            let modified_ms = metadata.modified().map(millis).unwrap_or(0);
+            objects.push(StoredObject { name, modified_ms, bytes: metadata.len() });
+        }
+        Ok(objects)
+    }
+}
+
+impl StorageBackend for Spooled {
+    fn describe(&self) -> String {
+        self.backend.describe()
+    }
+
+    fn put(&self, name: &str, source: &Path, modified: SystemTime) -> Result<(), String> {
+        self.backend.put(name, source, modified)
+    }
+
+    fn get(&self, name: &str) -> Result<Box<dyn Read + Send>, String> {
+        match File::open(self.spool.join(name)) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(_) => self.backend.get(name),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        match std::fs::remove_file(self.spool.join(name)) {
+            Ok(()) => Ok(()),
+            Err(_) => self.backend.delete(name),
+        }
+    }
+
+    // What's in the spool still counts when the backend can't be reached
+    fn list(&self) -> Result<Vec<StoredObject>, String> {
+        let mut objects = self.spooled()?;
+        match self.backend.list() {
+            Ok(stored) => objects.extend(stored),
+            Err(e) if !objects.is_empty() => eprintln!("Listing only spooled segments, {} failed: {}", self.backend.describe(), e),
+            Err(e) => return Err(e),
+        }
+        Ok(objects)
+    }
+
+    fn is_recording_directory(&self, directory: &str) -> bool {
+        self.backend.is_recording_directory(directory)
+    }
+
+    fn is_spooled(&self, name: &str) -> bool {
+        self.spool.join(name).is_file()
+    }
+}
@@ -0,0 +1,37 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// {"command": "stream_priorities", "priorities": {"main": 10, "door": 5}}: replaces the
+// previous assignment. Higher goes first; streams left out rank below every named one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StreamPrioritiesCommand {
+    pub priorities: HashMap<String, u32>,
+}
+
+// How the server ranks the streams sharing this uplink (the camera itself and its
+// virtual views) for when there isn't room for all of them
+#[derive(Clone, Default)]
+pub struct StreamPriorities {
+    priorities: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl StreamPriorities {
+    // Returns what the audit log records as the outcome
+    pub fn apply(&self, command: &StreamPrioritiesCommand) -> String {
+        let mut ranked: Vec<_> = command.priorities.iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let order: Vec<String> = ranked.iter().map(|(stream, priority)| format!("{} {}", stream, priority)).collect();
+        println!("Stream priorities set: {}", if order.is_empty() { "none".to_string() } else { order.join(", ") });
+        *self.priorities.lock().unwrap() = command.priorities.clone();
+        "applied".to_string()
+    }
+
+    // None until the server assigns any, when every stream is treated the same
+    pub fn snapshot(&self) -> Option<HashMap<String, u32>> {
+        let priorities = self.priorities.lock().unwrap();
+        (!priorities.is_empty()).then(|| priorities.clone())
+    }
+}
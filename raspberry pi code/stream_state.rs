@@ -0,0 +1,179 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+// Lifecycle of the uplink connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    Idle,
+    Connecting,
+    Joined,
+    Streaming,
+    Degraded,
+    // Connected, but the server paused the uplink because nobody is watching
+    Paused,
+    Reconnecting,
+    // The server handed our camera id to a newer instance; this one no longer uploads
+    Replaced,
+}
+
+// What the old instance does once a newer one has taken over its camera id
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplacedAction {
+    // Drop the uplink but keep capturing, so local recording and outputs carry on
+    #[default]
+    KeepRecording,
+    // Shut down cleanly, with exit status 0 so a service manager doesn't restart it
+    Exit,
+}
+
+impl ReplacedAction {
+    // Whether a {"session_replaced": ...} notice is about this process. The camera id is
+    // the enrolled device's, the same for every instance on that device, so the session
+    // each instance joins with is what tells them apart; a notice naming some other
+    // session isn't about us, and one naming none is for whoever gets it.
+    pub fn is_for(notice: &serde_json::Value, session: &str) -> bool {
+        notice.get("session").and_then(|s| s.as_str()).is_none_or(|s| s == session)
+    }
+
+    // The notice in a server message, if it says this session was replaced
+    pub fn notice<'a>(message: &'a serde_json::Value, session: &str) -> Option<&'a serde_json::Value> {
+        message.get("session_replaced").filter(|notice| Self::is_for(notice, session))
+    }
+}
+
+impl StreamState {
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamState::Idle => "idle",
+            StreamState::Connecting => "connecting",
+            StreamState::Joined => "joined",
+            StreamState::Streaming => "streaming",
+            StreamState::Degraded => "degraded",
+            StreamState::Paused => "paused",
+            StreamState::Reconnecting => "reconnecting",
+            StreamState::Replaced => "replaced",
+        }
+    }
+
+    fn can_move_to(self, to: StreamState) -> bool {
+        use StreamState::*;
+        matches!(
+            (self, to),
+            (_, Idle)
+                | (Idle, Connecting)
+                | (Reconnecting, Connecting)
+                | (Connecting, Joined)
+                | (Joined, Streaming)
+                | (Streaming, Degraded)
+                | (Degraded, Streaming)
+                | (Joined | Streaming | Degraded, Paused)
+                | (Paused, Streaming)
+                | (Connecting | Joined | Streaming | Degraded | Paused, Reconnecting)
+                | (Joined | Streaming | Degraded | Paused, Replaced)
+        )
+    }
+
+    // States in which frames can reach the server
+    pub fn is_connected(self) -> bool {
+        matches!(self, StreamState::Joined | StreamState::Streaming | StreamState::Degraded | StreamState::Paused)
+    }
+}
+
+// Current state shared between the uplink task and anything observing it
+#[derive(Clone)]
+pub struct StreamStatus {
+    inner: Arc<Mutex<(StreamState, Instant)>>,
+    reconnects: Arc<AtomicU64>,
+}
+
+impl StreamStatus {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new((StreamState::Idle, Instant::now()))),
+            reconnects: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn get(&self) -> StreamState {
+        self.inner.lock().unwrap().0
+    }
+
+    // Current state and how long we've been in it
+    pub fn snapshot(&self) -> (StreamState, std::time::Duration) {
+        let inner = self.inner.lock().unwrap();
+        (inner.0, inner.1.elapsed())
+    }
+
+    // Times the uplink has lost its connection since start
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    // Move to a new state. Returns the previous state if anything changed.
+    pub fn transition(&self, to: StreamState) -> Option<StreamState> {
+        let mut inner = self.inner.lock().unwrap();
+        let from = inner.0;
+        if from == to {
+            return None;
+        }
+        if !from.can_move_to(to) {
+            eprintln!("Unexpected stream state transition {} -> {}", from.name(), to.name());
+        }
+        println!("Stream state: {} -> {}", from.name(), to.name());
+        *inner = (to, Instant::now());
+        if to == StreamState::Reconnecting {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DeviceIdentity;
+    use serde_json::json;
+
+    fn key_path() -> String {
+        std::env::temp_dir().join(format!("camera-{}.key", uuid::Uuid::new_v4())).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn instances_on_one_device_share_its_camera_id() {
+        let path = key_path();
+        let first = DeviceIdentity::load_or_generate(&path).unwrap();
+        let second = DeviceIdentity::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(first.camera_id(), second.camera_id());
+    }
+
+    #[test]
+    fn different_devices_have_different_camera_ids() {
+        let paths = [key_path(), key_path()];
+        let ids = paths.each_ref().map(|path| DeviceIdentity::load_or_generate(path).unwrap().camera_id());
+        paths.iter().for_each(|path| { let _ = std::fs::remove_file(path); });
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn replaced_message_only_stops_the_older_session() {
+        let message = json!({ "session_replaced": { "session": "session-1", "by": "session-2" } });
+        assert_eq!(ReplacedAction::notice(&message, "session-1"), Some(&message["session_replaced"]));
+        assert_eq!(ReplacedAction::notice(&message, "session-2"), None);
+        assert_eq!(ReplacedAction::notice(&json!({ "command": "snapshot" }), "session-1"), None);
+    }
+
+    #[test]
+    fn notice_without_a_session_applies() {
+        assert!(ReplacedAction::notice(&json!({ "session_replaced": {} }), "session-1").is_some());
+        assert!(ReplacedAction::is_for(&json!({}), "session-1"));
+        assert!(!ReplacedAction::is_for(&json!({ "session": "session-2" }), "session-1"));
+    }
+}
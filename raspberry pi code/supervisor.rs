@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use tokio::{
+    task::{Id, JoinSet},
+    time::sleep,
+};
+
+// A restartable task is rebuilt from its factory each time it ends
+type TaskFactory = Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+struct TaskSpec {
+    name: &'static str,
+    // None for essential tasks, which are never restarted
+    factory: Option<TaskFactory>,
+    restarts: Vec<Instant>,
+}
+
+// Restart limit for restartable tasks before we give up on them
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+// Owns the long-running tasks so that none of them can die unnoticed
+pub struct Supervisor {
+    tasks: JoinSet<()>,
+    specs: HashMap<Id, TaskSpec>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            specs: HashMap::new(),
+        }
+    }
+
+    // A task the process can't work without; when it ends the process shuts down
+    pub fn spawn_essential<F>(&mut self, name: &'static str, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.tasks.spawn(future).id();
+        self.specs.insert(id, TaskSpec { name, factory: None, restarts: Vec::new() });
+    }
+
+    // A task that is restarted (within limits) whenever it ends or panics
+    pub fn spawn_restartable<F, Fut>(&mut self, name: &'static str, factory: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let factory: TaskFactory = Box::new(move || Box::pin(factory()));
+        self.start(TaskSpec { name, factory: Some(factory), restarts: Vec::new() });
+    }
+
+    fn start(&mut self, spec: TaskSpec) {
+        let future = (spec.factory.as_ref().expect("Only restartable tasks can be started again"))();
+        let id = self.tasks.spawn(future).id();
+        self.specs.insert(id, spec);
+    }
+
+    // Watch the tasks until one the process can't run without is gone; returns its name
+    pub async fn run(&mut self) -> &'static str {
+        loop {
+            let Some(result) = self.tasks.join_next_with_id().await else {
+                return "all tasks";
+            };
+            let (id, outcome) = match result {
+                Ok((id, ())) => (id, "finished"),
+                Err(e) if e.is_panic() => (e.id(), "panicked"),
+                Err(e) => (e.id(), "was cancelled"),
+            };
+            let Some(mut spec) = self.specs.remove(&id) else {
+                continue;
+            };
+            eprintln!("Task {} {}", spec.name, outcome);
+
+            if spec.factory.is_none() {
+                return spec.name;
+            }
+
+            let now = Instant::now();
+            spec.restarts.retain(|started| now.duration_since(*started) < RESTART_WINDOW);
+            if spec.restarts.len() >= MAX_RESTARTS {
+                eprintln!("Task {} restarted {} times in {:?}, giving up", spec.name, MAX_RESTARTS, RESTART_WINDOW);
+                return spec.name;
+            }
+            spec.restarts.push(now);
+
+            sleep(Duration::from_secs(1)).await;
+            println!("Restarting task {}", spec.name);
+            self.start(spec);
+        }
+    }
+
+    // Abort whatever is still running and wait for it to unwind
+    pub async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+    }
+}
+
+// Make sure panics in any task or thread end up in the log with their location
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        eprintln!("Panic in thread {}: {}", thread.name().unwrap_or("unnamed"), info);
+        default_hook(info);
+    }));
+}
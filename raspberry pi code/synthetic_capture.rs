@@ -0,0 +1,268 @@
+use jpeg_encoder::{ColorType, Encoder};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, time::Duration};
+use tokio::process::Command;
+
+use crate::capture_source::CaptureConfig;
+use crate::config;
+
+// Generated JPEGs instead of a camera, written to stdout by a child process the way the
+// capture pipeline writes them, but with the cuts between reads and the damage chosen
+// here. Everything is seeded, so a run can be repeated exactly to see what the frame
+// extraction, concealment and uplink make of the same broken stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyntheticCaptureConfig {
+    pub frame_rate: u32,
+    // Sizes of the writes the stream is cut into, used in turn; small and odd ones put
+    // frame boundaries and markers across reads. Empty writes each frame whole.
+    pub write_sizes: Vec<usize>,
+    // Every nth frame stops short of its end, straight into the next one (0 for never)
+    pub truncate_every: u32,
+    // Every nth frame has part of its scan overwritten with random bytes
+    pub corrupt_every: u32,
+    // Every nth frame is followed by stray bytes that belong to no frame
+    pub garbage_every: u32,
+    pub seed: u32,
+    // Stop after this many frames; 0 runs until stopped
+    pub frames: u32,
+}
+
+impl Default for SyntheticCaptureConfig {
+    fn default() -> Self {
+        Self {
+            frame_rate: 15,
+            write_sizes: Vec::new(),
+            truncate_every: 0,
+            corrupt_every: 0,
+            garbage_every: 0,
+            seed: 1,
+            frames: 0,
+        }
+    }
+}
+
+// xorshift32; the same seed always gives the same stream
+struct Random(u32);
+
+impl Random {
+    // xorshift stays at zero forever from a zero state, so that seed gets a fixed odd one
+    fn new(seed: u32) -> Self {
+        Random(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn bytes(&mut self, count: usize) -> Vec<u8> {
+        (0..count).map(|_| (self.next() >> 24) as u8).collect()
+    }
+}
+
+// A gradient with a bar sweeping across it, so motion and scene changes have something
+// to see, and a little seeded noise so frames don't compress identically
+fn frame(number: u32, width: u16, height: u16, quality: u8, random: &mut Random) -> Vec<u8> {
+    let (width_px, height_px) = (width as usize, height as usize);
+    let bar = (number as usize * 8) % width_px.max(1);
+    let mut pixels = Vec::with_capacity(width_px * height_px * 3);
+    for y in 0..height_px {
+        for x in 0..width_px {
+            let noise = (random.next() >> 29) as u8;
+            if x.abs_diff(bar) < width_px / 32 + 1 {
+                pixels.extend_from_slice(&[240, 240, 240]);
+            } else {
+                pixels.extend_from_slice(&[(x * 255 / width_px) as u8 ^ noise, (y * 255 / height_px) as u8, 96 + noise]);
+            }
+        }
+    }
+    let mut jpeg = Vec::new();
+    let mut encoder = Encoder::new(&mut jpeg, quality);
+    // Restart markers, as the concealment needs them to repair anything
+    encoder.set_restart_interval(8);
+    if let Err(e) = encoder.encode(&pixels, width, height, ColorType::Rgb) {
+        eprintln!("Synthetic capture: failed to encode frame {}: {}", number, e);
+    }
+    jpeg
+}
+
+fn every(n: u32, number: u32) -> bool {
+    n > 0 && number.is_multiple_of(n)
+}
+
+// This binary again, as the capture child
+pub fn command(capture: &CaptureConfig, width: u32, height: u32, quality: u32) -> Command {
+    let program = std::env::current_exe().unwrap_or_else(|_| "rust_stream".into());
+    let mut command = Command::new(program);
+    command.args([
+        "--synthetic-capture".to_string(),
+        serde_json::to_string(&capture.synthetic).unwrap_or_default(),
+        "--width".to_string(),
+        width.to_string(),
+        "--height".to_string(),
+        height.to_string(),
+        "--quality".to_string(),
+        quality.to_string(),
+    ]);
+    command
+}
+
+// The generated stream, a frame at a time, each as the writes it is cut into
+pub struct SyntheticStream {
+    config: SyntheticCaptureConfig,
+    width: u16,
+    height: u16,
+    quality: u8,
+    random: Random,
+    // Position in config.write_sizes, which carries on across frames
+    next_size: usize,
+    number: u32,
+}
+
+impl SyntheticStream {
+    pub fn new(config: SyntheticCaptureConfig, width: u32, height: u32, quality: u32) -> Self {
+        let random = Random::new(config.seed);
+        let (width, height) = (width.clamp(16, 4096) as u16, height.clamp(16, 4096) as u16);
+        Self { config, width, height, quality: quality.clamp(1, 100) as u8, random, next_size: 0, number: 1 }
+    }
+}
+
+// Each fault is reported on stderr with the frame number, so it can be matched against
+// what came out the other end
+impl Iterator for SyntheticStream {
+    type Item = Vec<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Vec<Vec<u8>>> {
+        let (config, number) = (&self.config, self.number);
+        if config.frames != 0 && number > config.frames {
+            return None;
+        }
+        let mut data = frame(number, self.width, self.height, self.quality, &mut self.random);
+        if every(config.corrupt_every, number) && data.len() > 1024 {
+            let start = data.len() / 2;
+            let damage = self.random.bytes(64);
+            data[start..start + 64].copy_from_slice(&damage);
+            eprintln!("Synthetic capture: frame {} corrupted", number);
+        }
+        if every(config.truncate_every, number) {
+            data.truncate(data.len() * 3 / 5);
+            eprintln!("Synthetic capture: frame {} truncated to {} bytes", number, data.len());
+        }
+        if every(config.garbage_every, number) {
+            let count = 1 + (self.random.next() % 200) as usize;
+            data.extend(self.random.bytes(count));
+            eprintln!("Synthetic capture: frame {} followed by {} stray bytes", number, count);
+        }
+
+        let sizes: Vec<usize> = config.write_sizes.iter().copied().filter(|size| *size > 0).collect();
+        let mut writes = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let size = match sizes.is_empty() {
+                true => rest.len(),
+                false => sizes[self.next_size % sizes.len()].min(rest.len()),
+            };
+            self.next_size += 1;
+            writes.push(rest[..size].to_vec());
+            rest = &rest[size..];
+        }
+        self.number += 1;
+        Some(writes)
+    }
+}
+
+// --synthetic-capture: the child side
+pub fn run_child() -> i32 {
+    let config: SyntheticCaptureConfig = config::flag_value("--synthetic-capture")
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    let number = |flag: &str, default: u32| config::flag_value(flag).and_then(|v| v.parse().ok()).unwrap_or(default);
+    let (width, height, quality) = (number("--width", 640), number("--height", 360), number("--quality", 70));
+
+    let mut stdout = std::io::stdout().lock();
+    let period = Duration::from_secs_f64(1.0 / config.frame_rate.max(1) as f64);
+    for writes in SyntheticStream::new(config, width, height, quality) {
+        for write in writes {
+            // The parent went away
+            if stdout.write_all(&write).and_then(|_| stdout.flush()).is_err() {
+                return 0;
+            }
+        }
+        std::thread::sleep(period);
+    }
+    0
+}
+
+// One frame, for stills and the self-test
+pub fn still(capture: &CaptureConfig, width: u32, height: u32, quality: u32) -> Vec<u8> {
+    let mut random = Random::new(capture.synthetic.seed);
+    frame(1, width.clamp(16, 4096) as u16, height.clamp(16, 4096) as u16, quality.clamp(1, 100) as u8, &mut random)
+}
+
+// The stream above served in-process, one write per read as a pipe would deliver it,
+// for tests of the frame extraction
+#[cfg(test)]
+pub struct SyntheticBackend {
+    writes: Option<std::collections::VecDeque<Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl SyntheticBackend {
+    // `config.frames` has to be set, the whole stream is generated up front
+    pub fn new(config: SyntheticCaptureConfig, width: u32, height: u32, quality: u32) -> Self {
+        let writes = SyntheticStream::new(config, width, height, quality).flatten().collect();
+        Self { writes: Some(writes) }
+    }
+}
+
+#[cfg(test)]
+impl crate::capture_source::CaptureBackend for SyntheticBackend {
+    type Output = SyntheticOutput;
+
+    fn take_output(&mut self) -> Option<SyntheticOutput> {
+        self.writes.take().map(SyntheticOutput)
+    }
+}
+
+#[cfg(test)]
+pub struct SyntheticOutput(std::collections::VecDeque<Vec<u8>>);
+
+#[cfg(test)]
+impl tokio::io::AsyncRead for SyntheticOutput {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if let Some(mut write) = self.0.pop_front() {
+            let read = write.len().min(buf.remaining());
+            buf.put_slice(&write[..read]);
+            if read < write.len() {
+                self.0.push_front(write.split_off(read));
+            }
+        }
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_seed_still_gives_random_bytes() {
+        let mut random = Random::new(0);
+        assert!((0..4).map(|_| random.next()).all(|value| value != 0));
+    }
+
+    #[test]
+    fn same_seed_gives_same_stream() {
+        let config = SyntheticCaptureConfig { frames: 3, corrupt_every: 2, garbage_every: 3, ..Default::default() };
+        let first: Vec<_> = SyntheticStream::new(config.clone(), 64, 48, 70).collect();
+        let second: Vec<_> = SyntheticStream::new(config, 64, 48, 70).collect();
+        assert_eq!(first, second);
+    }
+}
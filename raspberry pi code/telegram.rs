@@ -0,0 +1,256 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::broadcast::error::RecvError, time::sleep};
+
+use crate::alarm::{AlarmHandle, AlarmMode};
+use crate::events::CameraEvents;
+use crate::snapshot::LatestFrame;
+use crate::stream_state::StreamStatus;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    // The only chat the bot talks to and accepts commands from
+    pub chat_id: i64,
+    // Whether event alerts are sent when the camera starts up (without an alarm configured)
+    pub armed: bool,
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            bot_token: String::new(),
+            chat_id: 0,
+            armed: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Updates {
+    result: Vec<Update>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<ChatMessage>,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Clone)]
+struct Bot {
+    client: reqwest::Client,
+    config: TelegramConfig,
+}
+
+impl Bot {
+    fn url(&self, method: &str) -> String {
+        format!("https://api.telegram.org/bot{}/{}", self.config.bot_token, method)
+    }
+
+    async fn send_text(&self, text: &str) -> Result<(), String> {
+        self.client
+            .post(self.url("sendMessage"))
+            .json(&json!({ "chat_id": self.config.chat_id, "text": text }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn send_photo(&self, jpeg: Vec<u8>, caption: &str) -> Result<(), String> {
+        let photo = reqwest::multipart::Part::bytes(jpeg)
+            .file_name("snapshot.jpg")
+            .mime_str("image/jpeg")
+            .map_err(|e| e.to_string())?;
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", self.config.chat_id.to_string())
+            .text("caption", caption.to_string())
+            .part("photo", photo);
+        self.client
+            .post(self.url("sendPhoto"))
+            .multipart(form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn send_audio_clip(&self, wav: &[u8]) -> Result<(), String> {
+        let document = reqwest::multipart::Part::bytes(wav.to_vec())
+            .file_name("sound.wav")
+            .mime_str("audio/wav")
+            .map_err(|e| e.to_string())?;
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", self.config.chat_id.to_string())
+            .part("document", document);
+        self.client
+            .post(self.url("sendDocument"))
+            .multipart(form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    // Snapshot with a caption, or just the caption when there is no frame yet
+    async fn send_snapshot(&self, latest_frame: &LatestFrame, caption: &str) -> Result<(), String> {
+        match latest_frame.get() {
+            Some(jpeg) => self.send_photo(jpeg, caption).await,
+            None => self.send_text(&format!("{} (no snapshot available)", caption)).await,
+        }
+    }
+
+    async fn get_updates(&self, offset: i64) -> Result<Vec<Update>, String> {
+        let updates: Updates = self.client
+            .get(self.url("getUpdates"))
+            .query(&[("offset", offset.to_string()), ("timeout", "30".to_string())])
+            .timeout(Duration::from_secs(40))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(updates.result)
+    }
+}
+
+// A report followed by its images, each captioned, e.g. the daily digest
+pub async fn send_report(config: &TelegramConfig, text: &str, images: &[(String, Vec<u8>)]) -> Result<(), String> {
+    let bot = Bot { client: reqwest::Client::new(), config: config.clone() };
+    bot.send_text(text).await?;
+    for (caption, jpeg) in images {
+        bot.send_photo(jpeg.clone(), caption).await?;
+    }
+    Ok(())
+}
+
+// Forward events to the chat while armed and answer /snapshot, /arm, /disarm and /status.
+// With an alarm configured, /arm and /disarm control the alarm (which already filters the
+// events) instead of just muting the bot.
+pub fn spawn_telegram_bot(
+    config: TelegramConfig,
+    camera_id: String,
+    events: &CameraEvents,
+    latest_frame: LatestFrame,
+    status: StreamStatus,
+    alarm: Option<AlarmHandle>
+) {
+    // The alarm does its own filtering, so the bot stays unmuted when there is one
+    let armed = Arc::new(AtomicBool::new(config.armed || alarm.is_some()));
+    let bot = Bot { client: reqwest::Client::new(), config };
+
+    let mut event_rx = events.subscribe();
+    let alert_bot = bot.clone();
+    let alert_frame = latest_frame.clone();
+    let alert_armed = armed.clone();
+    let alert_camera_id = camera_id.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match event_rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            if !alert_armed.load(Ordering::Relaxed) {
+                continue;
+            }
+            let caption = format!("{}: {}", alert_camera_id, event.describe());
+            if let Err(e) = alert_bot.send_snapshot(&alert_frame, &caption).await {
+                eprintln!("Failed to send Telegram alert: {}", e);
+            }
+            if let Some(wav) = event.clip() {
+                if let Err(e) = alert_bot.send_audio_clip(wav).await {
+                    eprintln!("Failed to send the sound clip to Telegram: {}", e);
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut offset = 0;
+        loop {
+            let updates = match bot.get_updates(offset).await {
+                Ok(updates) => updates,
+                Err(e) => {
+                    eprintln!("Telegram polling failed: {}", e);
+                    sleep(Duration::from_secs(10)).await;
+                    continue;
+                }
+            };
+
+            for update in updates {
+                offset = update.update_id + 1;
+                let Some(message) = update.message else { continue };
+                // Ignore everyone except the configured chat
+                if message.chat.id != bot.config.chat_id {
+                    continue;
+                }
+                let text = message.text.unwrap_or_default();
+                let command = text.split_whitespace().next().unwrap_or("").split('@').next().unwrap_or("");
+
+                let result = match command {
+                    "/snapshot" => bot.send_snapshot(&latest_frame, &camera_id).await,
+                    "/arm" => match &alarm {
+                        Some(alarm) => {
+                            alarm.set_mode(AlarmMode::ArmedAway).await;
+                            bot.send_text("Arming (away)").await
+                        }
+                        None => {
+                            armed.store(true, Ordering::Relaxed);
+                            bot.send_text("Armed, alerts will be sent").await
+                        }
+                    },
+                    "/disarm" => match &alarm {
+                        Some(alarm) => {
+                            alarm.set_mode(AlarmMode::Disarmed).await;
+                            bot.send_text("Disarmed").await
+                        }
+                        None => {
+                            armed.store(false, Ordering::Relaxed);
+                            bot.send_text("Disarmed, alerts paused").await
+                        }
+                    },
+                    "/status" => {
+                        let (state, since) = status.snapshot();
+                        let alerts = match &alarm {
+                            Some(alarm) => alarm.state().name(),
+                            None if armed.load(Ordering::Relaxed) => "armed".to_string(),
+                            None => "disarmed".to_string(),
+                        };
+                        let summary = format!("{}\nStream: {} for {}s\nAlerts: {}", camera_id, state.name(), since.as_secs(), alerts);
+                        bot.send_text(&summary).await
+                    }
+                    _ => bot.send_text("Commands: /snapshot, /arm, /disarm, /status").await,
+                };
+                if let Err(e) = result {
+                    eprintln!("Failed to answer Telegram command {}: {}", command, e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+// Where this camera sits in a larger deployment. Sent when joining, so a server
+// managing many cameras can route and authorize by site, and used to pick out the
+// commands addressed to a site or group rather than to one camera.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TenancyConfig {
+    pub site_id: Option<String>,
+    pub group: Option<String>,
+    // Free-form key/value tags, e.g. {"floor": "2", "zone": "loading-dock"}
+    pub labels: BTreeMap<String, String>,
+}
+
+// Who a command is for, as {"command": ..., "target": {"site_id": ..., "group": ...}}.
+// Every field given has to match; a command without a target is for whoever receives it.
+// camera_id is the one the device key gives, the same across restarts.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CommandTarget {
+    camera_id: Option<String>,
+    site_id: Option<String>,
+    group: Option<String>,
+    labels: BTreeMap<String, String>,
+}
+
+impl TenancyConfig {
+    pub fn is_set(&self) -> bool {
+        self.site_id.is_some() || self.group.is_some() || !self.labels.is_empty()
+    }
+
+    // Top-level fields of the join message
+    pub fn join_fields(&self) -> serde_json::Value {
+        json!({ "site_id": self.site_id, "group": self.group, "labels": self.labels })
+    }
+
+    pub fn addressed_to(&self, json: &serde_json::Value, camera_id: &str) -> bool {
+        let Some(target) = json.get("target") else {
+            return true;
+        };
+        let target = match serde_json::from_value::<CommandTarget>(target.clone()) {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("Ignoring command with an unreadable target: {}", e);
+                return false;
+            }
+        };
+        let matches = |wanted: &Option<String>, ours: Option<&str>| wanted.as_deref().is_none_or(|wanted| Some(wanted) == ours);
+        matches(&target.camera_id, Some(camera_id))
+            && matches(&target.site_id, self.site_id.as_deref())
+            && matches(&target.group, self.group.as_deref())
+            && target.labels.iter().all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+}
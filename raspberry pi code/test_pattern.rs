@@ -0,0 +1,54 @@
+use serde::Deserialize;
+
+// Generated video in place of the camera, for working on the server and measuring
+// latency on machines without camera hardware
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TestPatternConfig {
+    // videotestsrc pattern name, e.g. "smpte", "ball", "snow"
+    pub pattern: String,
+    // Pixels per frame the pattern scrolls sideways, so motion and encoder changes are visible
+    pub horizontal_speed: i32,
+}
+
+impl Default for TestPatternConfig {
+    fn default() -> Self {
+        Self {
+            pattern: "smpte".to_string(),
+            horizontal_speed: 4,
+        }
+    }
+}
+
+impl TestPatternConfig {
+    // Source element, replacing libcamerasrc
+    pub fn source_args(&self) -> Vec<String> {
+        vec![
+            "videotestsrc".into(),
+            "is-live=true".into(),
+            format!("pattern={}", self.pattern),
+            format!("horizontal-speed={}", self.horizontal_speed),
+        ]
+    }
+
+    // Burned-in timestamps, placed right after the caps so recordings get them too.
+    // The wall clock with microseconds can be compared against the time a frame is
+    // shown on the server; the running time shows dropped or repeated frames.
+    pub fn overlay_args(&self) -> Vec<String> {
+        vec![
+            "clockoverlay".into(),
+            "time-format=%H:%M:%S.%f".into(),
+            "halignment=left".into(),
+            "valignment=bottom".into(),
+            "shaded-background=true".into(),
+            "font-desc=Monospace 24".into(),
+            "!".into(),
+            "timeoverlay".into(),
+            "halignment=right".into(),
+            "valignment=bottom".into(),
+            "shaded-background=true".into(),
+            "font-desc=Monospace 24".into(),
+            "!".into(),
+        ]
+    }
+}
@@ -0,0 +1,162 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::interval;
+
+use crate::cpu_budget::CpuGovernor;
+use crate::events::{CameraEvent, CameraEvents};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ThermalConfig {
+    // Millidegrees Celsius, as the kernel reports them
+    pub sensor_path: String,
+    // Above this the stream drops to the low resolution and a lower frame rate. The
+    // Pi firmware starts throttling at 80°C, so both stay under that.
+    pub warm_c: f64,
+    // Above this every optional processor is switched off as well
+    pub hot_c: f64,
+    // A level is only left once the SoC is this much cooler than where it was entered
+    pub hysteresis_c: f64,
+    pub check_interval_seconds: u64,
+    // While warm or hot the camera captures at most this many frames a second, so the
+    // sensor and encoder work less rather than only the uplink
+    pub warm_framerate: u32,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        Self {
+            sensor_path: "/sys/class/thermal/thermal_zone0/temp".to_string(),
+            warm_c: 70.0,
+            hot_c: 78.0,
+            hysteresis_c: 5.0,
+            check_interval_seconds: 10,
+            warm_framerate: 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThermalLevel {
+    Normal,
+    Warm,
+    Hot,
+}
+
+impl ThermalLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            2 => ThermalLevel::Hot,
+            1 => ThermalLevel::Warm,
+            _ => ThermalLevel::Normal,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThermalLevel::Normal => "normal",
+            ThermalLevel::Warm => "warm",
+            ThermalLevel::Hot => "hot",
+        }
+    }
+}
+
+fn read_celsius(path: &str) -> Result<f64, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let millidegrees: f64 = text.trim().parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    Ok(millidegrees / 1000.0)
+}
+
+// Where the SoC temperature stands. The capture loop restarts the pipeline at the
+// capped resolution and frame rate when the level changes.
+#[derive(Clone)]
+pub struct ThermalState {
+    level: Arc<AtomicU8>,
+    changed: Arc<AtomicBool>,
+    framerate: u32,
+}
+
+impl ThermalState {
+    pub fn level(&self) -> ThermalLevel {
+        ThermalLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    // True once after the level went up or down
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+
+    // None while the SoC is cool enough for the full frame rate. A viewer boost may
+    // raise the cap while warm, not while hot.
+    pub fn framerate_cap(&self, boosted: Option<u32>) -> Option<u32> {
+        match self.level() {
+            ThermalLevel::Normal => None,
+            ThermalLevel::Warm => Some(self.framerate.max(boosted.unwrap_or(0)).max(1)),
+            ThermalLevel::Hot => Some(self.framerate.max(1)),
+        }
+    }
+}
+
+// Step the camera's work down as the SoC heats up, ahead of the firmware throttling
+// the CPU and stalling everything at once, and back up once it has cooled off
+pub fn spawn_thermal_monitor(config: ThermalConfig, governor: Option<CpuGovernor>, events: &CameraEvents) -> ThermalState {
+    let state = ThermalState {
+        level: Arc::new(AtomicU8::new(ThermalLevel::Normal as u8)),
+        changed: Arc::new(AtomicBool::new(false)),
+        framerate: config.warm_framerate,
+    };
+    let monitor = state.clone();
+    let events = events.clone();
+
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(config.check_interval_seconds.max(1)));
+        loop {
+            check.tick().await;
+            let temperature = match read_celsius(&config.sensor_path) {
+                Ok(temperature) => temperature,
+                Err(e) => {
+                    eprintln!("Thermal monitor stopped, failed to read {}: {}", config.sensor_path, e);
+                    return;
+                }
+            };
+
+            let current = monitor.level();
+            let level = if temperature >= config.hot_c {
+                ThermalLevel::Hot
+            } else if temperature >= config.warm_c {
+                ThermalLevel::Warm.max(current)
+            } else {
+                current
+            };
+            // Going down only past the hysteresis, one level per check
+            let level = match level {
+                ThermalLevel::Hot if temperature < config.hot_c - config.hysteresis_c => ThermalLevel::Warm,
+                ThermalLevel::Warm if temperature < config.warm_c - config.hysteresis_c => ThermalLevel::Normal,
+                level => level,
+            };
+            if level == current {
+                continue;
+            }
+
+            if level > current {
+                eprintln!("SoC at {:.1}°C, now {}: scaling back the stream", temperature, level.name());
+            } else {
+                println!("SoC cooled to {:.1}°C, now {}: restoring the stream", temperature, level.name());
+            }
+            if let Some(governor) = &governor {
+                governor.force_all(level == ThermalLevel::Hot);
+            }
+            monitor.level.store(level as u8, Ordering::Relaxed);
+            monitor.changed.store(true, Ordering::Relaxed);
+            events.publish(CameraEvent::Thermal { temperature_c: temperature as f32, level: level.name() });
+        }
+    });
+
+    state
+}
@@ -0,0 +1,157 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::capture_clock::CaptureClock;
+
+// Requests older than this without a reply are forgotten
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSyncSource {
+    // Steer the capture clock to the server's clock
+    Server,
+    // The system clock is already disciplined (PTP with ptp4l/phc2sys, or chrony on the
+    // LAN); only measure the offset to the server
+    System,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeSyncConfig {
+    pub source: TimeSyncSource,
+    pub interval_seconds: u64,
+    // The offset comes from the quickest round trip among this many recent exchanges;
+    // slow ones are the ones where queueing skewed the timing
+    pub window: usize,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self { source: TimeSyncSource::Server, interval_seconds: 5, window: 8 }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Reply {
+    id: u64,
+    // Server time the request arrived and the reply left; a server that only stamps
+    // once can leave out t2
+    t1: f64,
+    t2: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    // Server clock minus ours
+    offset_ms: f64,
+    round_trip_ms: f64,
+}
+
+#[derive(Default)]
+struct SyncState {
+    next_id: u64,
+    // Send time of each unanswered request
+    pending: HashMap<u64, f64>,
+    samples: VecDeque<Sample>,
+    best: Option<Sample>,
+}
+
+fn precise_ms() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64() * 1000.0
+}
+
+// NTP-style offset measurement against the server, so cameras in a rig stamp frames
+// on the same timeline: {"time_sync": {"id", "t0"}} goes out, and the server answers
+// {"time_sync_reply": {"id", "t1", "t2"}} with its receive and send times.
+#[derive(Clone)]
+pub struct TimeSync {
+    config: TimeSyncConfig,
+    clock: CaptureClock,
+    state: Arc<Mutex<SyncState>>,
+}
+
+impl TimeSync {
+    pub fn new(config: TimeSyncConfig, clock: CaptureClock) -> Self {
+        Self { config, clock, state: Arc::new(Mutex::new(SyncState::default())) }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_seconds.max(1))
+    }
+
+    // The next request, stamped now; send it straight away
+    pub fn request(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        if state.pending.len() >= MAX_PENDING {
+            state.pending.clear();
+        }
+        let t0 = precise_ms();
+        state.pending.insert(id, t0);
+        json!({ "time_sync": { "id": id, "t0": t0 } }).to_string()
+    }
+
+    // Returns false when the message isn't a time sync reply
+    pub fn handle_reply(&self, json: &serde_json::Value) -> bool {
+        let t3 = precise_ms();
+        let Some(reply) = json.get("time_sync_reply") else {
+            return false;
+        };
+        let reply: Reply = match serde_json::from_value(reply.clone()) {
+            Ok(reply) => reply,
+            Err(e) => {
+                eprintln!("Invalid time sync reply: {}", e);
+                return true;
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        let Some(t0) = state.pending.remove(&reply.id) else {
+            return true;
+        };
+        let t2 = reply.t2.unwrap_or(reply.t1);
+        let sample = Sample {
+            offset_ms: ((reply.t1 - t0) + (t2 - t3)) / 2.0,
+            round_trip_ms: (t3 - t0) - (t2 - reply.t1),
+        };
+        if state.samples.len() >= self.config.window.max(1) {
+            state.samples.pop_front();
+        }
+        state.samples.push_back(sample);
+        let best = state
+            .samples
+            .iter()
+            .copied()
+            .min_by(|a, b| a.round_trip_ms.total_cmp(&b.round_trip_ms))
+            .unwrap_or(sample);
+        if state.best.is_none() {
+            println!("Clock offset to the server is {:.1}ms ({:.1}ms round trip)", best.offset_ms, best.round_trip_ms);
+        }
+        state.best = Some(best);
+        drop(state);
+
+        if self.config.source == TimeSyncSource::Server {
+            self.clock.set_reference_offset(best.offset_ms);
+        }
+        true
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "source": match self.config.source {
+                TimeSyncSource::Server => "server",
+                TimeSyncSource::System => "system",
+            },
+            "offset_ms": state.best.map(|best| (best.offset_ms * 10.0).round() / 10.0),
+            "round_trip_ms": state.best.map(|best| (best.round_trip_ms * 10.0).round() / 10.0),
+            "samples": state.samples.len(),
+        })
+    }
+}
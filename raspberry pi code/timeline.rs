@@ -0,0 +1,286 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    io::{BufRead, BufReader},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+
+use crate::event_queue::EventQueue;
+use crate::events::CameraEvents;
+use crate::export;
+use crate::maintenance::Maintenance;
+use crate::metadata::MetadataTap;
+use crate::motion::MotionState;
+use crate::recording::RecordingConfig;
+use crate::storage;
+
+// {"command": "timeline_query", "from": ..., "to": ...}; times in UNIX millis
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimelineQueryCommand {
+    pub id: Option<String>,
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Clone)]
+struct IndexedSegment {
+    name: String,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Clone)]
+struct IndexedEvent {
+    // The event queue's seq, as the server got it; None without the queue
+    id: Option<u64>,
+    kind: String,
+    timestamp: u64,
+    // Raised during maintenance mode, so nobody was alerted
+    maintenance: bool,
+}
+
+#[derive(Default)]
+struct TimelineState {
+    segments: Vec<IndexedSegment>,
+    // Finished motion intervals, plus the start of the current one
+    motion: Vec<(u64, u64)>,
+    motion_started: Option<u64>,
+    events: Vec<IndexedEvent>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn overlaps(start: u64, end: u64, from: u64, to: u64) -> bool {
+    start < to && end > from
+}
+
+// What was recorded when and what happened in it, so scrub bars don't need the segments
+// listed or the metadata scanned. Segments come from the storage backend; motion
+// intervals and events are kept as lines of their own in the metadata sidecar and read
+// back from it on start.
+#[derive(Clone)]
+pub struct Timeline {
+    metadata: Option<MetadataTap>,
+    state: Arc<Mutex<TimelineState>>,
+}
+
+impl Timeline {
+    // The sidecar holds a line per frame, so it is read line by line on a blocking thread;
+    // what is indexed meanwhile is kept and merged in
+    fn reload(&self, directory: &str) {
+        let mut files: Vec<_> = std::fs::read_dir(directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("metadata-") && name.ends_with(".jsonl")))
+            .collect();
+        files.sort();
+        let (mut motion, mut events) = (Vec::new(), Vec::new());
+        for path in files {
+            let Ok(file) = std::fs::File::open(&path) else { continue };
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                // Per-frame samples have neither, and are most of the file
+                if !line.contains("\"event\"") && !line.contains("\"start\"") {
+                    continue;
+                }
+                let Ok(entry) = serde_json::from_str::<serde_json::Value>(&line) else { continue };
+                if let (Some(start), Some(end), Some(true)) = (entry["start"].as_u64(), entry["end"].as_u64(), entry["motion"].as_bool()) {
+                    motion.push((start, end));
+                } else if let (Some(id), Some(kind), Some(timestamp)) = (entry.get("event"), entry["kind"].as_str(), entry["timestamp"].as_u64()) {
+                    let maintenance = entry["maintenance"].as_bool() == Some(true);
+                    events.push(IndexedEvent { id: id.as_u64(), kind: kind.to_string(), timestamp, maintenance });
+                }
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        // Segments may have been listed already; nothing older than them is kept
+        let oldest = state.segments.first().map_or(0, |s| s.start);
+        motion.retain(|(_, end)| *end >= oldest);
+        events.retain(|event| event.timestamp >= oldest);
+        motion.append(&mut state.motion);
+        events.append(&mut state.events);
+        state.motion = motion;
+        state.events = events;
+    }
+
+    fn append(&self, line: serde_json::Value) {
+        if let Some(metadata) = &self.metadata {
+            metadata.mark(line);
+        }
+    }
+
+    fn motion_line(start: u64, end: u64) -> serde_json::Value {
+        json!({ "motion": true, "start": start, "end": end })
+    }
+
+    fn event_line(event: &IndexedEvent) -> serde_json::Value {
+        let mut line = json!({ "event": event.id, "kind": event.kind, "timestamp": event.timestamp });
+        if event.maintenance {
+            line["maintenance"] = json!(true);
+        }
+        line
+    }
+
+    fn add_event(&self, id: Option<u64>, kind: &str, timestamp: u64, maintenance: bool) {
+        let event = IndexedEvent { id, kind: kind.to_string(), timestamp, maintenance };
+        self.append(Self::event_line(&event));
+        self.state.lock().unwrap().events.push(event);
+    }
+
+    fn set_motion(&self, active: bool) {
+        let mut state = self.state.lock().unwrap();
+        match (active, state.motion_started) {
+            (true, None) => state.motion_started = Some(now_ms()),
+            (false, Some(start)) => {
+                let end = now_ms();
+                state.motion_started = None;
+                state.motion.push((start, end));
+                drop(state);
+                self.append(Self::motion_line(start, end));
+            }
+            _ => {}
+        }
+    }
+
+    // Bring the segment list in line with the storage backend. Motion and events older
+    // than the oldest remaining segment are dropped with it.
+    fn sync_segments(&self, listed: Vec<export::Segment>) {
+        let mut state = self.state.lock().unwrap();
+        state.segments = listed.into_iter().map(|s| IndexedSegment { name: s.name, start: s.start, end: s.end }).collect();
+        state.segments.sort_by_key(|s| s.start);
+        let Some(oldest) = state.segments.first().map(|s| s.start) else {
+            return;
+        };
+        state.motion.retain(|(_, end)| *end >= oldest);
+        state.events.retain(|event| event.timestamp >= oldest);
+    }
+
+    // Everything overlapping [from, to): recorded segments, motion intervals (an ongoing
+    // one ends now) and events, for rendering a scrub bar
+    pub fn query(&self, camera_id: &str, command: &TimelineQueryCommand) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let (from, to) = (command.from, command.to);
+        let segments: Vec<_> = state.segments.iter()
+            .filter(|s| overlaps(s.start, s.end, from, to))
+            .map(|s| json!({ "name": s.name, "start": s.start, "end": s.end }))
+            .collect();
+        let motion: Vec<_> = state.motion.iter().copied()
+            .chain(state.motion_started.map(|start| (start, now_ms())))
+            .filter(|(start, end)| overlaps(*start, *end, from, to))
+            .map(|(start, end)| json!({ "start": start, "end": end }))
+            .collect();
+        let events: Vec<_> = state.events.iter()
+            .filter(|event| event.timestamp >= from && event.timestamp < to)
+            .map(|event| json!({ "id": event.id, "kind": event.kind, "timestamp": event.timestamp, "maintenance": event.maintenance }))
+            .collect();
+        json!({
+            "timeline": {
+                "id": command.id,
+                "camera_id": camera_id,
+                "from": from,
+                "to": to,
+                "segments": segments,
+                "motion": motion,
+                "events": events,
+            }
+        })
+    }
+}
+
+// Keep the timeline index up to date: events and motion as they happen, segments
+// whenever one should have finished. With the event queue on, events are indexed under
+// the seq the server gets them with.
+pub fn spawn_timeline(
+    recording: RecordingConfig,
+    motion: Option<MotionState>,
+    events: &CameraEvents,
+    event_queue: Option<&EventQueue>,
+    metadata: Option<MetadataTap>,
+    maintenance: Maintenance
+) -> Timeline {
+    if metadata.is_none() {
+        println!("Timeline: recording.metadata is off, motion and events are only indexed until a restart");
+    }
+    let timeline = Timeline { metadata, state: Arc::new(Mutex::new(TimelineState::default())) };
+    let reloaded = timeline.clone();
+    let directory = recording.directory.clone();
+    tokio::task::spawn_blocking(move || reloaded.reload(&directory));
+
+    let event_timeline = timeline.clone();
+    match event_queue {
+        Some(queue) => {
+            let mut queued = queue.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match queued.recv().await {
+                        Ok(record) => event_timeline.add_event(
+                            record["seq"].as_u64(),
+                            record["kind"].as_str().unwrap_or_default(),
+                            record["timestamp"].as_u64().unwrap_or_else(now_ms),
+                            record["maintenance"].as_bool() == Some(true)
+                        ),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
+        None => {
+            let mut event_rx = events.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match event_rx.recv().await {
+                        Ok(event) => event_timeline.add_event(
+                            None,
+                            event.kind(),
+                            event.timestamp_ms().unwrap_or_else(now_ms),
+                            maintenance.covers(event.kind())
+                        ),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
+    }
+
+    if let Some(motion) = motion {
+        let motion_timeline = timeline.clone();
+        tokio::spawn(async move {
+            let mut check = interval(Duration::from_secs(1));
+            loop {
+                check.tick().await;
+                motion_timeline.set_motion(motion.is_active());
+            }
+        });
+    }
+
+    let segment_timeline = timeline.clone();
+    let backend = storage::open(&recording);
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(recording.segment_seconds.max(60)));
+        loop {
+            check.tick().await;
+            let list_recording = recording.clone();
+            let list_backend = backend.clone();
+            let listed = tokio::task::spawn_blocking(move || export::list_segments(&list_recording, list_backend.as_ref())).await;
+            match listed {
+                Ok(Ok(segments)) => {
+                    // The segment being recorded still grows; it is indexed once finished
+                    let finished_before = now_ms().saturating_sub(recording.finished_after_seconds * 1000);
+                    let finished = segments.into_iter().filter(|s| s.end < finished_before).collect();
+                    segment_timeline.sync_segments(finished);
+                }
+                Ok(Err(e)) => eprintln!("Timeline failed to list recordings: {}", e),
+                Err(e) => eprintln!("Timeline failed to list recordings: {}", e),
+            }
+        }
+    });
+
+    timeline
+}
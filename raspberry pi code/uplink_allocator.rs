@@ -0,0 +1,187 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::interval;
+
+use crate::stats_db::StatsCounters;
+use crate::stream_priority::StreamPriorities;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UplinkAllocatorConfig {
+    // What the uplink is assumed to carry until throughput has been measured
+    pub initial_kbps: u32,
+    // The estimate never drops below this, so a stalled link isn't mistaken for none
+    pub min_kbps: u32,
+}
+
+impl Default for UplinkAllocatorConfig {
+    fn default() -> Self {
+        Self { initial_kbps: 4000, min_kbps: 200 }
+    }
+}
+
+// A stream that has sent nothing for this long no longer gets a share
+const IDLE_AFTER: Duration = Duration::from_secs(5);
+const TICK: Duration = Duration::from_secs(1);
+
+struct StreamShare {
+    // Smoothed rate the stream's pipeline produces, whether or not it all fits
+    demand_kbps: f64,
+    offered_bytes: u64,
+    allocated_kbps: f64,
+    // Token bucket holding up to a second of the allocation
+    tokens: f64,
+    refilled: Instant,
+    last_offered: Instant,
+}
+
+struct AllocatorState {
+    capacity_kbps: f64,
+    streams: HashMap<Arc<str>, StreamShare>,
+}
+
+// Splits what the uplink is measured to carry between the streams sharing it, by the
+// priority the server gave each one and how much each actually produces, so they don't
+// crowd each other out of the one frame queue. A stream over its share has frames
+// dropped before they are queued, except those the caller says must be sent.
+#[derive(Clone)]
+pub struct UplinkAllocator {
+    priorities: StreamPriorities,
+    state: Arc<Mutex<AllocatorState>>,
+}
+
+fn kbps_to_bytes(kbps: f64) -> f64 {
+    kbps * 1000.0 / 8.0
+}
+
+impl UplinkAllocator {
+    pub fn admit(&self, stream_id: &Arc<str>, bytes: usize, must_send: bool) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let sharing = state.streams.len() + state.streams.contains_key(stream_id) as usize;
+        let provisional = state.capacity_kbps / sharing.max(1) as f64;
+        let share = state.streams.entry(stream_id.clone()).or_insert_with(|| StreamShare {
+            demand_kbps: 0.0,
+            offered_bytes: 0,
+            allocated_kbps: provisional,
+            tokens: kbps_to_bytes(provisional),
+            refilled: now,
+            last_offered: now,
+        });
+        share.offered_bytes += bytes as u64;
+        share.last_offered = now;
+
+        let burst = kbps_to_bytes(share.allocated_kbps);
+        share.tokens = (share.tokens + now.duration_since(share.refilled).as_secs_f64() * burst).min(burst);
+        share.refilled = now;
+        // Nothing to share with
+        let alone = state.streams.len() == 1;
+        let share = state.streams.get_mut(stream_id).expect("inserted above");
+        if alone || must_send || share.tokens >= bytes as f64 {
+            share.tokens = (share.tokens - bytes as f64).max(-burst);
+            true
+        } else {
+            false
+        }
+    }
+
+    // The stream's share, for the frame stats
+    pub fn stats(&self, stream_id: &str) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let share = state.streams.get(stream_id);
+        json!({
+            "capacity_kbps": state.capacity_kbps.round(),
+            "streams": state.streams.len(),
+            "demand_kbps": share.map_or(0.0, |share| share.demand_kbps.round()),
+            "allocated_kbps": share.map_or(0.0, |share| share.allocated_kbps.round()),
+        })
+    }
+
+    fn reallocate(&self, config: &UplinkAllocatorConfig, sent_kbps: f64, congested: bool) {
+        let priorities = self.priorities.snapshot().unwrap_or_default();
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.streams.retain(|_, share| now.duration_since(share.last_offered) < IDLE_AFTER);
+        for share in state.streams.values_mut() {
+            let offered_kbps = share.offered_bytes as f64 * 8.0 / 1000.0 / TICK.as_secs_f64();
+            share.demand_kbps = 0.7 * share.demand_kbps + 0.3 * offered_kbps;
+            share.offered_bytes = 0;
+        }
+
+        // Congestion means the link carries about what got through; otherwise it carries
+        // at least that, and some more is tried while the streams want more than it has
+        let demand: f64 = state.streams.values().map(|share| share.demand_kbps).sum();
+        let capacity = if congested {
+            sent_kbps.min(state.capacity_kbps) * 0.9
+        } else if demand > state.capacity_kbps {
+            state.capacity_kbps.max(sent_kbps) * 1.05
+        } else {
+            state.capacity_kbps.max(sent_kbps)
+        };
+        state.capacity_kbps = capacity.max(config.min_kbps as f64);
+
+        // Weighted max-min fairness: streams that want less than their weighted share get
+        // what they want, and what they leave is split again among the rest
+        let capacity = state.capacity_kbps;
+        let mut open: Vec<(&Arc<str>, &mut StreamShare, f64)> = state
+            .streams
+            .iter_mut()
+            .map(|(stream, share)| {
+                let weight = priorities.get(&**stream).copied().unwrap_or(0) as f64 + 1.0;
+                (stream, share, weight)
+            })
+            .collect();
+        let mut remaining = capacity;
+        while !open.is_empty() {
+            let per_weight = remaining / open.iter().map(|(_, _, weight)| weight).sum::<f64>();
+            let (satisfied, unsatisfied): (Vec<_>, Vec<_>) =
+                open.into_iter().partition(|(_, share, weight)| share.demand_kbps <= weight * per_weight);
+            if satisfied.is_empty() {
+                for (_, share, weight) in unsatisfied {
+                    share.allocated_kbps = weight * per_weight;
+                }
+                break;
+            }
+            for (_, share, _) in satisfied {
+                share.allocated_kbps = share.demand_kbps;
+                remaining -= share.demand_kbps;
+            }
+            open = unsatisfied;
+        }
+    }
+}
+
+// Re-estimates the uplink and the shares every second from what was sent and what the
+// congestion controller decided
+pub fn spawn_uplink_allocator(
+    config: UplinkAllocatorConfig,
+    priorities: StreamPriorities,
+    stats: StatsCounters,
+    congested: Arc<AtomicBool>,
+) -> UplinkAllocator {
+    let allocator = UplinkAllocator {
+        priorities,
+        state: Arc::new(Mutex::new(AllocatorState { capacity_kbps: config.initial_kbps as f64, streams: HashMap::new() })),
+    };
+    let worker = allocator.clone();
+    tokio::spawn(async move {
+        let mut tick = interval(TICK);
+        let mut sent = stats.bytes_sent.load(Ordering::Relaxed);
+        loop {
+            tick.tick().await;
+            let total = stats.bytes_sent.load(Ordering::Relaxed);
+            let sent_kbps = total.saturating_sub(sent) as f64 * 8.0 / 1000.0 / TICK.as_secs_f64();
+            sent = total;
+            worker.reallocate(&config, sent_kbps, congested.load(Ordering::Relaxed));
+        }
+    });
+    allocator
+}
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ViewerConfig {
+    // Full frame rate for this long after the last viewer leaves, so someone flicking
+    // between cameras doesn't come back to a stalled one
+    pub grace_seconds: u64,
+    // With nobody watching only motion frames go out, and a still this often so the
+    // server's thumbnail stays current (0 for none)
+    pub idle_still_seconds: u64,
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self { grace_seconds: 30, idle_still_seconds: 10 }
+    }
+}
+
+struct Viewers {
+    // None until the server reports a count on this connection
+    count: Option<u32>,
+    // When the count last dropped to zero
+    left_at: Option<Instant>,
+}
+
+// How many viewers the server has subscribed to this camera, from {"viewers": n}. A
+// server that never reports a count gets the full stream as before.
+#[derive(Clone)]
+pub struct ViewerCount {
+    config: ViewerConfig,
+    viewers: Arc<Mutex<Viewers>>,
+}
+
+impl ViewerCount {
+    pub fn new(config: ViewerConfig) -> Self {
+        Self { config, viewers: Arc::new(Mutex::new(Viewers { count: None, left_at: None })) }
+    }
+
+    pub fn update(&self, count: u32) {
+        let mut viewers = self.viewers.lock().unwrap();
+        if viewers.count == Some(count) {
+            return;
+        }
+        match (viewers.count, count) {
+            (_, 0) => {
+                println!("No viewers left, sending motion only after {}s", self.config.grace_seconds);
+                viewers.left_at = Some(Instant::now());
+            }
+            (None | Some(0), _) => println!("{} viewer(s) watching, sending the full stream", count),
+            (Some(previous), _) => println!("Viewers: {} -> {}", previous, count),
+        }
+        viewers.count = Some(count);
+    }
+
+    // A new connection starts from not knowing again
+    pub fn reset(&self) {
+        let mut viewers = self.viewers.lock().unwrap();
+        viewers.count = None;
+        viewers.left_at = None;
+    }
+
+    pub fn count(&self) -> Option<u32> {
+        self.viewers.lock().unwrap().count
+    }
+
+    // The server says nobody has been watching for the whole grace period
+    pub fn unwatched(&self) -> bool {
+        let viewers = self.viewers.lock().unwrap();
+        viewers.count == Some(0) && viewers.left_at.is_some_and(|at| at.elapsed() >= Duration::from_secs(self.config.grace_seconds))
+    }
+
+    pub fn idle_still_every(&self) -> Option<Duration> {
+        (self.config.idle_still_seconds > 0).then(|| Duration::from_secs(self.config.idle_still_seconds))
+    }
+}
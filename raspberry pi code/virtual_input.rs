@@ -0,0 +1,204 @@
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStdin},
+    sync::Mutex,
+};
+
+#[cfg(unix)]
+use crate::control_socket::bind_private;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VirtualInputFormat {
+    // Concatenated JPEGs
+    Mjpeg,
+    // Uncompressed frames of `width` x `height` in `pixel_format`, back to back
+    Raw,
+}
+
+// Video fed in by another process (a thermal camera driver, a game engine) instead of
+// the camera. It goes through the same pipeline, so recording, adaptation and the uplink
+// work as usual.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VirtualInputConfig {
+    // "stdin", or the path of a Unix socket the feeding process connects to; only
+    // processes running as the same user may
+    pub source: String,
+    pub format: VirtualInputFormat,
+    // Raw input only
+    pub width: u32,
+    pub height: u32,
+    // GStreamer format name, e.g. I420, RGB, GRAY8
+    pub pixel_format: String,
+    pub frame_rate: u32,
+}
+
+impl Default for VirtualInputConfig {
+    fn default() -> Self {
+        Self {
+            source: "stdin".to_string(),
+            format: VirtualInputFormat::Mjpeg,
+            width: 640,
+            height: 480,
+            pixel_format: "I420".to_string(),
+            frame_rate: 15,
+        }
+    }
+}
+
+impl VirtualInputConfig {
+    // Bytes per raw frame; None for formats we don't know the layout of
+    fn raw_frame_bytes(&self) -> Option<usize> {
+        let pixels = (self.width * self.height) as usize;
+        match self.pixel_format.as_str() {
+            "I420" | "YV12" | "NV12" | "NV21" => Some(pixels * 3 / 2),
+            "YUY2" | "UYVY" | "RGB16" | "GRAY16_LE" | "GRAY16_BE" => Some(pixels * 2),
+            "RGB" | "BGR" => Some(pixels * 3),
+            "RGBA" | "BGRA" | "RGBx" | "BGRx" => Some(pixels * 4),
+            "GRAY8" => Some(pixels),
+            _ => None,
+        }
+    }
+}
+
+// Relays the input into the stdin of whichever GStreamer process is current. The pipeline
+// is restarted whenever the settings change, so the input outlives any one process.
+#[derive(Clone)]
+pub struct VirtualInput {
+    config: VirtualInputConfig,
+    sink: Arc<Mutex<Option<ChildStdin>>>,
+}
+
+impl VirtualInput {
+    pub fn spawn(config: VirtualInputConfig) -> Self {
+        let input = Self::fed_internally(config);
+        let relay = input.clone();
+        tokio::spawn(async move { relay.run().await });
+        input
+    }
+
+    // Input written from inside the process with `write`, e.g. by the stabilizer
+    pub fn fed_internally(config: VirtualInputConfig) -> Self {
+        Self { config, sink: Arc::new(Mutex::new(None)) }
+    }
+
+    // Source elements, replacing libcamerasrc; they end in raw video the caps can scale
+    pub fn source_args(&self) -> Vec<String> {
+        let mut args = vec!["fdsrc".to_string(), "fd=0".to_string(), "!".to_string()];
+        match self.config.format {
+            VirtualInputFormat::Mjpeg => args.extend(["jpegparse", "!", "jpegdec", "!"].map(String::from)),
+            VirtualInputFormat::Raw => args.extend([
+                "rawvideoparse".to_string(),
+                format!("width={}", self.config.width),
+                format!("height={}", self.config.height),
+                format!("format={}", self.config.pixel_format.to_lowercase()),
+                format!("framerate={}/1", self.config.frame_rate),
+                "!".to_string(),
+            ]),
+        }
+        args.extend(["videoconvert", "!", "videoscale"].map(String::from));
+        args
+    }
+
+    // Take over the stdin of a freshly started pipeline
+    pub async fn attach(&self, child: &mut Child) {
+        *self.sink.lock().await = child.stdin.take();
+    }
+
+    async fn run(&self) {
+        // Raw frames are relayed whole so a pipeline restart can't leave one half-written
+        // and shift every frame after it; JPEG parsing finds its way back on its own
+        let chunk = match self.config.format {
+            VirtualInputFormat::Mjpeg => 64 * 1024,
+            VirtualInputFormat::Raw => match self.config.raw_frame_bytes() {
+                Some(bytes) => bytes,
+                None => {
+                    eprintln!("Virtual input: unknown raw pixel format {}", self.config.pixel_format);
+                    return;
+                }
+            },
+        };
+
+        if self.config.source == "stdin" {
+            println!("Reading virtual camera input from stdin");
+            self.relay(tokio::io::stdin(), chunk).await;
+            eprintln!("Virtual input: stdin closed");
+            return;
+        }
+
+        self.listen(chunk).await;
+    }
+
+    #[cfg(unix)]
+    async fn listen(&self, chunk: usize) {
+        // Owner only: whatever connects decides what the security feed shows
+        let path = &self.config.source;
+        let listener = match bind_private(path, 0o600) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Virtual input: failed to listen on {}: {}", path, e);
+                return;
+            }
+        };
+        println!("Waiting for virtual camera input on {}", path);
+        // One feeding process at a time; the next one may connect when it goes away
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    // The socket's mode should already see to this; checked again in case
+                    // it was loosened on disk
+                    let uid = unsafe { libc::geteuid() };
+                    match stream.peer_cred() {
+                        Ok(peer) if peer.uid() == uid => {}
+                        Ok(peer) => {
+                            eprintln!("Virtual input: refusing a connection from uid {}", peer.uid());
+                            continue;
+                        }
+                        Err(e) => {
+                            eprintln!("Virtual input: can't tell who connected, refusing: {}", e);
+                            continue;
+                        }
+                    }
+                    println!("Virtual input connected");
+                    self.relay(stream, chunk).await;
+                    println!("Virtual input disconnected");
+                }
+                Err(e) => eprintln!("Virtual input: accept failed: {}", e),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn listen(&self, _chunk: usize) {
+        eprintln!("Virtual input: {} can't be listened on without Unix sockets, use \"stdin\"", self.config.source);
+    }
+
+    async fn relay(&self, mut reader: impl AsyncRead + Unpin, chunk: usize) {
+        let raw = self.config.format == VirtualInputFormat::Raw;
+        let mut buffer = vec![0u8; chunk];
+        loop {
+            let read = if raw {
+                reader.read_exact(&mut buffer).await.map(|_| chunk)
+            } else {
+                reader.read(&mut buffer).await
+            };
+            let length = match read {
+                Ok(0) | Err(_) => return,
+                Ok(length) => length,
+            };
+            self.write(&buffer[..length]).await;
+        }
+    }
+
+    // Between pipelines the data is dropped; a live source has nothing to catch up on
+    pub async fn write(&self, data: &[u8]) {
+        let mut sink = self.sink.lock().await;
+        let Some(stdin) = sink.as_mut() else { return };
+        if stdin.write_all(data).await.is_err() {
+            *sink = None;
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{process::Command, time::interval};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    // Restart the capture pipeline when no frame has come out of it for this long
+    pub stall_seconds: u64,
+    // Run the power-cycle hook after this many stall restarts in a row
+    pub restarts_before_power_cycle: u32,
+    // Command (program and arguments) that power-cycles the camera, e.g. a relay or USB hub script
+    pub power_cycle_command: Option<Vec<String>>,
+    // File rewritten every heartbeat_seconds while frames flow, for external watchdogs
+    // (e.g. the watchdog daemon's `file`/`change` check) that don't go through systemd
+    pub heartbeat_file: Option<String>,
+    pub heartbeat_seconds: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            stall_seconds: 10,
+            restarts_before_power_cycle: 3,
+            power_cycle_command: None,
+            heartbeat_file: None,
+            heartbeat_seconds: 5,
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// Time of the last frame extracted from the capture pipeline
+#[derive(Clone)]
+pub struct FrameWatchdog {
+    last_frame_ms: Arc<AtomicU64>,
+}
+
+impl FrameWatchdog {
+    pub fn new() -> Self {
+        Self { last_frame_ms: Arc::new(AtomicU64::new(now_ms())) }
+    }
+
+    // Called for every frame; also used to give a freshly started pipeline a grace period
+    pub fn tick(&self) {
+        self.last_frame_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn stalled_for(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.last_frame_ms.load(Ordering::Relaxed)))
+    }
+}
+
+// Last resort when restarting the pipeline doesn't bring frames back
+pub async fn power_cycle_camera(config: &WatchdogConfig) {
+    let Some((program, args)) = config.power_cycle_command.as_ref().and_then(|c| c.split_first()) else {
+        eprintln!("Capture still stalled and no power_cycle_command is configured");
+        return;
+    };
+
+    println!("Power-cycling the camera: {}", program);
+    match Command::new(program).args(args).status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Power-cycle command exited with {}", status),
+        Err(e) => eprintln!("Failed to run power-cycle command: {}", e),
+    }
+}
+
+// Rewrite the heartbeat file with a beat counter and the time of the last frame while the
+// capture pipeline is producing frames. Once it stalls the file goes stale, which is what
+// the external watchdog looks for; a hung process stops it just the same.
+pub fn spawn_heartbeat(config: &WatchdogConfig, watchdog: FrameWatchdog) {
+    let Some(path) = config.heartbeat_file.clone() else { return };
+    let period = Duration::from_secs(config.heartbeat_seconds.max(1));
+    let stall = Duration::from_secs(config.stall_seconds);
+    println!("Writing a heartbeat to {} every {:?}", path, period);
+
+    tokio::spawn(async move {
+        let partial = format!("{}.tmp", path);
+        let mut beats: u64 = 0;
+        let mut check = interval(period);
+        loop {
+            check.tick().await;
+            if watchdog.stalled_for() >= stall {
+                continue;
+            }
+            beats += 1;
+            let contents = format!("{} {}\n", beats, watchdog.last_frame_ms.load(Ordering::Relaxed));
+            // Renamed into place so readers never see a half-written file
+            let result = match tokio::fs::write(&partial, contents).await {
+                Ok(()) => tokio::fs::rename(&partial, &path).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to write heartbeat {}: {}", path, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,312 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::time::interval;
+
+use crate::clock::TimeConfig;
+use crate::encryption::SegmentEncryptor;
+use crate::recording::RecordingConfig;
+use crate::storage::{self, StorageBackend};
+
+// O_DIRECT needs buffers, offsets and lengths aligned to the device block size
+const DIRECT_ALIGN: usize = 4096;
+const DIRECT_BLOCKS: usize = 256;
+
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct AlignedBlock([u8; DIRECT_ALIGN]);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WearConfig {
+    // The pipeline records here (ideally a tmpfs such as /dev/shm) and finished segments
+    // are moved to the card in one sequential write, instead of many small ones
+    pub staging_directory: Option<String>,
+    // Bypass the page cache when moving segments to the card
+    pub direct_io: bool,
+    // Buffer for the metadata sidecar, which otherwise gets a small write per frame
+    pub write_buffer_kb: usize,
+    // Block device holding the recordings, for write accounting
+    pub device: String,
+    // Program/erase cycles the card is rated for; consumer cards manage a few hundred
+    pub rated_write_cycles: u64,
+    // Warn when the day's writes would wear the card out sooner than this
+    pub warn_below_years: f64,
+}
+
+impl Default for WearConfig {
+    fn default() -> Self {
+        Self {
+            staging_directory: None,
+            direct_io: false,
+            write_buffer_kb: 64,
+            device: "mmcblk0".to_string(),
+            rated_write_cycles: 500,
+            warn_below_years: 3.0,
+        }
+    }
+}
+
+// Open for writing past the page cache
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create(true).truncate(true).custom_flags(libc::O_DIRECT).open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_direct(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    // No O_DIRECT on macOS; F_NOCACHE does the same for an open file
+    #[cfg(target_os = "macos")]
+    unsafe {
+        libc::fcntl(std::os::fd::AsRawFd::as_raw_fd(&file), libc::F_NOCACHE, 1);
+    }
+    Ok(file)
+}
+
+// Fill `buffer` from `input`, short only at the end
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// Copy a file in large sequential writes, a buffer at a time, and give the copy the
+// given modification time. With `direct` the block-aligned part bypasses the page cache
+// and the tail is appended.
+pub fn copy_file(source: &Path, path: &Path, direct: bool, modified: SystemTime) -> std::io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut input = File::open(source)?;
+    let mut output = if direct { open_direct(&partial)? } else { File::create(&partial)? };
+    let mut blocks = vec![AlignedBlock([0; DIRECT_ALIGN]); DIRECT_BLOCKS];
+    // Safe: the blocks are plain bytes and contiguous
+    let buffer = unsafe { std::slice::from_raw_parts_mut(blocks.as_mut_ptr() as *mut u8, DIRECT_ALIGN * DIRECT_BLOCKS) };
+
+    loop {
+        let filled = read_full(&mut input, buffer)?;
+        let aligned = if direct { filled / DIRECT_ALIGN * DIRECT_ALIGN } else { filled };
+        output.write_all(&buffer[..aligned])?;
+        if filled < buffer.len() {
+            if aligned < filled {
+                output = OpenOptions::new().append(true).open(&partial)?;
+                output.write_all(&buffer[aligned..filled])?;
+            }
+            break;
+        }
+    }
+    output.sync_all()?;
+    output.set_modified(modified)?;
+    std::fs::rename(&partial, path)
+}
+
+fn idle_for(path: &Path) -> Option<(SystemTime, Duration)> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some((modified, SystemTime::now().duration_since(modified).ok()?))
+}
+
+fn seal_file(encryptor: &mut SegmentEncryptor, source: &Path, sealed: &Path) -> Result<(), String> {
+    let plain = File::open(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    let out = File::create(sealed).map_err(|e| format!("{}: {}", sealed.display(), e))?;
+    let mut out = BufWriter::with_capacity(DIRECT_ALIGN * DIRECT_BLOCKS, out);
+    encryptor.seal(BufReader::new(plain), &mut out)?;
+    out.into_inner().map_err(|e| e.to_string())?.sync_all().map_err(|e| e.to_string())
+}
+
+// Hand finished segments to the storage backend, encrypting them on the way. The
+// segment being recorded is written to every frame, so idle ones are finished. False
+// when the backend failed; the rest wait in the spool for the next try.
+fn finish_segments(recording: &RecordingConfig, backend: &dyn StorageBackend, encryptor: &mut Option<SegmentEncryptor>) -> bool {
+    let source = recording.write_directory();
+    let entries = match std::fs::read_dir(source) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to list {}: {}", source, e);
+            return true;
+        }
+    };
+    let after = Duration::from_secs(recording.finished_after_seconds);
+
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("ts") {
+            continue;
+        }
+        let Some((modified, idle)) = idle_for(&path) else { continue };
+        if idle < after {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+
+        let result = match encryptor.as_mut() {
+            // Sealed onto the recording disk; the plaintext only ever sits in staging
+            Some(encryptor) => {
+                let sealed_name = encryptor.file_name(name);
+                let sealed = Path::new(&recording.directory).join(format!("{}.partial", sealed_name));
+                let result = seal_file(encryptor, &path, &sealed).and_then(|()| backend.put(&sealed_name, &sealed, modified));
+                let _ = std::fs::remove_file(&sealed);
+                result
+            }
+            None => backend.put(name, &path, modified),
+        };
+        match result {
+            // Gone already when the backend could move it into place
+            Ok(()) => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => eprintln!("Failed to remove {}: {}", path.display(), e),
+                _ => {}
+            },
+            Err(e) => {
+                eprintln!("Failed to store segment {} in {}: {}", path.display(), backend.describe(), e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Post-process finished segments in the background, when staging, encryption or a
+// storage backend other than the recording directory needs it
+pub fn spawn_segment_finisher(recording: RecordingConfig) {
+    let backend = storage::open(&recording);
+    // Recording in the clear is what encryption is there to prevent, so the camera doesn't
+    // start rather than carry on without it
+    let mut encryptor = match recording.encryption.clone() {
+        None => None,
+        Some(_) if recording.write_directory() == recording.directory => {
+            eprintln!("Recording encryption needs wear.staging_directory on a tmpfs, or segments sit on the card unencrypted until they are sealed");
+            std::process::exit(1);
+        }
+        Some(encryption) => match SegmentEncryptor::new(encryption) {
+            Ok(encryptor) => Some(encryptor),
+            Err(e) => {
+                eprintln!("Failed to set up recording encryption: {}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+    if encryptor.is_none() && backend.is_recording_directory(recording.write_directory()) {
+        return;
+    }
+    println!("Finished segments are stored in {}", backend.describe());
+
+    tokio::spawn(async move {
+        let mut check = interval(Duration::from_secs(10));
+        // A backend that is down is tried less and less often, up to every few minutes
+        let mut backoff = Duration::ZERO;
+        let mut retry_at = Instant::now();
+        loop {
+            check.tick().await;
+            if Instant::now() < retry_at {
+                continue;
+            }
+            let task_recording = recording.clone();
+            let task_backend = backend.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let stored = finish_segments(&task_recording, task_backend.as_ref(), &mut encryptor);
+                (stored, encryptor)
+            })
+            .await;
+            let stored;
+            (stored, encryptor) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("Segment finisher stopped: {}", e);
+                    return;
+                }
+            };
+            backoff = if stored { Duration::ZERO } else { (backoff * 2).clamp(Duration::from_secs(20), Duration::from_secs(300)) };
+            if !backoff.is_zero() {
+                eprintln!("Keeping segments in {} for now, trying {} again in {}s", recording.write_directory(), backend.describe(), backoff.as_secs());
+            }
+            retry_at = Instant::now() + backoff;
+        }
+    });
+}
+
+// Bytes written to the device since boot, from the kernel's block statistics
+fn bytes_written(device: &str) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/sys/block/{}/stat", device)).ok()?;
+    let sectors: u64 = stat.split_whitespace().nth(6)?.parse().ok()?;
+    Some(sectors * 512)
+}
+
+fn capacity(device: &str) -> Option<u64> {
+    let size = std::fs::read_to_string(format!("/sys/block/{}/size", device)).ok()?;
+    Some(size.trim().parse::<u64>().ok()? * 512)
+}
+
+fn log_day(recording: &RecordingConfig, date: &str, bytes: u64) {
+    let path = format!("{}/wear.jsonl", recording.directory);
+    let line = json!({ "date": date, "bytes_written": bytes });
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        eprintln!("Failed to log card writes to {}: {}", path, e);
+    }
+}
+
+// Account the bytes written to the card per local day (all writers, not just ours) and
+// warn once a day when that rate would exhaust the card's rated endurance too soon
+pub fn spawn_wear_monitor(recording: RecordingConfig, time: TimeConfig) {
+    let config = recording.wear.clone();
+    let (Some(start), Some(capacity)) = (bytes_written(&config.device), capacity(&config.device)) else {
+        eprintln!("SD card wear accounting disabled: no block statistics for {}", config.device);
+        return;
+    };
+    let endurance = capacity as f64 * config.rated_write_cycles as f64;
+
+    tokio::spawn(async move {
+        let mut day = time.now().format("%Y-%m-%d").to_string();
+        let mut day_start = start;
+        let mut day_started_at = Instant::now();
+        let mut warned = false;
+        let mut check = interval(Duration::from_secs(600));
+
+        loop {
+            check.tick().await;
+            let Some(written) = bytes_written(&config.device) else { continue };
+            let today = written.saturating_sub(day_start);
+
+            let now = time.now().format("%Y-%m-%d").to_string();
+            if now != day {
+                println!("SD card writes on {}: {} MB", day, today / 1_000_000);
+                log_day(&recording, &day, today);
+                day = now;
+                day_start = written;
+                day_started_at = Instant::now();
+                warned = false;
+                continue;
+            }
+
+            // Extrapolate once there is enough of the day to go on
+            let elapsed = day_started_at.elapsed().as_secs_f64();
+            if warned || elapsed < 3600.0 || today == 0 {
+                continue;
+            }
+            let per_day = today as f64 / elapsed * 86400.0;
+            let years = endurance / per_day / 365.0;
+            if years < config.warn_below_years {
+                eprintln!(
+                    "SD card wear warning: writing about {} MB/day, the card's rated endurance lasts about {:.1} years at this rate",
+                    per_day as u64 / 1_000_000,
+                    years
+                );
+                warned = true;
+            }
+        }
+    });
+}
@@ -0,0 +1,39 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use rust_embed::RustEmbed;
+
+// The page, script and stylesheet in web_ui/, compiled into the binary so the camera
+// needs nothing else on disk to serve them
+#[derive(RustEmbed)]
+#[folder = "web_ui/"]
+struct Assets;
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn asset(path: &str) -> Response {
+    match Assets::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type(path)), (header::CACHE_CONTROL, "no-cache")], file.data).into_response(),
+        None => (StatusCode::NOT_FOUND, "not found").into_response(),
+    }
+}
+
+// Live preview, events and settings in a browser, for running the camera standalone
+// without a server. Public: it holds nothing, and asks for the API token itself.
+pub fn router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new()
+        .route("/", get(|| asset("index.html")))
+        .route("/ui/{file}", get(|Path(file): Path<String>| async move { asset(&file).await }))
+}
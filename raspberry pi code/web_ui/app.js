@@ -0,0 +1,310 @@
+'use strict';
+
+// Everything goes through the REST API on this same address. The token is only sent
+// to sign in; after that the session is an HttpOnly cookie the page never sees.
+const PREVIEW_EVERY_MS = 500;
+const EVENTS_EVERY_MS = 3000;
+const STATUS_EVERY_MS = 2000;
+const OVERLAY_ID = 'web_ui';
+
+const $ = (id) => document.getElementById(id);
+let timers = [];
+let config = null;
+let role = null;
+
+class Unauthorized extends Error {}
+
+async function api(method, path, body) {
+  const options = { method, headers: {}, credentials: 'same-origin' };
+  if (body !== undefined) {
+    options.headers['Content-Type'] = 'application/json';
+    options.body = typeof body === 'string' ? body : JSON.stringify(body);
+  }
+  const response = await fetch(path, options);
+  if (response.status === 401) {
+    throw new Unauthorized('bad token');
+  }
+  if (!response.ok) {
+    const error = await response.json().catch(() => ({}));
+    throw new Error(error.error || `${response.status} ${response.statusText}`);
+  }
+  return response;
+}
+
+function signIn() {
+  $('login').hidden = true;
+  $('app').hidden = false;
+  refreshStatus();
+  refreshPreview();
+  refreshEvents();
+  timers = [
+    setInterval(refreshStatus, STATUS_EVERY_MS),
+    setInterval(refreshPreview, PREVIEW_EVERY_MS),
+    setInterval(refreshEvents, EVENTS_EVERY_MS),
+  ];
+}
+
+function signOut(message) {
+  timers.forEach(clearInterval);
+  timers = [];
+  role = null;
+  $('app').hidden = true;
+  $('login').hidden = false;
+  $('login-error').textContent = message || '';
+}
+
+// Any request turned away means the session ran out or the camera restarted
+function failed(error, where) {
+  if (error instanceof Unauthorized) {
+    signOut('Signed out, sign in again');
+  } else if (where) {
+    where.textContent = error.message;
+    where.className = 'error';
+  }
+}
+
+function succeeded(message, where) {
+  where.textContent = message;
+  where.className = '';
+}
+
+async function refreshStatus() {
+  try {
+    const status = await (await api('GET', '/api/status')).json();
+    // Viewer tokens can't change anything, so they don't get the controls or settings
+    if (status.role !== role) {
+      role = status.role;
+      document.body.dataset.role = role;
+      if (role === 'admin') loadSettings();
+    }
+    $('camera-id').textContent = status.camera_id;
+    $('state').textContent = status.state;
+    $('state').dataset.state = status.state;
+    const parts = [status.resolution, status.codec.toUpperCase(), `quality ${status.quality}`, `queue ${status.queue_size}`];
+    if (status.congested) parts.push('congested');
+    if (status.paused) parts.push('paused');
+    if (status.illuminator) parts.push(`illuminator ${JSON.stringify(status.illuminator)}`);
+    $('status-line').textContent = parts.join(' · ');
+    $('alarm-control').hidden = status.alarm === null;
+    if (status.alarm !== null && document.activeElement !== $('alarm-mode')) {
+      $('alarm-mode').value = status.alarm;
+    }
+  } catch (error) {
+    failed(error);
+  }
+}
+
+// A still at a time rather than a stream, so the size follows the page; skipped while
+// the tab is hidden or the last one is still loading
+let previewLoading = false;
+async function refreshPreview() {
+  if (document.hidden || previewLoading) return;
+  previewLoading = true;
+  try {
+    const width = Math.min(1280, Math.round($('preview').clientWidth * devicePixelRatio) || 640);
+    const blob = await (await api('GET', `/api/snapshot?width=${width}`)).blob();
+    const previous = $('preview').src;
+    $('preview').src = URL.createObjectURL(blob);
+    if (previous.startsWith('blob:')) URL.revokeObjectURL(previous);
+  } catch (error) {
+    failed(error);
+  } finally {
+    previewLoading = false;
+  }
+}
+
+async function refreshEvents() {
+  try {
+    const kind = $('event-kind').value;
+    const query = kind ? `?limit=50&kind=${encodeURIComponent(kind)}` : '?limit=50';
+    const { events } = await (await api('GET', `/api/events${query}`)).json();
+    const list = $('events');
+    list.replaceChildren(...events.map((event) => {
+      const item = document.createElement('li');
+      const time = document.createElement('time');
+      time.textContent = new Date(event.timestamp).toLocaleString();
+      item.append(time, ` ${event.description}`);
+      return item;
+    }));
+    if (events.length === 0) {
+      list.textContent = 'Nothing yet';
+    }
+    // The filter offers every kind seen so far
+    const known = new Set([...$('event-kind').options].map((option) => option.value));
+    for (const event of events) {
+      if (!known.has(event.kind)) {
+        $('event-kind').add(new Option(event.kind, event.kind));
+        known.add(event.kind);
+      }
+    }
+  } catch (error) {
+    failed(error);
+  }
+}
+
+async function control(method, params) {
+  try {
+    const { result } = await (await api('POST', `/api/control/${method}`, params)).json();
+    succeeded(typeof result === 'string' ? result : JSON.stringify(result), $('control-result'));
+    refreshStatus();
+  } catch (error) {
+    failed(error, $('control-result'));
+  }
+}
+
+// The settings form mirrors the config file: a fieldset per section, an input per
+// value. Lists, and sections that are off (null), are edited as JSON.
+function field(key, value, path) {
+  const label = document.createElement('label');
+  label.append(key.replaceAll('_', ' '));
+  let input;
+  if (typeof value === 'boolean') {
+    input = document.createElement('input');
+    input.type = 'checkbox';
+    input.checked = value;
+    label.className = 'checkbox';
+  } else if (typeof value === 'number') {
+    input = document.createElement('input');
+    input.type = 'number';
+    input.step = 'any';
+    input.value = value;
+  } else if (typeof value === 'string') {
+    input = document.createElement('input');
+    input.value = value;
+    if (/password|token|secret|key/.test(key)) input.type = 'password';
+  } else {
+    input = document.createElement('textarea');
+    input.value = JSON.stringify(value, null, 2);
+    input.dataset.json = 'true';
+  }
+  input.dataset.path = JSON.stringify(path);
+  label.append(input);
+  return label;
+}
+
+function section(object, path) {
+  const nodes = [];
+  for (const [key, value] of Object.entries(object)) {
+    if (value !== null && typeof value === 'object' && !Array.isArray(value)) {
+      const fieldset = document.createElement('fieldset');
+      const legend = document.createElement('legend');
+      legend.textContent = key.replaceAll('_', ' ');
+      fieldset.append(legend, ...section(value, [...path, key]));
+      nodes.push(fieldset);
+    } else {
+      nodes.push(field(key, value, [...path, key]));
+    }
+  }
+  return nodes;
+}
+
+function fromForm() {
+  const edited = structuredClone(config);
+  for (const input of $('settings').querySelectorAll('[data-path]')) {
+    const path = JSON.parse(input.dataset.path);
+    const key = path.pop();
+    const parent = path.reduce((object, name) => object[name], edited);
+    if (input.type === 'checkbox') {
+      parent[key] = input.checked;
+    } else if (input.type === 'number') {
+      parent[key] = input.value === '' ? null : Number(input.value);
+    } else if (input.dataset.json) {
+      try {
+        parent[key] = JSON.parse(input.value);
+      } catch (error) {
+        throw new Error(`${path.concat(key).join('.')}: ${error.message}`);
+      }
+    } else {
+      parent[key] = input.value;
+    }
+  }
+  return edited;
+}
+
+function showSettings(showJson) {
+  $('settings').hidden = showJson;
+  $('settings-json').hidden = !showJson;
+  $('show-form').classList.toggle('selected', !showJson);
+  $('show-json').classList.toggle('selected', showJson);
+}
+
+async function loadSettings() {
+  try {
+    config = await (await api('GET', '/api/config')).json();
+    $('settings').replaceChildren(...section(config, []));
+    $('settings-json').value = JSON.stringify(config, null, 2);
+  } catch (error) {
+    failed(error, $('settings-result'));
+  }
+}
+
+async function saveSettings() {
+  try {
+    const body = $('settings-json').hidden ? JSON.stringify(fromForm()) : $('settings-json').value;
+    await api('PUT', '/api/config', body);
+    succeeded('Saved; restart the camera to apply', $('settings-result'));
+    await loadSettings();
+  } catch (error) {
+    failed(error, $('settings-result'));
+  }
+}
+
+$('login').addEventListener('submit', async (event) => {
+  event.preventDefault();
+  try {
+    const response = await fetch('/api/session', {
+      method: 'POST',
+      headers: { Authorization: `Bearer ${$('token').value}` },
+      credentials: 'same-origin',
+    });
+    $('token').value = '';
+    if (response.status === 401) throw new Unauthorized('bad token');
+    if (!response.ok) throw new Error(`${response.status} ${response.statusText}`);
+    signIn();
+  } catch (error) {
+    signOut(error instanceof Unauthorized ? 'The token was not accepted' : error.message);
+  }
+});
+$('sign-out').addEventListener('click', async () => {
+  await fetch('/api/session', { method: 'DELETE', credentials: 'same-origin' }).catch(() => {});
+  signOut();
+});
+
+$('alarm-mode').addEventListener('change', () => control('alarm', { mode: $('alarm-mode').value }));
+$('overlay-form').addEventListener('submit', (event) => {
+  event.preventDefault();
+  control('overlay', { id: OVERLAY_ID, kind: 'text', text: $('overlay-text').value, x: 0.02, y: 0.08 });
+});
+$('overlay-clear').addEventListener('click', () => control('clear_overlay', { id: OVERLAY_ID }));
+$('restart').addEventListener('click', () => {
+  if (confirm('Restart the camera? It is only started again when run under a service manager.')) {
+    control('shutdown');
+  }
+});
+$('event-kind').addEventListener('change', refreshEvents);
+
+$('show-form').addEventListener('click', () => {
+  try {
+    config = JSON.parse($('settings-json').value);
+    $('settings').replaceChildren(...section(config, []));
+    showSettings(false);
+  } catch (error) {
+    failed(error, $('settings-result'));
+  }
+});
+$('show-json').addEventListener('click', () => {
+  try {
+    $('settings-json').value = JSON.stringify(fromForm(), null, 2);
+    showSettings(true);
+  } catch (error) {
+    failed(error, $('settings-result'));
+  }
+});
+$('save-settings').addEventListener('click', saveSettings);
+$('settings').addEventListener('submit', (event) => {
+  event.preventDefault();
+  saveSettings();
+});
+
+// Still signed in from before if the cookie is
+api('GET', '/api/status').then(signIn, () => signOut());